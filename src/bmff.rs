@@ -0,0 +1,80 @@
+//! Minimal ISO base media file format (MP4/MOV/CR3/HEIF) box walker, for
+//! reading metadata the higher-level parsers do not expose.
+
+use anyhow::Result;
+use std::io::{Read, Seek, SeekFrom};
+
+/// A box header: its four-character type and the byte range of its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxRange {
+    pub kind: [u8; 4],
+    pub start: u64,
+    pub end: u64,
+}
+
+/// List the boxes laid out in `[start, end)`.
+pub fn children<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Result<Vec<BoxRange>> {
+    let mut boxes = Vec::new();
+    let mut pos = start;
+
+    while pos + 8 <= end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let size32 = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let kind = [header[4], header[5], header[6], header[7]];
+
+        let (header_len, size) = match size32 {
+            // Box extends to the end of its parent
+            0 => (8, end - pos),
+            // 64-bit size follows the type
+            1 => {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                (16, u64::from_be_bytes(large))
+            }
+            n => (8, n),
+        };
+        if size < header_len || pos + size > end {
+            anyhow::bail!(
+                "Malformed box '{}' at offset {}",
+                String::from_utf8_lossy(&kind),
+                pos
+            );
+        }
+
+        boxes.push(BoxRange {
+            kind,
+            start: pos + header_len,
+            end: pos + size,
+        });
+        pos += size;
+    }
+
+    Ok(boxes)
+}
+
+/// Find the first box of the given type in `[start, end)`.
+pub fn find<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+    kind: &[u8; 4],
+) -> Result<Option<BoxRange>> {
+    Ok(children(reader, start, end)?
+        .into_iter()
+        .find(|b| &b.kind == kind))
+}
+
+/// Read a box's whole payload.
+pub fn read_payload<R: Read + Seek>(reader: &mut R, range: BoxRange) -> Result<Vec<u8>> {
+    read_at(reader, range.start, range.end - range.start)
+}
+
+/// Read `len` bytes at `offset`.
+pub fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: u64) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0u8; len as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
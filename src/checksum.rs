@@ -0,0 +1,244 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::Xxh3;
+
+const CACHE_FILENAME: &str = ".image-processor-hashes.json";
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// Algorithm files are hashed with to verify copies, list them in manifests
+/// and find identical ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    /// 64-bit XXH3, the fastest, for catching copy errors rather than
+    /// tampering; as `xxhsum -H3` prints it
+    Xxh3,
+    /// BLAKE3, cryptographic and still fast
+    Blake3,
+    /// SHA-256, slower, but what archival standards and `sha256sum` expect
+    #[default]
+    Sha256,
+}
+
+impl std::fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HashAlgo::Xxh3 => "xxh3",
+            HashAlgo::Blake3 => "blake3",
+            HashAlgo::Sha256 => "sha256",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Incremental hash, for hashing data while it is being copied.
+pub struct Hasher(State);
+
+enum State {
+    Xxh3(Box<Xxh3>),
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn new(algo: HashAlgo) -> Self {
+        Self(match algo {
+            HashAlgo::Xxh3 => State::Xxh3(Box::default()),
+            HashAlgo::Blake3 => State::Blake3(Box::default()),
+            HashAlgo::Sha256 => State::Sha256(Sha256::new()),
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.0 {
+            State::Xxh3(hasher) => hasher.update(data),
+            State::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            State::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn algo(&self) -> HashAlgo {
+        match self.0 {
+            State::Xxh3(_) => HashAlgo::Xxh3,
+            State::Blake3(_) => HashAlgo::Blake3,
+            State::Sha256(_) => HashAlgo::Sha256,
+        }
+    }
+
+    /// The digest as lowercase hex.
+    pub fn finish(self) -> String {
+        match self.0 {
+            State::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
+            State::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            State::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+/// Hash of a file's contents with `algo`, as lowercase hex.
+pub fn hash_file(path: &Path, algo: HashAlgo) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Hasher::new(algo);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedHash {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    /// Caches written before the algorithm could be chosen hold SHA-256
+    #[serde(default)]
+    algo: HashAlgo,
+    hash: String,
+}
+
+/// Persistent cache of destination file hashes, stored in the output
+/// directory. An entry is only reused while the file's size and mtime are
+/// unchanged, so repeated verification of an untouched archive costs a stat
+/// per file instead of a full read.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    entries: HashMap<PathBuf, CachedHash>,
+}
+
+impl HashCache {
+    /// Load the cache from the output directory, or start an empty one.
+    pub fn load(output_dir: &Path) -> Self {
+        fs::read_to_string(output_dir.join(CACHE_FILENAME))
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the cache atomically to the output directory.
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let target = output_dir.join(CACHE_FILENAME);
+        let tmp = output_dir.join(format!(
+            ".image-processor-hashes.tmp.{}",
+            std::process::id()
+        ));
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, &target)?;
+        Ok(())
+    }
+
+    /// Cached `algo` hash of `path`, if the file is unchanged since it was
+    /// stored.
+    pub fn cached(&self, path: &Path, algo: HashAlgo) -> Result<Option<String>> {
+        let (size, mtime_secs, mtime_nanos) = stat(path)?;
+        Ok(self
+            .entries
+            .get(path)
+            .filter(|c| {
+                c.size == size && c.mtime_secs == mtime_secs && c.mtime_nanos == mtime_nanos
+            })
+            .filter(|c| c.algo == algo)
+            .map(|c| c.hash.clone()))
+    }
+
+    /// Remember the `algo` hash of `path` for its current size and mtime.
+    pub fn store(&mut self, path: &Path, algo: HashAlgo, hash: String) -> Result<()> {
+        let (size, mtime_secs, mtime_nanos) = stat(path)?;
+        self.entries.insert(
+            path.to_path_buf(),
+            CachedHash {
+                size,
+                mtime_secs,
+                mtime_nanos,
+                algo,
+                hash,
+            },
+        );
+        Ok(())
+    }
+
+    /// `algo` hash of `path`, reused from the cache when the file is
+    /// unchanged, otherwise computed and stored.
+    pub fn hash(&mut self, path: &Path, algo: HashAlgo) -> Result<String> {
+        if let Some(hash) = self.cached(path, algo)? {
+            return Ok(hash);
+        }
+        let hash = hash_file(path, algo)?;
+        self.store(path, algo, hash.clone())?;
+        Ok(hash)
+    }
+}
+
+fn stat(path: &Path) -> Result<(u64, u64, u32)> {
+    let meta = fs::metadata(path)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((meta.len(), mtime.as_secs(), mtime.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.cr2");
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            hash_file(&path, HashAlgo::Sha256).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash_file(&path, HashAlgo::Xxh3).unwrap(),
+            "78af5f94892f3950"
+        );
+        assert_eq!(
+            hash_file(&path, HashAlgo::Blake3).unwrap(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn test_cache_reused_until_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.cr2");
+        fs::write(&path, b"abc").unwrap();
+
+        let mut cache = HashCache::default();
+        let first = cache.hash(&path, HashAlgo::Sha256).unwrap();
+        cache.save(dir.path()).unwrap();
+
+        // Tamper with the cached hash: an unchanged file must reuse it
+        let mut cache = HashCache::load(dir.path());
+        cache.entries.get_mut(&path).unwrap().hash = "cached".to_string();
+        assert_eq!(cache.hash(&path, HashAlgo::Sha256).unwrap(), "cached");
+        // Unless it is asked for with another algorithm
+        assert_eq!(
+            cache.hash(&path, HashAlgo::Xxh3).unwrap(),
+            hash_file(&path, HashAlgo::Xxh3).unwrap()
+        );
+        assert_eq!(cache.hash(&path, HashAlgo::Xxh3).unwrap().len(), 16);
+
+        // A size change invalidates the entry
+        fs::write(&path, b"abcd").unwrap();
+        let second = cache.hash(&path, HashAlgo::Sha256).unwrap();
+        assert_ne!(second, "cached");
+        assert_ne!(second, first);
+    }
+}
@@ -1,23 +1,807 @@
-use clap::Parser;
+use crate::output::ProgressFormat;
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use image_processor::checksum::HashAlgo;
+use image_processor::metadata::Zone;
+use image_processor::preserve::Attribute;
+use image_processor::proxy;
+use image_processor::routing::{self, Route};
+use image_processor::scanner::{self, LrfMode};
+use image_processor::session::{self, FolderTemplate, Gap, Layout, NameTemplate, Sort, Stragglers};
+use image_processor::transfer::{ExtensionCase, LinkMode, OnConflict, OnError};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(name = "image-processor")]
-#[command(about = "Copy CR2/MP4 files from SD card to destination, organized by shooting session")]
-pub struct Args {
-    /// Input directory (e.g. SD card mount point)
+#[command(
+    about = "Copy photo and video files from SD card to destination, organized by shooting session"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Read default options from this file instead of
+    /// ~/.config/image-processor/config.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Append a log of scanned files, metadata failures, copies and errors
+    /// to this file
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Copy files from the card into session folders
+    Import(ImportArgs),
+    /// Wait for the card to be mounted, import it, and repeat for the next card
+    Watch(WatchArgs),
+    /// Import two cards that mirror each other, such as the slots of a
+    /// dual-slot camera, copying identical files once and reporting files
+    /// found on only one card
+    MergeCards(MergeCardsArgs),
+    /// Import in a full-screen terminal UI showing the scan, the sessions,
+    /// each worker's file, the throughput and the errors. `p` pauses, `s`
+    /// skips the selected worker's file and `q` aborts cleanly.
+    Tui(ImportArgs),
+    /// Continue an interrupted import into the destination
+    Resume(ResumeArgs),
+    /// List the sessions found on the card without copying anything
+    Scan(ScanArgs),
+    /// Re-check files from a previous import against their sources without
+    /// copying. Unchanged destination files reuse their cached hash.
+    Verify(VerifyArgs),
+    /// Print the state of an interrupted import
+    Status(StatusArgs),
+    /// Remove the files and folders created by the last import into the
+    /// destination(s), leaving pre-existing content untouched
+    Undo(UndoArgs),
+}
+
+impl Command {
+    /// Whether the command prints a JSON report instead of human output.
+    pub fn json(&self) -> bool {
+        match self {
+            Command::Import(args) => args.console.json,
+            Command::Watch(args) => args.import.console.json,
+            Command::MergeCards(args) => args.console.json,
+            Command::Tui(args) => args.console.json,
+            Command::Resume(args) => args.console.json,
+            Command::Scan(args) => args.console.json,
+            _ => false,
+        }
+    }
+
+    /// Whether --quiet was given.
+    pub fn quiet(&self) -> bool {
+        match self {
+            Command::Import(args) => args.console.quiet,
+            Command::Watch(args) => args.import.console.quiet,
+            Command::MergeCards(args) => args.console.quiet,
+            Command::Resume(args) => args.console.quiet,
+            Command::Scan(args) => args.console.quiet,
+            _ => false,
+        }
+    }
+
+    /// How progress is reported on the console.
+    pub fn progress_format(&self) -> ProgressFormat {
+        match self {
+            Command::Import(args) => args.console.progress_format,
+            Command::Watch(args) => args.import.console.progress_format,
+            Command::MergeCards(args) => args.console.progress_format,
+            Command::Resume(args) => args.console.progress_format,
+            Command::Scan(args) => args.console.progress_format,
+            _ => ProgressFormat::Bars,
+        }
+    }
+
+    /// How many times -v was given.
+    pub fn verbosity(&self) -> u8 {
+        match self {
+            Command::Import(args) => args.selection.verbose,
+            Command::Watch(args) => args.import.selection.verbose,
+            Command::MergeCards(args) => args.selection.verbose,
+            Command::Tui(args) => args.selection.verbose,
+            Command::Resume(args) => args.selection.verbose,
+            Command::Scan(args) => args.selection.verbose,
+            Command::Verify(args) => args.selection.verbose,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    #[command(flatten)]
+    pub source: InputArgs,
+
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    #[command(flatten)]
+    pub destination: DestinationArgs,
+
+    #[command(flatten)]
+    pub transfer: TransferArgs,
+
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub import: ImportArgs,
+
+    /// Seconds between checks for the card being inserted or removed
+    #[arg(long, default_value_t = 2.0, value_parser = parse_interval)]
+    pub poll_interval: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct MergeCardsArgs {
+    /// The two cards, given as `-i <first> -i <second>`
+    #[arg(short, long, required = true)]
+    pub input: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    #[command(flatten)]
+    pub destination: DestinationArgs,
+
+    #[command(flatten)]
+    pub transfer: TransferArgs,
+
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ResumeArgs {
+    /// Input directory, repeatable (default: the ones recorded by the
+    /// interrupted import)
+    #[arg(short, long)]
+    pub input: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    #[command(flatten)]
+    pub destination: DestinationArgs,
+
+    #[command(flatten)]
+    pub transfer: TransferArgs,
+
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    #[command(flatten)]
+    pub source: InputArgs,
+
+    /// Output directory of the imports of these cards, whose metadata cache
+    /// is read and updated
     #[arg(short, long)]
-    pub input: PathBuf,
+    pub output: Option<PathBuf>,
 
-    /// Output directory where session folders will be created
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub source: InputArgs,
+
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+
+    #[command(flatten)]
+    pub destination: DestinationArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct StatusArgs {
+    /// Output directory of the import
     #[arg(short, long)]
     pub output: PathBuf,
 
-    /// Minimum gap in hours between consecutive files to start a new session
-    #[arg(long, default_value_t = 6.0)]
-    pub gap_hours: f64,
+    /// Input directory, checked for a state copy when the output has none
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+
+    /// Also list every file copied and every partly copied file
+    #[arg(short, long, default_value_t = false)]
+    pub verbose: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct UndoArgs {
+    /// Output directory of the import to undo
+    #[arg(short, long, required_unless_present = "route")]
+    pub output: Option<PathBuf>,
+
+    /// Also undo the import into these routed roots, e.g. `cr2,cr3=/Volumes/Work`
+    #[arg(long, value_parser = routing::parse_route)]
+    pub route: Vec<Route>,
+
+    /// List what would be removed without removing anything
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    /// Remove the imported files even if they were changed since the import
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+}
+
+/// Where the card is.
+#[derive(Args, Debug)]
+pub struct InputArgs {
+    /// Input directory (e.g. SD card mount point), or `ptp:` for a camera
+    /// connected over PTP/MTP (`ptp:<port>` to pick one). Repeat it to import
+    /// several cards or folders as one
+    #[arg(short, long, required_unless_present = "auto")]
+    pub input: Vec<PathBuf>,
+
+    /// Detect a mounted card (a removable volume with a DCIM or PRIVATE/AVCHD
+    /// folder) instead of giving --input, which takes precedence
+    #[arg(long, default_value_t = false)]
+    pub auto: bool,
+}
+
+/// Which files are read from the card and how they are grouped into sessions.
+#[derive(Args, Debug, Clone)]
+pub struct SelectionArgs {
+    /// File extensions to import, comma-separated
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = scanner::parse_extension,
+        default_value = scanner::DEFAULT_EXTENSIONS
+    )]
+    pub extensions: Vec<String>,
+
+    /// Skip card paths matching this glob, relative to the input and case
+    /// insensitive, e.g. `**/MISC/**` or `*.CTG` (repeatable)
+    #[arg(long, value_parser = scanner::parse_glob)]
+    pub exclude: Vec<String>,
+
+    /// Skip files smaller than this, e.g. `1` for the empty files a failing
+    /// card leaves, or `100KB`. Sidecars are not size-filtered.
+    #[arg(long, value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Skip files larger than this, e.g. `2GB` to leave out long videos
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Check the structure of each file and leave out damaged ones, such as
+    /// raws without a TIFF header or videos without a `moov` box, instead of
+    /// dating them by modified time. An import copies them, and the files
+    /// whose date cannot be read, to `_quarantine` in the output.
+    #[arg(long, default_value_t = false)]
+    pub quarantine: bool,
+
+    /// Read only the numbered camera folders of the card's `DCIM` folder
+    /// (`100CANON`, `101CANON`, ...) and the clip folders of camcorders,
+    /// skipping `MISC`, `CANONMSC` and everything else on the card
+    #[arg(long, default_value_t = false)]
+    pub dcim: bool,
+
+    /// What to do with the `.LRF` previews DJI drones write next to each
+    /// video; their `.SRT` flight logs are always copied along
+    #[arg(long, value_enum, default_value_t = LrfMode::Copy)]
+    pub lrf: LrfMode,
+
+    /// Only import files taken at or after this date (`2024-06-01`) or time
+    /// (`2024-06-01T14:30`), in the capture time zone
+    #[arg(long, value_parser = parse_date_bound)]
+    pub since: Option<NaiveDateTime>,
+
+    /// Only import files taken before this date or time; `--since 2024-06-01
+    /// --until 2024-06-02` selects June 1st
+    #[arg(long, value_parser = parse_date_bound)]
+    pub until: Option<NaiveDateTime>,
+
+    /// Only import photos from cameras whose make or model contains this,
+    /// ignoring case (`"Canon EOS R5"`, `r5`). Repeatable.
+    #[arg(long = "camera", value_name = "CAMERA")]
+    pub cameras: Vec<String>,
+
+    /// Order files are grouped into sessions in: by file number (`seq`;
+    /// several cards are merged by time), capture time, or capture time then
+    /// number (`hybrid`)
+    #[arg(long, value_enum, default_value_t = Sort::Seq)]
+    pub sort: Sort,
+
+    /// Minimum gap in hours between consecutive files to start a new
+    /// session, or `auto` to pick one from the intervals between the files
+    #[arg(long, visible_alias = "gap", default_value = "6", value_parser = Gap::parse, value_name = "HOURS|auto")]
+    pub gap_hours: Gap,
+
+    /// Merge sessions of fewer than this many files into another, such as a
+    /// test shot taken hours before a shoot (see --stragglers)
+    #[arg(long, value_name = "N")]
+    pub min_session_files: Option<usize>,
+
+    /// Where the files of sessions below --min-session-files go: into the
+    /// session nearest in time, or into one `_misc` folder
+    #[arg(long, value_enum, default_value_t = Stragglers::Nearest)]
+    pub stragglers: Stragglers,
+
+    /// Also start a new session when consecutive photos were taken more than
+    /// this many kilometres apart (from EXIF GPS)
+    #[arg(long)]
+    pub gps_split_km: Option<f64>,
+
+    /// Convert capture times to this zone before grouping: a UTC offset
+    /// (`+02:00`) or a name (`Europe/Paris`). Dates without a recorded offset
+    /// are taken to be in this zone already.
+    #[arg(long, visible_alias = "utc-offset", value_parser = Zone::parse)]
+    pub timezone: Option<Zone>,
+
+    /// Session folder name pattern; `/` nests folders. Variables: {date},
+    /// {year}, {month}, {day}, {time}, {index}, {camera}, {count}, {event}
+    #[arg(long, default_value = "{date}", value_parser = FolderTemplate::parse)]
+    pub folder_template: FolderTemplate,
+
+    /// Nest the session folders in a folder per year (`2024/2024-06-14`) or
+    /// per year and month (`2024/06/2024-06-14`)
+    #[arg(long, value_enum, default_value_t = Layout::Flat)]
+    pub layout: Layout,
+
+    /// Put each camera body's files in its own subfolder of the session,
+    /// e.g. `2024-01-15/CanonEOSR5`
+    #[arg(long, default_value_t = false)]
+    pub split_by_camera: bool,
+
+    /// Text for {event} in --folder-template
+    #[arg(long, value_parser = parse_event)]
+    pub event: Option<String>,
+
+    /// Print per-file details such as where each date was read from. With
+    /// --log-file, -v also logs every scanned file and -vv every state write.
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Warn when more than this fraction of files had to use the filesystem
+    /// modified time instead of a date from their metadata
+    #[arg(long, default_value_t = 0.1)]
+    pub fallback_warn_ratio: f64,
+
+    /// Abort instead of warning when too many files lack a metadata date
+    #[arg(long, default_value_t = false)]
+    pub require_metadata_dates: bool,
+
+    /// Copy byte-identical files, such as a photo written to both card
+    /// slots, only once
+    #[arg(long, default_value_t = false)]
+    pub skip_identical: bool,
+
+    /// Algorithm to hash files with when verifying copies, writing
+    /// manifests and finding identical files
+    #[arg(long, value_enum, default_value_t = HashAlgo::Sha256)]
+    pub hash_algo: HashAlgo,
+
+    /// Set by `merge-cards`: the two inputs should hold the same files
+    #[arg(skip)]
+    pub mirrored: bool,
+}
+
+/// Where sessions are written and how destination files are named.
+#[derive(Args, Debug)]
+pub struct DestinationArgs {
+    /// Output directory where session folders will be created.
+    /// With --route, receives the files no route matches.
+    #[arg(short, long, required_unless_present = "route")]
+    pub output: Option<PathBuf>,
+
+    /// Send files with the given extensions to another destination root,
+    /// e.g. `cr2,cr3=/Volumes/Work` (repeatable, first match wins)
+    #[arg(long, value_parser = routing::parse_route)]
+    pub route: Vec<Route>,
 
+    /// Also write every file to this second root, reading each source once.
+    /// Completion is tracked per destination, so either can be resumed.
+    #[arg(long, conflicts_with = "route")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Case of destination file extensions
+    #[arg(long, value_enum, default_value_t = ExtensionCase::Lower)]
+    pub extension_case: ExtensionCase,
+
+    /// Put each session's files in `RAW`, `JPEG`, `VIDEO` and `AUDIO` subfolders
+    #[arg(long, default_value_t = false)]
+    pub split_by_type: bool,
+
+    /// Put each GoPro recording split into chapters (`GH010123.MP4`,
+    /// `GH020123.MP4`, ...) in a folder of its own in its session
+    #[arg(long, default_value_t = false)]
+    pub chapter_folders: bool,
+
+    /// Destination file name pattern, e.g. `{date}_{time}_{seq}_{camera}.{ext}`.
+    /// Variables: {date}, {year}, {month}, {day}, {time}, {seq}, {camera},
+    /// {name}, {ext}
+    #[arg(long, value_parser = NameTemplate::parse)]
+    pub rename_template: Option<NameTemplate>,
+}
+
+/// How files are copied.
+#[derive(Args, Debug)]
+pub struct TransferArgs {
     /// Show what would be done without actually copying files
     #[arg(long, default_value_t = false)]
     pub dry_run: bool,
+
+    /// Before copying, review the sessions at a prompt: merge, split, rename
+    /// or exclude them
+    #[arg(long, default_value_t = false)]
+    pub review: bool,
+
+    /// Once every file is copied and verified, unmount the card so it can be
+    /// pulled out right away. Needs --verify (or --move)
+    #[arg(long, default_value_t = false)]
+    pub eject: bool,
+
+    /// How files are placed in the session folders: `copy`, `hardlink`
+    /// (when input and output share a filesystem, copying otherwise) or
+    /// `symlink` (to the absolute source path)
+    #[arg(long, value_enum, default_value_t = LinkMode::Copy)]
+    pub link_mode: LinkMode,
+
+    /// Same as --link-mode hardlink
+    #[arg(long, default_value_t = false, conflicts_with = "link_mode")]
+    pub link: bool,
+
+    /// Hash the source while copying and compare it with the destination
+    /// read back afterwards. Mismatched files are not marked as copied.
+    #[arg(long, default_value_t = false)]
+    pub verify: bool,
+
+    /// Delete each source file after its copy has been verified (implies --verify)
+    #[arg(long = "move", conflicts_with = "dry_run")]
+    pub move_files: bool,
+
+    /// Flush every copied file and its folder to disk before marking it as
+    /// copied, so the state never lists a file a power loss could lose
+    #[arg(long, default_value_t = false)]
+    pub fsync: bool,
+
+    /// Also copy these attributes of each file, comma-separated: `mode`
+    /// (permissions), `birthtime` (macOS and Windows), `xattrs` (Linux and
+    /// macOS, e.g. Finder tags). The modification time is always kept.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub preserve: Vec<Attribute>,
+
+    /// Skip files already anywhere under the output directory with the same
+    /// name, size and capture date
+    #[arg(long, default_value_t = false)]
+    pub skip_duplicates: bool,
+
+    /// Skip files already imported from this card into the output by an
+    /// earlier import, as recorded in the output's import history
+    #[arg(long, default_value_t = false)]
+    pub skip_imported: bool,
+
+    /// What to do when a destination file already exists
+    #[arg(long, value_enum, default_value_t = OnConflict::Rename)]
+    pub on_conflict: OnConflict,
+
+    /// What to do when a file cannot be copied: stop the import, or go on
+    /// with the other files and list the failed ones at the end
+    #[arg(long, value_enum, default_value_t = OnError::Abort)]
+    pub on_error: OnError,
+
+    /// Number of files to transfer in parallel
+    #[arg(short, long, default_value_t = 1, value_parser = parse_jobs)]
+    pub jobs: usize,
+
+    /// Write a manifest.json into every session folder listing each file's
+    /// original path, size, hash, capture date, camera and exposure
+    #[arg(long, default_value_t = false)]
+    pub manifest: bool,
+
+    /// Write a SHA256SUMS file (B3SUMS or XXH3SUMS with --hash-algo) into
+    /// every session folder, to check its files with the standard tools
+    #[arg(long, default_value_t = false)]
+    pub checksum_file: bool,
+
+    /// Extract the largest JPEG embedded in each raw (CR2, CR3, NEF, ARW) into
+    /// a `previews` folder in its session, for culling without a raw decoder
+    #[arg(long, default_value_t = false)]
+    pub thumbnails: bool,
+
+    /// After copying, render a JPEG preview of each photo, at most this many
+    /// pixels on the long edge, into a `_previews` folder in its session
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "2048",
+        value_name = "PIXELS",
+        value_parser = parse_pixels
+    )]
+    pub previews: Option<u32>,
+
+    /// Make a low-bitrate proxy of every MP4/MOV in a `proxies` folder in
+    /// its session, with ffmpeg or the --proxy-command
+    #[arg(long, default_value_t = false)]
+    pub proxy: bool,
+
+    /// Command making a proxy, run without a shell; `{input}` and `{output}`
+    /// are replaced by the copied video and the proxy to write
+    #[arg(long, default_value = proxy::DEFAULT_PROXY_COMMAND)]
+    pub proxy_command: String,
+
+    /// Cap the bytes written per second across all jobs, e.g. `80MB` or
+    /// `500KiB` (a backup destination counts towards the same cap)
+    #[arg(long, value_parser = parse_rate)]
+    pub max_rate: Option<u64>,
+
+    /// How many times a failed read of a file on the card is retried, the
+    /// file opened again, before the import stops
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// Seconds to wait before the first retry of a failed read, doubled for
+    /// each retry after
+    #[arg(long, default_value_t = 1.0, value_parser = parse_delay)]
+    pub retry_delay: f64,
+
+    /// Last-chance recovery from a failing card: when a file still cannot be
+    /// read after its retries, read it block by block, write the blocks that
+    /// cannot be read as zeros and copy the rest, listing the damaged ranges
+    #[arg(long, default_value_t = false)]
+    pub salvage: bool,
+
+    /// Buffers a file is read ahead into while earlier ones are written
+    #[arg(long, default_value_t = 4, value_parser = parse_jobs)]
+    pub buffers: usize,
+
+    /// Size of each read buffer, e.g. `1MiB`
+    #[arg(long, default_value = "256KiB", value_parser = parse_buffer_size)]
+    pub buffer_size: usize,
+
+    /// Start even when the files to copy do not fit in the destination's
+    /// free space, with a warning
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+
+    /// Break the lock another import holds on the output, when it is known
+    /// not to run any more
+    #[arg(long, default_value_t = false)]
+    pub force_unlock: bool,
+
+    /// Discard the state of an interrupted import into the destinations and
+    /// copy every file again, after asking for confirmation
+    #[arg(long, default_value_t = false)]
+    pub reset_state: bool,
+
+    /// Discard the state with --reset-state without asking
+    #[arg(long, default_value_t = false, requires = "reset_state")]
+    pub yes: bool,
+
+    /// Longest destination path allowed, in bytes (default: 260 on Windows,
+    /// 4096 elsewhere). Raise it when using Windows extended-length paths.
+    #[arg(long)]
+    pub max_path: Option<usize>,
+
+    /// Command run before anything is copied, without a shell, with the
+    /// planned sessions as JSON on stdin; exiting non-zero cancels the import
+    #[arg(long)]
+    pub pre_hook: Option<String>,
+
+    /// Command run once per session after the import, without a shell;
+    /// `{session_dir}`, `{session}`, `{files}` and `{bytes}` are replaced, and
+    /// passed as IMAGE_PROCESSOR_* environment variables too
+    #[arg(long)]
+    pub post_hook: Option<String>,
+
+    /// Command run once after the import, like --post-hook, with `{input}`,
+    /// `{inputs}`, `{output_dir}`, `{output_dirs}`, `{sessions}`, `{files}`
+    /// and `{bytes}`
+    #[arg(long)]
+    pub post_import_hook: Option<String>,
+}
+
+impl TransferArgs {
+    pub fn link_mode(&self) -> LinkMode {
+        if self.link {
+            LinkMode::Hardlink
+        } else {
+            self.link_mode
+        }
+    }
+}
+
+/// How results are reported.
+#[derive(Args, Debug)]
+pub struct ConsoleArgs {
+    /// Print a JSON report of the sessions and per-file results on stdout
+    /// instead of progress bars and messages. Warnings still go to stderr.
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+
+    /// `ndjson` prints one JSON object per line on stdout for every scan and
+    /// transfer event, for frontends following an import live
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bars, conflicts_with = "json")]
+    pub progress_format: ProgressFormat,
+
+    /// Print nothing but warnings, errors and a final summary, e.g. when run
+    /// from cron
+    #[arg(short, long, default_value_t = false)]
+    pub quiet: bool,
+
+    /// Show a desktop notification summing up the run when it ends
+    #[arg(long, default_value_t = false)]
+    pub notify: bool,
+
+    /// POST a JSON summary of the run to this URL when it ends
+    #[arg(long)]
+    pub webhook_url: Option<String>,
+}
+
+/// Parse a byte count with an optional decimal (`KB`, `MB`, `GB`) or binary
+/// (`KiB`, `MiB`, `GiB`) unit, e.g. `80MB` or `1.5GiB`.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        other => return Err(format!("unknown size unit '{}'", other)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse a transfer rate: a size per second, with or without the `/s`.
+fn parse_rate(s: &str) -> Result<u64, String> {
+    match parse_size(s.trim().trim_end_matches("/s"))? {
+        0 => Err("must be more than 0 bytes per second".to_string()),
+        rate => Ok(rate),
+    }
+}
+
+fn parse_buffer_size(s: &str) -> Result<usize, String> {
+    match parse_size(s)? {
+        0 => Err("must be more than 0 bytes".to_string()),
+        size => usize::try_from(size).map_err(|e| e.to_string()),
+    }
+}
+
+/// Parse a `--since`/`--until` bound: a date, meaning its midnight, or a
+/// date and time with or without seconds.
+fn parse_date_bound(s: &str) -> Result<NaiveDateTime, String> {
+    let s = s.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default());
+    }
+    [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+    .ok_or_else(|| {
+        format!(
+            "expected a date like 2024-06-01 or a time like 2024-06-01T14:30, got '{}'",
+            s
+        )
+    })
+}
+
+/// A number of seconds to wait between checks, more than 0.
+fn parse_interval(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(seconds),
+        Ok(_) => Err("must be a number of seconds more than 0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// A number of seconds to wait, 0 for none.
+fn parse_delay(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        Ok(_) => Err("must be a number of seconds, 0 or more".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// An `--event` that makes a folder name of its own.
+fn parse_event(s: &str) -> Result<String, String> {
+    match session::folder_component(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err("must be usable as a folder name, not empty, `.` or `..`".to_string()),
+    }
+}
+
+fn parse_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_pixels(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("80MB"), Ok(80_000_000));
+        assert_eq!(parse_rate("80 MB/s"), Ok(80_000_000));
+        assert_eq!(parse_rate("1.5GiB"), Ok(1_610_612_736));
+        assert_eq!(parse_rate("4096"), Ok(4096));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("10 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_parse_pixels() {
+        assert_eq!(parse_pixels("1024"), Ok(1024));
+        assert!(parse_pixels("0").is_err());
+        assert!(parse_pixels("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_seconds() {
+        assert_eq!(parse_interval("0.5"), Ok(0.5));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("-1").is_err());
+        assert!(parse_interval("NaN").is_err());
+        assert!(parse_interval("inf").is_err());
+        assert_eq!(parse_delay("0"), Ok(0.0));
+        assert!(parse_delay("-0.5").is_err());
+        assert!(parse_delay("NaN").is_err());
+    }
+
+    #[test]
+    fn test_parse_date_bound() {
+        let day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        assert_eq!(
+            parse_date_bound("2024-06-01"),
+            Ok(day.and_hms_opt(0, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_date_bound("2024-06-01T14:30"),
+            Ok(day.and_hms_opt(14, 30, 0).unwrap())
+        );
+        assert_eq!(
+            parse_date_bound("2024-06-01 14:30:15"),
+            Ok(day.and_hms_opt(14, 30, 15).unwrap())
+        );
+        assert!(parse_date_bound("yesterday").is_err());
+        assert!(parse_date_bound("2024-13-01").is_err());
+    }
 }
@@ -0,0 +1,178 @@
+use crate::cli::Cli;
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser};
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+/// Config file read when --config is not given, relative to the config directory.
+const DEFAULT_CONFIG: &str = "image-processor/config.toml";
+
+/// Parse the command line, filling options it leaves out from the config file.
+pub fn parse_args() -> Result<Cli> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let given = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&argv)
+        .ok();
+
+    let explicit = given
+        .as_ref()
+        .and_then(|m| m.get_one::<PathBuf>("config"))
+        .cloned();
+    let path = match explicit {
+        Some(path) => path,
+        None => match default_path() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(Cli::parse_from(argv)),
+        },
+    };
+    let text = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let merged = merge_config(argv, &text, given.as_ref())
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    Ok(Cli::parse_from(merged))
+}
+
+/// `$XDG_CONFIG_HOME/image-processor/config.toml`, falling back to `~/.config`.
+fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join(DEFAULT_CONFIG))
+}
+
+/// Add the options set in `config` to the command-line arguments, skipping
+/// any option the command line sets itself so it takes precedence. Keys are
+/// long option names, with `-` or `_` as separator; options the chosen
+/// subcommand does not take are ignored.
+fn merge_config(
+    argv: Vec<OsString>,
+    config: &str,
+    given: Option<&clap::ArgMatches>,
+) -> Result<Vec<OsString>> {
+    let table: toml::Table = config.parse()?;
+    let command = Cli::command();
+    let subcommand = given
+        .and_then(|m| m.subcommand())
+        .and_then(|(name, matches)| Some((command.find_subcommand(name)?, matches)));
+    let mut defaults = Vec::new();
+
+    for (key, value) in &table {
+        let long = key.replace('_', "-");
+        let find = |cmd: &clap::Command| {
+            cmd.get_arguments()
+                .find(|a| a.get_long() == Some(long.as_str()) && long != "config")
+                .cloned()
+        };
+        if !command.get_subcommands().any(|sub| find(sub).is_some()) {
+            anyhow::bail!("Unknown option `{}`", key);
+        }
+        let Some((arg, matches)) =
+            subcommand.and_then(|(sub, matches)| Some((find(sub)?, matches)))
+        else {
+            continue;
+        };
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(items) => items.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(set) if !arg.get_action().takes_values() => {
+                    if *set {
+                        defaults.push(format!("--{}", long).into());
+                    }
+                }
+                toml::Value::String(s) if arg.get_action().takes_values() => {
+                    defaults.push(format!("--{}={}", long, s).into());
+                }
+                toml::Value::Integer(_) | toml::Value::Float(_)
+                    if arg.get_action().takes_values() =>
+                {
+                    defaults.push(format!("--{}={}", long, value).into());
+                }
+                _ => anyhow::bail!("Unsupported value for `{}`: {}", key, value),
+            }
+        }
+    }
+
+    // Subcommand options may follow the subcommand's own arguments in any order
+    let mut merged = argv;
+    merged.extend(defaults);
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Command, ImportArgs};
+    use image_processor::session::Gap;
+
+    fn parse_with(cli: &[&str], config: &str) -> Result<Cli> {
+        let argv: Vec<OsString> = std::iter::once("image-processor")
+            .chain(cli.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let given = Cli::command()
+            .ignore_errors(true)
+            .try_get_matches_from(&argv)
+            .ok();
+        let merged = merge_config(argv, config, given.as_ref())?;
+        Ok(Cli::try_parse_from(merged)?)
+    }
+
+    fn import_args(cli: Cli) -> ImportArgs {
+        match cli.command {
+            Command::Import(args) => args,
+            other => panic!("expected import, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_config_fills_missing_options_and_cli_wins() {
+        let config = r#"
+            output = "/mnt/photos"
+            gap_hours = 4
+            verify = true
+            extensions = ["cr3", "mp4"]
+        "#;
+        let args = import_args(parse_with(&["import", "-i", "/card"], config).unwrap());
+        assert_eq!(args.destination.output, Some(PathBuf::from("/mnt/photos")));
+        assert_eq!(args.selection.gap_hours, Gap::Hours(4.0));
+        assert!(args.transfer.verify);
+        assert_eq!(args.selection.extensions, vec!["cr3", "mp4"]);
+
+        let cli = [
+            "import",
+            "-i",
+            "/card",
+            "--gap-hours",
+            "2",
+            "--extensions",
+            "nef",
+        ];
+        let args = import_args(parse_with(&cli, config).unwrap());
+        assert_eq!(args.selection.gap_hours, Gap::Hours(2.0));
+        assert_eq!(args.selection.extensions, vec!["nef"]);
+    }
+
+    #[test]
+    fn test_config_options_for_other_subcommands_ignored() {
+        let config = "output = \"/mnt/photos\"\njobs = 4\n";
+        let cli = parse_with(&["scan", "-i", "/card"], config).unwrap();
+        assert!(matches!(cli.command, Command::Scan(_)));
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_options() {
+        assert!(parse_with(&["import", "-i", "/card"], "outptu = \"/mnt\"").is_err());
+        assert!(parse_with(&["import", "-i", "/card"], "verify = \"yes\"").is_err());
+    }
+}
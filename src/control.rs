@@ -0,0 +1,117 @@
+//! Pause, skip and abort a running transfer from another thread, such as a
+//! terminal UI's key handler.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// How often a paused worker looks whether it may go on.
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+
+/// Requests a transfer's workers pick up between buffers.
+#[derive(Debug, Default)]
+pub struct Control {
+    paused: AtomicBool,
+    aborted: AtomicBool,
+    /// Workers asked to give up their current file
+    skips: Mutex<HashSet<usize>>,
+}
+
+/// Why a worker stopped copying a file before its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupted {
+    /// The file was skipped; the worker goes on with the next one
+    Skipped,
+    /// The whole transfer was aborted
+    Aborted,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interrupted::Skipped => f.write_str("skipped by the user"),
+            Interrupted::Aborted => f.write_str("aborted by the user"),
+        }
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+impl Control {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Pause the workers if they run, let them go on if they are paused.
+    pub fn toggle_pause(&self) {
+        self.paused.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    /// Stop every worker at its next buffer. Files being copied are left
+    /// partial, for the next run to resume.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+
+    /// Make `worker` give up the file it is copying.
+    pub fn skip(&self, worker: usize) {
+        self.skips.lock().unwrap().insert(worker);
+    }
+
+    /// Forget a skip asked of `worker` too late for its last file.
+    pub(crate) fn clear_skip(&self, worker: usize) {
+        self.skips.lock().unwrap().remove(&worker);
+    }
+
+    /// Wait while paused. Returns false once the transfer is aborted.
+    pub(crate) fn wait(&self) -> bool {
+        while self.is_paused() && !self.is_aborted() {
+            thread::sleep(PAUSE_POLL);
+        }
+        !self.is_aborted()
+    }
+
+    /// Wait while paused, then report whether `worker` should stop its file.
+    pub(crate) fn check(&self, worker: usize) -> Result<(), Interrupted> {
+        if !self.wait() {
+            return Err(Interrupted::Aborted);
+        }
+        if self.skips.lock().unwrap().remove(&worker) {
+            return Err(Interrupted::Skipped);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_and_abort() {
+        let control = Control::new();
+        assert_eq!(control.check(0), Ok(()));
+        control.skip(1);
+        assert_eq!(control.check(0), Ok(()));
+        assert_eq!(control.check(1), Err(Interrupted::Skipped));
+        // A skip applies to one file only
+        assert_eq!(control.check(1), Ok(()));
+
+        // An abort releases paused workers
+        control.toggle_pause();
+        assert!(control.is_paused());
+        control.abort();
+        assert_eq!(control.check(0), Err(Interrupted::Aborted));
+    }
+}
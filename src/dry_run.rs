@@ -0,0 +1,166 @@
+use crate::output::say;
+use image_processor::transfer::OnConflict;
+use image_processor::{volumes, TransferEvent};
+use indicatif::DecimalBytes;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What a dry run would do in a destination: the session folders it would
+/// fill, the space that takes, and the files it would leave out.
+pub struct DryRunSummary {
+    /// The output first, then the backup if any
+    roots: Vec<PathBuf>,
+    /// Files and bytes per folder, including the folders above nested ones
+    folders: Mutex<BTreeMap<PathBuf, Totals>>,
+    skipped: Mutex<Vec<(PathBuf, String)>>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    files: usize,
+    bytes: u64,
+}
+
+impl DryRunSummary {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            folders: Mutex::new(BTreeMap::new()),
+            skipped: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn handle(&self, event: &TransferEvent<'_>) {
+        let (source, reason) = match *event {
+            TransferEvent::WouldCopy { source, dest } => {
+                let bytes = fs::metadata(source).map(|m| m.len()).unwrap_or(0);
+                let mut folders = self.folders.lock().unwrap();
+                for folder in dest.ancestors().skip(1) {
+                    if !self.roots.iter().any(|root| folder.starts_with(root)) {
+                        break;
+                    }
+                    let totals = folders.entry(folder.to_path_buf()).or_default();
+                    totals.files += 1;
+                    totals.bytes += bytes;
+                }
+                return;
+            }
+            TransferEvent::AlreadyCopied { source } => (source, "already copied".to_string()),
+            TransferEvent::Duplicate { source, existing } => (
+                source,
+                format!("already in the library as {}", existing.display()),
+            ),
+            TransferEvent::Conflict {
+                source,
+                existing,
+                resolution: OnConflict::Skip | OnConflict::Ask,
+                ..
+            } => (source, format!("{} already exists", existing.display())),
+            _ => return,
+        };
+        self.skipped
+            .lock()
+            .unwrap()
+            .push((source.to_path_buf(), reason));
+    }
+
+    /// Print the folder tree of each root with its disk usage, then the
+    /// skipped files.
+    pub fn print(&self) {
+        let folders = self.folders.lock().unwrap();
+        for root in &self.roots {
+            let Some(total) = folders.get(root) else {
+                say!("\n[dry-run] Nothing to copy into {}", root.display());
+                continue;
+            };
+            say!(
+                "\n[dry-run] Would copy {} files ({}) into {}:",
+                total.files,
+                DecimalBytes(total.bytes),
+                root.display()
+            );
+            for (folder, totals) in folders.range(root.clone()..).skip(1) {
+                let Ok(relative) = folder.strip_prefix(root) else {
+                    break;
+                };
+                let depth = relative.components().count();
+                let name = relative.file_name().unwrap_or_default().to_string_lossy();
+                say!(
+                    "{:indent$}{}/  {} files, {}",
+                    "",
+                    name,
+                    totals.files,
+                    DecimalBytes(totals.bytes),
+                    indent = depth * 2
+                );
+            }
+            say!("{}", space_line(root, total.bytes));
+        }
+
+        let mut skipped = self.skipped.lock().unwrap();
+        if !skipped.is_empty() {
+            skipped.sort();
+            say!("\n[dry-run] Would skip {} files:", skipped.len());
+            for (source, reason) in skipped.iter() {
+                say!("  {} ({})", source.display(), reason);
+            }
+        }
+    }
+}
+
+/// How `needed` bytes compare with the space left on `root`'s filesystem.
+fn space_line(root: &Path, needed: u64) -> String {
+    match volumes::available_space(root) {
+        Some(available) if available >= needed => format!(
+            "Needs {} of {} available, leaving {}",
+            DecimalBytes(needed),
+            DecimalBytes(available),
+            DecimalBytes(available - needed)
+        ),
+        Some(available) => format!(
+            "Needs {} but only {} is available: {} short",
+            DecimalBytes(needed),
+            DecimalBytes(available),
+            DecimalBytes(needed - available)
+        ),
+        None => format!("Needs {} (free space unknown)", DecimalBytes(needed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folder_totals_include_parents() {
+        let summary = DryRunSummary::new(vec![PathBuf::from("/out")]);
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("IMG_0001.CR2");
+        fs::write(&source, b"raw").unwrap();
+        for dest in [
+            "/out/2024/2024-01-15/IMG_0001.cr2",
+            "/out/2024/2024-01-16/IMG_0001.cr2",
+        ] {
+            summary.handle(&TransferEvent::WouldCopy {
+                source: &source,
+                dest: Path::new(dest),
+            });
+        }
+        summary.handle(&TransferEvent::AlreadyCopied { source: &source });
+
+        let folders = summary.folders.lock().unwrap();
+        let files = |folder: &str| folders[Path::new(folder)].files;
+        assert_eq!(
+            (
+                files("/out"),
+                files("/out/2024"),
+                files("/out/2024/2024-01-15")
+            ),
+            (2, 2, 1)
+        );
+        assert_eq!(folders[Path::new("/out")].bytes, 6);
+        assert_eq!(summary.skipped.lock().unwrap().len(), 1);
+    }
+}
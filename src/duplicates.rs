@@ -0,0 +1,266 @@
+use crate::checksum::{self, HashAlgo};
+use crate::metadata::{self, Zone};
+use crate::session::DatedFile;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Files already in a destination library, indexed by lowercased file name
+/// and size. Capture dates are only read for files matching on both.
+#[derive(Debug, Default)]
+pub struct LibraryIndex {
+    files: HashMap<(String, u64), Vec<PathBuf>>,
+    zone: Option<Zone>,
+}
+
+impl LibraryIndex {
+    /// Index every file under `root`, skipping dotfiles such as the state and
+    /// journal files. Capture dates are read in `zone`, as the card's were.
+    pub fn build(root: &Path, zone: Option<Zone>) -> Self {
+        let mut index = Self {
+            zone,
+            ..Self::default()
+        };
+        for entry in WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            index
+                .files
+                .entry((name, meta.len()))
+                .or_default()
+                .push(entry.into_path());
+        }
+        index
+    }
+
+    /// An existing file with the same name, size and capture date as `file`.
+    pub fn find(&self, file: &DatedFile) -> Option<&Path> {
+        let name = file.path.file_name()?.to_string_lossy().to_lowercase();
+        let size = fs::metadata(&file.path).ok()?.len();
+        self.files
+            .get(&(name, size))?
+            .iter()
+            .find(|existing| capture_date(existing, self.zone) == Some(file.datetime))
+            .map(|existing| existing.as_path())
+    }
+}
+
+/// Keep one of each set of byte-identical files, such as a photo a dual-slot
+/// camera wrote to both cards, recording the others in its
+/// [`DatedFile::identical`]. Only files sharing a size are hashed. The first
+/// file of a set is kept, with its own sidecars. Files are compared by their
+/// `algo` hash.
+pub fn merge_identical(files: Vec<DatedFile>, algo: HashAlgo) -> Result<Vec<DatedFile>> {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        by_size
+            .entry(fs::metadata(&file.path)?.len())
+            .or_default()
+            .push(i);
+    }
+    // Index of the file each duplicate is a copy of
+    let mut copy_of: HashMap<usize, usize> = HashMap::new();
+    for indices in by_size.values().filter(|indices| indices.len() > 1) {
+        let mut by_hash: HashMap<String, usize> = HashMap::new();
+        for &i in indices {
+            let hash = checksum::hash_file(&files[i].path, algo)?;
+            match by_hash.get(&hash) {
+                Some(&kept) => {
+                    copy_of.insert(i, kept);
+                }
+                None => {
+                    by_hash.insert(hash, i);
+                }
+            }
+        }
+    }
+
+    let mut identical: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+    for (&i, &kept) in &copy_of {
+        log::info!(
+            "{} is identical to {}, copying it once",
+            files[i].path.display(),
+            files[kept].path.display()
+        );
+        identical
+            .entry(kept)
+            .or_default()
+            .push(files[i].path.clone());
+    }
+    Ok(files
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !copy_of.contains_key(i))
+        .map(|(i, mut file)| {
+            if let Some(mut copies) = identical.remove(&i) {
+                copies.sort();
+                file.identical.extend(copies);
+            }
+            file
+        })
+        .collect())
+}
+
+/// A file of one of two mirrored cards with no byte-identical copy on the
+/// other, found by [`unmatched_files`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnmatchedFile {
+    pub path: PathBuf,
+    /// The file at the same place on the other card, whose content differs:
+    /// one of the two is likely corrupt. `None` when the other card lacks it.
+    pub differs_from: Option<PathBuf>,
+}
+
+/// The files of `files`, merged with [`merge_identical`] from two cards
+/// that should mirror each other, that are on only one of `cards`.
+pub fn unmatched_files(files: &[DatedFile], cards: [&Path; 2]) -> Vec<UnmatchedFile> {
+    let mut unmatched = Vec::new();
+    for file in files {
+        let copies = || std::iter::once(&file.path).chain(&file.identical);
+        let on_card = |card: &Path| copies().any(|path| path.starts_with(card));
+        let (card, other) = match (on_card(cards[0]), on_card(cards[1])) {
+            (true, false) => (cards[0], cards[1]),
+            (false, true) => (cards[1], cards[0]),
+            _ => continue,
+        };
+        let same_place = file
+            .path
+            .strip_prefix(card)
+            .map(|relative| other.join(relative));
+        unmatched.push(UnmatchedFile {
+            path: file.path.clone(),
+            differs_from: same_place.ok().filter(|path| path.is_file()),
+        });
+    }
+    unmatched
+}
+
+fn capture_date(path: &Path, zone: Option<Zone>) -> Option<NaiveDateTime> {
+    metadata::extract_datetime_in(path, zone)
+        .ok()
+        .map(|(datetime, _)| datetime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::DateSource;
+
+    #[test]
+    fn test_find_matches_name_size_and_date() {
+        let card = tempfile::tempdir().unwrap();
+        let library = tempfile::tempdir().unwrap();
+        let existing = library.path().join("2024-01-15").join("img_0001.cr2");
+        fs::create_dir(existing.parent().unwrap()).unwrap();
+        fs::write(&existing, b"raw data").unwrap();
+        let datetime = capture_date(&existing, None).unwrap();
+
+        let source = card.path().join("IMG_0001.CR2");
+        fs::write(&source, b"raw data").unwrap();
+        let mut file = DatedFile {
+            path: source.clone(),
+            datetime,
+            date_source: DateSource::Exif,
+            sequence_number: Some(1),
+            sidecars: Vec::new(),
+            identical: Vec::new(),
+            camera: None,
+        };
+
+        let index = LibraryIndex::build(library.path(), None);
+        assert_eq!(index.find(&file), Some(existing.as_path()));
+
+        file.datetime += chrono::Duration::seconds(1);
+        assert_eq!(index.find(&file), None);
+
+        fs::write(&source, b"other raw data").unwrap();
+        file.datetime = datetime;
+        assert_eq!(index.find(&file), None);
+    }
+
+    #[test]
+    fn test_merge_identical_keeps_first_copy() {
+        let card = tempfile::tempdir().unwrap();
+        let dated = |name: &str, data: &[u8]| {
+            let path = card.path().join(name);
+            fs::write(&path, data).unwrap();
+            DatedFile {
+                path,
+                datetime: NaiveDateTime::default(),
+                date_source: DateSource::Exif,
+                sequence_number: None,
+                sidecars: Vec::new(),
+                identical: Vec::new(),
+                camera: None,
+            }
+        };
+        let files = vec![
+            dated("A_0001.CR2", b"raw one"),
+            dated("A_0002.CR2", b"raw two"),
+            dated("B_0001.CR2", b"raw one"),
+        ];
+
+        let merged = merge_identical(files, HashAlgo::Sha256).unwrap();
+        let kept: Vec<_> = merged.iter().map(|f| f.path.file_name().unwrap()).collect();
+        assert_eq!(kept, ["A_0001.CR2", "A_0002.CR2"]);
+        assert_eq!(merged[0].identical, [card.path().join("B_0001.CR2")]);
+        assert!(merged[1].identical.is_empty());
+    }
+
+    #[test]
+    fn test_unmatched_files_between_mirrored_cards() {
+        let cards = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let dated = |card: usize, name: &str, data: &[u8]| {
+            let path = cards[card].path().join(name);
+            fs::write(&path, data).unwrap();
+            DatedFile {
+                path,
+                datetime: NaiveDateTime::default(),
+                date_source: DateSource::Exif,
+                sequence_number: None,
+                sidecars: Vec::new(),
+                identical: Vec::new(),
+                camera: None,
+            }
+        };
+        let files = vec![
+            dated(0, "IMG_0001.CR2", b"raw one"),
+            dated(0, "IMG_0002.CR2", b"raw two"),
+            dated(0, "IMG_0003.CR2", b"raw three"),
+            dated(1, "IMG_0001.CR2", b"raw one"),
+            dated(1, "IMG_0002.CR2", b"raw tw0"),
+        ];
+
+        let merged = merge_identical(files, HashAlgo::Xxh3).unwrap();
+        let unmatched = unmatched_files(&merged, [cards[0].path(), cards[1].path()]);
+        let slot = |card: usize, name: &str| cards[card].path().join(name);
+        assert_eq!(
+            unmatched,
+            [
+                UnmatchedFile {
+                    path: slot(0, "IMG_0002.CR2"),
+                    differs_from: Some(slot(1, "IMG_0002.CR2")),
+                },
+                UnmatchedFile {
+                    path: slot(0, "IMG_0003.CR2"),
+                    differs_from: None,
+                },
+                UnmatchedFile {
+                    path: slot(1, "IMG_0002.CR2"),
+                    differs_from: Some(slot(0, "IMG_0002.CR2")),
+                },
+            ]
+        );
+    }
+}
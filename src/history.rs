@@ -0,0 +1,38 @@
+//! The import history: files imported from each card into an output
+//! directory, kept in its [`Store`](crate::store::Store) across imports
+//! (unlike the `TransferState`, which is removed once an import completes)
+//! so a card inserted again only brings in files never seen.
+
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Identify a file on its card by its key, size and modification time, so
+/// a different photo reusing a deleted file's name is still imported.
+pub fn file_id(path: &Path, file_key: &str) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "{}|{}|{}",
+        file_key,
+        metadata.len(),
+        mtime.as_secs()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_id_changes_with_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("IMG_0001.CR2");
+        fs::write(&photo, b"raw").unwrap();
+        let id = file_id(&photo, "DCIM/IMG_0001.CR2").unwrap();
+        assert!(id.starts_with("DCIM/IMG_0001.CR2|3|"));
+        // Same name, different content
+        fs::write(&photo, b"other raw").unwrap();
+        assert_ne!(file_id(&photo, "DCIM/IMG_0001.CR2").unwrap(), id);
+    }
+}
@@ -0,0 +1,232 @@
+//! User commands run around an import, such as `--post-hook`.
+
+use anyhow::{Context, Result};
+use std::io::{self, ErrorKind, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+/// Prefix of the environment variables a hook receives.
+const ENV_PREFIX: &str = "IMAGE_PROCESSOR_";
+
+/// Where a hook's standard output goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookOutput {
+    /// The console's stdout
+    Stdout,
+    /// stderr, when stdout is reserved for a machine-readable document
+    Stderr,
+    /// Nowhere, while a full-screen UI owns the terminal
+    Discard,
+}
+
+/// Run `template`, split into words like a shell would, with every `{name}` of `vars`
+/// replaced by its value. Each variable is also passed in the environment as
+/// `IMAGE_PROCESSOR_<NAME>`. The command runs without a shell and shares the
+/// console, its stdout going to `output`; it fails unless the command exits
+/// successfully.
+pub fn run(template: &str, vars: &[(&str, String)], output: HookOutput) -> Result<()> {
+    let (program, mut command) = command(template, vars, output)?;
+    let status = command
+        .status()
+        .with_context(|| format!("Cannot run {}", program))?;
+    check(&program, status)
+}
+
+/// [`run`], writing `input` to the command's stdin. It is written from
+/// another thread, so a command that prints before reading all of it cannot
+/// deadlock on a full pipe.
+pub fn run_with_input(
+    template: &str,
+    vars: &[(&str, String)],
+    output: HookOutput,
+    input: &[u8],
+) -> Result<()> {
+    let (program, mut command) = command(template, vars, output)?;
+    let mut child = command
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Cannot run {}", program))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let (written, status) = thread::scope(|scope| {
+        // Dropping stdin once written closes it, so the command sees the end
+        let writer = scope.spawn(move || stdin.write_all(input));
+        let status = child.wait();
+        (
+            writer.join().expect("the stdin writer does not panic"),
+            status,
+        )
+    });
+    let status = status?;
+    match written {
+        // A command may decide without reading everything
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => {
+            return Err(e).with_context(|| format!("Cannot write to {}", program))
+        }
+        _ => {}
+    }
+    check(&program, status)
+}
+
+/// The command `template` describes, and its program.
+fn command(
+    template: &str,
+    vars: &[(&str, String)],
+    output: HookOutput,
+) -> Result<(String, Command)> {
+    let args: Vec<String> = split_words(template)?
+        .into_iter()
+        .map(|arg| {
+            vars.iter().fold(arg.to_string(), |arg, (name, value)| {
+                arg.replace(&format!("{{{}}}", name), value)
+            })
+        })
+        .collect();
+    let (program, args) = args.split_first().context("The hook command is empty")?;
+
+    let mut command = Command::new(program);
+    command.args(args);
+    match output {
+        HookOutput::Stdout => {}
+        HookOutput::Stderr => {
+            command.stdout(io::stderr());
+        }
+        HookOutput::Discard => {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+    }
+    for (name, value) in vars {
+        command.env(
+            format!("{}{}", ENV_PREFIX, name.to_ascii_uppercase()),
+            value,
+        );
+    }
+    log::info!("Running hook {} {}", program, args.join(" "));
+    Ok((program.clone(), command))
+}
+
+/// Split `command` into words on unquoted whitespace. Single quotes keep
+/// everything up to the next one, double quotes keep everything but `\"`
+/// and `\\` escapes, and a backslash outside quotes escapes any character.
+fn split_words(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("Unclosed quote in `{}`", command),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => word.extend(['\\', c]),
+                            None => anyhow::bail!("Unclosed quote in `{}`", command),
+                        },
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("Unclosed quote in `{}`", command),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn check(program: &str, status: ExitStatus) -> Result<()> {
+    if !status.success() {
+        anyhow::bail!("{} failed ({})", program, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_words() {
+        let words = split_words(r#"jq -e 'all(.x; . == "a b")' "say \"hi\"" a\ b ''"#).unwrap();
+        assert_eq!(
+            words,
+            [
+                "jq",
+                "-e",
+                r#"all(.x; . == "a b")"#,
+                r#"say "hi""#,
+                "a b",
+                ""
+            ]
+        );
+        assert!(split_words("sh -c 'exit 1").is_err());
+        assert!(split_words("sh -c \"exit 1").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_passes_placeholders_and_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("hook.sh");
+        std::fs::write(
+            &script,
+            "[ \"$1\" = /out/2024-01-15 ] && [ \"$IMAGE_PROCESSOR_FILES\" = 3 ]",
+        )
+        .unwrap();
+        let vars = [
+            ("session_dir", "/out/2024-01-15".to_string()),
+            ("files", "3".to_string()),
+        ];
+
+        let template = format!("sh {} {{session_dir}}", script.display());
+        run(&template, &vars, HookOutput::Stdout).unwrap();
+        let error = run(
+            &template,
+            &[("session_dir", "/out/other".to_string())],
+            HookOutput::Stdout,
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("sh failed"), "{}", error);
+
+        // Several paths in one placeholder stay one argument, one per line
+        std::fs::write(&script, "[ \"$1\" = \"$IMAGE_PROCESSOR_OUTPUT_DIRS\" ] && [ $(printf '%s\\n' \"$1\" | wc -l) = 2 ]").unwrap();
+        run(
+            &format!("sh {} {{output_dirs}}", script.display()),
+            &[("output_dirs", "/a\n/b c".to_string())],
+            HookOutput::Stdout,
+        )
+        .unwrap();
+
+        run_with_input(
+            "grep -q 2024-01-15",
+            &[],
+            HookOutput::Stdout,
+            b"{\"folder\":\"2024-01-15\"}",
+        )
+        .unwrap();
+        assert!(run_with_input(
+            "grep -q 2024-01-16",
+            &[],
+            HookOutput::Stdout,
+            b"{\"folder\":\"2024-01-15\"}"
+        )
+        .is_err());
+
+        // A plan larger than a pipe buffer
+        let plan = vec![b'x'; 1 << 20];
+        run_with_input("sh -c 'cat >/dev/null'", &[], HookOutput::Stderr, &plan).unwrap();
+    }
+}
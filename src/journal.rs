@@ -0,0 +1,530 @@
+use crate::transfer;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const JOURNAL_FILENAME: &str = ".image-processor-journal.json";
+
+/// Changes to the journal since it was last written in full, one JSON
+/// object per line.
+const LOG_FILENAME: &str = ".image-processor-journal.log";
+
+/// Record of everything an import created in one output directory, kept after
+/// the transfer completes so the last import can be undone.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportJournal {
+    /// Matches the `TransferState` this import was run with
+    pub transfer_id: String,
+    /// Directories imported from, in the order they were given
+    #[serde(alias = "input_dir", deserialize_with = "one_or_many")]
+    pub inputs: Vec<PathBuf>,
+    /// Directories that did not exist before the import, in creation order
+    pub created_dirs: Vec<PathBuf>,
+    /// Destination files written by the import that did not exist before
+    pub created_files: Vec<PathBuf>,
+    /// What the copies looked like once written, so undo can tell they were
+    /// changed since; empty in journals written before they were recorded
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub written: BTreeMap<PathBuf, Fingerprint>,
+    /// Whether the import deleted its sources (`--move`)
+    #[serde(default)]
+    pub moved_sources: bool,
+    /// Sources `--move` deleted, with the copy left in their place; none in
+    /// journals written before moves were recorded
+    #[serde(default)]
+    pub moved_files: Option<Vec<MovedFile>>,
+    /// Changes not yet appended to the log
+    #[serde(skip)]
+    unsaved: Vec<Change>,
+}
+
+/// One change to a journal, as appended to its log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Change {
+    Dir(PathBuf),
+    File(PathBuf),
+    Written {
+        file: PathBuf,
+        fingerprint: Fingerprint,
+    },
+    Moved(MovedFile),
+}
+
+/// A line of the log, which only applies to the journal of its transfer.
+#[derive(Serialize, Deserialize)]
+struct LogLine {
+    transfer_id: String,
+    change: Change,
+}
+
+/// Size and modified time of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub len: u64,
+    /// Nanoseconds since the Unix epoch
+    pub modified_ns: u64,
+}
+
+impl Fingerprint {
+    /// The fingerprint of `path` itself, not of what a symlink points to.
+    pub fn of(path: &Path) -> Result<Self> {
+        let meta = fs::symlink_metadata(path)?;
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Self {
+            len: meta.len(),
+            modified_ns: modified.as_nanos() as u64,
+        })
+    }
+}
+
+/// A source deleted by `--move` and the copy that replaced it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MovedFile {
+    pub source: PathBuf,
+    pub copy: PathBuf,
+}
+
+impl ImportJournal {
+    pub fn new(transfer_id: &str, inputs: &[&Path]) -> Self {
+        Self {
+            transfer_id: transfer_id.to_string(),
+            inputs: inputs.iter().map(|dir| dir.to_path_buf()).collect(),
+            moved_files: Some(Vec::new()),
+            ..Default::default()
+        }
+    }
+
+    /// The input directories, comma-separated.
+    pub fn describe_inputs(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        inputs.join(", ")
+    }
+
+    pub fn record_dir(&mut self, dir: PathBuf) {
+        self.record(Change::Dir(dir));
+    }
+
+    pub fn record_file(&mut self, file: PathBuf) {
+        self.record(Change::File(file));
+    }
+
+    /// Record how `file`, created by the import, looks now that it is written.
+    pub fn record_written(&mut self, file: &Path) -> Result<()> {
+        self.record(Change::Written {
+            file: file.to_path_buf(),
+            fingerprint: Fingerprint::of(file)?,
+        });
+        Ok(())
+    }
+
+    /// Record that `source` is about to be deleted, `copy` being verified.
+    pub fn record_moved(&mut self, source: PathBuf, copy: PathBuf) {
+        self.record(Change::Moved(MovedFile { source, copy }));
+    }
+
+    /// Apply `change`, keeping it for the log unless it changed nothing.
+    fn record(&mut self, change: Change) {
+        if self.apply(change.clone()) {
+            self.unsaved.push(change);
+        }
+    }
+
+    fn apply(&mut self, change: Change) -> bool {
+        match change {
+            Change::Dir(dir) if !self.created_dirs.contains(&dir) => self.created_dirs.push(dir),
+            Change::File(file) if !self.created_files.contains(&file) => {
+                self.created_files.push(file)
+            }
+            Change::Dir(_) | Change::File(_) => return false,
+            Change::Written { file, fingerprint } => {
+                self.written.insert(file, fingerprint);
+            }
+            Change::Moved(moved) => self.moved_files.get_or_insert_with(Vec::new).push(moved),
+        }
+        true
+    }
+}
+
+/// Journals written before several inputs were supported hold a single path.
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(dir) => vec![dir],
+        OneOrMany::Many(dirs) => dirs,
+    })
+}
+
+/// Move `copy` back to `source`, copying when they are on different filesystems.
+fn restore(copy: &Path, source: &Path) -> Result<()> {
+    if let Some(parent) = source.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(copy, source).is_err() {
+        fs::copy(copy, source)?;
+        fs::remove_file(copy)?;
+    }
+    Ok(())
+}
+
+fn journal_path(output_dir: &Path) -> PathBuf {
+    output_dir.join(JOURNAL_FILENAME)
+}
+
+fn log_path(output_dir: &Path) -> PathBuf {
+    output_dir.join(LOG_FILENAME)
+}
+
+/// The journal of `output_dir` with the changes in its log applied. A line
+/// cut short by a crash, or left by another transfer, is skipped.
+pub fn load_journal(output_dir: &Path) -> Option<ImportJournal> {
+    let data = fs::read_to_string(journal_path(output_dir)).ok()?;
+    let mut journal: ImportJournal = serde_json::from_str(&data).ok()?;
+    let log = fs::read_to_string(log_path(output_dir)).unwrap_or_default();
+    for line in log
+        .lines()
+        .filter_map(|line| serde_json::from_str::<LogLine>(line).ok())
+    {
+        if line.transfer_id == journal.transfer_id {
+            journal.apply(line.change);
+        }
+    }
+    Some(journal)
+}
+
+/// Load the journal of the transfer being resumed, or start a new one.
+pub fn load_or_new(output_dir: &Path, transfer_id: &str, inputs: &[&Path]) -> ImportJournal {
+    match load_journal(output_dir) {
+        Some(journal) if journal.transfer_id == transfer_id => journal,
+        _ => ImportJournal::new(transfer_id, inputs),
+    }
+}
+
+/// Write the journal atomically to the output directory, in full, which
+/// empties its log.
+pub fn save_journal(journal: &mut ImportJournal, output_dir: &Path) -> Result<()> {
+    let target = journal_path(output_dir);
+    let tmp = output_dir.join(format!(
+        ".image-processor-journal.tmp.{}",
+        std::process::id()
+    ));
+    fs::write(&tmp, serde_json::to_string_pretty(journal)?)?;
+    fs::rename(&tmp, &target)?;
+    journal.unsaved.clear();
+    remove_log(output_dir)
+}
+
+/// Append the changes to the journal since it was last saved to its log,
+/// which costs the same however many files the import has written. The
+/// journal itself must have been saved once.
+pub fn append_journal(journal: &mut ImportJournal, output_dir: &Path) -> Result<()> {
+    if journal.unsaved.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for change in journal.unsaved.drain(..) {
+        let line = LogLine {
+            transfer_id: journal.transfer_id.clone(),
+            change,
+        };
+        lines += &(serde_json::to_string(&line)? + "\n");
+    }
+    let path = log_path(output_dir);
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+    log.write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+fn remove_log(output_dir: &Path) -> Result<()> {
+    match fs::remove_file(log_path(output_dir)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The files the last import into `output_dir` wrote that were modified or
+/// replaced since. Files removed since are not listed: undo skips them anyway.
+pub fn changed_files(output_dir: &Path) -> Vec<PathBuf> {
+    let Some(journal) = load_journal(output_dir) else {
+        return Vec::new();
+    };
+    changed(&journal)
+}
+
+fn changed(journal: &ImportJournal) -> Vec<PathBuf> {
+    journal
+        .written
+        .iter()
+        .filter(|(path, written)| Fingerprint::of(path).is_ok_and(|now| now != **written))
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Outcome of undoing an import: what was, or in a dry run would be, done.
+#[derive(Debug, Default)]
+pub struct UndoReport {
+    pub removed_files: Vec<PathBuf>,
+    pub removed_dirs: Vec<PathBuf>,
+    /// Moved files put back where the import took them from
+    pub restored_files: Vec<MovedFile>,
+    /// Directories left in place because they contain files the import did not create
+    pub kept_dirs: Vec<PathBuf>,
+}
+
+/// Delete the files and directories recorded in the output directory's
+/// journal, newest first. Directories are only removed once empty, so
+/// anything that was not created by the import is left untouched. Files a
+/// `--move` import deleted from the card are first moved back there.
+/// Refuses to do anything if a copy was changed since the import, unless
+/// `force`d.
+pub fn undo_last_import(output_dir: &Path, dry_run: bool, force: bool) -> Result<UndoReport> {
+    let journal = load_journal(output_dir)
+        .ok_or_else(|| anyhow::anyhow!("No import journal found in {}", output_dir.display()))?;
+    let changed = changed(&journal);
+    if let (Some(first), false) = (changed.first(), force) {
+        anyhow::bail!(
+            "{} file(s) were changed since the import into {}, {} among them; pass --force to remove them anyway",
+            changed.len(),
+            output_dir.display(),
+            first.display()
+        );
+    }
+    let moved_files: &[MovedFile] = match &journal.moved_files {
+        Some(moved_files) => moved_files,
+        None if !journal.moved_sources => &[],
+        None => anyhow::bail!(
+            "The last import into {} moved files off {} without recording them; undoing it would delete the only copy",
+            output_dir.display(),
+            journal.describe_inputs()
+        ),
+    };
+    let mut report = UndoReport::default();
+
+    for moved in moved_files.iter().rev() {
+        if moved.source.exists() || !moved.copy.exists() {
+            continue;
+        }
+        if !dry_run {
+            restore(&moved.copy, &moved.source).with_context(|| {
+                format!(
+                    "Cannot restore {} to {}",
+                    moved.copy.display(),
+                    moved.source.display()
+                )
+            })?;
+        }
+        report.restored_files.push(moved.clone());
+    }
+
+    for file in journal.created_files.iter().rev() {
+        // A copy that was interrupted left its part file instead
+        let part = transfer::part_path(file);
+        if part.exists() {
+            if !dry_run {
+                fs::remove_file(&part)?;
+            }
+            report.removed_files.push(part);
+        }
+        let restored = dry_run
+            && moved_files
+                .iter()
+                .any(|m| &m.copy == file && !m.source.exists());
+        if !file.exists() || restored {
+            continue;
+        }
+        if !dry_run {
+            fs::remove_file(file)?;
+        }
+        report.removed_files.push(file.clone());
+    }
+
+    for dir in journal.created_dirs.iter().rev() {
+        if !dir.is_dir() {
+            continue;
+        }
+        if dry_run {
+            report.removed_dirs.push(dir.clone());
+            continue;
+        }
+        match fs::remove_dir(dir) {
+            Ok(()) => report.removed_dirs.push(dir.clone()),
+            Err(_) => report.kept_dirs.push(dir.clone()),
+        }
+    }
+
+    if !dry_run {
+        fs::remove_file(journal_path(output_dir))?;
+        remove_log(output_dir)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_reads_single_input() {
+        let old: ImportJournal =
+            serde_json::from_str(r#"{"transfer_id": "id", "input_dir": "/card", "created_dirs": [], "created_files": []}"#)
+                .unwrap();
+        assert_eq!(old.inputs, [PathBuf::from("/card")]);
+
+        let journal = ImportJournal::new("id", &[Path::new("/slot1"), Path::new("/slot2")]);
+        let read: ImportJournal =
+            serde_json::from_str(&serde_json::to_string(&journal).unwrap()).unwrap();
+        assert_eq!(read.inputs, journal.inputs);
+        assert_eq!(read.describe_inputs(), "/slot1, /slot2");
+    }
+
+    #[test]
+    fn test_changes_appended_to_log() {
+        let out = tempfile::tempdir().unwrap();
+        let mut journal = ImportJournal::new("id", &[Path::new("/card")]);
+        journal.record_dir(out.path().join("2024-01-15"));
+        save_journal(&mut journal, out.path()).unwrap();
+
+        journal.record_file(out.path().join("2024-01-15/IMG_0001.cr2"));
+        journal.record_file(out.path().join("2024-01-15/IMG_0001.cr2"));
+        append_journal(&mut journal, out.path()).unwrap();
+        journal.record_file(out.path().join("2024-01-15/IMG_0002.cr2"));
+        append_journal(&mut journal, out.path()).unwrap();
+        let log = fs::read_to_string(log_path(out.path())).unwrap();
+        assert_eq!(log.lines().count(), 2);
+
+        // A line cut short by a crash, and one of another transfer, are skipped
+        let mut log = fs::OpenOptions::new()
+            .append(true)
+            .open(log_path(out.path()))
+            .unwrap();
+        log.write_all(br#"{"transfer_id":"other","change":{"file":"/elsewhere"}}"#)
+            .unwrap();
+        log.write_all(b"\n{\"transfer_id\":\"id\",\"chan").unwrap();
+        let read = load_journal(out.path()).unwrap();
+        assert_eq!(read.created_dirs, journal.created_dirs);
+        assert_eq!(read.created_files, journal.created_files);
+
+        save_journal(&mut journal, out.path()).unwrap();
+        assert!(!log_path(out.path()).exists());
+        assert_eq!(load_journal(out.path()).unwrap().created_files.len(), 2);
+    }
+
+    #[test]
+    fn test_undo_removes_only_created_paths() {
+        let out = tempfile::tempdir().unwrap();
+        let existing_dir = out.path().join("2024-01-14");
+        fs::create_dir(&existing_dir).unwrap();
+        fs::write(existing_dir.join("old.cr2"), b"old").unwrap();
+
+        let new_dir = out.path().join("2024-01-15");
+        fs::create_dir(&new_dir).unwrap();
+        fs::write(new_dir.join("IMG_0001.cr2"), b"new").unwrap();
+        fs::write(existing_dir.join("IMG_0002.cr2"), b"new").unwrap();
+        // The copy of a third file was interrupted
+        fs::write(new_dir.join("IMG_0003.cr2.part"), b"ne").unwrap();
+
+        let mut journal = ImportJournal::new("id", &[Path::new("/card")]);
+        journal.record_dir(new_dir.clone());
+        journal.record_file(new_dir.join("IMG_0001.cr2"));
+        journal.record_file(existing_dir.join("IMG_0002.cr2"));
+        journal.record_file(new_dir.join("IMG_0003.cr2"));
+        save_journal(&mut journal, out.path()).unwrap();
+
+        let report = undo_last_import(out.path(), false, false).unwrap();
+        assert_eq!(report.removed_files.len(), 3);
+        assert_eq!(report.removed_dirs, std::slice::from_ref(&new_dir));
+        assert!(!new_dir.exists());
+        assert!(existing_dir.join("old.cr2").is_file());
+        assert!(!existing_dir.join("IMG_0002.cr2").exists());
+        assert!(load_journal(out.path()).is_none());
+    }
+
+    #[test]
+    fn test_undo_restores_moved_files() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let session_dir = out.path().join("2024-01-15");
+        fs::create_dir(&session_dir).unwrap();
+        // The import was interrupted after moving the first file only
+        let (moved, kept) = (
+            card.path().join("DCIM/IMG_0001.CR2"),
+            card.path().join("DCIM/IMG_0002.CR2"),
+        );
+        fs::create_dir(card.path().join("DCIM")).unwrap();
+        fs::write(&kept, b"raw 2").unwrap();
+        fs::write(session_dir.join("IMG_0001.cr2"), b"raw 1").unwrap();
+        fs::write(session_dir.join("IMG_0002.cr2"), b"raw 2").unwrap();
+
+        let mut journal = ImportJournal::new("id", &[card.path()]);
+        journal.moved_sources = true;
+        journal.record_dir(session_dir.clone());
+        journal.record_file(session_dir.join("IMG_0001.cr2"));
+        journal.record_file(session_dir.join("IMG_0002.cr2"));
+        journal.record_moved(moved.clone(), session_dir.join("IMG_0001.cr2"));
+        save_journal(&mut journal, out.path()).unwrap();
+
+        let report = undo_last_import(out.path(), false, false).unwrap();
+        let counts = (
+            report.restored_files.len(),
+            report.removed_files.len(),
+            report.removed_dirs.len(),
+        );
+        assert_eq!(counts, (1, 1, 1));
+        assert_eq!(fs::read(&moved).unwrap(), b"raw 1");
+        assert_eq!(fs::read(&kept).unwrap(), b"raw 2");
+        assert!(!session_dir.exists());
+
+        // A journal from before moves were recorded cannot be undone safely
+        journal.moved_files = None;
+        save_journal(&mut journal, out.path()).unwrap();
+        assert!(undo_last_import(out.path(), false, false).is_err());
+    }
+
+    #[test]
+    fn test_undo_refuses_changed_files() {
+        let out = tempfile::tempdir().unwrap();
+        let (edited, untouched) = (
+            out.path().join("IMG_0001.jpg"),
+            out.path().join("IMG_0002.jpg"),
+        );
+        fs::write(&edited, b"as copied").unwrap();
+        fs::write(&untouched, b"as copied").unwrap();
+        let mut journal = ImportJournal::new("id", &[Path::new("/card")]);
+        for file in [&edited, &untouched] {
+            journal.record_file(file.clone());
+            journal.record_written(file).unwrap();
+        }
+        save_journal(&mut journal, out.path()).unwrap();
+        assert!(changed_files(out.path()).is_empty());
+
+        fs::write(&edited, b"retouched").unwrap();
+        assert_eq!(changed_files(out.path()), std::slice::from_ref(&edited));
+        assert!(undo_last_import(out.path(), false, false).is_err());
+        assert!(edited.exists() && untouched.exists());
+
+        let report = undo_last_import(out.path(), false, true).unwrap();
+        assert_eq!(report.removed_files.len(), 2);
+    }
+}
@@ -0,0 +1,211 @@
+//! Import photos and videos from a memory card into folders per shooting session.
+//!
+//! [`scan`] finds supported files and reads their capture dates,
+//! [`group_into_sessions`] splits them into sessions, and [`Transfer`] copies
+//! the sessions into a destination directory.
+
+mod bmff;
+pub mod checksum;
+pub mod control;
+pub mod duplicates;
+pub mod history;
+pub mod hook;
+pub mod journal;
+pub mod link;
+pub mod lock;
+pub mod manifest;
+pub mod metadata;
+pub mod metadata_cache;
+pub mod preserve;
+pub mod preview;
+pub mod proxy;
+pub mod ptp;
+pub mod remote;
+pub mod routing;
+pub mod scanner;
+pub mod session;
+pub mod state;
+pub mod store;
+pub mod throttle;
+pub mod transfer;
+pub mod validate;
+pub mod volumes;
+
+pub use session::{group_into_sessions, DatedFile, Session};
+pub use transfer::{Transfer, TransferEvent, TransferOptions};
+
+use anyhow::Result;
+use metadata_cache::MetadataCache;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Progress reported while [`scan_with_progress`] reads capture dates.
+#[derive(Debug)]
+pub enum ScanEvent<'a> {
+    /// `total` files were found and their dates are about to be read
+    Found { total: usize },
+    /// A file's date was read
+    Dated { file: &'a DatedFile },
+    /// A file was left out because its date could not be read, or it is
+    /// damaged
+    Skipped {
+        path: &'a Path,
+        error: &'a anyhow::Error,
+    },
+}
+
+/// Find the files under `input_dir` with one of `extensions` and read their
+/// capture dates. Files whose date cannot be read are left out.
+pub fn scan(input_dir: &Path, extensions: &[String]) -> Result<Vec<DatedFile>> {
+    scan_with_progress(
+        input_dir,
+        extensions,
+        &scanner::Filter::default(),
+        None,
+        None,
+        |_| {},
+    )
+}
+
+/// [`scan`], calling `on_event` as files are found and dated, and leaving
+/// out the files `filter` excludes, or finds damaged. With a `zone`, capture times are
+/// converted to it (see [`metadata::extract_datetime_in`]).
+///
+/// Dates are read on rayon's thread pool, as reading many small headers
+/// off a card is mostly waiting on it. `on_event` is still called on the
+/// calling thread, in the order the files are dated, and the files are
+/// returned in the order they were found. Files `cache` holds unchanged
+/// are not read again, and the others are added to it.
+pub fn scan_with_progress(
+    input_dir: &Path,
+    extensions: &[String],
+    filter: &scanner::Filter,
+    zone: Option<metadata::Zone>,
+    cache: Option<&Mutex<MetadataCache>>,
+    mut on_event: impl FnMut(ScanEvent<'_>),
+) -> Result<Vec<DatedFile>> {
+    let scanned = scanner::scan_files(input_dir, extensions, filter)?;
+    if let Some(cache) = cache {
+        forget_removed(cache, input_dir, &scanned);
+    }
+    let check_structure = filter.check_structure;
+    log::info!("Found {} files in {}", scanned.len(), input_dir.display());
+    on_event(ScanEvent::Found {
+        total: scanned.len(),
+    });
+
+    let mut slots: Vec<Option<DatedFile>> = Vec::new();
+    slots.resize_with(scanned.len(), || None);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            scanned
+                .into_par_iter()
+                .enumerate()
+                .for_each_with(tx, |tx, (i, file)| {
+                    // The receiver only goes away if `on_event` panicked
+                    let _ = tx.send((i, date_file(file, zone, cache, check_structure)));
+                });
+        });
+        for (i, dated) in rx {
+            match dated {
+                Ok(dated) => {
+                    on_event(ScanEvent::Dated { file: &dated });
+                    slots[i] = Some(dated);
+                }
+                Err((path, error)) => on_event(ScanEvent::Skipped {
+                    path: &path,
+                    error: &error,
+                }),
+            }
+        }
+    });
+    Ok(slots.into_iter().flatten().collect())
+}
+
+/// Drop from `cache` the files under `input_dir` that are gone, so it does
+/// not grow with every card ever read into the output. Files the scan found
+/// are there; only the others are looked for, without holding the cache.
+fn forget_removed(
+    cache: &Mutex<MetadataCache>,
+    input_dir: &Path,
+    scanned: &[scanner::ScannedFile],
+) {
+    let found: HashSet<&Path> = scanned.iter().map(|file| file.path.as_path()).collect();
+    let held = cache.lock().unwrap().paths_under(input_dir);
+    let gone: Vec<PathBuf> = held
+        .into_iter()
+        .filter(|path| !found.contains(path.as_path()) && !path.exists())
+        .collect();
+    cache.lock().unwrap().remove(&gone);
+}
+
+/// Read the capture date of a scanned file, from `cache` if it holds it,
+/// or fail with its path. With `check_structure`, a damaged file fails too,
+/// even when cached.
+fn date_file(
+    file: scanner::ScannedFile,
+    zone: Option<metadata::Zone>,
+    cache: Option<&Mutex<MetadataCache>>,
+    check_structure: bool,
+) -> Result<DatedFile, (PathBuf, anyhow::Error)> {
+    if check_structure {
+        if let Err(error) = validate::check(&file.path) {
+            let error = error.context("The file is damaged");
+            log::warn!("{}: {:#}", file.path.display(), error);
+            return Err((file.path, error));
+        }
+    }
+    let fingerprint = match journal::Fingerprint::of(&file.path) {
+        Ok(fingerprint) => fingerprint,
+        Err(error) => {
+            log::warn!("Could not read {}: {:#}", file.path.display(), error);
+            return Err((file.path, error));
+        }
+    };
+    let cached = cache.and_then(|cache| {
+        cache
+            .lock()
+            .unwrap()
+            .cached(&file.path, &fingerprint)
+            .cloned()
+    });
+    let read = match cached {
+        Some(cached) => cached,
+        None => match metadata_cache::read_metadata(&file.path, fingerprint) {
+            Ok(read) => {
+                if let Some(cache) = cache {
+                    cache.lock().unwrap().insert(&file.path, read.clone());
+                }
+                read
+            }
+            Err(error) => {
+                log::warn!(
+                    "Could not read date from {}: {:#}",
+                    file.path.display(),
+                    error
+                );
+                return Err((file.path, error));
+            }
+        },
+    };
+    let datetime = read.recorded.normalize(zone);
+    log::debug!(
+        "{}: {} ({})",
+        file.path.display(),
+        datetime,
+        read.date_source
+    );
+    Ok(DatedFile {
+        path: file.path,
+        datetime,
+        date_source: read.date_source,
+        sequence_number: file.sequence_number,
+        sidecars: file.sidecars,
+        identical: Vec::new(),
+        camera: read.camera,
+    })
+}
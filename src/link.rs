@@ -0,0 +1,182 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Whether two existing paths live on the same filesystem (same device ID).
+#[cfg(unix)]
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(ma), Ok(mb)) => ma.dev() == mb.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn same_filesystem(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+/// Create `dest` as a copy-on-write clone of `src`.
+/// Fails if the filesystem does not support cloning.
+#[cfg(target_os = "linux")]
+pub fn reflink(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = fs::File::open(src)?;
+    let destination = fs::File::create(dest)?;
+    let ret = unsafe { libc::ioctl(destination.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if ret == -1 {
+        let err = io::Error::last_os_error();
+        drop(destination);
+        let _ = fs::remove_file(dest);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn reflink(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_cstring = |p: &Path| {
+        CString::new(p.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let src_c = to_cstring(src)?;
+    let dest_c = to_cstring(dest)?;
+    remove_if_exists(dest)?;
+    let ret = unsafe { libc::clonefile(src_c.as_ptr(), dest_c.as_ptr(), 0) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn reflink(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink is not supported on this platform",
+    ))
+}
+
+/// Copy up to `len` bytes from `source` to `dest` inside the kernel, from
+/// and advancing both files' positions. Returns how many bytes were copied,
+/// 0 at the end of `source`. Fails if the filesystems cannot do it.
+#[cfg(target_os = "linux")]
+pub fn copy_range(source: &fs::File, dest: &fs::File, len: usize) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::copy_file_range(
+            source.as_raw_fd(),
+            std::ptr::null_mut(),
+            dest.as_raw_fd(),
+            std::ptr::null_mut(),
+            len,
+            0,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn copy_range(_source: &fs::File, _dest: &fs::File, _len: usize) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "copy_file_range is not supported on this platform",
+    ))
+}
+
+/// Create `dest` as a hard link to `src`, replacing a leftover file from an
+/// interrupted run.
+pub fn hard_link(src: &Path, dest: &Path) -> io::Result<()> {
+    remove_if_exists(dest)?;
+    fs::hard_link(src, dest)
+}
+
+/// Create `dest` as a symbolic link to the absolute path of `src`, replacing
+/// a leftover file from an interrupted run.
+pub fn symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    let target = fs::canonicalize(src)?;
+    remove_if_exists(dest)?;
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, dest)
+    }
+    #[cfg(windows)]
+    {
+        std::os::windows::fs::symlink_file(target, dest)
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_links_replace_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw").unwrap();
+        assert!(same_filesystem(&src, dir.path()));
+
+        let hard = dir.path().join("hard.cr2");
+        fs::write(&hard, b"left by an interrupted run").unwrap();
+        hard_link(&src, &hard).unwrap();
+        assert_eq!(
+            fs::metadata(&hard).unwrap().ino(),
+            fs::metadata(&src).unwrap().ino()
+        );
+
+        let soft = dir.path().join("soft.cr2");
+        fs::write(&soft, b"left by an interrupted run").unwrap();
+        symlink(&src, &soft).unwrap();
+        assert_eq!(
+            fs::read_link(&soft).unwrap(),
+            fs::canonicalize(&src).unwrap()
+        );
+        assert_eq!(fs::read(&soft).unwrap(), b"raw");
+        assert!(symlink(&dir.path().join("missing.cr2"), &soft).is_err());
+    }
+
+    #[test]
+    fn test_clone_falls_back_cleanly() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw").unwrap();
+
+        // Filesystems without copy-on-write leave nothing behind to copy over
+        let clone = dir.path().join("clone.cr2");
+        match reflink(&src, &clone) {
+            Ok(()) => assert_eq!(fs::read(&clone).unwrap(), b"raw"),
+            Err(_) => assert!(!clone.exists()),
+        }
+
+        let copy = dir.path().join("copy.cr2");
+        let (source, dest) = (
+            fs::File::open(&src).unwrap(),
+            fs::File::create(&copy).unwrap(),
+        );
+        if cfg!(target_os = "linux") {
+            assert_eq!(copy_range(&source, &dest, 16).unwrap(), 3);
+            assert_eq!(copy_range(&source, &dest, 16).unwrap(), 0);
+            assert_eq!(fs::read(&copy).unwrap(), b"raw");
+        } else {
+            assert!(copy_range(&source, &dest, 16).is_err());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Keep two imports from writing to the same output at once: each takes an
+//! advisory lock on `.image-processor-lock` in the output for as long as it
+//! runs. The operating system releases the lock of a process that dies, so
+//! only a filesystem that keeps locks of a gone machine, a network share for
+//! instance, can leave a stale one. Remote outputs have no locks; there the
+//! file only names the import writing to them (see [`RemoteLock`]).
+
+use crate::remote::Destination;
+use anyhow::{Context, Result};
+use std::fs::{self, File, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+pub const LOCK_FILENAME: &str = ".image-processor-lock";
+
+/// The lock on one output, released when dropped.
+#[derive(Debug)]
+pub struct ImportLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ImportLock {
+    /// Lock `output_dir`, failing with the process holding it if another
+    /// import does, or if the filesystem cannot lock files. With `force`, a
+    /// lock held elsewhere is broken instead, and an import goes on without
+    /// a lock where there are none.
+    pub fn acquire(output_dir: &Path, force: bool) -> Result<Option<Self>> {
+        let path = output_dir.join(LOCK_FILENAME);
+        if force && path.exists() {
+            log::warn!("Breaking the lock {}", path.display());
+            // A new file is not locked, whoever holds the old one
+            fs::remove_file(&path).with_context(|| format!("Cannot remove {}", path.display()))?;
+        }
+        loop {
+            let mut file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .with_context(|| format!("Cannot create {}", path.display()))?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    let mut holder = String::new();
+                    let _ = file.read_to_string(&mut holder);
+                    anyhow::bail!(
+                        "Another import ({}) is writing to {}. Wait for it to finish, or pass \
+                         --force-unlock if no import is running there",
+                        describe_holder(&holder),
+                        output_dir.display()
+                    );
+                }
+                Err(TryLockError::Error(e)) if force => {
+                    log::warn!(
+                        "Cannot lock {}, importing without a lock: {}",
+                        path.display(),
+                        e
+                    );
+                    return Ok(None);
+                }
+                Err(TryLockError::Error(e)) => anyhow::bail!(
+                    "Cannot lock {}: {}. Pass --force-unlock to import without a lock",
+                    path.display(),
+                    e
+                ),
+            }
+            // The import that held the lock may have removed the file between
+            // its opening here and its locking, leaving a lock no other import
+            // sees
+            if !is_linked(&file, &path) {
+                continue;
+            }
+            file.set_len(0)?;
+            file.rewind()?;
+            writeln!(file, "{}", std::process::id())?;
+            return Ok(Some(Self { file, path }));
+        }
+    }
+}
+
+/// Whether `path` still names the open `file`.
+#[cfg(unix)]
+fn is_linked(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(named)) => (open.dev(), open.ino()) == (named.dev(), named.ino()),
+        _ => false,
+    }
+}
+
+/// Without inode numbers to compare, the file is taken to be the one named.
+#[cfg(not(unix))]
+fn is_linked(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+fn describe_holder(holder: &str) -> String {
+    match holder.trim() {
+        "" => "another process".to_string(),
+        pid => format!("process {}", pid),
+    }
+}
+
+impl Drop for ImportLock {
+    fn drop(&mut self) {
+        // Removed before it is unlocked, so an import that opened this file
+        // meanwhile finds it gone once it gets the lock and opens a new one.
+        // A file that replaced it after --force-unlock is the new holder's.
+        if is_linked(&self.file, &self.path) {
+            let _ = fs::remove_file(&self.path);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+/// The lock on a remote output: a file naming the import writing to it,
+/// removed when dropped. Looking for the file and writing it are two
+/// requests, so two imports starting at the same moment can both go ahead.
+pub struct RemoteLock<'a> {
+    dest: &'a dyn Destination,
+}
+
+impl<'a> RemoteLock<'a> {
+    /// Lock `dest`, failing with the process holding it if another import
+    /// does. With `force`, a lock held elsewhere is replaced instead.
+    pub fn acquire(dest: &'a dyn Destination, force: bool) -> Result<Self> {
+        let path = Path::new(LOCK_FILENAME);
+        match dest.read(path)? {
+            Some(holder) if !force => anyhow::bail!(
+                "Another import ({}) is writing to {}. Wait for it to finish, or pass \
+                 --force-unlock if no import is running there",
+                describe_holder(&String::from_utf8_lossy(&holder)),
+                dest.describe()
+            ),
+            Some(_) => log::warn!("Breaking the lock {} on {}", LOCK_FILENAME, dest.describe()),
+            None => {}
+        }
+        let pid = format!("{}\n", std::process::id());
+        dest.write(path, &mut pid.as_bytes(), None)
+            .with_context(|| format!("Cannot write {} to {}", LOCK_FILENAME, dest.describe()))?;
+        Ok(Self { dest })
+    }
+}
+
+impl Drop for RemoteLock<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.dest.remove(Path::new(LOCK_FILENAME)) {
+            log::warn!(
+                "Could not remove the lock from {}: {:#}",
+                self.dest.describe(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::LocalDir;
+
+    #[test]
+    fn test_second_lock_refused_until_released() {
+        let out = tempfile::tempdir().unwrap();
+        let lock = ImportLock::acquire(out.path(), false).unwrap();
+        let pid = fs::read_to_string(out.path().join(LOCK_FILENAME)).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+
+        let error = ImportLock::acquire(out.path(), false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains(&format!("process {}", std::process::id())),
+            "{}",
+            error
+        );
+        let forced = ImportLock::acquire(out.path(), true).unwrap();
+        // The broken lock leaves the file of the one that broke it alone
+        drop(lock);
+        assert!(out.path().join(LOCK_FILENAME).exists());
+        drop(forced);
+        assert!(!out.path().join(LOCK_FILENAME).exists());
+
+        let _lock = ImportLock::acquire(out.path(), false).unwrap();
+    }
+
+    #[test]
+    fn test_remote_lock_refused_until_released() {
+        let out = tempfile::tempdir().unwrap();
+        let dest = LocalDir {
+            root: out.path().to_path_buf(),
+        };
+        let lock = RemoteLock::acquire(&dest, false).unwrap();
+        let error = RemoteLock::acquire(&dest, false).err().unwrap().to_string();
+        assert!(
+            error.contains(&format!("process {}", std::process::id())),
+            "{}",
+            error
+        );
+        drop(lock);
+        assert!(!out.path().join(LOCK_FILENAME).exists());
+        let _lock = RemoteLock::acquire(&dest, false).unwrap();
+    }
+}
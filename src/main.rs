@@ -1,130 +1,1443 @@
 mod cli;
-mod metadata;
-mod scanner;
-mod session;
-mod state;
-mod transfer;
+mod config;
+mod dry_run;
+mod logging;
+mod ndjson;
+mod notify;
+mod output;
+mod progress;
+mod report;
+mod review;
+mod tui;
+mod watch;
+mod webhook;
 
-use anyhow::Result;
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::{Context, Result};
+use cli::{
+    Command, ConsoleArgs, DestinationArgs, ImportArgs, InputArgs, MergeCardsArgs, ResumeArgs,
+    SelectionArgs, StatusArgs, TransferArgs, UndoArgs, VerifyArgs, WatchArgs,
+};
+use dry_run::DryRunSummary;
+use image_processor::metadata_cache::MetadataCache;
+use image_processor::routing::Route;
+use image_processor::transfer::{LinkMode, OnConflict};
+use image_processor::{
+    duplicates, hook, journal, link, ptp, remote, routing, scanner, session, state, transfer,
+    validate, volumes, ScanEvent,
+};
+use indicatif::{DecimalBytes, ProgressStyle};
+use output::{say, summary};
+use report::{Report, TransferLog};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
 
-fn main() -> Result<()> {
-    let args = cli::Args::parse();
+/// Exit status of a run that went through but left some files out.
+const EXIT_FILES_FAILED: u8 = 2;
+
+fn main() -> ExitCode {
+    match run_cli() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            if e.is::<report::FilesFailed>() || e.is::<transfer::FilesFailed>() {
+                ExitCode::from(EXIT_FILES_FAILED)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+fn run_cli() -> Result<()> {
+    let cli = config::parse_args()?;
+    if cli.command.json() {
+        output::set_machine_readable();
+    }
+    if cli.command.quiet() {
+        output::set_quiet(true);
+    }
+    output::set_progress_format(cli.command.progress_format());
+    if let Some(path) = &cli.log_file {
+        logging::init(path, cli.command.verbosity())?;
+    }
+    let result = run(&cli.command);
+    if let Err(e) = &result {
+        log::error!("{:#}", e);
+        if output::is_ndjson() {
+            ndjson::emit(serde_json::json!({ "event": "error", "message": format!("{:#}", e) }));
+        }
+    }
+    result
+}
+
+fn run(command: &Command) -> Result<()> {
+    match command {
+        Command::Import(args) => {
+            check_camera_link_mode(&args.source.input, &args.transfer)?;
+            let inputs = resolve_inputs(&args.source, cache_dir(&args.destination))?;
+            let mut report = Report::default();
+            let result = import(
+                &inputs,
+                &args.selection,
+                &args.destination,
+                &args.transfer,
+                &mut report,
+            );
+            if result.is_ok() && !args.transfer.dry_run {
+                remove_staged(&inputs, &report);
+            }
+            finish(&args.console, report, result)
+        }
+        Command::Watch(args) => watch(args),
+        Command::MergeCards(args) => {
+            let mut report = Report::default();
+            let result = merge_cards(args, &mut report);
+            finish(&args.console, report, result)
+        }
+        Command::Tui(args) => {
+            check_camera_link_mode(&args.source.input, &args.transfer)?;
+            let inputs = resolve_inputs(&args.source, cache_dir(&args.destination))?;
+            let mut report = Report::default();
+            let result = import_in_tui(&inputs, args, &mut report);
+            if result.is_ok() && !args.transfer.dry_run {
+                remove_staged(&inputs, &report);
+            }
+            finish(&args.console, report, result)
+        }
+        Command::Resume(args) => resume(args),
+        Command::Scan(args) => {
+            refuse_cameras(&args.source, "scan")?;
+            let inputs = resolve_inputs(&args.source, args.output.as_deref())?;
+            let mut report = Report::default();
+            let result = scan(
+                &inputs,
+                &args.selection,
+                args.output.as_deref(),
+                &mut report,
+            )
+            .map(|_| ());
+            finish(&args.console, report, result)
+        }
+        Command::Verify(args) => verify(args),
+        Command::Status(args) => status(args),
+        Command::Undo(args) => undo(args),
+    }
+}
+
+/// List the files that failed, notify, post to the webhook and print the
+/// JSON report when asked for, then pass `result` on, failing it if files
+/// were left out. The run is over by then, so a notification or webhook that
+/// fails is only a warning.
+fn finish(console: &ConsoleArgs, mut report: Report, result: Result<()>) -> Result<()> {
+    report.finish(&result);
+    let failures = report.failures();
+    if !failures.is_empty() && !output::is_ndjson() {
+        eprintln!("{} file(s) failed:", failures.len());
+        for failure in &failures {
+            eprintln!(
+                "  {:<8} {}: {}",
+                failure.stage,
+                failure.path.display(),
+                failure.error
+            );
+        }
+    }
+    if let Err(e) = report.write_errors(&failures) {
+        output::warn(format_args!(
+            "cannot write {}: {:#}",
+            report::ERRORS_FILENAME,
+            e
+        ));
+    }
+    if console.notify {
+        if let Err(e) = notify::notify(&report, &result) {
+            output::warn(format_args!("cannot show a desktop notification: {:#}", e));
+        }
+    }
+    if let Some(url) = &console.webhook_url {
+        if let Err(e) = webhook::post(url, &report) {
+            output::warn(format_args!("cannot post the summary to {}: {:#}", url, e));
+        }
+    }
+    if console.json {
+        report.print()?;
+    }
+    match result {
+        Ok(()) if !failures.is_empty() => Err(report::FilesFailed(failures.len()).into()),
+        result => result,
+    }
+}
+
+/// The cards to read: every --input, or the mounted card found with --auto.
+/// A camera given as `ptp:` is first downloaded into `staging_root`.
+fn resolve_inputs(source: &InputArgs, staging_root: Option<&Path>) -> Result<Vec<PathBuf>> {
+    if !source.input.is_empty() {
+        return source
+            .input
+            .iter()
+            .map(|input| stage_camera(input, staging_root))
+            .collect();
+    }
+    let cards = volumes::detect_cards();
+    match cards.as_slice() {
+        [] => anyhow::bail!("No mounted card with a DCIM or AVCHD folder found, pass --input"),
+        [card] => {
+            say!("Using card at {}", card.display());
+            Ok(vec![card.clone()])
+        }
+        _ => choose_card(&cards).map(|card| vec![card]),
+    }
+}
+
+/// Download the camera `input` names over PTP/MTP into a staging folder of
+/// `staging_root` and return that folder, or `input` itself if it is not a
+/// camera.
+fn stage_camera(input: &Path, staging_root: Option<&Path>) -> Result<PathBuf> {
+    let Some(camera) = ptp::Gphoto2::from_input(input) else {
+        return Ok(input.to_path_buf());
+    };
+    let root = staging_root
+        .filter(|root| !remote::is_remote(root))
+        .context("Importing from a camera needs a local --output to download its files to")?;
+    let dir = root.join(ptp::STAGING_DIR).join(camera.staging_name());
+    say!("Downloading from the camera into {}", dir.display());
+    let pb = output::progress_bar(0);
+    pb.set_style(
+        ProgressStyle::default_spinner().template("{spinner} Downloaded {pos} files {msg}")?,
+    );
+    ptp::stage(&camera, &dir, |file| {
+        pb.set_message(file.name.clone());
+        pb.inc(1);
+    })?;
+    pb.finish_and_clear();
+    Ok(dir)
+}
+
+/// Only an import downloads a camera: anything else would download all of
+/// its files only to look at them.
+fn refuse_cameras(source: &InputArgs, command: &str) -> Result<()> {
+    if let Some(input) = source
+        .input
+        .iter()
+        .find(|input| ptp::Gphoto2::from_input(input).is_some())
+    {
+        anyhow::bail!(
+            "{} cannot read the camera {}, only import downloads a camera's files; pass the folder it is mounted at",
+            command,
+            input.display()
+        );
+    }
+    Ok(())
+}
+
+/// Files downloaded from a camera are deleted once imported, which would
+/// leave symbolic links to them dangling.
+fn check_camera_link_mode(inputs: &[PathBuf], transfer: &TransferArgs) -> Result<()> {
+    let camera = inputs
+        .iter()
+        .any(|input| ptp::Gphoto2::from_input(input).is_some() || ptp::is_staged(input));
+    if camera && transfer.link_mode() == LinkMode::Symlink {
+        anyhow::bail!("--link-mode symlink cannot be used with a camera, the files it links to are deleted once imported");
+    }
+    Ok(())
+}
+
+/// Delete the files downloaded from a camera once all of them are imported,
+/// so the next import from it downloads its new files only.
+fn remove_staged(inputs: &[PathBuf], report: &Report) {
+    if !report.failures().is_empty() {
+        return;
+    }
+    for input in inputs.iter().filter(|input| ptp::is_staged(input)) {
+        if let Err(e) = std::fs::remove_dir_all(input) {
+            output::warn(format!(
+                "Could not remove the files downloaded to {}: {}",
+                input.display(),
+                e
+            ));
+        }
+        // Left in place while another camera's files are still staged
+        let _ = input.parent().map(std::fs::remove_dir);
+    }
+}
+
+/// Ask which of several detected cards to import. Prompts go to stderr so
+/// they never mix with a `--json` report or NDJSON events on stdout.
+fn choose_card(cards: &[PathBuf]) -> Result<PathBuf> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("Several cards are mounted, pass --input to choose one");
+    }
+    eprintln!("Found {} cards:", cards.len());
+    for (i, card) in cards.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, card.display());
+    }
+    eprint!("Import which card? [1-{}] ", cards.len());
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    answer
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| cards.get(n.wrapping_sub(1)))
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("No card chosen"))
+}
+
+/// Import every card as it is inserted, until interrupted.
+fn watch(args: &WatchArgs) -> Result<()> {
+    let import_args = &args.import;
+    let interval = std::time::Duration::from_secs_f64(args.poll_interval);
+    let fixed_input = match import_args.source.input.as_slice() {
+        [] => None,
+        [input] if ptp::Gphoto2::from_input(input).is_some() => {
+            anyhow::bail!("watch cannot wait for a camera, pass a folder")
+        }
+        [input] => Some(input.clone()),
+        _ => anyhow::bail!("watch takes a single --input"),
+    };
+    if import_args.transfer.reset_state {
+        anyhow::bail!(
+            "watch would discard the state of every card it imports, use import --reset-state"
+        );
+    }
+    match &fixed_input {
+        Some(input) => say!("Waiting for {}...", input.display()),
+        None => say!("Waiting for a card..."),
+    }
+    let find_card = || match &fixed_input {
+        Some(input) => watch::card_present(input).then(|| input.clone()),
+        None => volumes::detect_cards().into_iter().next(),
+    };
+    watch::watch(interval, find_card, |card| {
+        let mut report = Report::default();
+        let result = import(
+            &[card.to_path_buf()],
+            &import_args.selection,
+            &import_args.destination,
+            &import_args.transfer,
+            &mut report,
+        );
+        finish(&import_args.console, report, result)
+    })
+}
+
+/// Scan the cards, read every file's date and group the files of all of them
+/// into sessions. Returns no sessions when there is nothing to import. Dates
+/// are cached in `cache_dir`, if it exists, for the next scan of the cards.
+fn scan(
+    inputs: &[PathBuf],
+    selection: &SelectionArgs,
+    cache_dir: Option<&Path>,
+    report: &mut Report,
+) -> Result<Vec<session::Session>> {
+    report.inputs = inputs.to_vec();
+    report.input = inputs.first().cloned().unwrap_or_default();
+    if selection.folder_template.uses("event") && selection.event.is_none() {
+        anyhow::bail!("--folder-template uses {{event}}, pass --event");
+    }
 
     // Validate paths
-    if !args.input.exists() {
-        anyhow::bail!("Input directory does not exist: {}", args.input.display());
+    for input in inputs {
+        if !input.exists() {
+            anyhow::bail!("Input directory does not exist: {}", input.display());
+        }
+        if !input.is_dir() {
+            anyhow::bail!("Input path is not a directory: {}", input.display());
+        }
     }
-    if !args.input.is_dir() {
-        anyhow::bail!("Input path is not a directory: {}", args.input.display());
+    // A card given twice, or a folder of one given as well, would be read twice
+    let canonical: Vec<PathBuf> = inputs
+        .iter()
+        .map(std::fs::canonicalize)
+        .collect::<io::Result<_>>()?;
+    for (i, a) in canonical.iter().enumerate() {
+        if let Some(j) = canonical
+            .iter()
+            .enumerate()
+            .position(|(j, b)| j != i && a.starts_with(b))
+        {
+            anyhow::bail!(
+                "--input {} overlaps --input {}",
+                inputs[i].display(),
+                inputs[j].display()
+            );
+        }
     }
 
-    // Scan for CR2/MP4 files
-    println!("Scanning {}...", args.input.display());
-    let scanned = scanner::scan_files(&args.input)?;
-    if scanned.is_empty() {
-        println!("No CR2/MP4 files found.");
-        return Ok(());
+    let mut filter = scanner::Filter::exclude(&selection.exclude)?;
+    filter.min_size = selection.min_size;
+    filter.max_size = selection.max_size;
+    filter.check_structure = selection.quarantine;
+    filter.lrf = selection.lrf;
+    filter.dcim = selection.dcim;
+    let cache_dir = cache_dir.filter(|dir| dir.is_dir());
+    let cache = cache_dir.map(|dir| Mutex::new(MetadataCache::load(dir)));
+    let mut found = 0;
+    let mut dated_files = Vec::new();
+    for input in inputs {
+        found += scan_input(
+            input,
+            selection,
+            &filter,
+            cache.as_ref(),
+            report,
+            &mut dated_files,
+        )?;
+    }
+    if let (Some(dir), Some(cache)) = (cache_dir, cache) {
+        if let Err(e) = cache.into_inner().unwrap().save(dir) {
+            output::warn(format_args!(
+                "cannot save the metadata cache in {}: {:#}",
+                dir.display(),
+                e
+            ));
+        }
+    }
+    if found == 0 {
+        say!(
+            "No {} files found.",
+            selection.extensions.join("/").to_ascii_uppercase()
+        );
+        return Ok(Vec::new());
+    }
+    // Each card is scanned in sequence order; their numbers do not mix, so
+    // merged cards go by date
+    match selection.sort {
+        session::Sort::Seq if inputs.len() > 1 => {
+            session::sort_files(&mut dated_files, session::Sort::Time)
+        }
+        sort => session::sort_files(&mut dated_files, sort),
+    }
+
+    if dated_files.is_empty() {
+        say!("No files with readable dates found.");
+        return Ok(Vec::new());
+    }
+
+    if selection.since.is_some() || selection.until.is_some() {
+        let before = dated_files.len();
+        dated_files.retain(|f| {
+            selection.since.is_none_or(|since| f.datetime >= since)
+                && selection.until.is_none_or(|until| f.datetime < until)
+        });
+        say!(
+            "{} of {} files are in the date range",
+            dated_files.len(),
+            before
+        );
+        if dated_files.is_empty() {
+            return Ok(Vec::new());
+        }
+    }
+
+    if !selection.cameras.is_empty() {
+        let before = dated_files.len();
+        dated_files.retain(|f| {
+            f.camera
+                .as_ref()
+                .is_some_and(|camera| selection.cameras.iter().any(|p| camera.matches(p)))
+        });
+        say!(
+            "{} of {} files are from the selected cameras",
+            dated_files.len(),
+            before
+        );
+        if dated_files.is_empty() {
+            return Ok(Vec::new());
+        }
+    }
+
+    if selection.skip_identical {
+        let before = dated_files.len();
+        dated_files = duplicates::merge_identical(dated_files, selection.hash_algo)?;
+        if dated_files.len() < before {
+            say!(
+                "{} files are identical copies of others and will not be copied",
+                before - dated_files.len()
+            );
+        }
+    }
+    if let (true, [first, second]) = (selection.mirrored, inputs) {
+        report.unmatched = duplicates::unmatched_files(&dated_files, [first, second]);
+        for file in &report.unmatched {
+            match &file.differs_from {
+                Some(other) => output::warn(format_args!(
+                    "{} differs from {}, one of them may be corrupt",
+                    file.path.display(),
+                    other.display()
+                )),
+                None => output::warn(format_args!("{} is only on one card", file.path.display())),
+            }
+        }
+        if report.unmatched.is_empty() {
+            say!("Both cards hold the same files");
+        }
+    }
+
+    say!("By type:");
+    for stats in scanner::summarize_extensions(dated_files.iter().flat_map(|f| {
+        // Paired JPEGs count as files of their own
+        let paired = f.sidecars.iter().filter(|p| !scanner::is_sidecar(p));
+        std::iter::once(&f.path).chain(paired).map(|p| p.as_path())
+    })) {
+        say!(
+            "  {}: {} files, {}",
+            stats.extension.to_ascii_uppercase(),
+            stats.files,
+            DecimalBytes(stats.bytes)
+        );
+    }
+
+    // Surface files whose date silently fell back to the filesystem mtime
+    let fallback_count = dated_files
+        .iter()
+        .filter(|f| !f.date_source.is_metadata())
+        .count();
+    let fallback_ratio = fallback_count as f64 / dated_files.len() as f64;
+    if fallback_count > 0 && fallback_ratio > selection.fallback_warn_ratio {
+        let message = format!(
+            "{} of {} files ({:.0}%) have no readable metadata date and were dated by filesystem modified time; \
+             session folders may not reflect when they were shot",
+            fallback_count,
+            dated_files.len(),
+            fallback_ratio * 100.0
+        );
+        if selection.require_metadata_dates {
+            anyhow::bail!("{} (aborting because of --require-metadata-dates)", message);
+        }
+        output::warn(message);
     }
-    println!("Found {} files", scanned.len());
 
-    // Extract metadata (datetime) for each file
-    let pb = ProgressBar::new(scanned.len() as u64);
+    // Group into sessions
+    let gap_hours = selection.gap_hours.hours_for(&dated_files);
+    if selection.gap_hours == session::Gap::Auto {
+        log::info!("Picked a session gap of {:.2} hours", gap_hours);
+        say!("Picked a session gap of {:.1} hours", gap_hours);
+        report.gap_hours = Some(gap_hours);
+    }
+    let mut sessions = session::group_into_sessions(dated_files, gap_hours);
+    if let Some(km) = selection.gps_split_km {
+        sessions = session::split_by_location(sessions, km);
+    }
+    let mut misc = None;
+    if let Some(min) = selection.min_session_files {
+        match selection.stragglers {
+            session::Stragglers::Nearest => sessions = session::merge_small_sessions(sessions, min),
+            session::Stragglers::Misc => misc = session::take_small_sessions(&mut sessions, min),
+        }
+    }
+    session::apply_folder_template(
+        &mut sessions,
+        &selection.folder_template,
+        selection.event.as_deref(),
+    );
+    session::apply_layout(&mut sessions, selection.layout);
+    sessions.extend(misc);
+    if selection.split_by_camera {
+        sessions = session::split_by_camera(sessions);
+    }
+    report.add_sessions(&sessions);
+    if output::is_ndjson() {
+        ndjson::sessions(&sessions);
+    }
+    if tui::is_active() {
+        tui::sessions(&sessions);
+    }
+    say!("Organized into {} session(s):", sessions.len());
+    for session in &sessions {
+        say!("  {} ({} files)", session.folder_name, session.files.len());
+    }
+    Ok(sessions)
+}
+
+/// Scan one card for files with the requested extensions and read their
+/// dates into `dated_files`. Returns how many files were found.
+fn scan_input(
+    input: &Path,
+    selection: &SelectionArgs,
+    filter: &scanner::Filter,
+    cache: Option<&Mutex<MetadataCache>>,
+    report: &mut Report,
+    dated_files: &mut Vec<session::DatedFile>,
+) -> Result<usize> {
+    say!("Scanning {}...", input.display());
+    if output::is_ndjson() {
+        ndjson::scan_started(input);
+    }
+    if tui::is_active() {
+        tui::scan_started(input);
+    }
+    let pb = output::progress_bar(0);
     pb.set_style(
         ProgressStyle::default_bar()
-            .template("Reading metadata {pos}/{len} {wide_bar} {msg}")?
+            .template("Reading metadata {pos}/{len} {wide_bar} [{eta}] {msg}")?
             .progress_chars("=> "),
     );
-    let mut dated_files: Vec<session::DatedFile> = Vec::with_capacity(scanned.len());
-    for file in &scanned {
-        let file_name = file
-            .path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy();
-        pb.set_message(file_name.to_string());
-        let datetime = match metadata::extract_datetime(&file.path) {
-            Ok(dt) => dt,
-            Err(e) => {
-                pb.suspend(|| {
-                    eprintln!(
-                        "Warning: could not read date from {}: {}",
-                        file.path.display(),
-                        e
+    let mut found = 0;
+    let scanned = image_processor::scan_with_progress(
+        input,
+        &selection.extensions,
+        filter,
+        selection.timezone,
+        cache,
+        |event| {
+            if output::is_ndjson() {
+                ndjson::scan_event(&event);
+            }
+            if tui::is_active() {
+                tui::scan_event(&event);
+            }
+            match event {
+                ScanEvent::Found { total } => {
+                    found = total;
+                    if total > 0 {
+                        pb.suspend(|| say!("Found {} files", total));
+                        pb.set_length(total as u64);
+                    }
+                }
+                ScanEvent::Dated { file } => {
+                    if selection.verbose > 0 {
+                        pb.suspend(|| {
+                            say!(
+                                "  {} {} ({})",
+                                file.path.display(),
+                                file.datetime,
+                                file.date_source
+                            );
+                        });
+                    }
+                    pb.set_message(
+                        file.path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string(),
                     );
-                });
-                pb.inc(1);
-                continue;
+                    pb.inc(1);
+                }
+                ScanEvent::Skipped { path, error } => {
+                    report.unreadable.push(report::FileError {
+                        path: path.to_path_buf(),
+                        error: format!("{:#}", error),
+                    });
+                    if !output::is_ndjson() && !tui::is_active() {
+                        pb.suspend(|| {
+                            eprintln!("Warning: could not read {}: {:#}", path.display(), error);
+                        });
+                    }
+                    pb.inc(1);
+                }
             }
+        },
+    )?;
+    pb.finish_and_clear();
+    dated_files.extend(scanned);
+    Ok(found)
+}
+
+/// Import the union of two mirrored cards: identical files are copied once
+/// and files on only one card are reported.
+fn merge_cards(args: &MergeCardsArgs, report: &mut Report) -> Result<()> {
+    if args.input.len() != 2 {
+        anyhow::bail!(
+            "merge-cards takes exactly two --input cards, got {}",
+            args.input.len()
+        );
+    }
+    let selection = SelectionArgs {
+        skip_identical: true,
+        mirrored: true,
+        ..args.selection.clone()
+    };
+    import(
+        &args.input,
+        &selection,
+        &args.destination,
+        &args.transfer,
+        report,
+    )
+}
+
+/// Import in the full-screen UI. It cannot prompt, so options that would
+/// are refused up front.
+fn import_in_tui(inputs: &[PathBuf], args: &ImportArgs, report: &mut Report) -> Result<()> {
+    let transfer = &args.transfer;
+    if transfer.review || transfer.on_conflict == OnConflict::Ask {
+        anyhow::bail!("tui cannot prompt, drop --review and --on-conflict ask or use import");
+    }
+    if transfer.dry_run {
+        anyhow::bail!("tui copies files, use import --dry-run to preview an import");
+    }
+    if transfer.reset_state && !transfer.yes {
+        anyhow::bail!("tui cannot ask before discarding the state, pass --yes with --reset-state");
+    }
+    tui::run(|| import(inputs, &args.selection, &args.destination, transfer, report))
+}
+
+/// Where the metadata of the cards is cached: the output, or the first
+/// route's root without one.
+fn cache_dir(destination: &DestinationArgs) -> Option<&Path> {
+    destination_roots(destination.output.as_deref(), &destination.route)
+        .first()
+        .copied()
+}
+
+/// Split sessions by destination root, failing if any file has none.
+fn route_sessions(
+    sessions: &[session::Session],
+    destination: &DestinationArgs,
+) -> Result<Vec<(PathBuf, Vec<session::Session>)>> {
+    let router = routing::Router::new(destination.route.clone(), destination.output.clone());
+    if let Some(file) = sessions
+        .iter()
+        .flat_map(|s| &s.files)
+        .find(|f| router.root_for(&f.path).is_none())
+    {
+        anyhow::bail!(
+            "No --route matches {} and no --output was given for unmatched files",
+            file.path.display()
+        );
+    }
+    Ok(routing::split_by_root(sessions, &router))
+}
+
+fn import(
+    inputs: &[PathBuf],
+    selection: &SelectionArgs,
+    destination: &DestinationArgs,
+    transfer: &TransferArgs,
+    report: &mut Report,
+) -> Result<()> {
+    report.dry_run = transfer.dry_run;
+    if transfer.move_files && transfer.link_mode() == LinkMode::Symlink {
+        anyhow::bail!("--move cannot be combined with --link-mode symlink, the links would point to deleted files");
+    }
+    check_camera_link_mode(inputs, transfer)?;
+    if transfer.move_files && transfer.salvage {
+        anyhow::bail!("--move cannot be combined with --salvage, a file only partly read would be deleted from the card");
+    }
+    if transfer.eject && !transfer.dry_run && !(transfer.verify || transfer.move_files) {
+        anyhow::bail!(
+            "--eject needs --verify, so the card is only ejected once every copy is checked"
+        );
+    }
+    if transfer.reset_state {
+        if transfer.dry_run {
+            output::warn(
+                "--reset-state is ignored in a dry run, which shows what resuming would skip",
+            );
+        } else {
+            reset_state(inputs, destination, transfer.yes)?;
+        }
+    }
+    let mut sessions = scan(inputs, selection, cache_dir(destination), report)?;
+    let quarantine_damaged =
+        selection.quarantine && !transfer.dry_run && !report.unreadable.is_empty();
+    if sessions.is_empty() {
+        if quarantine_damaged {
+            quarantine(inputs, destination, report, &[]);
+        }
+        return Ok(());
+    }
+    if transfer.review {
+        sessions = review::review(sessions)?;
+        report.add_sessions(&sessions);
+    }
+    let destinations = route_sessions(&sessions, destination)?;
+
+    if let Some(hook) = &transfer.pre_hook {
+        let destinations: Vec<_> = destinations
+            .iter()
+            .map(|(root, sessions)| serde_json::json!({ "output": root, "sessions": report::session_reports(sessions) }))
+            .collect();
+        let plan = serde_json::json!({ "inputs": inputs, "dry_run": transfer.dry_run, "destinations": destinations });
+        hook::run_with_input(hook, &[], hook_output(), plan.to_string().as_bytes())
+            .context("The pre-hook cancelled the import")?;
+    }
+    if transfer.dry_run {
+        say!("\n[dry-run] No files will be copied.");
+    }
+
+    let options = transfer::TransferOptions {
+        dry_run: transfer.dry_run,
+        extension_case: destination.extension_case,
+        split_by_type: destination.split_by_type,
+        chapter_folders: destination.chapter_folders,
+        lrf: selection.lrf,
+        rename: destination.rename_template.clone(),
+        link_mode: transfer.link_mode(),
+        mirror_state_to_input: destinations.len() == 1,
+        max_path: transfer.max_path.unwrap_or(transfer::DEFAULT_MAX_PATH),
+        verify: transfer.verify || transfer.move_files,
+        hash_algo: selection.hash_algo,
+        jobs: transfer.jobs,
+        move_sources: transfer.move_files,
+        skip_duplicates: transfer.skip_duplicates,
+        on_conflict: transfer.on_conflict,
+        on_error: transfer.on_error,
+        time_zone: selection.timezone,
+        fsync: transfer.fsync,
+        preserve: transfer.preserve.clone(),
+        backup_dir: destination.backup_dir.clone(),
+        max_rate: transfer.max_rate,
+        retry: transfer::Retry {
+            retries: transfer.retries,
+            delay: std::time::Duration::from_secs_f64(transfer.retry_delay),
+            salvage: transfer.salvage,
+        },
+        buffers: transfer::Buffers {
+            count: transfer.buffers,
+            size: transfer.buffer_size,
+        },
+        force: transfer.force,
+        force_unlock: transfer.force_unlock,
+        // Merged cards share one history, valid as long as they are given together
+        card_id: Some(
+            inputs
+                .iter()
+                .map(|input| volumes::volume_id(input))
+                .collect::<Vec<_>>()
+                .join("+"),
+        ),
+        skip_imported: transfer.skip_imported,
+        manifest: transfer.manifest,
+        checksum_file: transfer.checksum_file,
+        thumbnails: transfer.thumbnails,
+        previews: transfer.previews,
+        proxy_command: transfer.proxy.then(|| transfer.proxy_command.clone()),
+    };
+    for (output_dir, root_sessions) in &destinations {
+        if destinations.len() > 1 {
+            say!("\nDestination {}:", output_dir.display());
+        }
+        let log = TransferLog::default();
+        let post_hook = transfer.post_hook.as_deref().filter(|_| !transfer.dry_run);
+        let result = import_to(
+            inputs,
+            output_dir,
+            root_sessions,
+            options.clone(),
+            post_hook,
+            &log,
+            selection.verbose,
+        );
+        report.destinations.push(log.into_report(output_dir));
+        result?;
+    }
+    if quarantine_damaged {
+        quarantine(inputs, destination, report, &destinations);
+    }
+    if let (Some(hook), false) = (&transfer.post_import_hook, transfer.dry_run) {
+        let files = sessions.iter().flat_map(|s| &s.files);
+        let vars = [
+            ("input", inputs[0].display().to_string()),
+            (
+                "inputs",
+                inputs
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            ("output_dir", destinations[0].0.display().to_string()),
+            (
+                "output_dirs",
+                destinations
+                    .iter()
+                    .map(|(root, _)| root.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            ("sessions", sessions.len().to_string()),
+            ("files", files.clone().count().to_string()),
+            (
+                "bytes",
+                files.map(|f| f.total_size()).sum::<u64>().to_string(),
+            ),
+        ];
+        hook::run(hook, &vars, hook_output()).context("The post-import hook failed")?;
+    }
+    if transfer.eject && !transfer.dry_run {
+        eject(inputs, &destinations);
+    }
+
+    say!("Done.");
+    for line in report.details() {
+        say!("{}", line);
+    }
+    summary!("{}", report.summary());
+    Ok(())
+}
+
+/// Copy the files the scan left out to the quarantine folder of the first
+/// destination root, so what a failing card still holds of them is kept.
+/// The copies join the journal of the import into that root, among the
+/// `imported` ones, or make one of their own, so `undo` removes them.
+/// Failing to is only a warning: the files are listed as failed anyway.
+fn quarantine(
+    inputs: &[PathBuf],
+    destination: &DestinationArgs,
+    report: &Report,
+    imported: &[(PathBuf, Vec<session::Session>)],
+) {
+    let Some(root) = cache_dir(destination) else {
+        return;
+    };
+    if remote::is_remote(root) {
+        output::warn(format_args!(
+            "damaged files are not quarantined to a remote output, {}",
+            root.display()
+        ));
+        return;
+    }
+    let input_dirs: Vec<&Path> = inputs.iter().map(|input| input.as_path()).collect();
+    let files: Vec<(PathBuf, String)> = report
+        .unreadable
+        .iter()
+        .map(|f| (f.path.clone(), f.error.clone()))
+        .collect();
+    let journal = imported
+        .iter()
+        .any(|(output_dir, _)| output_dir == root)
+        .then(|| journal::load_journal(root))
+        .flatten();
+    let mut journal =
+        journal.unwrap_or_else(|| journal::ImportJournal::new(&state::uuid_v4(), &input_dirs));
+    let copied = validate::quarantine(&files, &input_dirs, root, &mut journal)
+        .and_then(|copied| journal::save_journal(&mut journal, root).map(|()| copied));
+    match copied {
+        Ok(copied) => say!(
+            "Copied {} file(s) that could not be imported to {}",
+            copied.len(),
+            root.join(validate::QUARANTINE_DIR).display()
+        ),
+        Err(e) => output::warn(format_args!("cannot quarantine the damaged files: {:#}", e)),
+    }
+}
+
+/// Discard the state of an interrupted import into each destination root,
+/// once confirmed, so the import copies every file again. A state written by
+/// a newer version is discarded too.
+fn reset_state(inputs: &[PathBuf], destination: &DestinationArgs, yes: bool) -> Result<()> {
+    let input_dirs: Vec<&Path> = inputs.iter().map(|input| input.as_path()).collect();
+    for root in destination_roots(destination.output.as_deref(), &destination.route) {
+        if let Some(dest) = remote::open(root)? {
+            let progress = match state::load_remote(&*dest) {
+                Ok(None) => continue,
+                Ok(Some(state)) => format!(
+                    "{}/{} files copied",
+                    state.completed_files.len(),
+                    state.total_files
+                ),
+                Err(e) => format!("{:#}", e),
+            };
+            confirm_reset(&dest.describe(), &progress, yes)?;
+            state::cleanup_remote(&*dest);
+            continue;
+        }
+        let progress = match state::load_state(&input_dirs, root) {
+            Ok(None) => continue,
+            Ok(Some(state)) => format!(
+                "{}/{} files copied",
+                state.completed_files.len(),
+                state.total_files
+            ),
+            Err(e) => format!("{:#}", e),
         };
-        dated_files.push(session::DatedFile {
-            path: file.path.clone(),
-            datetime,
-            sequence_number: file.sequence_number,
-        });
-        pb.inc(1);
+        confirm_reset(&root.display().to_string(), &progress, yes)?;
+        state::cleanup_state(&input_dirs, root);
+        if !matches!(state::load_state(&input_dirs, root), Ok(None)) {
+            anyhow::bail!(
+                "Could not discard the state of the interrupted import into {}",
+                root.display()
+            );
+        }
+        say!(
+            "Discarded the state of the interrupted import into {}",
+            root.display()
+        );
     }
-    pb.finish_and_clear();
+    Ok(())
+}
 
-    if dated_files.is_empty() {
-        println!("No files with readable dates found.");
+/// Ask on the terminal, like [`choose_card`], whether to discard the state of
+/// the interrupted import into `destination`, failing unless confirmed.
+fn confirm_reset(destination: &str, progress: &str, yes: bool) -> Result<()> {
+    if yes {
         return Ok(());
     }
+    if !io::stdin().is_terminal() {
+        anyhow::bail!(
+            "--reset-state would discard the interrupted import into {} ({}); pass --yes to confirm without a terminal",
+            destination,
+            progress
+        );
+    }
+    eprint!(
+        "Discard the interrupted import into {} ({}) and copy every file again? [y/N] ",
+        destination, progress
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => anyhow::bail!(
+            "Kept the state of the interrupted import into {}, nothing copied",
+            destination
+        ),
+    }
+}
 
-    // Group into sessions
-    let sessions = session::group_into_sessions(dated_files, args.gap_hours);
-    println!("Organized into {} session(s):", sessions.len());
-    for session in &sessions {
-        println!(
-            "  {} ({} files)",
-            session.folder_name,
-            session.files.len()
+/// Unmount the cards of a finished import. A state left in a destination
+/// means files remain to be copied there, so the cards stay mounted. Failing
+/// to eject is only a warning: the import itself went through.
+fn eject(inputs: &[PathBuf], destinations: &[(PathBuf, Vec<session::Session>)]) {
+    let input_dirs: Vec<&Path> = inputs.iter().map(|input| input.as_path()).collect();
+    // A state this version cannot read may hold files still to copy too
+    let remaining = |root: &PathBuf| !matches!(state::load_state(&input_dirs, root), Ok(None));
+    if let Some((root, _)) = destinations.iter().find(|(root, _)| remaining(root)) {
+        output::warn(format_args!(
+            "not ejecting, files remain to be copied to {}",
+            root.display()
+        ));
+        return;
+    }
+    // Two folders of one card are one volume to eject. A camera's files are
+    // staged on the output, which is not to be ejected.
+    let mut volumes_seen = std::collections::HashSet::new();
+    for input in inputs.iter().filter(|input| !ptp::is_staged(input)) {
+        if !volumes_seen.insert(volumes::volume_id(input)) {
+            continue;
+        }
+        match volumes::eject(input) {
+            Ok(()) => say!("Ejected {}, the card can be removed", input.display()),
+            Err(e) => output::warn(format_args!("cannot eject {}: {:#}", input.display(), e)),
+        }
+    }
+}
+
+/// Where hooks print: stderr when stdout is reserved for a machine-readable
+/// document, nowhere while the TUI draws on the terminal.
+fn hook_output() -> hook::HookOutput {
+    if tui::is_active() {
+        hook::HookOutput::Discard
+    } else if output::is_machine_readable() {
+        hook::HookOutput::Stderr
+    } else {
+        hook::HookOutput::Stdout
+    }
+}
+
+/// Run the --post-hook for a session imported into `output_dir`, once its
+/// last file is copied.
+fn run_session_hook(hook: &str, output_dir: &Path, session: &session::Session) -> Result<()> {
+    let vars = [
+        (
+            "session_dir",
+            output_dir.join(&session.folder_name).display().to_string(),
+        ),
+        ("session", session.folder_name.clone()),
+        ("files", session.files.len().to_string()),
+        (
+            "bytes",
+            session
+                .files
+                .iter()
+                .map(|f| f.total_size())
+                .sum::<u64>()
+                .to_string(),
+        ),
+    ];
+    hook::run(hook, &vars, hook_output())
+        .with_context(|| format!("The post hook failed for {}", session.folder_name))
+}
+
+/// Every destination root named by the destination options.
+fn destination_roots<'a>(output: Option<&'a Path>, route: &'a [Route]) -> Vec<&'a Path> {
+    output
+        .into_iter()
+        .chain(route.iter().map(|r| r.root.as_path()))
+        .collect()
+}
+
+/// Re-run an interrupted import. The inputs default to the directories
+/// recorded in the destination's journal.
+fn resume(args: &ResumeArgs) -> Result<()> {
+    if args.transfer.reset_state {
+        anyhow::bail!(
+            "resume continues the interrupted import, use import --reset-state to start it over"
         );
     }
+    let destination = &args.destination;
+    let roots = destination_roots(destination.output.as_deref(), &destination.route);
+    let given: Vec<&Path> = args.input.iter().map(|input| input.as_path()).collect();
+    let mut interrupted: Vec<&Path> = Vec::new();
+    for root in roots.iter().copied() {
+        if state::load_state(&given, root)?.is_some() {
+            interrupted.push(root);
+        }
+    }
+    if interrupted.is_empty() {
+        anyhow::bail!("No interrupted import found in the given destination(s)");
+    }
 
-    if args.dry_run {
-        println!("\n[dry-run] No files will be copied.");
+    let inputs = if args.input.is_empty() {
+        interrupted
+            .iter()
+            .find_map(|root| journal::load_journal(root))
+            .map(|journal| journal.inputs)
+            .filter(|inputs| !inputs.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!("The interrupted import did not record its input, pass --input")
+            })?
+    } else {
+        args.input.clone()
+    };
+    for input in &inputs {
+        say!("Resuming import from {}", input.display());
+    }
+    let mut report = Report::default();
+    let result = import(
+        &inputs,
+        &args.selection,
+        destination,
+        &args.transfer,
+        &mut report,
+    );
+    if result.is_ok() && !args.transfer.dry_run {
+        remove_staged(&inputs, &report);
     }
+    finish(&args.console, report, result)
+}
 
-    // Prepare output directory
-    if !args.dry_run {
-        std::fs::create_dir_all(&args.output)?;
+/// Transfer the sessions routed to one destination root, with its own state.
+fn import_to(
+    inputs: &[PathBuf],
+    output_dir: &Path,
+    sessions: &[session::Session],
+    options: transfer::TransferOptions,
+    post_hook: Option<&str>,
+    log: &TransferLog,
+    verbose: u8,
+) -> Result<()> {
+    for input in inputs {
+        if options.link_mode == LinkMode::Hardlink
+            && !options.dry_run
+            && !link::same_filesystem(input, output_dir)
+        {
+            output::warn(format_args!(
+                "hard links requested but {} and {} are on different filesystems, files will be copied",
+                input.display(),
+                output_dir.display()
+            ));
+        }
     }
 
-    // Load or create transfer state
-    let total_files = sessions.iter().map(|s| s.files.len()).sum::<usize>();
-    let total_bytes: u64 = sessions
-        .iter()
-        .flat_map(|s| &s.files)
-        .filter_map(|f| std::fs::metadata(&f.path).ok())
-        .map(|m| m.len())
-        .sum();
-
-    let mut transfer_state = if !args.dry_run {
-        match state::load_state(&args.input, &args.output) {
-            Some(existing) => {
-                let skipped = existing.completed_files.len();
-                if skipped > 0 {
-                    println!("Resuming transfer: {}/{} files already copied", skipped, total_files);
+    let summary = options.dry_run.then(|| {
+        let roots = std::iter::once(output_dir.to_path_buf()).chain(options.backup_dir.clone());
+        DryRunSummary::new(roots.collect())
+    });
+    let bars = progress::TransferBars::new(verbose);
+    let answer_for_all = Mutex::new(None);
+    // The first failed post hook fails the import once the copy is over
+    let hook_failure = Mutex::new(None);
+    let transfer = transfer::Transfer::with_inputs(inputs, output_dir)
+        .options(options)
+        .on_progress(|event| {
+            if let (transfer::TransferEvent::SessionFinished { folder_name }, Some(hook)) =
+                (&event, post_hook)
+            {
+                let session = sessions
+                    .iter()
+                    .find(|session| session.folder_name == *folder_name);
+                if let Some(Err(e)) =
+                    session.map(|session| run_session_hook(hook, output_dir, session))
+                {
+                    hook_failure.lock().unwrap().get_or_insert(e);
                 }
-                existing
             }
-            None => state::TransferState::new(total_files, total_bytes),
+            log.handle(&event);
+            if let Some(summary) = &summary {
+                summary.handle(&event);
+            }
+            if output::is_ndjson() {
+                ndjson::transfer_event(&event);
+            } else if tui::is_active() {
+                tui::transfer_event(&event);
+            } else {
+                bars.handle(event);
+            }
+        })
+        .on_conflict(|source, existing| ask_conflict(source, existing, &answer_for_all));
+    let transfer = match tui::control() {
+        Some(control) => transfer.control(control),
+        None => transfer,
+    };
+    let result = transfer.run(sessions);
+    if let Some(summary) = &summary {
+        summary.print();
+    }
+    let hook_failure = hook_failure.lock().unwrap().take();
+    match hook_failure {
+        Some(e) => result.and(Err(e)),
+        None => result,
+    }
+}
+
+/// Ask on the terminal what to do with `source` when `existing` is in its
+/// way, on stderr like [`choose_card`]. An uppercase answer applies to every
+/// remaining conflict.
+fn ask_conflict(
+    source: &Path,
+    existing: &Path,
+    answer_for_all: &Mutex<Option<OnConflict>>,
+) -> OnConflict {
+    if let Some(answer) = *answer_for_all.lock().unwrap() {
+        return answer;
+    }
+    if !io::stdin().is_terminal() {
+        output::warn(format_args!(
+            "{} already exists and there is no terminal to ask, skipping",
+            existing.display()
+        ));
+        return OnConflict::Skip;
+    }
+    loop {
+        eprint!(
+            "{} already exists (copying {}). [s]kip, [o]verwrite, [r]ename, or S/O/R for all: ",
+            existing.display(),
+            source.display()
+        );
+        let mut answer = String::new();
+        if io::stderr().flush().is_err() || io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            return OnConflict::Skip;
         }
-    } else {
-        state::TransferState::new(total_files, total_bytes)
+        let choice = match answer.trim().to_ascii_lowercase().as_str() {
+            "s" => OnConflict::Skip,
+            "o" => OnConflict::Overwrite,
+            "r" => OnConflict::Rename,
+            _ => continue,
+        };
+        if answer.trim().chars().all(|c| c.is_ascii_uppercase()) {
+            *answer_for_all.lock().unwrap() = Some(choice);
+        }
+        return choice;
+    }
+}
+
+/// Compare already imported files with their sources without copying.
+fn verify(args: &VerifyArgs) -> Result<()> {
+    refuse_cameras(&args.source, "verify")?;
+    let inputs = resolve_inputs(&args.source, cache_dir(&args.destination))?;
+    let sessions = scan(
+        &inputs,
+        &args.selection,
+        cache_dir(&args.destination),
+        &mut Report::default(),
+    )?;
+    let destinations = route_sessions(&sessions, &args.destination)?;
+    if let Some((root, _)) = destinations
+        .iter()
+        .find(|(root, _)| remote::is_remote(root))
+    {
+        anyhow::bail!(
+            "verify cannot check the remote output {}, import with --verify instead",
+            root.display()
+        );
+    }
+    // The backup holds every file, like the output it cannot be routed with
+    let backup = args
+        .destination
+        .backup_dir
+        .as_deref()
+        .map(|dir| (dir, sessions.as_slice()));
+
+    let mut problems = 0;
+    let roots = destinations
+        .iter()
+        .map(|(dir, sessions)| (dir.as_path(), sessions.as_slice()));
+    for (output_dir, sessions) in roots.chain(backup) {
+        let total_files: usize = sessions
+            .iter()
+            .flat_map(|s| &s.files)
+            .map(|f| 1 + f.sidecars.len())
+            .sum();
+        let pb = output::progress_bar(total_files as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("Verifying {pos}/{len} {wide_bar} [{eta}] {msg}")?
+                .progress_chars("=> "),
+        );
+        let report = transfer::revalidate_sessions(
+            sessions,
+            output_dir,
+            args.destination.extension_case,
+            args.destination.split_by_type,
+            args.destination.chapter_folders,
+            args.destination.rename_template.as_ref(),
+            args.selection.lrf,
+            args.selection.hash_algo,
+            |name| {
+                pb.set_message(name.to_string());
+                pb.inc(1);
+            },
+        )?;
+        pb.finish_and_clear();
+        println!(
+            "{}: {} files verified, {} mismatched, {} missing",
+            output_dir.display(),
+            report.verified,
+            report.mismatched.len(),
+            report.missing.len()
+        );
+        for path in &report.mismatched {
+            println!("  MISMATCH {}", path.display());
+        }
+        for path in &report.missing {
+            println!("  MISSING  {}", path.display());
+        }
+        problems += report.mismatched.len() + report.missing.len();
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{} file(s) failed verification", problems);
+    }
+    println!("Done.");
+    Ok(())
+}
+
+/// Print what the state file of an interrupted import records.
+fn status(args: &StatusArgs) -> Result<()> {
+    let Some(state) = state::load_state(args.input.as_deref().as_slice(), &args.output)? else {
+        println!("No interrupted import in {}", args.output.display());
+        return Ok(());
     };
+    println!("Transfer {}", state.transfer_id);
+    println!(
+        "  {}/{} files copied, {} of {}, {} left",
+        state.completed_files.len(),
+        state.total_files,
+        DecimalBytes(state.done_bytes),
+        DecimalBytes(state.total_bytes),
+        DecimalBytes(state.remaining_bytes())
+    );
+    if !state.in_progress.is_empty() {
+        println!("  {} files partly copied", state.in_progress.len());
+    }
+    if let Some(backed_up) = &state.backup_completed {
+        println!(
+            "  {}/{} files backed up",
+            backed_up.len(),
+            state.total_files
+        );
+    }
+    let sessions = state.session_progress();
+    let incomplete: Vec<_> = sessions
+        .iter()
+        .filter(|(_, (done, total))| done < total)
+        .collect();
+    if !incomplete.is_empty() {
+        println!(
+            "  {}/{} sessions incomplete:",
+            incomplete.len(),
+            sessions.len()
+        );
+        for (folder, (done, total)) in incomplete {
+            println!("    {} ({}/{} files copied)", folder, done, total);
+        }
+    }
+    if let Some(journal) = journal::load_journal(&args.output) {
+        if journal.transfer_id == state.transfer_id {
+            println!("  input: {}", journal.describe_inputs());
+        }
+    }
+    if !args.verbose {
+        return Ok(());
+    }
+    let mut completed: Vec<&String> = state.completed_files.iter().collect();
+    completed.sort();
+    for key in completed {
+        println!("  done {}", key);
+    }
+    let mut partial: Vec<(&String, &u64)> = state.in_progress.iter().collect();
+    partial.sort();
+    for (dest, bytes) in partial {
+        println!("  partial {} ({} written)", dest, DecimalBytes(*bytes));
+    }
+    Ok(())
+}
 
-    // Transfer files
-    transfer::transfer_sessions(&sessions, &args.output, &args.input, &mut transfer_state, args.dry_run)?;
+/// Remove everything the last import created in each destination root.
+fn undo(args: &UndoArgs) -> Result<()> {
+    let roots = destination_roots(args.output.as_deref(), &args.route);
 
-    // Cleanup state files on successful completion
-    if !args.dry_run && transfer_state.all_done() {
-        state::cleanup_state(&args.input, &args.output);
-        println!("State files cleaned up.");
+    // Check every destination before touching any of them
+    if !args.force {
+        let changed: Vec<PathBuf> = roots
+            .iter()
+            .flat_map(|root| journal::changed_files(root))
+            .collect();
+        if let Some(first) = changed.first() {
+            anyhow::bail!(
+                "{} imported file(s) were changed since the import, {} among them; pass --force to remove them anyway",
+                changed.len(),
+                first.display()
+            );
+        }
+    }
+
+    let mut undone = 0;
+    for root in roots {
+        if journal::load_journal(root).is_none() {
+            continue;
+        }
+        let report = journal::undo_last_import(root, args.dry_run, args.force)?;
+        if args.dry_run {
+            for moved in &report.restored_files {
+                println!(
+                    "[dry-run] restore {} to {}",
+                    moved.copy.display(),
+                    moved.source.display()
+                );
+            }
+            for file in &report.removed_files {
+                println!("[dry-run] remove {}", file.display());
+            }
+            for dir in &report.removed_dirs {
+                println!("[dry-run] remove {}/", dir.display());
+            }
+        }
+        println!(
+            "{}: removed {} files and {} folders",
+            root.display(),
+            report.removed_files.len(),
+            report.removed_dirs.len()
+        );
+        if !report.restored_files.is_empty() {
+            println!(
+                "  restored {} moved files to where they were imported from",
+                report.restored_files.len()
+            );
+        }
+        for dir in &report.kept_dirs {
+            println!(
+                "  kept {} (contains files not created by the import)",
+                dir.display()
+            );
+        }
+        if !args.dry_run {
+            state::cleanup_state(&[], root);
+        }
+        undone += 1;
     }
 
+    if undone == 0 {
+        anyhow::bail!("No import journal found in the given destination(s), nothing to undo");
+    }
     println!("Done.");
     Ok(())
 }
@@ -0,0 +1,322 @@
+use crate::checksum::HashAlgo;
+use crate::metadata::{self, Exposure};
+use crate::remote::Destination;
+use crate::scanner;
+use crate::session::DatedFile;
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Provenance of the files imported into one session folder, written as
+/// `manifest.json` in the folder with `--manifest`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Name in the session folder
+    pub file: String,
+    /// Where the file was imported from
+    pub original_path: PathBuf,
+    pub size: u64,
+    /// Content hash, under the name of its algorithm
+    #[serde(flatten)]
+    pub hash: FileHash,
+    /// Capture date the file was sorted by, and where it was read from
+    pub captured: NaiveDateTime,
+    pub date_source: String,
+    /// Make and model of the camera, for photos
+    pub camera: Option<String>,
+    #[serde(flatten)]
+    pub exposure: Exposure,
+    /// Byte-identical copies of the file that were found in the same scan
+    /// and not imported
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identical: Vec<PathBuf>,
+}
+
+/// Hash of a file in a manifest, as `"sha256": "<hex>"` or the like.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileHash {
+    Xxh3(String),
+    Blake3(String),
+    Sha256(String),
+}
+
+impl FileHash {
+    pub fn new(algo: HashAlgo, hex: String) -> Self {
+        match algo {
+            HashAlgo::Xxh3 => FileHash::Xxh3(hex),
+            HashAlgo::Blake3 => FileHash::Blake3(hex),
+            HashAlgo::Sha256 => FileHash::Sha256(hex),
+        }
+    }
+
+    /// The digest as lowercase hex.
+    pub fn hex(&self) -> &str {
+        match self {
+            FileHash::Xxh3(hex) | FileHash::Blake3(hex) | FileHash::Sha256(hex) => hex,
+        }
+    }
+
+    /// The line of `file` in a checksum file, as `xxhsum -H3`, `b3sum` or
+    /// `sha256sum` print it.
+    fn checksum_line(&self, file: &str) -> String {
+        match self {
+            FileHash::Xxh3(hex) => format!("XXH3_{}  {}", hex, file),
+            FileHash::Blake3(hex) | FileHash::Sha256(hex) => format!("{}  {}", hex, file),
+        }
+    }
+}
+
+/// Name of the checksum file of a session folder, as the tool that checks it
+/// names it: `XXH3SUMS`, `B3SUMS` or `SHA256SUMS`.
+pub fn checksums_filename(algo: HashAlgo) -> &'static str {
+    match algo {
+        HashAlgo::Xxh3 => "XXH3SUMS",
+        HashAlgo::Blake3 => "B3SUMS",
+        HashAlgo::Sha256 => "SHA256SUMS",
+    }
+}
+
+/// `existing` checksum file lines with those of `entries` added, replacing
+/// the lines of the same files, sorted by file.
+fn merge_checksums(existing: &str, entries: &[ManifestEntry]) -> String {
+    let mut lines: BTreeMap<String, String> = existing
+        .lines()
+        .filter_map(|line| {
+            line.split_once("  ")
+                .map(|(_, file)| (file.to_string(), line.to_string()))
+        })
+        .collect();
+    for entry in entries {
+        lines.insert(entry.file.clone(), entry.hash.checksum_line(&entry.file));
+    }
+    lines.into_values().map(|line| line + "\n").collect()
+}
+
+impl ManifestEntry {
+    /// Describe `src`, one of `file`'s primary or sidecar files, written as
+    /// `name` with content hash `hash`. Metadata sidecars have no camera or
+    /// exposure; a paired JPEG shares the camera read from its raw.
+    pub fn new(file: &DatedFile, src: &Path, name: &str, hash: FileHash) -> Self {
+        Self {
+            file: name.to_string(),
+            original_path: src.to_path_buf(),
+            size: fs::metadata(src).map(|m| m.len()).unwrap_or(0),
+            hash,
+            captured: file.datetime,
+            date_source: file.date_source.to_string(),
+            camera: if scanner::is_sidecar(src) {
+                None
+            } else {
+                file.camera.as_ref().map(|c| c.name())
+            },
+            exposure: metadata::exposure(src).unwrap_or_default(),
+            identical: if src == file.path {
+                file.identical.clone()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl Manifest {
+    /// Add `entries`, replacing any earlier entries for the same files.
+    fn merge(&mut self, entries: Vec<ManifestEntry>) {
+        self.files
+            .retain(|old| !entries.iter().any(|new| new.file == old.file));
+        self.files.extend(entries);
+        self.files.sort_by(|a, b| a.file.cmp(&b.file));
+    }
+}
+
+fn manifest_path(session_dir: &Path) -> PathBuf {
+    session_dir.join(MANIFEST_FILENAME)
+}
+
+/// The manifest of the local folder `session_dir`, empty if it has none.
+pub fn load_manifest(session_dir: &Path) -> Manifest {
+    fs::read_to_string(manifest_path(session_dir))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Add `entries` to the manifest of the session folder `session_dir` of
+/// `dest`, replacing any earlier entries for the same files, and write it
+/// atomically. Returns the path of the manifest within `dest`.
+pub fn update_manifest(
+    dest: &dyn Destination,
+    session_dir: &Path,
+    entries: Vec<ManifestEntry>,
+) -> Result<PathBuf> {
+    let path = manifest_path(session_dir);
+    let mut manifest: Manifest = match dest.read(&path)? {
+        Some(data) => serde_json::from_slice(&data).unwrap_or_default(),
+        None => Manifest::default(),
+    };
+    manifest.merge(entries);
+    write_atomically(dest, &path, &serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(path)
+}
+
+/// Add `entries` to the `algo` checksum file of the session folder
+/// `session_dir` of `dest`, so its files can be checked with `sha256sum -c`
+/// or the like from inside the folder, and write it atomically. Returns the
+/// path of the checksum file within `dest`.
+pub fn update_checksums(
+    dest: &dyn Destination,
+    session_dir: &Path,
+    algo: HashAlgo,
+    entries: &[ManifestEntry],
+) -> Result<PathBuf> {
+    let path = session_dir.join(checksums_filename(algo));
+    let existing = dest
+        .read(&path)?
+        .map(|data| String::from_utf8_lossy(&data).into_owned())
+        .unwrap_or_default();
+    write_atomically(dest, &path, merge_checksums(&existing, entries).as_bytes())?;
+    Ok(path)
+}
+
+/// Write `data` to `path` in `dest` through a temporary file renamed into
+/// place, unless `dest` only shows files once complete anyway.
+fn write_atomically(dest: &dyn Destination, path: &Path, data: &[u8]) -> Result<()> {
+    let written = if dest.writes_in_place() {
+        dest.write(path, &mut &*data, None).map(drop)
+    } else {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{}.tmp.{}", name, std::process::id()));
+        dest.write(&tmp, &mut &*data, None)
+            .and_then(|_| dest.rename(&tmp, path))
+    };
+    written.with_context(|| format!("Cannot write {} to {}", path.display(), dest.describe()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::DateSource;
+    use crate::remote::LocalDir;
+
+    #[test]
+    fn test_update_replaces_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw").unwrap();
+        let file = DatedFile {
+            path: src.clone(),
+            datetime: NaiveDateTime::parse_from_str("2024-01-15 10:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            date_source: DateSource::Exif,
+            sequence_number: None,
+            sidecars: Vec::new(),
+            identical: Vec::new(),
+            camera: None,
+        };
+        let session_dir = dir.path().join("2024-01-15");
+        fs::create_dir(&session_dir).unwrap();
+
+        let entry = |name: &str, hash: &str| {
+            ManifestEntry::new(&file, &src, name, FileHash::Sha256(hash.to_string()))
+        };
+        let dest = LocalDir {
+            root: dir.path().to_path_buf(),
+        };
+        let session = Path::new("2024-01-15");
+        update_manifest(
+            &dest,
+            session,
+            vec![entry("IMG_0002.cr2", "b"), entry("IMG_0001.cr2", "a")],
+        )
+        .unwrap();
+        let path = update_manifest(&dest, session, vec![entry("IMG_0001.cr2", "c")]).unwrap();
+        assert_eq!(path, session.join(MANIFEST_FILENAME));
+
+        let manifest = load_manifest(&session_dir);
+        let files: Vec<(&str, &str)> = manifest
+            .files
+            .iter()
+            .map(|e| (e.file.as_str(), e.hash.hex()))
+            .collect();
+        assert_eq!(files, [("IMG_0001.cr2", "c"), ("IMG_0002.cr2", "b")]);
+        assert_eq!(manifest.files[0].size, 3);
+        assert_eq!(manifest.files[0].camera, None);
+
+        // The key names the algorithm, so manifests written before it could
+        // be chosen read as SHA-256
+        let written = fs::read_to_string(session_dir.join(MANIFEST_FILENAME)).unwrap();
+        assert!(written.contains("\"sha256\": \"c\""), "{}", written);
+        let entry = ManifestEntry::new(
+            &file,
+            &src,
+            "IMG_0003.cr2",
+            FileHash::new(HashAlgo::Xxh3, "d".to_string()),
+        );
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.contains("\"xxh3\":\"d\""), "{}", json);
+        let read: ManifestEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.hash, FileHash::Xxh3("d".to_string()));
+    }
+
+    #[test]
+    fn test_checksum_file_in_sum_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw").unwrap();
+        let file = DatedFile {
+            path: src.clone(),
+            datetime: NaiveDateTime::parse_from_str("2024-01-15 10:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            date_source: DateSource::Exif,
+            sequence_number: None,
+            sidecars: Vec::new(),
+            identical: Vec::new(),
+            camera: None,
+        };
+        let entry = |name: &str, algo, hash: &str| {
+            ManifestEntry::new(&file, &src, name, FileHash::new(algo, hash.to_string()))
+        };
+
+        let sha256 = |name, hash| entry(name, HashAlgo::Sha256, hash);
+        let dest = LocalDir {
+            root: dir.path().to_path_buf(),
+        };
+        let root = Path::new("");
+        let path = update_checksums(
+            &dest,
+            root,
+            HashAlgo::Sha256,
+            &[sha256("RAW/b.cr2", "bb"), sha256("a.jpg", "aa")],
+        )
+        .unwrap();
+        update_checksums(&dest, root, HashAlgo::Sha256, &[sha256("a.jpg", "cc")]).unwrap();
+        assert_eq!(path, Path::new("SHA256SUMS"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join(&path)).unwrap(),
+            "bb  RAW/b.cr2\ncc  a.jpg\n"
+        );
+
+        let path = update_checksums(
+            &dest,
+            root,
+            HashAlgo::Xxh3,
+            &[entry("a.jpg", HashAlgo::Xxh3, "dd")],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join(&path)).unwrap(),
+            "XXH3_dd  a.jpg\n"
+        );
+    }
+}
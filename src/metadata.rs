@@ -1,34 +1,494 @@
+use crate::bmff;
 use anyhow::Result;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
 
-/// Extract the creation datetime from a file.
-/// Tries EXIF for CR2, mvhd for MP4, falls back to filesystem modified time.
-pub fn extract_datetime(path: &Path) -> Result<NaiveDateTime> {
+/// UUID of the Canon box inside a CR3's `moov` that holds the CMT1-4 TIFF blocks.
+const CANON_CR3_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+/// Where a file's datetime was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateSource {
+    Exif,
+    Mp4Container,
+    /// A date and time in the file's name, as phones and drones write them
+    Filename,
+    /// The recording date an AVCHD camcorder writes into its `.MTS` streams
+    Avchd,
+    /// The origination date of a Broadcast WAV's `bext` chunk, as audio
+    /// recorders write it
+    Bwf,
+    FilesystemMtime,
+}
+
+impl DateSource {
+    /// Whether the date came from the file itself rather than the filesystem.
+    pub fn is_metadata(self) -> bool {
+        self != DateSource::FilesystemMtime
+    }
+}
+
+impl std::fmt::Display for DateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DateSource::Exif => "exif",
+            DateSource::Mp4Container => "mp4",
+            DateSource::Filename => "filename",
+            DateSource::Avchd => "avchd",
+            DateSource::Bwf => "bwf",
+            DateSource::FilesystemMtime => "mtime",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Time zone that capture times are normalized to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    /// A fixed UTC offset such as `+02:00`
+    Fixed(FixedOffset),
+    /// An IANA zone such as `Europe/Paris`, following its daylight saving rules
+    Named(chrono_tz::Tz),
+}
+
+impl Zone {
+    /// Parse `UTC`, an IANA name, or an offset such as `+02:00`, `-0530` or `+9`.
+    pub fn parse(s: &str) -> Result<Zone, String> {
+        if let Some(offset) = parse_utc_offset(s) {
+            return Ok(Zone::Fixed(offset));
+        }
+        s.parse::<chrono_tz::Tz>().map(Zone::Named).map_err(|_| {
+            format!(
+                "'{}' is not a UTC offset (+02:00) or time zone name (Europe/Paris)",
+                s
+            )
+        })
+    }
+
+    /// Wall-clock time in this zone at the UTC instant `utc`.
+    fn local_time(self, utc: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Zone::Fixed(offset) => offset.from_utc_datetime(&utc).naive_local(),
+            Zone::Named(tz) => tz.from_utc_datetime(&utc).naive_local(),
+        }
+    }
+}
+
+/// Parse `+HH:MM`, `+HHMM` or `+HH` (also with `-`).
+fn parse_utc_offset(s: &str) -> Option<FixedOffset> {
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() > 2 => rest.split_at(rest.len() - 2),
+        None => (rest, "0"),
+    };
+    if hours.is_empty()
+        || !hours
+            .bytes()
+            .chain(minutes.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// A capture time as the file records it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Recorded {
+    /// Camera wall-clock time, zone unknown
+    Local(NaiveDateTime),
+    /// Wall-clock time with the UTC offset it was recorded at
+    Offset(DateTime<FixedOffset>),
+    /// UTC, as in an MP4 `mvhd`
+    Utc(NaiveDateTime),
+}
+
+impl Recorded {
+    /// The time in `zone`. Without a zone, each file keeps the wall-clock time
+    /// it recorded (UTC for `mvhd`). Times without an offset are taken to be
+    /// in `zone` already.
+    pub fn normalize(self, zone: Option<Zone>) -> NaiveDateTime {
+        match (self, zone) {
+            (Recorded::Local(dt), _) => dt,
+            (Recorded::Offset(dt), None) => dt.naive_local(),
+            (Recorded::Offset(dt), Some(zone)) => zone.local_time(dt.naive_utc()),
+            (Recorded::Utc(dt), None) => dt,
+            (Recorded::Utc(dt), Some(zone)) => zone.local_time(dt),
+        }
+    }
+}
+
+/// Reads the capture time, and optionally the camera, exposure and location,
+/// of files of the extensions it is registered for with
+/// [`register_extractor`].
+pub trait MetadataExtractor: Send + Sync {
+    /// The capture time `path` records and where it was read from. An error
+    /// makes the file fall back to its filesystem modified time.
+    fn capture_time(&self, path: &Path) -> Result<(Recorded, DateSource)>;
+
+    /// The camera that recorded `path`, if the format names one.
+    fn camera(&self, _path: &Path) -> Option<Camera> {
+        None
+    }
+
+    /// [`capture_time`](Self::capture_time) and [`camera`](Self::camera)
+    /// together, for formats that can read both from one parse of the file.
+    fn capture_time_and_camera(
+        &self,
+        path: &Path,
+    ) -> (Result<(Recorded, DateSource)>, Option<Camera>) {
+        (self.capture_time(path), self.camera(path))
+    }
+
+    /// The exposure settings of `path`, if the format records them.
+    fn exposure(&self, _path: &Path) -> Option<Exposure> {
+        None
+    }
+
+    /// Where `path` was taken, if the format records it.
+    fn location(&self, _path: &Path) -> Option<Location> {
+        None
+    }
+}
+
+/// EXIF read by kamadak-exif: TIFF-based raws, HEIF, and any other container
+/// it understands (JPEG, TIFF, DNG, PNG, WebP).
+struct ExifExtractor;
+
+impl MetadataExtractor for ExifExtractor {
+    fn capture_time(&self, path: &Path) -> Result<(Recorded, DateSource)> {
+        extract_exif_datetime(path).map(|dt| (dt, DateSource::Exif))
+    }
+
+    fn camera(&self, path: &Path) -> Option<Camera> {
+        exif_camera(&read_exif(path)?)
+    }
+
+    fn capture_time_and_camera(
+        &self,
+        path: &Path,
+    ) -> (Result<(Recorded, DateSource)>, Option<Camera>) {
+        match read_exif_container(path) {
+            Ok(exif) => (
+                exif_datetime(&exif).map(|dt| (dt, DateSource::Exif)),
+                exif_camera(&exif),
+            ),
+            Err(e) => (Err(e), None),
+        }
+    }
+
+    fn exposure(&self, path: &Path) -> Option<Exposure> {
+        let exif = read_exif(path)?;
+        let [iso, time, f_number, focal_length] = EXPOSURE_TAGS.map(|tag| {
+            exif.get_field(tag, exif::In::PRIMARY)
+                .map(|f| f.value.clone())
+        });
+        exposure_from_values(iso, time, f_number, focal_length)
+    }
+
+    fn location(&self, path: &Path) -> Option<Location> {
+        let exif = read_exif(path)?;
+        let [lat_ref, lat, lon_ref, lon] = [
+            exif::Tag::GPSLatitudeRef,
+            exif::Tag::GPSLatitude,
+            exif::Tag::GPSLongitudeRef,
+            exif::Tag::GPSLongitude,
+        ]
+        .map(|tag| {
+            exif.get_field(tag, exif::In::PRIMARY)
+                .map(|f| f.value.clone())
+        });
+        location_from_values(lat_ref, lat?, lon_ref, lon?)
+    }
+}
+
+/// The Canon metadata boxes of a CR3.
+struct Cr3Extractor;
+
+impl MetadataExtractor for Cr3Extractor {
+    fn capture_time(&self, path: &Path) -> Result<(Recorded, DateSource)> {
+        extract_cr3_datetime(path).map(|dt| (dt, DateSource::Exif))
+    }
+
+    fn camera(&self, path: &Path) -> Option<Camera> {
+        let [make, model, serial]: [_; 3] = cr3_ascii_values(path, &CR3_CAMERA_TAGS)
+            .ok()?
+            .try_into()
+            .ok()?;
+        camera_from_ascii(make, model, serial)
+    }
+
+    fn capture_time_and_camera(
+        &self,
+        path: &Path,
+    ) -> (Result<(Recorded, DateSource)>, Option<Camera>) {
+        let lookups = [CR3_DATETIME_TAGS.as_slice(), &CR3_CAMERA_TAGS].concat();
+        let values = match cr3_ascii_values(path, &lookups) {
+            Ok(values) => values,
+            Err(e) => return (Err(e), None),
+        };
+        let (datetimes, camera) = values.split_at(CR3_DATETIME_TAGS.len());
+        let camera = match camera {
+            [make, model, serial] => camera_from_ascii(make.clone(), model.clone(), serial.clone()),
+            _ => None,
+        };
+        (
+            cr3_datetime(datetimes).map(|dt| (dt, DateSource::Exif)),
+            camera,
+        )
+    }
+
+    fn exposure(&self, path: &Path) -> Option<Exposure> {
+        // All in the EXIF IFD (CMT2)
+        let [iso, time, f_number, focal_length]: [_; 4] =
+            cr3_values(path, &EXPOSURE_TAGS.map(|tag| (b"CMT2", tag.number())))
+                .ok()?
+                .try_into()
+                .ok()?;
+        exposure_from_values(iso, time, f_number, focal_length)
+    }
+
+    fn location(&self, path: &Path) -> Option<Location> {
+        // CR3 keeps the GPS IFD as its own TIFF block in CMT4
+        let [lat_ref, lat, lon_ref, lon]: [_; 4] = cr3_values(
+            path,
+            &[(b"CMT4", 1), (b"CMT4", 2), (b"CMT4", 3), (b"CMT4", 4)],
+        )
+        .ok()?
+        .try_into()
+        .ok()?;
+        location_from_values(lat_ref, lat?, lon_ref, lon?)
+    }
+}
+
+/// QuickTime metadata or the `mvhd` of an MP4/MOV container.
+struct Mp4Extractor;
+
+impl MetadataExtractor for Mp4Extractor {
+    fn capture_time(&self, path: &Path) -> Result<(Recorded, DateSource)> {
+        extract_mp4_datetime(path).map(|dt| (dt, DateSource::Mp4Container))
+    }
+}
+
+/// The MDPM recording date of an AVCHD `.MTS` stream.
+struct MtsExtractor;
+
+impl MetadataExtractor for MtsExtractor {
+    fn capture_time(&self, path: &Path) -> Result<(Recorded, DateSource)> {
+        extract_mts_datetime(path).map(|dt| (dt, DateSource::Avchd))
+    }
+}
+
+/// The `bext` origination date of a Broadcast WAV.
+struct WavExtractor;
+
+impl MetadataExtractor for WavExtractor {
+    fn capture_time(&self, path: &Path) -> Result<(Recorded, DateSource)> {
+        extract_bwf_datetime(path).map(|dt| (dt, DateSource::Bwf))
+    }
+}
+
+type Registry = RwLock<HashMap<String, Arc<dyn MetadataExtractor>>>;
+
+/// Extractors by lowercased extension, starting with the built-in ones.
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let exif: Arc<dyn MetadataExtractor> = Arc::new(ExifExtractor);
+        let mp4: Arc<dyn MetadataExtractor> = Arc::new(Mp4Extractor);
+        let mut extractors = HashMap::new();
+        // kamadak-exif reads the HEIF `Exif` item itself
+        for ext in ["cr2", "nef", "arw", "dng", "heic", "heif"] {
+            extractors.insert(ext.to_string(), exif.clone());
+        }
+        extractors.insert(
+            "cr3".to_string(),
+            Arc::new(Cr3Extractor) as Arc<dyn MetadataExtractor>,
+        );
+        // Insta360 `insv` files are MP4 containers
+        for ext in ["mp4", "mov", "insv"] {
+            extractors.insert(ext.to_string(), mp4.clone());
+        }
+        extractors.insert(
+            "mts".to_string(),
+            Arc::new(MtsExtractor) as Arc<dyn MetadataExtractor>,
+        );
+        extractors.insert(
+            "wav".to_string(),
+            Arc::new(WavExtractor) as Arc<dyn MetadataExtractor>,
+        );
+        RwLock::new(extractors)
+    })
+}
+
+/// Read the capture time of files with `extension` (case-insensitive) with
+/// `extractor`, replacing any extractor registered for it before, built-in
+/// ones included.
+pub fn register_extractor(extension: &str, extractor: impl MetadataExtractor + 'static) {
+    let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+    registry()
+        .write()
+        .unwrap()
+        .insert(extension, Arc::new(extractor));
+}
+
+/// The extractor registered for the extension of `path`, or EXIF for
+/// unknown extensions.
+fn extractor_for(path: &Path) -> Arc<dyn MetadataExtractor> {
     let ext = path
         .extension()
         .and_then(|e| e.to_str())
         .map(|e| e.to_ascii_lowercase());
+    ext.and_then(|ext| registry().read().unwrap().get(&ext).cloned())
+        .unwrap_or_else(|| Arc::new(ExifExtractor))
+}
 
-    let result = match ext.as_deref() {
-        Some("cr2") => extract_exif_datetime(path),
-        Some("mp4") => extract_mp4_datetime(path),
-        _ => Err(anyhow::anyhow!("Unsupported file type")),
-    };
+/// The EXIF of `path` as kamadak-exif reads it from any container.
+fn read_exif(path: &Path) -> Option<exif::Exif> {
+    read_exif_container(path).ok()
+}
+
+fn read_exif_container(path: &Path) -> Result<exif::Exif> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(exif::Reader::new().read_from_container(&mut reader)?)
+}
+
+/// Extract the creation datetime from a file, along with where it came from,
+/// with the [`MetadataExtractor`] registered for its extension. Built in are
+/// EXIF for CR2/CR3/NEF/ARW/DNG/HEIC, QuickTime metadata or mvhd for
+/// MP4/MOV/INSV, the AVCHD recording date for MTS and the `bext` chunk
+/// for WAV; other extensions are tried as any EXIF container
+/// kamadak-exif understands (JPEG, TIFF, PNG, WebP). Falls back to
+/// filesystem modified time.
+pub fn extract_datetime(path: &Path) -> Result<(NaiveDateTime, DateSource)> {
+    extract_datetime_in(path, None)
+}
+
+/// [`extract_datetime`], converted to `zone` when one is given so that files
+/// recorded in UTC (MP4 `mvhd`) and in camera local time sort together.
+pub fn extract_datetime_in(path: &Path, zone: Option<Zone>) -> Result<(NaiveDateTime, DateSource)> {
+    let (recorded, source) = extract_recorded(path)?;
+    Ok((recorded.normalize(zone), source))
+}
+
+/// The capture time `path` records, before it is normalized to a zone, and
+/// where it was read from, falling back to a date in its name (see
+/// [`filename_datetime`]), then to the filesystem modified time.
+pub fn extract_recorded(path: &Path) -> Result<(Recorded, DateSource)> {
+    recorded_or_fallback(path, extractor_for(path).capture_time(path))
+}
+
+/// [`extract_recorded`] and [`camera`] of `path`, read together.
+pub fn extract_recorded_and_camera(path: &Path) -> Result<(Recorded, DateSource, Option<Camera>)> {
+    let (found, camera) = extractor_for(path).capture_time_and_camera(path);
+    let (recorded, source) = recorded_or_fallback(path, found)?;
+    Ok((recorded, source, camera))
+}
 
-    match result {
-        Ok(dt) => Ok(dt),
-        Err(_) => extract_filesystem_datetime(path),
+/// The capture time an extractor `found` for `path`, or the fallbacks of
+/// [`extract_recorded`] when it found none.
+fn recorded_or_fallback(
+    path: &Path,
+    found: Result<(Recorded, DateSource)>,
+) -> Result<(Recorded, DateSource)> {
+    match found {
+        Ok(found) => Ok(found),
+        Err(e) => {
+            if let Some(recorded) = filename_datetime(path) {
+                log::debug!(
+                    "{}: no metadata date ({:#}), using the date in its name",
+                    path.display(),
+                    e
+                );
+                // Still fails for a file that cannot be read at all
+                std::fs::metadata(path)?;
+                return Ok((recorded, DateSource::Filename));
+            }
+            log::debug!(
+                "{}: no metadata date ({:#}), using modified time",
+                path.display(),
+                e
+            );
+            Ok((
+                extract_filesystem_datetime(path)?,
+                DateSource::FilesystemMtime,
+            ))
+        }
     }
 }
 
-fn extract_exif_datetime(path: &Path) -> Result<NaiveDateTime> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    let exif = exif::Reader::new().read_from_container(&mut reader)?;
+/// The date and time in the name of `path`, as phones, drones and action
+/// cameras write them: `YYYYMMDD` then `HHMMSS`, together or apart
+/// (`DJI_20240615_093011.MP4`, `VID_20240615_101530.mp4`,
+/// `20240615101530.jpg`), or `YYYY-MM-DD` then `HH-MM-SS` or `HH.MM.SS`
+/// (`Screenshot_2024-06-15-10-15-30.png`). Digits after the seconds, such as
+/// milliseconds, are ignored. Pixel phones (`PXL_`) name files in UTC, the
+/// others in local time.
+pub fn filename_datetime(path: &Path) -> Option<Recorded> {
+    let stem = path.file_stem()?.to_str()?;
+    let groups: Vec<&str> = stem
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|g| !g.is_empty())
+        .collect();
+    let datetime = groups.iter().enumerate().find_map(|(i, group)| {
+        let rest = &groups[i + 1..];
+        let (date, time) = match (group.len(), rest) {
+            (14.., _) => (&group[..8], group[8..14].to_string()),
+            (8, [time, ..]) if time.len() >= 6 => (*group, time[..6].to_string()),
+            (4, [month, day, hours, minutes, seconds, ..])
+                if [month, day, hours, minutes].iter().all(|g| g.len() == 2)
+                    && seconds.len() >= 2 =>
+            {
+                let date = NaiveDate::from_ymd_opt(
+                    group.parse().ok()?,
+                    month.parse().ok()?,
+                    day.parse().ok()?,
+                )?;
+                let time = NaiveTime::from_hms_opt(
+                    hours.parse().ok()?,
+                    minutes.parse().ok()?,
+                    seconds[..2].parse().ok()?,
+                )?;
+                return Some(date.and_time(time));
+            }
+            _ => return None,
+        };
+        let date = NaiveDate::parse_from_str(date, "%Y%m%d").ok()?;
+        let time = NaiveTime::parse_from_str(&time, "%H%M%S").ok()?;
+        Some(date.and_time(time))
+    })?;
+    // Long digit runs that only happen to parse, such as counters, are not dates
+    if !(1990..=2100).contains(&datetime.year()) {
+        return None;
+    }
+    if stem.to_ascii_uppercase().starts_with("PXL_") {
+        Some(Recorded::Utc(datetime))
+    } else {
+        Some(Recorded::Local(datetime))
+    }
+}
+
+fn extract_exif_datetime(path: &Path) -> Result<Recorded> {
+    exif_datetime(&read_exif_container(path)?)
+}
 
+fn exif_datetime(exif: &exif::Exif) -> Result<Recorded> {
     // Try DateTimeOriginal first, then DateTime
     let field = exif
         .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
@@ -38,10 +498,342 @@ fn extract_exif_datetime(path: &Path) -> Result<NaiveDateTime> {
     let value = field.display_value().to_string();
     // EXIF format: "2024-01-15 14:30:00"
     let dt = NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S")?;
-    Ok(dt)
+
+    // EXIF 2.31 cameras also record the UTC offset of the clock
+    let offset_tag = if field.tag == exif::Tag::DateTimeOriginal {
+        exif::Tag::OffsetTimeOriginal
+    } else {
+        exif::Tag::OffsetTime
+    };
+    let offset = exif
+        .get_field(offset_tag, exif::In::PRIMARY)
+        .and_then(|f| match &f.value {
+            exif::Value::Ascii(parts) => parts.first(),
+            _ => None,
+        })
+        .and_then(|raw| parse_utc_offset(String::from_utf8_lossy(raw).trim_matches(char::from(0))));
+    Ok(
+        match offset.and_then(|o| o.from_local_datetime(&dt).single()) {
+            Some(dt) => Recorded::Offset(dt),
+            None => Recorded::Local(dt),
+        },
+    )
+}
+
+/// CR3 is an ISO-BMFF container: the EXIF sub-IFD (with DateTimeOriginal) is
+/// stored as a standalone TIFF block in the `CMT2` box, and IFD0 (with
+/// DateTime) in `CMT1`, both inside a Canon `uuid` box under `moov`.
+fn extract_cr3_datetime(path: &Path) -> Result<Recorded> {
+    cr3_datetime(&cr3_ascii_values(path, &CR3_DATETIME_TAGS)?)
+}
+
+/// DateTimeOriginal (0x9003) in CMT2, then DateTime (0x0132) in CMT1.
+const CR3_DATETIME_TAGS: [(&[u8; 4], u16); 2] = [(b"CMT2", 0x9003), (b"CMT1", 0x0132)];
+
+/// Make and Model in IFD0 (CMT1), the serial in the EXIF IFD (CMT2).
+const CR3_CAMERA_TAGS: [(&[u8; 4], u16); 3] =
+    [(b"CMT1", 0x010f), (b"CMT1", 0x0110), (b"CMT2", 0xa431)];
+
+/// The first date of the values of [`CR3_DATETIME_TAGS`].
+fn cr3_datetime(values: &[Option<Vec<u8>>]) -> Result<Recorded> {
+    values
+        .iter()
+        .flatten()
+        .find_map(|v| exif_ascii_datetime(v))
+        .map(Recorded::Local)
+        .ok_or_else(|| anyhow::anyhow!("No EXIF datetime field found in CR3"))
+}
+
+/// ASCII values of the `(CMT box, tag)` pairs in a CR3's Canon metadata, one
+/// per lookup, `None` where the tag is missing.
+fn cr3_ascii_values(path: &Path, lookups: &[(&[u8; 4], u16)]) -> Result<Vec<Option<Vec<u8>>>> {
+    let values = cr3_values(path, lookups)?;
+    Ok(values
+        .into_iter()
+        .map(|value| match value {
+            Some(exif::Value::Ascii(parts)) => parts.into_iter().next(),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Values of the `(CMT box, tag)` pairs in a CR3's Canon metadata, one per
+/// lookup, `None` where the tag is missing.
+fn cr3_values(path: &Path, lookups: &[(&[u8; 4], u16)]) -> Result<Vec<Option<exif::Value>>> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let moov = bmff::find(&mut reader, 0, size, b"moov")?
+        .ok_or_else(|| anyhow::anyhow!("CR3 has no moov box"))?;
+    let mut canon = None;
+    for candidate in bmff::children(&mut reader, moov.start, moov.end)? {
+        if &candidate.kind == b"uuid"
+            && candidate.end - candidate.start >= 16
+            && bmff::read_at(&mut reader, candidate.start, 16)? == CANON_CR3_UUID
+        {
+            canon = Some(candidate);
+            break;
+        }
+    }
+    let canon = canon.ok_or_else(|| anyhow::anyhow!("CR3 has no Canon metadata box"))?;
+
+    let mut values = Vec::new();
+    for &(kind, tag) in lookups {
+        values.push(cr3_value(&mut reader, &canon, kind, tag)?);
+    }
+    Ok(values)
+}
+
+fn cr3_value(
+    reader: &mut BufReader<File>,
+    canon: &bmff::BoxRange,
+    kind: &[u8; 4],
+    tag: u16,
+) -> Result<Option<exif::Value>> {
+    let Some(block) = bmff::find(reader, canon.start + 16, canon.end, kind)? else {
+        return Ok(None);
+    };
+    let tiff = bmff::read_payload(reader, block)?;
+    let Ok(exif) = exif::Reader::new().read_raw(tiff) else {
+        return Ok(None);
+    };
+    let value = exif
+        .fields()
+        .find(|f| f.ifd_num == exif::In::PRIMARY && f.tag.number() == tag)
+        .map(|f| f.value.clone());
+    Ok(value)
+}
+
+/// Camera body that recorded a file, from the EXIF `Make`, `Model` and
+/// `BodySerialNumber` tags.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Camera {
+    pub make: Option<String>,
+    pub model: String,
+    pub serial: Option<String>,
+}
+
+impl Camera {
+    /// Display name such as `Canon EOS R5`: the model, led by the make's
+    /// first word when the model does not already start with it.
+    pub fn name(&self) -> String {
+        let brand = self
+            .make
+            .as_deref()
+            .and_then(|m| m.split_whitespace().next());
+        match brand {
+            Some(brand) if !self.model.to_lowercase().starts_with(&brand.to_lowercase()) => {
+                format!("{} {}", brand, self.model)
+            }
+            _ => self.model.clone(),
+        }
+    }
+
+    /// Short folder-friendly name such as `CanonEOSR5`: [`Camera::name`]
+    /// without spaces.
+    pub fn label(&self) -> String {
+        self.name().split_whitespace().collect()
+    }
+
+    /// Whether `pattern` appears, ignoring case, in the camera's name or its
+    /// make and model, so `canon`, `EOS R5` and `Canon EOS R5` all match.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.to_lowercase();
+        let make_model = format!(
+            "{} {}",
+            self.make.as_deref().unwrap_or_default(),
+            self.model
+        );
+        [self.name(), make_model]
+            .iter()
+            .any(|text| text.to_lowercase().contains(&pattern))
+    }
+}
+
+/// The camera that recorded `path`, from the [`MetadataExtractor`]
+/// registered for its extension. Videos have none.
+pub fn camera(path: &Path) -> Option<Camera> {
+    extractor_for(path).camera(path)
+}
+
+/// A camera from raw ASCII `Make`, `Model` and serial values; none without a
+/// model.
+fn camera_from_ascii(
+    make: Option<Vec<u8>>,
+    model: Option<Vec<u8>>,
+    serial: Option<Vec<u8>>,
+) -> Option<Camera> {
+    let text = |raw: Option<Vec<u8>>| {
+        let value = String::from_utf8_lossy(&raw?)
+            .trim_matches(char::from(0))
+            .trim()
+            .to_string();
+        (!value.is_empty()).then_some(value)
+    };
+    Some(Camera {
+        make: text(make),
+        model: text(model)?,
+        serial: text(serial),
+    })
+}
+
+/// The camera in the `Make`, `Model` and `BodySerialNumber` tags of `exif`.
+fn exif_camera(exif: &exif::Exif) -> Option<Camera> {
+    let [make, model, serial] = [
+        exif::Tag::Make,
+        exif::Tag::Model,
+        exif::Tag::BodySerialNumber,
+    ]
+    .map(|tag| match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(parts) => parts.first().cloned(),
+        _ => None,
+    });
+    camera_from_ascii(make, model, serial)
 }
 
-fn extract_mp4_datetime(path: &Path) -> Result<NaiveDateTime> {
+/// Camera model from the file's EXIF `Model` tag. Videos have none.
+pub fn camera_model(path: &Path) -> Option<String> {
+    camera(path).map(|c| c.model)
+}
+
+/// Exposure settings recorded in a photo's EXIF.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Exposure {
+    pub iso: Option<u32>,
+    /// Shutter speed as recorded, e.g. `1/250`
+    pub exposure_time: Option<String>,
+    pub f_number: Option<f64>,
+    pub focal_length_mm: Option<f64>,
+}
+
+/// The EXIF tags of an [`Exposure`]: ISO, shutter speed, aperture and focal length.
+const EXPOSURE_TAGS: [exif::Tag; 4] = [
+    exif::Tag::PhotographicSensitivity,
+    exif::Tag::ExposureTime,
+    exif::Tag::FNumber,
+    exif::Tag::FocalLength,
+];
+
+/// The exposure settings of `path`, from the EXIF `PhotographicSensitivity`
+/// (ISO), `ExposureTime`, `FNumber` and `FocalLength` tags, read by the
+/// [`MetadataExtractor`] registered for its extension. Videos have none.
+pub fn exposure(path: &Path) -> Option<Exposure> {
+    extractor_for(path).exposure(path)
+}
+
+/// An exposure from the values of [`EXPOSURE_TAGS`]; none when all are missing.
+fn exposure_from_values(
+    iso: Option<exif::Value>,
+    time: Option<exif::Value>,
+    f_number: Option<exif::Value>,
+    focal_length: Option<exif::Value>,
+) -> Option<Exposure> {
+    let rational = |value: Option<exif::Value>| match value? {
+        exif::Value::Rational(parts) => parts.first().map(|r| r.to_f64()).filter(|v| v.is_finite()),
+        _ => None,
+    };
+    let exposure = Exposure {
+        iso: iso.and_then(|v| v.get_uint(0)),
+        exposure_time: match time {
+            // Fractions of a second as `1/n`, as cameras display them
+            Some(exif::Value::Rational(parts)) => parts.first().filter(|r| r.denom != 0).map(|r| {
+                if r.num != 0 && r.num < r.denom && r.denom % r.num == 0 {
+                    format!("1/{}", r.denom / r.num)
+                } else {
+                    format!("{}", r.to_f64())
+                }
+            }),
+            _ => None,
+        },
+        f_number: rational(f_number),
+        focal_length_mm: rational(focal_length),
+    };
+    (exposure != Exposure::default()).then_some(exposure)
+}
+
+/// Where a photo was taken, in decimal degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Location {
+    /// Great-circle distance to `other`, in kilometres.
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        const EARTH_RADIUS_KM: f64 = 6371.0;
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// The GPS position recorded in the file's EXIF, if any, read by the
+/// [`MetadataExtractor`] registered for its extension. Videos have none.
+pub fn location(path: &Path) -> Option<Location> {
+    extractor_for(path).location(path)
+}
+
+/// A location from the EXIF GPS latitude and longitude and their references;
+/// none when the camera wrote zeros for lack of a fix.
+fn location_from_values(
+    lat_ref: Option<exif::Value>,
+    lat: exif::Value,
+    lon_ref: Option<exif::Value>,
+    lon: exif::Value,
+) -> Option<Location> {
+    let location = Location {
+        latitude: gps_coordinate(&lat, lat_ref.as_ref(), b'S')?,
+        longitude: gps_coordinate(&lon, lon_ref.as_ref(), b'W')?,
+    };
+    (location.latitude != 0.0 || location.longitude != 0.0).then_some(location)
+}
+
+/// Decimal degrees from EXIF degrees/minutes/seconds rationals, negative when
+/// the reference is `negative` (S or W).
+fn gps_coordinate(
+    value: &exif::Value,
+    reference: Option<&exif::Value>,
+    negative: u8,
+) -> Option<f64> {
+    let exif::Value::Rational(parts) = value else {
+        return None;
+    };
+    let degrees: f64 = parts
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|(part, scale)| part.to_f64() / scale)
+        .sum();
+    if parts.is_empty() || !degrees.is_finite() {
+        return None;
+    }
+    let sign = match reference {
+        Some(exif::Value::Ascii(v)) if v.first().and_then(|r| r.first()) == Some(&negative) => -1.0,
+        _ => 1.0,
+    };
+    Some(sign * degrees)
+}
+
+/// Parse an EXIF ASCII datetime ("2024:01:15 14:30:00").
+fn exif_ascii_datetime(value: &[u8]) -> Option<NaiveDateTime> {
+    let dt = exif::DateTime::from_ascii(value).ok()?;
+    NaiveDate::from_ymd_opt(dt.year as i32, dt.month as u32, dt.day as u32)?.and_hms_opt(
+        dt.hour as u32,
+        dt.minute as u32,
+        dt.second as u32,
+    )
+}
+
+fn extract_mp4_datetime(path: &Path) -> Result<Recorded> {
+    // Apple's creation date carries the local time and offset of the shot,
+    // unlike mvhd which is UTC (and often just the encoder's clock)
+    if let Ok(Some(dt)) = extract_quicktime_creationdate(path) {
+        return Ok(dt);
+    }
+
     let file = File::open(path)?;
     let size = file.metadata()?.len();
     let reader = BufReader::new(file);
@@ -56,12 +848,746 @@ fn extract_mp4_datetime(path: &Path) -> Result<NaiveDateTime> {
     // MP4 epoch: 1904-01-01 00:00:00 UTC
     let mp4_epoch = NaiveDateTime::parse_from_str("1904-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")?;
     let dt = mp4_epoch + chrono::Duration::seconds(creation_time as i64);
-    Ok(dt)
+    Ok(Recorded::Utc(dt))
+}
+
+/// UUID of the H.264 user data in which AVCHD camcorders write the Modified
+/// Digital Video Pack Metadata (MDPM) of a stream, followed by `MDPM`.
+const AVCHD_MDPM: [u8; 20] = [
+    0x17, 0xee, 0x8c, 0x60, 0xf8, 0x4d, 0x11, 0xd9, 0x8c, 0xd6, 0x08, 0x00, 0x20, 0x0c, 0x9a, 0x66,
+    b'M', b'D', b'P', b'M',
+];
+
+/// How much of an `.MTS` stream is searched for its MDPM. Camcorders repeat
+/// it with every group of pictures, so the first is near the start.
+const MTS_SEARCH_LIMIT: u64 = 4 << 20;
+
+/// The recording date of an AVCHD `.MTS` stream. The clip-info files next to
+/// it hold no date, but the stream carries the camcorder's MDPM in the
+/// H.264 user data: a count, then 5-byte entries of a tag and 4 bytes, the
+/// date being split over tags 0x18 and 0x19.
+fn extract_mts_datetime(path: &Path) -> Result<Recorded> {
+    let mut data = Vec::new();
+    File::open(path)?
+        .take(MTS_SEARCH_LIMIT)
+        .read_to_end(&mut data)?;
+    // A copy cut by a transport packet boundary is skipped for the next one
+    data.windows(AVCHD_MDPM.len())
+        .enumerate()
+        .filter(|(_, window)| *window == AVCHD_MDPM)
+        .find_map(|(at, _)| parse_mdpm(&data[at + AVCHD_MDPM.len()..]))
+        .map(Recorded::Local)
+        .ok_or_else(|| anyhow::anyhow!("No AVCHD recording date in the stream"))
 }
 
-fn extract_filesystem_datetime(path: &Path) -> Result<NaiveDateTime> {
+/// The date and time of an MDPM, which is wall-clock time. The time zone
+/// byte before the year is left out: camcorders set it unreliably, and the
+/// photos of the same card record their wall-clock time too.
+fn parse_mdpm(data: &[u8]) -> Option<NaiveDateTime> {
+    let (&count, rest) = data.split_first()?;
+    // H.264 escapes 00 00 0x with an 03 after the zeros, which midnight can
+    // bring into the date
+    let len = 5 * usize::from(count);
+    let mut entries = Vec::with_capacity(len);
+    let mut zeros = 0;
+    for &byte in rest {
+        if entries.len() == len {
+            break;
+        }
+        if !(zeros >= 2 && byte == 3) {
+            entries.push(byte);
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+    let value = |tag: u8| {
+        entries
+            .chunks_exact(5)
+            .find(|e| e[0] == tag)
+            .map(|e| [e[1], e[2], e[3], e[4]])
+    };
+    let ([_zone, century, year, month], [day, hour, minute, second]) = (value(0x18)?, value(0x19)?);
+    let bcd = |b: u8| {
+        (b >> 4 < 10 && b & 0x0f < 10).then(|| u32::from(b >> 4) * 10 + u32::from(b & 0x0f))
+    };
+    NaiveDate::from_ymd_opt(
+        (bcd(century)? * 100 + bcd(year)?) as i32,
+        bcd(month)?,
+        bcd(day)?,
+    )?
+    .and_hms_opt(bcd(hour)?, bcd(minute)?, bcd(second)?)
+}
+
+/// The origination date and time in the `bext` chunk of a Broadcast WAV
+/// (RIFF, or RF64 for files over 4 GB), which audio recorders set to the
+/// start of the recording: `yyyy-mm-dd` and `hh:mm:ss` after the 320 bytes
+/// of description, originator and reference, with any separators.
+fn extract_bwf_datetime(path: &Path) -> Result<Recorded> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if !matches!(&header[..4], b"RIFF" | b"RF64" | b"BW64") || &header[8..] != b"WAVE" {
+        return Err(anyhow::anyhow!("Not a WAV file"));
+    }
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk)?;
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        if &chunk[..4] == b"bext" {
+            let mut bext = vec![0u8; 338];
+            reader.read_exact(&mut bext)?;
+            let digits = |range: std::ops::Range<usize>| -> Option<Vec<u32>> {
+                let field = std::str::from_utf8(&bext[range]).ok()?;
+                field
+                    .split(|c: char| !c.is_ascii_digit())
+                    .map(|n| n.parse().ok())
+                    .collect()
+            };
+            let date = digits(320..330).zip(digits(330..338));
+            return date
+                .and_then(|(date, time)| match (date.as_slice(), time.as_slice()) {
+                    ([year, month, day], [hour, minute, second]) => {
+                        NaiveDate::from_ymd_opt(*year as i32, *month, *day)?
+                            .and_hms_opt(*hour, *minute, *second)
+                    }
+                    _ => None,
+                })
+                .map(Recorded::Local)
+                .ok_or_else(|| anyhow::anyhow!("No origination date in the bext chunk"));
+        }
+        // The sample data of an RF64 has its size elsewhere, and is last anyway
+        if &chunk[..4] == b"data" || len == u32::MAX {
+            return Err(anyhow::anyhow!("No bext chunk before the audio data"));
+        }
+        // Chunks are padded to an even size
+        reader.seek_relative(i64::from(len) + i64::from(len % 2))?;
+    }
+}
+
+/// Read `com.apple.quicktime.creationdate` from the `mdta` metadata of a
+/// QuickTime/MP4 file (`moov/meta` or `moov/udta/meta`), returning the local
+/// wall-clock time and offset it records.
+fn extract_quicktime_creationdate(path: &Path) -> Result<Option<Recorded>> {
+    const KEY: &[u8] = b"com.apple.quicktime.creationdate";
+
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let Some(moov) = bmff::find(&mut reader, 0, size, b"moov")? else {
+        return Ok(None);
+    };
+    let mut metas = Vec::new();
+    metas.extend(bmff::find(&mut reader, moov.start, moov.end, b"meta")?);
+    if let Some(udta) = bmff::find(&mut reader, moov.start, moov.end, b"udta")? {
+        metas.extend(bmff::find(&mut reader, udta.start, udta.end, b"meta")?);
+    }
+
+    for meta in metas {
+        // QuickTime `meta` is a plain box, the ISO variant a full box
+        let mut start = meta.start;
+        if bmff::read_at(&mut reader, start + 4, 4)? != b"hdlr" {
+            start += 4;
+        }
+        let boxes = bmff::children(&mut reader, start, meta.end)?;
+        let (Some(keys), Some(ilst)) = (
+            boxes.iter().find(|b| &b.kind == b"keys"),
+            boxes.iter().find(|b| &b.kind == b"ilst"),
+        ) else {
+            continue;
+        };
+
+        // keys: version/flags, entry count, then (size, namespace, name) entries
+        let keys = bmff::read_payload(&mut reader, *keys)?;
+        let mut index = None;
+        let mut pos = 8;
+        let mut n = 1u32;
+        while pos + 8 <= keys.len() {
+            let key_size = u32::from_be_bytes(keys[pos..pos + 4].try_into()?) as usize;
+            if key_size < 8 || pos + key_size > keys.len() {
+                break;
+            }
+            if &keys[pos + 8..pos + key_size] == KEY {
+                index = Some(n);
+                break;
+            }
+            pos += key_size;
+            n += 1;
+        }
+        let Some(index) = index else {
+            continue;
+        };
+
+        // ilst items are boxes whose type is the 1-based key index
+        for item in bmff::children(&mut reader, ilst.start, ilst.end)? {
+            if item.kind != index.to_be_bytes() {
+                continue;
+            }
+            if let Some(data) = bmff::find(&mut reader, item.start, item.end, b"data")? {
+                // data: type indicator (4), locale (4), value
+                let value = bmff::read_payload(&mut reader, data)?;
+                if value.len() > 8 {
+                    let text = String::from_utf8_lossy(&value[8..]);
+                    if let Some(dt) = parse_quicktime_date(text.trim_end_matches('\0')) {
+                        return Ok(Some(dt));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parse a QuickTime ISO 8601 date such as `2024-06-14T09:05:30+0200`,
+/// keeping the local time and offset it was recorded in.
+fn parse_quicktime_date(text: &str) -> Option<Recorded> {
+    DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%z")
+        .or_else(|_| DateTime::parse_from_rfc3339(text))
+        .map(Recorded::Offset)
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S")
+                .ok()
+                .map(Recorded::Local)
+        })
+}
+
+fn extract_filesystem_datetime(path: &Path) -> Result<Recorded> {
     let metadata = std::fs::metadata(path)?;
     let modified = metadata.modified()?;
-    let datetime: chrono::DateTime<chrono::Local> = modified.into();
-    Ok(datetime.naive_local())
+    let datetime: DateTime<chrono::Local> = modified.into();
+    Ok(Recorded::Offset(datetime.fixed_offset()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    struct FixedExtractor;
+
+    impl MetadataExtractor for FixedExtractor {
+        fn capture_time(&self, _path: &Path) -> Result<(Recorded, DateSource)> {
+            let dt = NaiveDate::from_ymd_opt(2024, 1, 15)
+                .unwrap()
+                .and_hms_opt(14, 30, 0)
+                .unwrap();
+            Ok((Recorded::Local(dt), DateSource::Exif))
+        }
+
+        fn camera(&self, _path: &Path) -> Option<Camera> {
+            Some(Camera {
+                make: Some("Test".to_string()),
+                model: "Cam".to_string(),
+                serial: None,
+            })
+        }
+    }
+
+    #[test]
+    fn test_registered_extractor_is_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("IMG_0001.TESTRAW");
+        std::fs::write(&path, b"not exif").unwrap();
+        assert_eq!(
+            extract_datetime(&path).unwrap().1,
+            DateSource::FilesystemMtime
+        );
+
+        register_extractor(".testraw", FixedExtractor);
+        let (dt, source) = extract_datetime(&path).unwrap();
+        assert_eq!(dt.to_string(), "2024-01-15 14:30:00");
+        assert_eq!(source, DateSource::Exif);
+        assert_eq!(camera(&path).unwrap().name(), "Test Cam");
+        // Not provided by the extractor, so not read as EXIF either
+        assert_eq!(exposure(&path), None);
+    }
+
+    /// Minimal little-endian TIFF with a single IFD0 DateTime entry.
+    fn tiff_with_datetime(value: &str) -> Vec<u8> {
+        tiff_with_ascii_tag(0x0132, value)
+    }
+
+    /// Minimal little-endian TIFF with a single ASCII entry in IFD0.
+    fn tiff_with_ascii_tag(tag: u16, value: &str) -> Vec<u8> {
+        let mut ascii = value.as_bytes().to_vec();
+        ascii.push(0);
+        let mut data = Vec::new();
+        data.extend_from_slice(b"II");
+        data.extend_from_slice(&42u16.to_le_bytes());
+        data.extend_from_slice(&8u32.to_le_bytes());
+        // IFD0: one entry, then the next-IFD offset
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&tag.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes()); // ASCII
+        data.extend_from_slice(&(ascii.len() as u32).to_le_bytes());
+        data.extend_from_slice(&26u32.to_le_bytes()); // value offset
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&ascii);
+        data
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// Minimal MP4 (ftyp + moov/mvhd) with the given mvhd creation_time.
+    fn mp4_with_creation_time(creation_time: u32) -> Vec<u8> {
+        let mut ftyp = b"isom".to_vec();
+        ftyp.extend_from_slice(&0u32.to_be_bytes());
+        ftyp.extend_from_slice(b"isommp41");
+
+        let mut mvhd = vec![0u8; 4]; // version 0, flags
+        mvhd.extend_from_slice(&creation_time.to_be_bytes());
+        mvhd.extend_from_slice(&creation_time.to_be_bytes()); // modification_time
+        mvhd.extend_from_slice(&1000u32.to_be_bytes()); // timescale
+        mvhd.extend_from_slice(&0u32.to_be_bytes()); // duration
+        mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume
+        mvhd.extend_from_slice(&[0u8; 10]); // reserved
+        for v in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+            mvhd.extend_from_slice(&v.to_be_bytes()); // matrix
+        }
+        mvhd.extend_from_slice(&[0u8; 24]); // pre_defined
+        mvhd.extend_from_slice(&2u32.to_be_bytes()); // next_track_id
+
+        let mut data = mp4_box(b"ftyp", &ftyp);
+        data.extend(mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd)));
+        data
+    }
+
+    /// Minimal CR3: ftyp + moov/uuid(Canon)/CMT2 holding DateTimeOriginal.
+    fn cr3_with_datetime(value: &str) -> Vec<u8> {
+        let mut canon = CANON_CR3_UUID.to_vec();
+        canon.extend(mp4_box(b"CMT2", &tiff_with_ascii_tag(0x9003, value)));
+
+        let mut data = mp4_box(b"ftyp", b"crx \0\0\0\x01crx isom");
+        data.extend(mp4_box(b"moov", &mp4_box(b"uuid", &canon)));
+        data
+    }
+
+    fn full_box(kind: &[u8; 4], version: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![version, 0, 0, 0];
+        data.extend_from_slice(payload);
+        mp4_box(kind, &data)
+    }
+
+    /// Minimal HEIF: ftyp + meta with one `Exif` item stored in `idat`.
+    fn heic_with_datetime(value: &str) -> Vec<u8> {
+        let mut exif_item = 0u32.to_be_bytes().to_vec(); // offset to TIFF header
+        exif_item.extend(tiff_with_datetime(value));
+
+        let mut infe = 1u16.to_be_bytes().to_vec(); // item_ID
+        infe.extend_from_slice(&0u16.to_be_bytes()); // protection index
+        infe.extend_from_slice(b"Exif\0");
+        let mut iinf = 1u16.to_be_bytes().to_vec();
+        iinf.extend(full_box(b"infe", 2, &infe));
+
+        let mut iloc = 0x4400u16.to_be_bytes().to_vec(); // 4-byte offsets and lengths
+        iloc.extend_from_slice(&1u16.to_be_bytes()); // item count
+        iloc.extend_from_slice(&1u16.to_be_bytes()); // item_ID
+        iloc.extend_from_slice(&1u16.to_be_bytes()); // construction method 1 (idat)
+        iloc.extend_from_slice(&0u16.to_be_bytes()); // data reference index
+        iloc.extend_from_slice(&1u16.to_be_bytes()); // extent count
+        iloc.extend_from_slice(&0u32.to_be_bytes());
+        iloc.extend_from_slice(&(exif_item.len() as u32).to_be_bytes());
+
+        let mut meta = full_box(b"iinf", 0, &iinf);
+        meta.extend(full_box(b"iloc", 1, &iloc));
+        meta.extend(mp4_box(b"idat", &exif_item));
+
+        let mut data = mp4_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        data.extend(full_box(b"meta", 0, &meta));
+        data
+    }
+
+    /// Minimal MOV: ftyp + moov/meta with an `mdta` creation date key.
+    fn mov_with_creationdate(value: &str) -> Vec<u8> {
+        let mut hdlr = vec![0u8; 8];
+        hdlr.extend_from_slice(b"mdta");
+        hdlr.extend_from_slice(&[0u8; 13]);
+
+        let mut keys = 2u32.to_be_bytes().to_vec();
+        for key in [
+            "com.apple.quicktime.make",
+            "com.apple.quicktime.creationdate",
+        ] {
+            keys.extend_from_slice(&((key.len() + 8) as u32).to_be_bytes());
+            keys.extend_from_slice(b"mdta");
+            keys.extend_from_slice(key.as_bytes());
+        }
+
+        let mut data = 1u32.to_be_bytes().to_vec(); // UTF-8
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(value.as_bytes());
+        let mut make = 1u32.to_be_bytes().to_vec();
+        make.extend_from_slice(&0u32.to_be_bytes());
+        make.extend_from_slice(b"Apple");
+        let mut ilst = mp4_box(&1u32.to_be_bytes(), &mp4_box(b"data", &make));
+        ilst.extend(mp4_box(&2u32.to_be_bytes(), &mp4_box(b"data", &data)));
+
+        let mut meta = full_box(b"hdlr", 0, &hdlr);
+        meta.extend(full_box(b"keys", 0, &keys));
+        meta.extend(mp4_box(b"ilst", &ilst));
+
+        let mut mov = mp4_box(b"ftyp", b"qt  \0\0\0\0qt  ");
+        mov.extend(mp4_box(b"moov", &mp4_box(b"meta", &meta)));
+        mov
+    }
+
+    fn write_temp(dir: &Path, name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = dir.join(name);
+        let mut file = File::create(&path).unwrap();
+        file.write_all(data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_date_source_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(
+            dir.path(),
+            "IMG_0001.CR2",
+            &tiff_with_datetime("2024:01:15 14:30:00"),
+        );
+        let (dt, source) = extract_datetime(&path).unwrap();
+        assert_eq!(source, DateSource::Exif);
+        assert_eq!(dt.to_string(), "2024-01-15 14:30:00");
+    }
+
+    #[test]
+    fn test_tiff_based_raw_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["DSC_0001.NEF", "DSC01234.ARW", "IMG_0001.DNG"] {
+            let path = write_temp(dir.path(), name, &tiff_with_datetime("2024:01:15 14:30:00"));
+            let (dt, source) = extract_datetime(&path).unwrap();
+            assert_eq!(source, DateSource::Exif, "{}", name);
+            assert_eq!(dt.to_string(), "2024-01-15 14:30:00");
+        }
+    }
+
+    #[test]
+    fn test_heic_exif_item() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(
+            dir.path(),
+            "IMG_0001.HEIC",
+            &heic_with_datetime("2024:07:01 18:45:10"),
+        );
+        let (dt, source) = extract_datetime(&path).unwrap();
+        assert_eq!(source, DateSource::Exif);
+        assert_eq!(dt.to_string(), "2024-07-01 18:45:10");
+    }
+
+    #[test]
+    fn test_cr3_datetime_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(
+            dir.path(),
+            "IMG_0001.CR3",
+            &cr3_with_datetime("2024:06:14 09:05:30"),
+        );
+        let (dt, source) = extract_datetime(&path).unwrap();
+        assert_eq!(source, DateSource::Exif);
+        assert_eq!(dt.to_string(), "2024-06-14 09:05:30");
+    }
+
+    #[test]
+    fn test_camera_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(
+            dir.path(),
+            "IMG_0001.CR2",
+            &tiff_with_ascii_tag(0x0110, "Canon EOS R5"),
+        );
+        assert_eq!(camera_model(&path).as_deref(), Some("Canon EOS R5"));
+        // Read with the date, the camera is still found when the date is not
+        let (_, source, camera) = extract_recorded_and_camera(&path).unwrap();
+        assert_eq!(source, DateSource::FilesystemMtime);
+        assert_eq!(camera.map(|c| c.model).as_deref(), Some("Canon EOS R5"));
+        let path = write_temp(
+            dir.path(),
+            "IMG_0002.CR2",
+            &tiff_with_datetime("2024:01:15 14:30:00"),
+        );
+        assert_eq!(camera_model(&path), None);
+        let (_, source, camera) = extract_recorded_and_camera(&path).unwrap();
+        assert_eq!((source, camera), (DateSource::Exif, None));
+    }
+
+    #[test]
+    fn test_camera_matches() {
+        let camera = Camera {
+            make: Some("Canon".to_string()),
+            model: "EOS R5".to_string(),
+            serial: None,
+        };
+        assert!(camera.matches("Canon EOS R5"));
+        assert!(camera.matches("r5"));
+        assert!(!camera.matches("EOS R6"));
+    }
+
+    #[test]
+    fn test_exposure() {
+        // IFD0 pointing to an EXIF IFD with ExposureTime 10/2500 and ISO 400
+        let mut data = b"II".to_vec();
+        data.extend_from_slice(&42u16.to_le_bytes());
+        data.extend_from_slice(&8u32.to_le_bytes());
+        for ifd in [
+            &[(0x8769u16, 4u16, 26u32)][..],
+            &[(0x829a, 5, 56), (0x8827, 3, 400)],
+        ] {
+            data.extend_from_slice(&(ifd.len() as u16).to_le_bytes());
+            for &(tag, kind, value) in ifd {
+                data.extend_from_slice(&tag.to_le_bytes());
+                data.extend_from_slice(&kind.to_le_bytes());
+                data.extend_from_slice(&1u32.to_le_bytes());
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&0u32.to_le_bytes());
+        }
+        data.extend_from_slice(&10u32.to_le_bytes());
+        data.extend_from_slice(&2500u32.to_le_bytes());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(dir.path(), "IMG_0001.CR2", &data);
+        let exposure = exposure(&path).unwrap();
+        assert_eq!(exposure.iso, Some(400));
+        assert_eq!(exposure.exposure_time.as_deref(), Some("1/250"));
+        assert_eq!(exposure.f_number, None);
+        let path = write_temp(
+            dir.path(),
+            "IMG_0002.CR2",
+            &tiff_with_datetime("2024:01:15 14:30:00"),
+        );
+        assert_eq!(super::exposure(&path), None);
+    }
+
+    #[test]
+    fn test_gps_location() {
+        let dms =
+            |d, m, s| exif::Value::Rational(vec![(d, 1).into(), (m, 1).into(), (s, 100).into()]);
+        let south = exif::Value::Ascii(vec![b"S".to_vec()]);
+        let lat = gps_coordinate(&dms(33, 51, 3540), Some(&south), b'S').unwrap();
+        assert!((lat + 33.8598).abs() < 1e-4);
+        let lon = gps_coordinate(&dms(151, 12, 4020), None, b'W').unwrap();
+        assert!((lon - 151.2112).abs() < 1e-4);
+
+        let sydney = Location {
+            latitude: lat,
+            longitude: lon,
+        };
+        let bondi = Location {
+            latitude: -33.8915,
+            longitude: 151.2767,
+        };
+        let distance = sydney.distance_km(&bondi);
+        assert!((6.5..7.5).contains(&distance), "{}", distance);
+    }
+
+    #[test]
+    fn test_mov_quicktime_creationdate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(
+            dir.path(),
+            "IMG_0001.MOV",
+            &mov_with_creationdate("2024-06-14T23:30:05+0200"),
+        );
+        let (dt, source) = extract_datetime(&path).unwrap();
+        assert_eq!(source, DateSource::Mp4Container);
+        // Local time of the recording, not UTC
+        assert_eq!(dt.to_string(), "2024-06-14 23:30:05");
+    }
+
+    #[test]
+    fn test_timezone_normalizes_utc_and_offset_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        let mov = write_temp(
+            dir.path(),
+            "IMG_0001.MOV",
+            &mov_with_creationdate("2024-06-14T18:30:05-0400"),
+        );
+        let cr2 = write_temp(
+            dir.path(),
+            "IMG_0002.CR2",
+            &tiff_with_datetime("2024:06:15 00:10:00"),
+        );
+        let mvhd = Recorded::Utc("2024-06-14T22:30:05".parse().unwrap());
+
+        let zone = Zone::parse("+02:00").unwrap();
+        let date = |path: &Path| extract_datetime_in(path, Some(zone)).unwrap().0.to_string();
+        assert_eq!(date(&mov), "2024-06-15 00:30:05");
+        assert_eq!(
+            mvhd.normalize(Some(zone)).to_string(),
+            "2024-06-15 00:30:05"
+        );
+        // No offset recorded: already camera local time
+        assert_eq!(date(&cr2), "2024-06-15 00:10:00");
+
+        let paris = Zone::parse("Europe/Paris").unwrap();
+        assert_eq!(
+            mvhd.normalize(Some(paris)).to_string(),
+            "2024-06-15 00:30:05"
+        );
+        assert_eq!(mvhd.normalize(None).to_string(), "2024-06-14 22:30:05");
+        assert_eq!(
+            Zone::parse("-0530").unwrap(),
+            Zone::Fixed(FixedOffset::west_opt(19_800).unwrap())
+        );
+        assert!(Zone::parse("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn test_date_source_zero_time_mp4_falls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(dir.path(), "MVI_0001.MP4", &mp4_with_creation_time(0));
+        let (_, source) = extract_datetime(&path).unwrap();
+        assert_eq!(source, DateSource::FilesystemMtime);
+    }
+
+    #[test]
+    fn test_date_source_unreadable_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(extract_datetime(&dir.path().join("missing.CR2")).is_err());
+
+        // A file with garbage contents is readable only through the filesystem
+        let path = write_temp(dir.path(), "IMG_0002.CR2", b"not a tiff");
+        let (_, source) = extract_datetime(&path).unwrap();
+        assert_eq!(source, DateSource::FilesystemMtime);
+    }
+
+    #[test]
+    fn test_date_from_filename() {
+        let local = |name: &str| match filename_datetime(Path::new(name)) {
+            Some(Recorded::Local(dt)) => Some(dt.to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            local("DJI_20240615_093011.MP4").as_deref(),
+            Some("2024-06-15 09:30:11")
+        );
+        assert_eq!(
+            local("VID_20240615_101530_123.mp4").as_deref(),
+            Some("2024-06-15 10:15:30")
+        );
+        assert_eq!(
+            local("20240615101530.jpg").as_deref(),
+            Some("2024-06-15 10:15:30")
+        );
+        assert_eq!(
+            local("Screenshot_2024-06-15-10-15-30.png").as_deref(),
+            Some("2024-06-15 10:15:30")
+        );
+        assert_eq!(
+            local("2024-06-15 10.15.30.jpg").as_deref(),
+            Some("2024-06-15 10:15:30")
+        );
+        let pixel = NaiveDate::from_ymd_opt(2024, 6, 15)
+            .unwrap()
+            .and_hms_milli_opt(10, 15, 30, 0)
+            .unwrap();
+        assert_eq!(
+            filename_datetime(Path::new("PXL_20240615_101530123.mp4")),
+            Some(Recorded::Utc(pixel))
+        );
+        for name in [
+            "IMG_0001.CR2",
+            "IMG-20240615-WA0001.jpg",
+            "DJI_20241340_093011.MP4",
+            "00000000_000000.mp4",
+        ] {
+            assert_eq!(filename_datetime(Path::new(name)), None, "{}", name);
+        }
+
+        // Used when the file records no date itself
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(dir.path(), "DJI_20240615_093011.MP4", b"no moov");
+        let (datetime, source) = extract_datetime(&path).unwrap();
+        assert_eq!(
+            (datetime.to_string().as_str(), source),
+            ("2024-06-15 09:30:11", DateSource::Filename)
+        );
+    }
+
+    #[test]
+    fn test_mts_recording_date() {
+        let mut stream = vec![0x47u8; 600];
+        // A copy cut short by a packet boundary, then a whole one whose
+        // 00:00:03 time H.264 escaped
+        stream.extend(AVCHD_MDPM);
+        stream.extend([2, 0x18, 0x80, 0x20, 0x24, 0x06, 0x47, 0x40]);
+        stream.extend(AVCHD_MDPM);
+        stream.extend([
+            3, 0x18, 0x80, 0x20, 0x24, 0x06, 0xe0, 0x01, 0x08, 0x00, 0x10, 0x19, 0x15, 0x00, 0x00,
+            0x03, 0x03,
+        ]);
+        stream.extend([0x47; 100]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_temp(dir.path(), "00001.MTS", &stream);
+        let (datetime, source) = extract_datetime(&path).unwrap();
+        assert_eq!(
+            (datetime.to_string().as_str(), source),
+            ("2024-06-15 00:00:03", DateSource::Avchd)
+        );
+
+        let path = write_temp(dir.path(), "00002.MTS", &[0x47; 400]);
+        assert_eq!(
+            extract_datetime(&path).unwrap().1,
+            DateSource::FilesystemMtime
+        );
+    }
+
+    #[test]
+    fn test_wav_bext_date() {
+        let chunk = |id: &[u8], data: &[u8]| {
+            let mut chunk = id.to_vec();
+            chunk.extend((data.len() as u32).to_le_bytes());
+            chunk.extend(data);
+            if data.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        };
+        let wav = |chunks: &[Vec<u8>]| {
+            let body: Vec<u8> = chunks.concat();
+            let mut wav = b"RIFF".to_vec();
+            wav.extend((4 + body.len() as u32).to_le_bytes());
+            wav.extend(b"WAVE");
+            wav.extend(body);
+            wav
+        };
+        let mut bext = vec![0u8; 320];
+        bext.extend(b"2024-06-15");
+        bext.extend(b"09:30:11");
+        bext.resize(602, 0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let recorded = wav(&[
+            chunk(b"JUNK", b"odd"),
+            chunk(b"bext", &bext),
+            chunk(b"data", &[0; 8]),
+        ]);
+        let path = write_temp(dir.path(), "ZOOM0001.WAV", &recorded);
+        let (datetime, source) = extract_datetime(&path).unwrap();
+        assert_eq!(
+            (datetime.to_string().as_str(), source),
+            ("2024-06-15 09:30:11", DateSource::Bwf)
+        );
+
+        let plain = wav(&[chunk(b"fmt ", &[0; 16]), chunk(b"data", &[0; 8])]);
+        let path = write_temp(dir.path(), "ZOOM0002.WAV", &plain);
+        assert_eq!(
+            extract_datetime(&path).unwrap().1,
+            DateSource::FilesystemMtime
+        );
+        let path = write_temp(dir.path(), "ZOOM0003.MP3", b"ID3\x04\0\0\0\0\0\0");
+        assert_eq!(
+            extract_datetime(&path).unwrap().1,
+            DateSource::FilesystemMtime
+        );
+    }
 }
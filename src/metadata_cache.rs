@@ -0,0 +1,127 @@
+//! Capture times and cameras read from a card, kept between runs so that
+//! scanning the same card again does not read every file's metadata again.
+
+use crate::journal::Fingerprint;
+use crate::metadata::{self, Camera, DateSource, Recorded};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CACHE_FILENAME: &str = ".image-processor-metadata.json";
+
+/// What was read from a file, for the size and modified time it had then.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedMetadata {
+    fingerprint: Fingerprint,
+    pub recorded: Recorded,
+    pub date_source: DateSource,
+    pub camera: Option<Camera>,
+}
+
+/// Persistent cache of source file metadata, stored in the output directory.
+/// The capture time is kept as the file records it, so a run with another
+/// `--timezone` can reuse it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MetadataCache {
+    entries: HashMap<PathBuf, CachedMetadata>,
+}
+
+impl MetadataCache {
+    /// Load the cache from the output directory, or start an empty one.
+    pub fn load(output_dir: &Path) -> Self {
+        fs::read_to_string(output_dir.join(CACHE_FILENAME))
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the cache atomically to the output directory.
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let target = output_dir.join(CACHE_FILENAME);
+        let tmp = output_dir.join(format!(
+            ".image-processor-metadata.tmp.{}",
+            std::process::id()
+        ));
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, &target)?;
+        Ok(())
+    }
+
+    /// Cached metadata of `path`, if the file still has the `fingerprint` it
+    /// had when it was read. The fingerprint is taken by the caller, so the
+    /// cache is not held while the card is read.
+    pub fn cached(&self, path: &Path, fingerprint: &Fingerprint) -> Option<&CachedMetadata> {
+        self.entries
+            .get(path)
+            .filter(|c| c.fingerprint == *fingerprint)
+    }
+
+    /// Paths of the files under `dir` the cache holds.
+    pub fn paths_under(&self, dir: &Path) -> Vec<PathBuf> {
+        self.entries
+            .keys()
+            .filter(|path| path.starts_with(dir))
+            .cloned()
+            .collect()
+    }
+
+    /// Forget the files at `paths`, which are gone.
+    pub fn remove(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            self.entries.remove(path);
+        }
+    }
+
+    /// Remember metadata read with [`read_metadata`] for `path`.
+    pub fn insert(&mut self, path: &Path, read: CachedMetadata) {
+        self.entries.insert(path.to_path_buf(), read);
+    }
+}
+
+/// Read the capture time and camera of `path`, from one parse of its
+/// metadata. `fingerprint` is taken before, so a file changing meanwhile is
+/// read again next time.
+pub fn read_metadata(path: &Path, fingerprint: Fingerprint) -> Result<CachedMetadata> {
+    let (recorded, date_source, camera) = metadata::extract_recorded_and_camera(path)?;
+    Ok(CachedMetadata {
+        fingerprint,
+        recorded,
+        date_source,
+        camera,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_invalidated_by_change() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let path = card.path().join("IMG_0001.CR2");
+        fs::write(&path, b"raw").unwrap();
+
+        let fingerprint = Fingerprint::of(&path).unwrap();
+        let mut cache = MetadataCache::load(out.path());
+        assert!(cache.cached(&path, &fingerprint).is_none());
+        let read = read_metadata(&path, fingerprint).unwrap();
+        assert_eq!(read.date_source, DateSource::FilesystemMtime);
+        cache.insert(&path, read.clone());
+        cache.save(out.path()).unwrap();
+
+        let mut cache = MetadataCache::load(out.path());
+        assert_eq!(cache.cached(&path, &fingerprint), Some(&read));
+        fs::write(&path, b"other raw").unwrap();
+        assert!(cache
+            .cached(&path, &Fingerprint::of(&path).unwrap())
+            .is_none());
+
+        assert_eq!(cache.paths_under(card.path()), [path.as_path()]);
+        assert!(cache.paths_under(out.path()).is_empty());
+        cache.remove(std::slice::from_ref(&path));
+        assert!(cache.paths_under(card.path()).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+//! `--progress-format ndjson`: every scan and transfer event as one JSON
+//! object per line on stdout, named by its `event` field, so a frontend can
+//! follow an import as it happens.
+
+use image_processor::session::Session;
+use image_processor::transfer::RenameReason;
+use image_processor::{ScanEvent, TransferEvent};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::Path;
+
+/// Print `line` on stdout. Lines from concurrent workers never interleave.
+pub fn emit(line: Value) {
+    let _ = write_line(&mut std::io::stdout().lock(), &line);
+}
+
+/// Write `line` to `out` as one line: JSON escapes the newlines of paths
+/// and error messages.
+fn write_line(out: &mut impl Write, line: &Value) -> std::io::Result<()> {
+    writeln!(out, "{}", line)
+}
+
+pub fn scan_started(input: &Path) {
+    emit(json!({ "event": "scan_started", "input": input }));
+}
+
+pub fn scan_event(event: &ScanEvent<'_>) {
+    emit(match event {
+        ScanEvent::Found { total } => json!({ "event": "scan_found", "total": total }),
+        ScanEvent::Dated { file } => json!({
+            "event": "metadata_read",
+            "path": file.path,
+            "datetime": file.datetime,
+            "date_source": file.date_source.to_string(),
+        }),
+        ScanEvent::Skipped { path, error } => json!({
+            "event": "metadata_failed",
+            "path": path,
+            "error": format!("{:#}", error),
+        }),
+    });
+}
+
+/// The sessions the scanned files were grouped into.
+pub fn sessions(sessions: &[Session]) {
+    let sessions: Vec<Value> = sessions
+        .iter()
+        .map(|s| json!({ "folder": s.folder_name, "files": s.files.len() }))
+        .collect();
+    emit(json!({ "event": "sessions", "sessions": sessions }));
+}
+
+pub fn transfer_event(event: &TransferEvent<'_>) {
+    if let Some(line) = transfer_line(event) {
+        emit(line);
+    }
+}
+
+/// The line describing `event`, if it is one a frontend follows.
+fn transfer_line(event: &TransferEvent<'_>) -> Option<Value> {
+    let line = match *event {
+        TransferEvent::Resuming { completed, total } => {
+            json!({ "event": "resuming", "completed": completed, "total": total })
+        }
+        TransferEvent::Started {
+            total_bytes,
+            done_bytes,
+            workers,
+        } => json!({
+            "event": "transfer_started",
+            "total_bytes": total_bytes,
+            "done_bytes": done_bytes,
+            "workers": workers,
+        }),
+        TransferEvent::FileStarted {
+            worker,
+            name,
+            bytes,
+        } => {
+            json!({ "event": "file_started", "worker": worker, "name": name, "bytes": bytes })
+        }
+        TransferEvent::Progress { worker, bytes } => {
+            json!({ "event": "progress", "worker": worker, "bytes": bytes })
+        }
+        TransferEvent::Copied {
+            worker,
+            source,
+            dest,
+            bytes,
+        } => {
+            json!({ "event": "copied", "worker": worker, "source": source, "dest": dest, "bytes": bytes })
+        }
+        TransferEvent::Verifying { worker, name } => {
+            json!({ "event": "verifying", "worker": worker, "name": name })
+        }
+        TransferEvent::ChecksumMismatch { source, dest } => {
+            json!({ "event": "checksum_mismatch", "source": source, "dest": dest })
+        }
+        TransferEvent::AlreadyCopied { source } => {
+            json!({ "event": "skipped", "source": source, "reason": "already_copied" })
+        }
+        TransferEvent::Duplicate { source, existing } => {
+            json!({ "event": "skipped", "source": source, "reason": "duplicate", "existing": existing })
+        }
+        TransferEvent::Conflict {
+            source,
+            existing,
+            dest,
+            resolution,
+        } => json!({
+            "event": "conflict",
+            "source": source,
+            "existing": existing,
+            "dest": dest,
+            "resolution": clap::ValueEnum::to_possible_value(&resolution).map(|v| v.get_name().to_string()),
+        }),
+        TransferEvent::MakingProxy { worker, name } => {
+            json!({ "event": "making_proxy", "worker": worker, "name": name })
+        }
+        TransferEvent::ProxyFailed { source, error } => {
+            json!({ "event": "proxy_failed", "source": source, "error": format!("{:#}", error) })
+        }
+        TransferEvent::Renamed {
+            source,
+            folder_name,
+            name,
+            reason,
+        } => {
+            let reason = match reason {
+                RenameReason::TooLong => "too_long",
+                RenameReason::CaseConflict => "case_conflict",
+            };
+            json!({ "event": "renamed", "source": source, "folder": folder_name, "name": name, "reason": reason })
+        }
+        TransferEvent::LowSpace {
+            root,
+            needed,
+            available,
+        } => {
+            json!({ "event": "low_space", "root": root, "needed": needed, "available": available })
+        }
+        TransferEvent::StateNotMirrored { dir, error } => {
+            json!({ "event": "state_not_mirrored", "dir": dir, "error": format!("{:#}", error) })
+        }
+        TransferEvent::RenderingPreviews { total } => {
+            json!({ "event": "rendering_previews", "total": total })
+        }
+        TransferEvent::PreviewFailed { source, error } => {
+            json!({ "event": "preview_failed", "source": source, "error": format!("{:#}", error) })
+        }
+        TransferEvent::WouldCopy { source, dest } => {
+            json!({ "event": "would_copy", "source": source, "dest": dest })
+        }
+        TransferEvent::FileFinished { worker, source } => {
+            json!({ "event": "file_done", "worker": worker, "source": source })
+        }
+        TransferEvent::Failed {
+            worker,
+            source,
+            error,
+        } => {
+            json!({ "event": "file_failed", "worker": worker, "source": source, "error": format!("{:#}", error) })
+        }
+        TransferEvent::Skipped { worker, source } => {
+            json!({ "event": "skipped", "worker": worker, "source": source, "reason": "by_user" })
+        }
+        TransferEvent::RetryingRead {
+            worker,
+            source,
+            offset,
+            attempt,
+            error,
+        } => json!({
+            "event": "read_retry",
+            "worker": worker,
+            "source": source,
+            "offset": offset,
+            "attempt": attempt,
+            "error": format!("{:#}", error),
+        }),
+        TransferEvent::Salvaged {
+            worker,
+            source,
+            damaged,
+        } => {
+            json!({ "event": "salvaged", "worker": worker, "source": source, "damaged": damaged })
+        }
+        TransferEvent::SourceChanged { source } => {
+            json!({ "event": "source_changed", "source": source })
+        }
+        TransferEvent::SessionFinished { folder_name } => {
+            json!({ "event": "session_done", "folder": folder_name })
+        }
+        TransferEvent::SessionFailed {
+            folder_name,
+            failed,
+        } => {
+            json!({ "event": "session_failed", "folder": folder_name, "failed": failed })
+        }
+        TransferEvent::WorkerFinished { worker } => {
+            json!({ "event": "worker_finished", "worker": worker })
+        }
+        TransferEvent::Finished => json!({ "event": "transfer_finished" }),
+        TransferEvent::StateCleanedUp => return None,
+    };
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_object_per_line() {
+        let error =
+            anyhow::anyhow!("Input/output error\nat block 3").context("Cannot read IMG_0001.CR2");
+        let events = [
+            TransferEvent::FileStarted {
+                worker: 0,
+                name: "2024-01-15/odd\nname.cr2",
+                bytes: 3,
+            },
+            TransferEvent::Failed {
+                worker: 1,
+                source: Path::new("card/IMG_0001.CR2"),
+                error: &error,
+            },
+            TransferEvent::StateCleanedUp,
+            TransferEvent::SessionFinished {
+                folder_name: "2024-01-15",
+            },
+            TransferEvent::Finished,
+        ];
+        let mut out = Vec::new();
+        for line in events.iter().filter_map(transfer_line) {
+            write_line(&mut out, &line).unwrap();
+        }
+
+        let out = String::from_utf8(out).unwrap();
+        let names: Vec<String> = out
+            .lines()
+            .map(|line| {
+                let value: Value = serde_json::from_str(line).unwrap();
+                value["event"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "file_started",
+                "file_failed",
+                "session_done",
+                "transfer_finished"
+            ]
+        );
+        assert!(out.ends_with('\n'));
+    }
+}
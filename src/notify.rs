@@ -0,0 +1,17 @@
+use crate::report::Report;
+use notify_rust::Notification;
+
+/// Show a desktop notification for a finished run: what was copied, or why
+/// it failed. Fails without a desktop session.
+pub fn notify(report: &Report, result: &anyhow::Result<()>) -> anyhow::Result<()> {
+    let (summary, body) = match result {
+        Ok(()) => ("Import finished", report.summary()),
+        Err(e) => ("Import failed", format!("{:#}\n{}", e, report.summary())),
+    };
+    Notification::new()
+        .appname("image-processor")
+        .summary(summary)
+        .body(&body)
+        .show()?;
+    Ok(())
+}
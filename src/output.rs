@@ -0,0 +1,109 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set when stdout is reserved for a machine-readable document, or with
+/// `--quiet`, so progress bars and informational lines are not printed.
+/// Warnings still go to stderr.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set when stdout is reserved for a machine-readable document, which the
+/// final summary would break.
+static MACHINE_READABLE: AtomicBool = AtomicBool::new(false);
+
+/// Set with `--progress-format ndjson`, see [`crate::ndjson`].
+static NDJSON: AtomicBool = AtomicBool::new(false);
+
+/// How progress is shown on the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// Progress bars and messages
+    Bars,
+    /// One JSON object per line on stdout for every event
+    Ndjson,
+}
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Reserve stdout for a machine-readable document: nothing else is printed
+/// on it.
+pub fn set_machine_readable() {
+    MACHINE_READABLE.store(true, Ordering::Relaxed);
+    set_quiet(true);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Report progress as `format`. NDJSON also silences the human output.
+pub fn set_progress_format(format: ProgressFormat) {
+    let ndjson = format == ProgressFormat::Ndjson;
+    NDJSON.store(ndjson, Ordering::Relaxed);
+    if ndjson {
+        set_machine_readable();
+    }
+}
+
+pub fn is_machine_readable() -> bool {
+    MACHINE_READABLE.load(Ordering::Relaxed)
+}
+
+pub fn is_ndjson() -> bool {
+    NDJSON.load(Ordering::Relaxed)
+}
+
+/// `println!` for informational output, silenced by [`set_quiet`].
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use say;
+
+/// `println!` for the final summary, printed even with `--quiet`.
+macro_rules! summary {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_machine_readable() {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use summary;
+
+/// Report a warning: on stderr, as a `warning` event with
+/// `--progress-format ndjson`, or in the TUI's error list. It is logged
+/// either way.
+pub fn warn(message: impl std::fmt::Display) {
+    log::warn!("{}", message);
+    if is_ndjson() {
+        crate::ndjson::emit(
+            serde_json::json!({ "event": "warning", "message": message.to_string() }),
+        );
+    } else if crate::tui::is_active() {
+        crate::tui::warning(message.to_string());
+    } else {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+/// A progress bar of `len` that is not drawn in quiet mode.
+pub fn progress_bar(len: u64) -> ProgressBar {
+    if is_quiet() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(len)
+    }
+}
+
+/// A set of progress bars that is not drawn in quiet mode.
+pub fn multi_progress() -> MultiProgress {
+    if is_quiet() {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// File attributes that can be carried over to the copy besides the
+/// modification time, which is always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Attribute {
+    /// Permission bits (only the read-only flag on Windows)
+    Mode,
+    /// Creation (birth) time, on macOS and Windows
+    Birthtime,
+    /// Extended attributes such as macOS Finder tags, on Linux and macOS
+    Xattrs,
+}
+
+/// Copy `attributes` of `src` onto `dest`. Attributes the platform cannot
+/// set are skipped. Permissions are applied last, as a read-only mode would
+/// prevent setting the others.
+pub fn preserve(src: &Path, dest: &Path, attributes: &[Attribute]) -> io::Result<()> {
+    let metadata = fs::metadata(src)?;
+    if attributes.contains(&Attribute::Xattrs) {
+        copy_xattrs(src, dest)?;
+    }
+    if attributes.contains(&Attribute::Birthtime) {
+        set_created(&metadata, dest)?;
+    }
+    if attributes.contains(&Attribute::Mode) {
+        fs::set_permissions(dest, metadata.permissions())?;
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "macos", windows))]
+fn set_created(metadata: &fs::Metadata, dest: &Path) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::FileTimesExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileTimesExt;
+
+    let times = fs::FileTimes::new().set_created(metadata.created()?);
+    fs::OpenOptions::new()
+        .write(true)
+        .open(dest)?
+        .set_times(times)
+}
+
+/// Linux has no call to set a file's birth time.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn set_created(_metadata: &fs::Metadata, _dest: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn copy_xattrs(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_cstring = |p: &Path| {
+        CString::new(p.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let src_c = to_cstring(src)?;
+    let dest_c = to_cstring(dest)?;
+
+    let names = read_sized(|buf, len| unsafe { xattr::list(src_c.as_ptr(), buf, len) })?;
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name_c =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let value = read_sized(|buf, len| unsafe {
+            xattr::get(src_c.as_ptr(), name_c.as_ptr(), buf, len)
+        })?;
+        let ret = unsafe {
+            xattr::set(
+                dest_c.as_ptr(),
+                name_c.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Call `read` once for the size, then again to fill a buffer of that size.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_sized(read: impl Fn(*mut libc::c_char, usize) -> libc::ssize_t) -> io::Result<Vec<u8>> {
+    loop {
+        let len = read(std::ptr::null_mut(), 0);
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; len as usize];
+        let read_len = read(buf.as_mut_ptr().cast(), buf.len());
+        if read_len >= 0 {
+            buf.truncate(read_len as usize);
+            return Ok(buf);
+        }
+        // The attribute grew between the two calls
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+/// The xattr calls, which take extra position and option arguments on macOS.
+#[cfg(target_os = "linux")]
+mod xattr {
+    use libc::{c_char, c_void, size_t, ssize_t};
+
+    pub unsafe fn list(path: *const c_char, buf: *mut c_char, len: size_t) -> ssize_t {
+        libc::listxattr(path, buf, len)
+    }
+
+    pub unsafe fn get(
+        path: *const c_char,
+        name: *const c_char,
+        buf: *mut c_char,
+        len: size_t,
+    ) -> ssize_t {
+        libc::getxattr(path, name, buf.cast::<c_void>(), len)
+    }
+
+    pub unsafe fn set(
+        path: *const c_char,
+        name: *const c_char,
+        value: *const c_void,
+        len: size_t,
+    ) -> i32 {
+        libc::setxattr(path, name, value, len, 0)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod xattr {
+    use libc::{c_char, c_void, size_t, ssize_t};
+
+    pub unsafe fn list(path: *const c_char, buf: *mut c_char, len: size_t) -> ssize_t {
+        libc::listxattr(path, buf, len, 0)
+    }
+
+    pub unsafe fn get(
+        path: *const c_char,
+        name: *const c_char,
+        buf: *mut c_char,
+        len: size_t,
+    ) -> ssize_t {
+        libc::getxattr(path, name, buf.cast::<c_void>(), len, 0, 0)
+    }
+
+    pub unsafe fn set(
+        path: *const c_char,
+        name: *const c_char,
+        value: *const c_void,
+        len: size_t,
+    ) -> i32 {
+        libc::setxattr(path, name, value, len, 0, 0)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn copy_xattrs(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_preserve_mode_and_xattrs() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dest) = (
+            dir.path().join("IMG_0001.CR2"),
+            dir.path().join("IMG_0001.cr2"),
+        );
+        fs::write(&src, b"raw").unwrap();
+        fs::write(&dest, b"raw").unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::set_permissions(&dest, fs::Permissions::from_mode(0o600)).unwrap();
+
+        preserve(&src, &dest, &[Attribute::Mode]).unwrap();
+        assert_eq!(
+            fs::metadata(&dest).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+
+        // Not every filesystem tests run on supports user xattrs
+        let src_c = std::ffi::CString::new(src.to_str().unwrap()).unwrap();
+        let name = c"user.tag";
+        let set = unsafe { xattr::set(src_c.as_ptr(), name.as_ptr(), b"Red".as_ptr().cast(), 3) };
+        if set == 0 {
+            preserve(&src, &dest, &[Attribute::Xattrs]).unwrap();
+            let dest_c = std::ffi::CString::new(dest.to_str().unwrap()).unwrap();
+            let value = read_sized(|buf, len| unsafe {
+                xattr::get(dest_c.as_ptr(), name.as_ptr(), buf, len)
+            })
+            .unwrap();
+            assert_eq!(value, b"Red");
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use crate::bmff;
+use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Subfolder of a session holding the JPEGs extracted with `--thumbnails`.
+pub const PREVIEWS_DIR: &str = "previews";
+
+/// Subfolder of a session holding the downscaled previews of `--previews`.
+pub const RENDERED_PREVIEWS_DIR: &str = "_previews";
+
+/// JPEG quality of rendered previews.
+const PREVIEW_QUALITY: u8 = 85;
+
+/// Raw formats whose embedded JPEG can be read.
+const RAW_WITH_PREVIEW: &[&str] = &["cr2", "cr3", "nef", "arw"];
+
+/// UUID of the CR3 box holding the `PRVW` preview JPEG.
+const CR3_PREVIEW_UUID: [u8; 16] = [
+    0xea, 0xf4, 0x2b, 0x5e, 0x1c, 0x98, 0x4b, 0x88, 0xb9, 0xfb, 0xb7, 0xdc, 0x40, 0x6e, 0x4d, 0x16,
+];
+
+/// Most IFDs followed in a TIFF-based raw, against loops in broken files.
+const MAX_IFDS: usize = 32;
+
+/// Byte range of the largest JPEG embedded in a raw file: for TIFF-based
+/// raws (CR2, NEF, ARW), the `StripOffsets`/`StripByteCounts` or
+/// `JPEGInterchangeFormat`/`JPEGInterchangeFormatLength` image of any IFD or
+/// SubIFD; for CR3, the `PRVW` box.
+pub fn embedded_jpeg(path: &Path) -> Result<Option<(u64, u64)>> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let range = if has_extension(path, &["cr3"]) {
+        cr3_preview(&mut file, size)?
+    } else {
+        tiff_largest_jpeg(&mut file, size)
+            .with_context(|| format!("{} is not a TIFF-based raw file", path.display()))?
+    };
+    Ok(range.filter(|&(offset, length)| length > 0 && offset + length <= size))
+}
+
+fn tiff_largest_jpeg(file: &mut File, size: u64) -> Result<Option<(u64, u64)>> {
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)?;
+    let little_endian = match &header[..4] {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => anyhow::bail!("unknown byte order"),
+    };
+    let u16_at = |b: &[u8]| {
+        let b = [b[0], b[1]];
+        if little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        }
+    };
+    let u32_at = |b: &[u8]| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        }
+    };
+
+    let mut pending = vec![u32_at(&header[4..]) as u64];
+    let mut visited = Vec::new();
+    let mut best: Option<(u64, u64)> = None;
+    while let Some(ifd) = pending.pop() {
+        if ifd == 0 || ifd + 2 > size || visited.contains(&ifd) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(ifd);
+
+        file.seek(SeekFrom::Start(ifd))?;
+        let mut count = [0u8; 2];
+        file.read_exact(&mut count)?;
+        let mut entries = vec![0u8; u16_at(&count) as usize * 12 + 4];
+        file.read_exact(&mut entries)?;
+        let (entries, next) = entries.split_at(entries.len() - 4);
+        pending.push(u32_at(next) as u64);
+
+        let (mut strip, mut strip_length) = (None, None);
+        let (mut jpeg, mut jpeg_length) = (None, None);
+        for entry in entries.chunks_exact(12) {
+            let (kind, count) = (u16_at(&entry[2..]), u32_at(&entry[4..]));
+            // SubIFDs: one offset inline, or several at the offset given
+            if u16_at(entry) == 0x014a && matches!(kind, 4 | 13) {
+                if count == 1 {
+                    pending.push(u32_at(&entry[8..]) as u64);
+                } else if count <= MAX_IFDS as u32 {
+                    file.seek(SeekFrom::Start(u32_at(&entry[8..]) as u64))?;
+                    let mut offsets = vec![0u8; count as usize * 4];
+                    file.read_exact(&mut offsets)?;
+                    pending.extend(offsets.chunks_exact(4).map(|o| u32_at(o) as u64));
+                }
+                continue;
+            }
+            // Otherwise only a single SHORT or LONG value, stored in the entry itself
+            if count != 1 {
+                continue;
+            }
+            let value = match kind {
+                3 => u16_at(&entry[8..]) as u64,
+                4 => u32_at(&entry[8..]) as u64,
+                _ => continue,
+            };
+            match u16_at(entry) {
+                0x0111 => strip = Some(value),
+                0x0117 => strip_length = Some(value),
+                0x0201 => jpeg = Some(value),
+                0x0202 => jpeg_length = Some(value),
+                _ => {}
+            }
+        }
+
+        for candidate in [strip.zip(strip_length), jpeg.zip(jpeg_length)]
+            .into_iter()
+            .flatten()
+        {
+            let (offset, length) = candidate;
+            if best.is_some_and(|(_, best_length)| best_length >= length) || offset + length > size
+            {
+                continue;
+            }
+            // Strips are only a JPEG when they start like one
+            let mut soi = [0u8; 2];
+            file.seek(SeekFrom::Start(offset))?;
+            if file.read_exact(&mut soi).is_ok() && soi == [0xff, 0xd8] {
+                best = Some(candidate);
+            }
+        }
+    }
+    Ok(best)
+}
+
+/// The JPEG of the `PRVW` box in a CR3's preview UUID box: a 16-byte header
+/// whose last four bytes are the JPEG's length, then the JPEG.
+fn cr3_preview(file: &mut File, size: u64) -> Result<Option<(u64, u64)>> {
+    let mut reader = BufReader::new(file);
+    for candidate in bmff::children(&mut reader, 0, size)? {
+        if &candidate.kind != b"uuid"
+            || candidate.end - candidate.start < 24
+            || bmff::read_at(&mut reader, candidate.start, 16)? != CR3_PREVIEW_UUID
+        {
+            continue;
+        }
+        // The UUID, then 8 bytes before the first child box
+        let Some(prvw) = bmff::find(&mut reader, candidate.start + 24, candidate.end, b"PRVW")?
+        else {
+            return Ok(None);
+        };
+        if prvw.end - prvw.start < 16 {
+            return Ok(None);
+        }
+        let header = bmff::read_at(&mut reader, prvw.start, 16)?;
+        let length = u32::from_be_bytes([header[12], header[13], header[14], header[15]]) as u64;
+        return Ok(Some((
+            prvw.start + 16,
+            length.min(prvw.end - prvw.start - 16),
+        )));
+    }
+    Ok(None)
+}
+
+/// The JPEG embedded in `raw`, if it is a raw format that has one.
+fn read_embedded_jpeg(raw: &Path) -> Result<Option<Vec<u8>>> {
+    if !has_extension(raw, RAW_WITH_PREVIEW) {
+        return Ok(None);
+    }
+    let Some((offset, length)) = embedded_jpeg(raw)? else {
+        return Ok(None);
+    };
+    let mut file = File::open(raw)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut jpeg = vec![0u8; length as usize];
+    file.read_exact(&mut jpeg)?;
+    Ok(jpeg.starts_with(&[0xff, 0xd8]).then_some(jpeg))
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// `dir/<stem of photo>.jpg`
+fn preview_path(photo: &Path, dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "{}.jpg",
+        photo.file_stem().unwrap_or_default().to_string_lossy()
+    ))
+}
+
+/// The photos among `photos` to render with [`render_preview`]: all but a
+/// raw shot together with a JPEG in the same folder, whose preview would
+/// have the JPEG's name. The JPEG is the picture the camera developed, so it
+/// is the one rendered.
+pub fn preview_sources(photos: &[PathBuf]) -> Vec<&Path> {
+    let is_jpeg = |photo: &Path| has_extension(photo, &["jpg", "jpeg"]);
+    let shot = |photo: &Path| preview_path(photo, photo.parent().unwrap_or(Path::new("")));
+    let jpegs: HashSet<PathBuf> = photos
+        .iter()
+        .filter(|photo| is_jpeg(photo))
+        .map(|photo| shot(photo))
+        .collect();
+    photos
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|photo| is_jpeg(photo) || !jpegs.contains(&shot(photo)))
+        .collect()
+}
+
+/// Write the largest JPEG embedded in `raw` to `dir/<stem>.jpg`, creating
+/// `dir` if needed. Returns the path written, or `None` if `raw` is not a
+/// CR2, CR3, NEF or ARW or embeds no JPEG, or if the preview already exists:
+/// a file of that name is left alone.
+pub fn extract_thumbnail(raw: &Path, dir: &Path) -> Result<Option<PathBuf>> {
+    let dest = preview_path(raw, dir);
+    if dest.exists() {
+        return Ok(None);
+    }
+    let Some(jpeg) = read_embedded_jpeg(raw)? else {
+        return Ok(None);
+    };
+    fs::create_dir_all(dir)?;
+    fs::write(&dest, jpeg).with_context(|| format!("Cannot write {}", dest.display()))?;
+    Ok(Some(dest))
+}
+
+/// Render a JPEG of `photo` no larger than `max_size` pixels on its long
+/// edge to `dir/<stem>.jpg`, creating `dir` if needed. JPEGs are decoded
+/// directly and raws (CR2, CR3, NEF, ARW) through their embedded JPEG;
+/// videos are skipped (`None`), as are photos whose preview already exists.
+/// Other raws, and raws without a usable embedded JPEG, are an error: no
+/// raw decoder is built in.
+pub fn render_preview(photo: &Path, dir: &Path, max_size: u32) -> Result<Option<PathBuf>> {
+    let dest = preview_path(photo, dir);
+    if has_extension(photo, &["mp4", "mov"]) || dest.exists() {
+        return Ok(None);
+    }
+    let image = if has_extension(photo, &["jpg", "jpeg"]) {
+        image::open(photo)?
+    } else {
+        let jpeg = read_embedded_jpeg(photo)?
+            .ok_or_else(|| anyhow::anyhow!("no embedded JPEG to render a preview from"))?;
+        image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg)?
+    };
+    let image = if image.width().max(image.height()) > max_size {
+        image.resize(max_size, max_size, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    fs::create_dir_all(dir)?;
+    let file = File::create(&dest).with_context(|| format!("Cannot write {}", dest.display()))?;
+    let encoder = JpegEncoder::new_with_quality(BufWriter::new(file), PREVIEW_QUALITY);
+    image.to_rgb8().write_with_encoder(encoder)?;
+    Ok(Some(dest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_cr2_jpeg() {
+        // IFD0 with StripOffsets (LONG) and StripByteCounts (SHORT), then the JPEG
+        let jpeg = [0xff, 0xd8, 0xff, 0xd9];
+        let mut data = b"II*\0".to_vec();
+        data.extend_from_slice(&8u32.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        for (tag, kind, value) in [(0x0111u16, 4u16, 38u32), (0x0117, 3, jpeg.len() as u32)] {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&kind.to_le_bytes());
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&jpeg);
+
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("IMG_0001.cr2");
+        fs::write(&raw, &data).unwrap();
+        let previews = dir.path().join(PREVIEWS_DIR);
+        let written = extract_thumbnail(&raw, &previews).unwrap().unwrap();
+        assert_eq!(written, previews.join("IMG_0001.jpg"));
+        assert_eq!(fs::read(&written).unwrap(), jpeg);
+        // A preview already there is not the import's to write
+        fs::write(&written, b"mine").unwrap();
+        assert_eq!(extract_thumbnail(&raw, &previews).unwrap(), None);
+        assert_eq!(fs::read(&written).unwrap(), b"mine");
+
+        let video = dir.path().join("MVI_0002.mp4");
+        fs::write(&video, &data).unwrap();
+        assert_eq!(extract_thumbnail(&video, &previews).unwrap(), None);
+    }
+
+    #[test]
+    fn test_largest_jpeg_in_subifds() {
+        // Big-endian IFD0 with a small JPEG strip and a SubIFD, whose
+        // JPEGInterchangeFormat image is larger
+        let mut data = b"MM\0*".to_vec();
+        data.extend_from_slice(&8u32.to_be_bytes());
+        let mut ifd = |entries: &[(u16, u16, u32)]| {
+            data.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+            for &(tag, kind, value) in entries {
+                data.extend_from_slice(&tag.to_be_bytes());
+                data.extend_from_slice(&kind.to_be_bytes());
+                data.extend_from_slice(&1u32.to_be_bytes());
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            data.extend_from_slice(&0u32.to_be_bytes());
+        };
+        ifd(&[(0x0111, 4, 80), (0x0117, 4, 4), (0x014a, 4, 50)]);
+        ifd(&[(0x0201, 4, 84), (0x0202, 4, 6)]);
+        data.extend_from_slice(&[0xff, 0xd8, 0xff, 0xd9]);
+        data.extend_from_slice(&[0xff, 0xd8, 0, 0, 0xff, 0xd9]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("DSC_0001.NEF");
+        fs::write(&raw, &data).unwrap();
+        assert_eq!(embedded_jpeg(&raw).unwrap(), Some((84, 6)));
+    }
+
+    #[test]
+    fn test_extract_cr3_preview() {
+        let jpeg = [0xff, 0xd8, 0xff, 0xd9];
+        let mut prvw = vec![0u8; 12];
+        prvw.extend_from_slice(&(jpeg.len() as u32).to_be_bytes());
+        prvw.extend_from_slice(&jpeg);
+        let mut payload = CR3_PREVIEW_UUID.to_vec();
+        payload.extend_from_slice(&[0; 8]);
+        payload.extend_from_slice(&((prvw.len() + 8) as u32).to_be_bytes());
+        payload.extend_from_slice(b"PRVW");
+        payload.extend_from_slice(&prvw);
+        let mut data = 16u32.to_be_bytes().to_vec();
+        data.extend_from_slice(b"ftypcrx \0\0\0\x01");
+        data.extend_from_slice(&((payload.len() + 8) as u32).to_be_bytes());
+        data.extend_from_slice(b"uuid");
+        data.extend_from_slice(&payload);
+
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("IMG_0001.CR3");
+        fs::write(&raw, &data).unwrap();
+        let previews = dir.path().join(PREVIEWS_DIR);
+        let written = extract_thumbnail(&raw, &previews).unwrap().unwrap();
+        assert_eq!(fs::read(&written).unwrap(), jpeg);
+    }
+
+    #[test]
+    fn test_render_preview_downscales() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("IMG_0001.JPG");
+        image::RgbImage::new(400, 300).save(&photo).unwrap();
+
+        let previews = dir.path().join(RENDERED_PREVIEWS_DIR);
+        let written = render_preview(&photo, &previews, 100).unwrap().unwrap();
+        assert_eq!(written, previews.join("IMG_0001.jpg"));
+        assert_eq!(image::image_dimensions(&written).unwrap(), (100, 75));
+        assert_eq!(render_preview(&photo, &previews, 100).unwrap(), None);
+
+        let raw = dir.path().join("IMG_0002.CR3");
+        fs::write(&raw, b"raw").unwrap();
+        assert!(render_preview(&raw, &previews, 100).is_err());
+    }
+
+    #[test]
+    fn test_raw_with_jpeg_not_rendered() {
+        let photos: Vec<PathBuf> = [
+            "a/IMG_0001.cr2",
+            "a/IMG_0001.jpg",
+            "a/IMG_0002.cr2",
+            "b/IMG_0001.cr2",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        let sources = preview_sources(&photos);
+        assert_eq!(
+            sources,
+            [
+                Path::new("a/IMG_0001.jpg"),
+                Path::new("a/IMG_0002.cr2"),
+                Path::new("b/IMG_0001.cr2")
+            ]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::output::{self, say};
+use image_processor::transfer::{OnConflict, RenameReason};
+use image_processor::TransferEvent;
+use indicatif::{DecimalBytes, MultiProgress, ProgressBar, ProgressStyle};
+use std::sync::OnceLock;
+
+/// Terminal rendering of a transfer: an overall byte bar plus one bar per
+/// worker showing the file it is on. Files skipped as duplicates or
+/// conflicts are listed with `-v`.
+pub struct TransferBars {
+    multi: MultiProgress,
+    bars: OnceLock<Bars>,
+    verbose: u8,
+}
+
+struct Bars {
+    total: ProgressBar,
+    workers: Vec<ProgressBar>,
+}
+
+impl TransferBars {
+    pub fn new(verbose: u8) -> Self {
+        Self {
+            multi: output::multi_progress(),
+            bars: OnceLock::new(),
+            verbose,
+        }
+    }
+
+    pub fn handle(&self, event: TransferEvent<'_>) {
+        match event {
+            TransferEvent::Resuming { completed, total } => {
+                say!(
+                    "Resuming transfer: {}/{} files already copied",
+                    completed,
+                    total
+                );
+            }
+            TransferEvent::Started {
+                total_bytes,
+                done_bytes,
+                workers,
+            } => {
+                let total = self.multi.add(ProgressBar::new(total_bytes));
+                total.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{wide_bar} {percent}% {bytes}/{total_bytes} [{eta}]")
+                        .unwrap()
+                        .progress_chars("=> "),
+                );
+                total.set_position(done_bytes);
+                let file_style = ProgressStyle::default_bar()
+                    .template("{wide_msg} {bytes}/{total_bytes}")
+                    .unwrap();
+                let workers = (0..workers)
+                    .map(|_| {
+                        let bar = self.multi.insert_before(&total, ProgressBar::new(0));
+                        bar.set_style(file_style.clone());
+                        bar
+                    })
+                    .collect();
+                let _ = self.bars.set(Bars { total, workers });
+            }
+            TransferEvent::FileStarted {
+                worker,
+                name,
+                bytes,
+            } => {
+                if let Some(bar) = self.worker(worker) {
+                    bar.reset();
+                    bar.set_length(bytes);
+                    bar.set_message(name.to_string());
+                }
+            }
+            TransferEvent::Progress { worker, bytes } => {
+                if let Some(bars) = self.bars.get() {
+                    bars.total.inc(bytes);
+                    bars.workers[worker].inc(bytes);
+                }
+            }
+            TransferEvent::Copied { .. } => {}
+            TransferEvent::Verifying { worker, name } => {
+                if let Some(bar) = self.worker(worker) {
+                    bar.set_message(format!("{} (verifying)", name));
+                }
+            }
+            TransferEvent::ChecksumMismatch { source, dest } => {
+                self.multi.suspend(|| {
+                    eprintln!(
+                        "Error: checksum mismatch after copying {} to {}",
+                        source.display(),
+                        dest.display()
+                    );
+                });
+            }
+            TransferEvent::Duplicate { source, existing } if self.verbose > 0 => {
+                self.multi.suspend(|| {
+                    say!(
+                        "Skipping {}: already in the library as {}",
+                        source.display(),
+                        existing.display()
+                    );
+                });
+            }
+            TransferEvent::Conflict {
+                source,
+                existing,
+                dest,
+                resolution,
+            } if self.verbose > 0 => {
+                let action = match resolution {
+                    OnConflict::Skip => "skipping".to_string(),
+                    OnConflict::Overwrite => "overwriting it".to_string(),
+                    OnConflict::Rename => format!("copying as {}", dest.display()),
+                    OnConflict::Ask => "would ask".to_string(),
+                };
+                self.multi.suspend(|| {
+                    say!(
+                        "{} already exists for {}, {}",
+                        existing.display(),
+                        source.display(),
+                        action
+                    );
+                });
+            }
+            TransferEvent::Duplicate { .. } | TransferEvent::Conflict { .. } => {}
+            TransferEvent::MakingProxy { worker, name } => {
+                if let Some(bar) = self.worker(worker) {
+                    bar.set_message(format!("{} (making proxy)", name));
+                }
+            }
+            TransferEvent::ProxyFailed { source, error } => {
+                self.multi.suspend(|| {
+                    eprintln!(
+                        "Warning: could not make a proxy of {}: {:#}",
+                        source.display(),
+                        error
+                    );
+                });
+            }
+            TransferEvent::RenderingPreviews { total } => say!("Rendering {} previews...", total),
+            TransferEvent::PreviewFailed { source, error } => {
+                self.multi.suspend(|| {
+                    eprintln!(
+                        "Warning: could not write a preview of {}: {:#}",
+                        source.display(),
+                        error
+                    );
+                });
+            }
+            TransferEvent::Renamed {
+                source,
+                folder_name,
+                name,
+                reason,
+            } => {
+                let why = match reason {
+                    RenameReason::TooLong => {
+                        "is too long for the destination filesystem".to_string()
+                    }
+                    RenameReason::CaseConflict => {
+                        format!(
+                            "conflicts with another file in {} (case-insensitive)",
+                            folder_name
+                        )
+                    }
+                };
+                self.multi.suspend(|| {
+                    eprintln!("Warning: {} {}, copying as {}", source.display(), why, name);
+                });
+            }
+            TransferEvent::LowSpace {
+                root,
+                needed,
+                available,
+            } => {
+                self.multi.suspend(|| {
+                    eprintln!(
+                        "Warning: not enough space: {} needed in {} but only {} is available, starting anyway",
+                        DecimalBytes(needed),
+                        root.display(),
+                        DecimalBytes(available)
+                    );
+                });
+            }
+            TransferEvent::RetryingRead { attempt, error, .. } => {
+                self.multi.suspend(|| {
+                    eprintln!("Warning: {:#}, retrying ({})", error, attempt);
+                });
+            }
+            TransferEvent::Salvaged {
+                source, damaged, ..
+            } => {
+                let lost: u64 = damaged.iter().map(|range| range.end - range.start).sum();
+                self.multi.suspend(|| {
+                    eprintln!(
+                        "Warning: {} of {} could not be read and were written as zeros",
+                        DecimalBytes(lost),
+                        source.display()
+                    );
+                });
+            }
+            TransferEvent::SourceChanged { source } => {
+                self.multi.suspend(|| {
+                    eprintln!(
+                        "Warning: {} changed since it was copied, copying it again",
+                        source.display()
+                    );
+                });
+            }
+            TransferEvent::StateNotMirrored { dir, error } => {
+                self.multi.suspend(|| {
+                    eprintln!(
+                        "Warning: could not write state to {}: {:#}",
+                        dir.display(),
+                        error
+                    );
+                });
+            }
+            // Summarized by the dry-run report once planning is done
+            TransferEvent::WouldCopy { .. } | TransferEvent::AlreadyCopied { .. } => {}
+            // Reported with the other failures once the run ends
+            TransferEvent::Failed { .. } => {}
+            TransferEvent::Skipped { source, .. } => {
+                self.multi.suspend(|| {
+                    say!("Skipped {}, it is copied by the next run", source.display());
+                });
+            }
+            // Each failed file is reported on its own
+            TransferEvent::FileFinished { .. }
+            | TransferEvent::SessionFinished { .. }
+            | TransferEvent::SessionFailed { .. } => {}
+            TransferEvent::WorkerFinished { worker } => {
+                if let Some(bar) = self.worker(worker) {
+                    bar.finish_and_clear();
+                }
+            }
+            TransferEvent::Finished => {
+                if let Some(bars) = self.bars.get() {
+                    bars.total.finish_and_clear();
+                }
+                say!("Transfer complete");
+            }
+            TransferEvent::StateCleanedUp => say!("State files cleaned up."),
+        }
+    }
+
+    fn worker(&self, worker: usize) -> Option<&ProgressBar> {
+        self.bars.get().map(|bars| &bars.workers[worker])
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Subfolder of a session holding the proxies made with `--proxy`.
+pub const PROXIES_DIR: &str = "proxies";
+
+/// Command a proxy is made with unless `--proxy-command` replaces it:
+/// 540p H.264 at a low quality setting, with compressed audio.
+pub const DEFAULT_PROXY_COMMAND: &str = "ffmpeg -nostdin -y -loglevel error -i {input} \
+     -vf scale=-2:540 -c:v libx264 -preset veryfast -crf 28 -c:a aac -b:a 96k {output}";
+
+/// Whether `path` is a video a proxy is made for.
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mp4") || ext.eq_ignore_ascii_case("mov"))
+}
+
+/// Make a proxy of `video` as `dir/<stem>.mp4` by running `template`, split
+/// on whitespace, with `{input}` and `{output}` replaced by the two paths.
+/// The command runs without a shell. Returns the proxy written, or `None`
+/// if a file of its name already exists, which is left alone.
+pub fn make_proxy(template: &str, video: &Path, dir: &Path) -> Result<Option<PathBuf>> {
+    let output = dir.join(format!(
+        "{}.mp4",
+        video.file_stem().unwrap_or_default().to_string_lossy()
+    ));
+    if output.exists() {
+        return Ok(None);
+    }
+    fs::create_dir_all(dir)?;
+    let args: Vec<String> = template
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{input}", &video.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy())
+        })
+        .collect();
+    let (program, args) = args.split_first().context("The proxy command is empty")?;
+
+    let result = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Cannot run {}", program));
+    let failure = match result {
+        Ok(run) if run.status.success() && output.is_file() => return Ok(Some(output)),
+        Ok(run) if run.status.success() => {
+            anyhow::anyhow!("{} did not write {}", program, output.display())
+        }
+        Ok(run) => {
+            let stderr = String::from_utf8_lossy(&run.stderr);
+            anyhow::anyhow!("{} failed ({}): {}", program, run.status, stderr.trim())
+        }
+        Err(e) => e,
+    };
+    // Leave no half-written proxy behind
+    let _ = fs::remove_file(&output);
+    Err(failure)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_proxy_runs_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let video = dir.path().join("MVI_0001.MOV");
+        fs::write(&video, b"video").unwrap();
+        let proxies = dir.path().join(PROXIES_DIR);
+
+        let proxy = make_proxy("cp {input} {output}", &video, &proxies)
+            .unwrap()
+            .unwrap();
+        assert_eq!(proxy, proxies.join("MVI_0001.mp4"));
+        assert_eq!(fs::read(&proxy).unwrap(), b"video");
+        assert_eq!(
+            make_proxy("sh -c false {input} {output}", &video, &proxies).unwrap(),
+            None
+        );
+        assert!(proxy.exists());
+
+        fs::remove_file(&proxy).unwrap();
+        let error = make_proxy("sh -c false {input} {output}", &video, &proxies).unwrap_err();
+        assert!(error.to_string().starts_with("sh failed"), "{}", error);
+        assert!(!proxy.exists());
+    }
+}
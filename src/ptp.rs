@@ -0,0 +1,277 @@
+//! Import from a camera that exposes its storage over PTP/MTP rather than as
+//! a mounted filesystem. The camera's files are downloaded with the
+//! `gphoto2` command line tool into a staging folder, which is then scanned
+//! and imported like a card.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Prefix of an `--input` naming a connected camera instead of a folder.
+pub const INPUT_PREFIX: &str = "ptp:";
+
+/// Folder of an output the camera's files are downloaded to before they are
+/// imported. Hidden, so it is never taken for part of the library.
+pub const STAGING_DIR: &str = ".image-processor-camera";
+
+/// A file on a camera's storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraFile {
+    /// Folder on the camera, e.g. `/store_00020001/DCIM/100CANON`
+    pub folder: String,
+    /// Number of the file in its folder, as the camera lists it
+    pub number: u32,
+    pub name: String,
+    pub modified: Option<SystemTime>,
+}
+
+/// Storage whose files can be listed and downloaded without being mounted.
+pub trait Source {
+    fn list(&self) -> Result<Vec<CameraFile>>;
+    /// Download `file` to `dest`, replacing anything there.
+    fn download(&self, file: &CameraFile, dest: &Path) -> Result<()>;
+}
+
+/// A camera reached through `gphoto2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gphoto2 {
+    /// gphoto2 port, e.g. `usb:001,004`; the first camera found without one
+    pub port: Option<String>,
+}
+
+impl Gphoto2 {
+    /// The camera an `--input` names: `ptp:` for the first one connected or
+    /// `ptp:<port>` for a given one, e.g. `ptp:usb:001,004`.
+    pub fn from_input(input: &Path) -> Option<Self> {
+        let port = input.to_str()?.strip_prefix(INPUT_PREFIX)?;
+        Some(Self {
+            port: (!port.is_empty()).then(|| port.to_string()),
+        })
+    }
+
+    /// Folder name the camera's files are staged under.
+    pub fn staging_name(&self) -> String {
+        match &self.port {
+            Some(port) => port.replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+            None => "camera".to_string(),
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String> {
+        let mut command = Command::new("gphoto2");
+        if let Some(port) = &self.port {
+            command.args(["--port", port]);
+        }
+        let run = command
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .context("Cannot run gphoto2, which importing from a camera over PTP/MTP needs")?;
+        if !run.status.success() {
+            let stderr = String::from_utf8_lossy(&run.stderr);
+            anyhow::bail!("gphoto2 failed ({}): {}", run.status, stderr.trim());
+        }
+        Ok(String::from_utf8_lossy(&run.stdout).into_owned())
+    }
+}
+
+impl Source for Gphoto2 {
+    fn list(&self) -> Result<Vec<CameraFile>> {
+        Ok(parse_listing(&self.run(&["--list-files"])?))
+    }
+
+    fn download(&self, file: &CameraFile, dest: &Path) -> Result<()> {
+        let number = file.number.to_string();
+        let dest = dest
+            .to_str()
+            .context("The staging folder is not valid UTF-8")?;
+        self.run(&[
+            "--folder",
+            &file.folder,
+            "--get-file",
+            &number,
+            "--filename",
+            dest,
+            "--force-overwrite",
+        ])?;
+        Ok(())
+    }
+}
+
+/// Files in the output of `gphoto2 --list-files`, where each folder's files
+/// follow a `There are N files in folder '<folder>':` line as
+/// `#<number> <name> <permissions> <size> <unit> [<resolution>] <type> [<mtime>]`.
+pub fn parse_listing(listing: &str) -> Vec<CameraFile> {
+    let mut files = Vec::new();
+    let mut folder = None;
+    for line in listing.lines() {
+        if let Some(rest) = line.strip_prefix("There ") {
+            folder = rest
+                .split_once(" folder '")
+                .and_then(|(_, f)| f.rsplit_once('\''))
+                .map(|(f, _)| f.to_string());
+            continue;
+        }
+        let (Some(folder), Some(entry)) = (&folder, line.strip_prefix('#')) else {
+            continue;
+        };
+        let fields: Vec<&str> = entry.split_whitespace().collect();
+        let (Some(number), Some(name)) =
+            (fields.first().and_then(|n| n.parse().ok()), fields.get(1))
+        else {
+            continue;
+        };
+        let modified = fields
+            .last()
+            .filter(|_| fields.len() > 5)
+            .and_then(|t| t.parse().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        files.push(CameraFile {
+            folder: folder.clone(),
+            number,
+            name: name.to_string(),
+            modified,
+        });
+    }
+    files
+}
+
+/// Download every file of `source` into `dir` under the camera's folders,
+/// with the camera's modified times. Files already there from an earlier
+/// attempt are kept. `on_file` is called before each download.
+pub fn stage(source: &impl Source, dir: &Path, mut on_file: impl FnMut(&CameraFile)) -> Result<()> {
+    for file in source.list()? {
+        let relative = Path::new(file.folder.trim_start_matches('/')).join(&file.name);
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            log::warn!(
+                "Skipping camera file {}/{}: not a plain path",
+                file.folder,
+                file.name
+            );
+            continue;
+        }
+        let dest = dir.join(relative);
+        let staged = fs::metadata(&dest)
+            .and_then(|meta| meta.modified())
+            .is_ok_and(|modified| file.modified.is_none_or(|m| m == modified));
+        if staged {
+            continue;
+        }
+        on_file(&file);
+        fs::create_dir_all(dest.parent().unwrap_or(dir))?;
+        // Downloaded under another name so an interrupted download is not
+        // taken for a staged file
+        let part = PathBuf::from(format!("{}.part", dest.display()));
+        source.download(&file, &part).with_context(|| {
+            format!(
+                "Failed to download {}/{} from the camera",
+                file.folder, file.name
+            )
+        })?;
+        if let Some(modified) = file.modified {
+            fs::File::options()
+                .write(true)
+                .open(&part)?
+                .set_modified(modified)?;
+        }
+        fs::rename(&part, &dest)?;
+        log::info!(
+            "Downloaded {}/{} to {}",
+            file.folder,
+            file.name,
+            dest.display()
+        );
+    }
+    Ok(())
+}
+
+/// Whether `input` is a staging folder [`stage`] downloaded a camera to.
+pub fn is_staged(input: &Path) -> bool {
+    input
+        .parent()
+        .and_then(Path::file_name)
+        .is_some_and(|name| name == STAGING_DIR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    const LISTING: &str = "\
+There is no file in folder '/'.
+There is no file in folder '/store_00020001/DCIM'.
+There are 2 files in folder '/store_00020001/DCIM/100CANON':
+#1     IMG_0001.CR2               rd 25413 KB image/x-canon-cr2 1705312800
+#2     IMG_0001.JPG               rd  6543 KB 6000x4000 image/jpeg 1705312801
+There is 1 file in folder '/store_00020001/DCIM/101CANON':
+#1     MVI_0002.MP4               rd 91234 KB video/mp4
+";
+
+    struct FakeCamera {
+        downloads: Cell<usize>,
+    }
+
+    impl Source for FakeCamera {
+        fn list(&self) -> Result<Vec<CameraFile>> {
+            Ok(parse_listing(LISTING))
+        }
+
+        fn download(&self, file: &CameraFile, dest: &Path) -> Result<()> {
+            self.downloads.set(self.downloads.get() + 1);
+            fs::write(dest, &file.name)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_camera_staged_once() {
+        let files = parse_listing(LISTING);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[1].folder, "/store_00020001/DCIM/100CANON");
+        assert_eq!(
+            (files[1].number, files[1].name.as_str()),
+            (2, "IMG_0001.JPG")
+        );
+        assert_eq!(
+            files[1].modified,
+            Some(UNIX_EPOCH + Duration::from_secs(1705312801))
+        );
+        assert_eq!(files[2].modified, None);
+        assert_eq!(
+            Gphoto2::from_input(Path::new("ptp:")),
+            Some(Gphoto2 { port: None })
+        );
+        assert_eq!(
+            Gphoto2::from_input(Path::new("ptp:usb:001,004"))
+                .unwrap()
+                .staging_name(),
+            "usb_001_004"
+        );
+        assert_eq!(Gphoto2::from_input(Path::new("/Volumes/EOS_DIGITAL")), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let camera = FakeCamera {
+            downloads: Cell::new(0),
+        };
+        stage(&camera, dir.path(), |_| {}).unwrap();
+        let raw = dir.path().join("store_00020001/DCIM/100CANON/IMG_0001.CR2");
+        assert_eq!(fs::read(&raw).unwrap(), b"IMG_0001.CR2");
+        assert_eq!(
+            fs::metadata(&raw).unwrap().modified().unwrap(),
+            files[0].modified.unwrap()
+        );
+        assert!(dir
+            .path()
+            .join("store_00020001/DCIM/101CANON/MVI_0002.MP4")
+            .is_file());
+
+        stage(&camera, dir.path(), |_| {}).unwrap();
+        assert_eq!(camera.downloads.get(), 3);
+    }
+}
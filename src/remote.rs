@@ -0,0 +1,613 @@
+//! Destinations outside the local filesystem, such as
+//! `--output sftp://user@nas/photos` or `--output s3://bucket/photos`. A
+//! [`Destination`] offers the few operations a transfer needs; [`LocalDir`]
+//! implements them on a local folder, [`Ssh`] on a remote machine reached
+//! with the `ssh` command and [`S3`] on object storage through the `aws`
+//! command.
+
+use crate::checksum::{self, HashAlgo};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// Schemes of an output on a machine reached over SSH.
+const SSH_SCHEMES: &[&str] = &["sftp://", "ssh://"];
+
+const S3_SCHEME: &str = "s3://";
+
+/// Exit status of the remote command reading a file that does not exist.
+const MISSING_STATUS: i32 = 3;
+
+/// A place files are written to, addressed by paths relative to its root.
+pub trait Destination: Sync {
+    /// The destination as given, for messages.
+    fn describe(&self) -> String;
+    /// Create `dir` and every missing folder above it.
+    fn create_dir_all(&self, dir: &Path) -> Result<()>;
+    fn exists(&self, path: &Path) -> Result<bool>;
+    /// Every file under `dir`, relative to it, in one request rather than
+    /// one [`exists`](Destination::exists) per file. Empty if `dir` does
+    /// not exist.
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>>;
+    /// Write all of `data` to `path`, replacing what is there, and give it
+    /// the `modified` time. Returns the number of bytes written.
+    fn write(&self, path: &Path, data: &mut dyn Read, modified: Option<SystemTime>) -> Result<u64>;
+    /// Whether a file written only shows once it is complete, so it needs
+    /// no part file renamed into place.
+    fn writes_in_place(&self) -> bool {
+        false
+    }
+    /// Rename `from` to `to`, replacing `to`.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+    /// Contents of `path`, if it exists.
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>>;
+    /// Remove `path` if it exists.
+    fn remove(&self, path: &Path) -> Result<()>;
+    /// SHA-256 of `path`, as [`checksum::hash_file`] computes it.
+    fn hash(&self, path: &Path) -> Result<String>;
+}
+
+/// Whether `output` names a destination outside the local filesystem.
+pub fn is_remote(output: &Path) -> bool {
+    output.to_str().is_some_and(|output| {
+        SSH_SCHEMES
+            .iter()
+            .chain([&S3_SCHEME])
+            .any(|scheme| output.starts_with(scheme))
+    })
+}
+
+/// The remote destination `output` names, or none for a local folder.
+pub fn open(output: &Path) -> Result<Option<Box<dyn Destination>>> {
+    if !is_remote(output) {
+        return Ok(None);
+    }
+    let url = output.to_str().unwrap_or_default();
+    if url.starts_with(S3_SCHEME) {
+        return Ok(Some(Box::new(S3::parse(url)?)));
+    }
+    Ok(Some(Box::new(Ssh::parse(url)?)))
+}
+
+/// A folder on the local filesystem.
+#[derive(Debug, Clone)]
+pub struct LocalDir {
+    pub root: PathBuf,
+}
+
+impl Destination for LocalDir {
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        Ok(fs::create_dir_all(self.root.join(dir))?)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        Ok(self.root.join(path).try_exists()?)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let dir = self.root.join(dir);
+        if !dir.try_exists()? {
+            return Ok(Vec::new());
+        }
+        let mut files = Vec::new();
+        for entry in WalkDir::new(&dir) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                files.push(entry.path().strip_prefix(&dir)?.to_path_buf());
+            }
+        }
+        Ok(files)
+    }
+
+    fn write(&self, path: &Path, data: &mut dyn Read, modified: Option<SystemTime>) -> Result<u64> {
+        let mut file = fs::File::create(self.root.join(path))?;
+        let written = io::copy(data, &mut file)?;
+        file.flush()?;
+        if let Some(modified) = modified {
+            file.set_modified(modified)?;
+        }
+        Ok(written)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        Ok(fs::rename(self.root.join(from), self.root.join(to))?)
+    }
+
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(path)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        match fs::remove_file(self.root.join(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn hash(&self, path: &Path) -> Result<String> {
+        checksum::hash_file(&self.root.join(path), HashAlgo::Sha256)
+    }
+}
+
+/// A folder on a machine reached with the system's `ssh` command, so keys,
+/// agents and `~/.ssh/config` apply as usual. Every operation runs a POSIX
+/// shell command there; one connection is shared by all of them where the
+/// `ssh` command supports it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ssh {
+    /// `[user@]host`
+    host: String,
+    port: Option<u16>,
+    /// Folder on the remote machine, relative to the home folder unless it
+    /// starts with `/`
+    root: String,
+}
+
+impl Ssh {
+    /// Parse `sftp://[user@]host[:port]/path`. The path is absolute, or
+    /// relative to the remote home folder when it starts with `/~/`.
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = SSH_SCHEMES
+            .iter()
+            .find_map(|scheme| url.strip_prefix(scheme))
+            .with_context(|| format!("Not an sftp:// or ssh:// URL: {}", url))?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .with_context(|| format!("Invalid port in {}", url))?;
+                (host, Some(port))
+            }
+            None => (authority, None),
+        };
+        let host_name = host.rsplit('@').next().unwrap_or_default();
+        if host_name.is_empty() || host.starts_with('-') {
+            anyhow::bail!("No host in {}", url);
+        }
+        let root = match path.strip_prefix('~') {
+            Some(home) => home.trim_start_matches('/').to_string(),
+            None => format!("/{}", path),
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            root: root.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// `path` under the root, quoted for the remote shell.
+    fn quoted(&self, path: &Path) -> String {
+        let full = match (self.root.as_str(), path.to_string_lossy()) {
+            ("", path) => path.into_owned(),
+            (root, path) if path.is_empty() => root.to_string(),
+            (root, path) => format!("{}/{}", root, path),
+        };
+        quote(if full.is_empty() { "." } else { &full })
+    }
+
+    fn command(&self, script: &str) -> Command {
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            command.args(["-p", &port.to_string()]);
+        }
+        if cfg!(unix) {
+            let control = std::env::temp_dir().join("image-processor-ssh-%C");
+            command
+                .args(["-o", "ControlMaster=auto", "-o", "ControlPersist=60", "-o"])
+                .arg(format!("ControlPath={}", control.display()));
+        }
+        command.arg(&self.host).arg("--").arg(script);
+        command
+    }
+
+    /// Run `script` on the remote machine, failing with its error output.
+    fn run(&self, script: &str) -> Result<Output> {
+        let output = run(self.command(script))?;
+        check(&self.host, &output)?;
+        Ok(output)
+    }
+}
+
+/// Run `command` to completion with its output captured.
+fn run(mut command: Command) -> Result<Output> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    command
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Cannot run {}, which a remote output needs", program))
+}
+
+/// Run `command` with `data` on its standard input, failing with its error
+/// output. Returns the number of bytes written to it.
+fn pipe_into(mut command: Command, data: &mut dyn Read, target: &str) -> Result<u64> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Cannot run {}, which a remote output needs", program))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let copied = io::copy(data, &mut stdin);
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    // A failed read of the source is what the caller has to hear about
+    let copied = copied?;
+    check(target, &output)?;
+    Ok(copied)
+}
+
+/// Fail with the error output of a remote command that did not succeed.
+fn check(target: &str, output: &Output) -> Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::bail!(
+        "Command on {} failed ({}): {}",
+        target,
+        output.status,
+        stderr.trim()
+    )
+}
+
+/// `s` in single quotes for a POSIX shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+impl Destination for Ssh {
+    fn describe(&self) -> String {
+        format!("{}:{}", self.host, self.root)
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        self.run(&format!("mkdir -p {}", self.quoted(dir)))?;
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        let output = self.run(&format!(
+            "if [ -e {} ]; then echo yes; fi",
+            self.quoted(path)
+        ))?;
+        Ok(output.stdout.starts_with(b"yes"))
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let dir = self.quoted(dir);
+        let output = self.run(&format!(
+            "if [ -d {} ]; then cd {} && find . -type f -print0; fi",
+            dir, dir
+        ))?;
+        Ok(output
+            .stdout
+            .split(|&b| b == 0)
+            .filter_map(|path| path.strip_prefix(b"./"))
+            .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+            .collect())
+    }
+
+    fn write(&self, path: &Path, data: &mut dyn Read, modified: Option<SystemTime>) -> Result<u64> {
+        let path = self.quoted(path);
+        let mut script = format!("cat > {}", path);
+        if let Some(modified) = modified {
+            let stamp = DateTime::<Utc>::from(modified).format("%Y%m%d%H%M.%S");
+            script.push_str(&format!(" && TZ=UTC0 touch -m -t {} {}", stamp, path));
+        }
+        pipe_into(self.command(&script), data, &self.host)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.run(&format!("mv -f {} {}", self.quoted(from), self.quoted(to)))?;
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        let path = self.quoted(path);
+        let output = run(self.command(&format!(
+            "if [ -e {} ]; then cat {}; else exit {}; fi",
+            path, path, MISSING_STATUS
+        )))?;
+        if output.status.code() == Some(MISSING_STATUS) {
+            return Ok(None);
+        }
+        check(&self.host, &output)?;
+        Ok(Some(output.stdout))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.run(&format!("rm -f {}", self.quoted(path)))?;
+        Ok(())
+    }
+
+    fn hash(&self, path: &Path) -> Result<String> {
+        let path = self.quoted(path);
+        let output = self.run(&format!(
+            "sha256sum {} 2>/dev/null || shasum -a 256 {}",
+            path, path
+        ))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout
+            .split_whitespace()
+            .next()
+            .map(str::to_string)
+            .with_context(|| format!("No checksum of {} from {}", path, self.host))
+    }
+}
+
+/// A bucket of S3-compatible object storage, reached with the `aws`
+/// command, so its credentials, profiles and `AWS_ENDPOINT_URL` apply.
+/// Session folders are key prefixes, and each file is one object, uploaded
+/// in parts when it is large, that shows once complete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3 {
+    bucket: String,
+    /// Prefix of every key, without a trailing `/`
+    prefix: String,
+}
+
+impl S3 {
+    /// Parse `s3://bucket[/prefix]`.
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix(S3_SCHEME)
+            .with_context(|| format!("Not an s3:// URL: {}", url))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() || bucket.starts_with('-') {
+            anyhow::bail!("No bucket in {}", url);
+        }
+        Ok(Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+
+    fn key(&self, path: &Path) -> String {
+        let path = path.to_string_lossy().replace('\\', "/");
+        match (self.prefix.as_str(), path.as_str()) {
+            ("", path) => path.to_string(),
+            (prefix, "") => prefix.to_string(),
+            (prefix, path) => format!("{}/{}", prefix, path),
+        }
+    }
+
+    /// Prefix of the keys of the objects under `dir`.
+    fn dir_prefix(&self, dir: &Path) -> String {
+        match self.key(dir) {
+            key if key.is_empty() => key,
+            key => key + "/",
+        }
+    }
+
+    fn uri(&self, path: &Path) -> String {
+        format!("{}{}/{}", S3_SCHEME, self.bucket, self.key(path))
+    }
+
+    fn aws(&self, args: &[&str]) -> Command {
+        let mut command = Command::new("aws");
+        command.args(args);
+        command
+    }
+
+    /// Run `aws` with `args`, failing with its error output.
+    fn run(&self, args: &[&str]) -> Result<Output> {
+        let output = run(self.aws(args))?;
+        check(&self.describe(), &output)?;
+        Ok(output)
+    }
+}
+
+impl Destination for S3 {
+    fn describe(&self) -> String {
+        self.uri(Path::new(""))
+    }
+
+    /// Prefixes need no creating.
+    fn create_dir_all(&self, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        let key = self.key(path);
+        let output = run(self.aws(&[
+            "s3api",
+            "head-object",
+            "--bucket",
+            &self.bucket,
+            "--key",
+            &key,
+        ]))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() && (stderr.contains("Not Found") || stderr.contains("404")) {
+            return Ok(false);
+        }
+        check(&self.describe(), &output)?;
+        Ok(true)
+    }
+
+    /// Lists the keys under `dir`'s prefix, which the `aws` command fetches
+    /// page by page.
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let prefix = self.dir_prefix(dir);
+        let output = self.run(&[
+            "s3api",
+            "list-objects-v2",
+            "--bucket",
+            &self.bucket,
+            "--prefix",
+            &prefix,
+            "--query",
+            "Contents[].Key",
+            "--output",
+            "json",
+        ])?;
+        let keys: Option<Vec<String>> = match output.stdout.trim_ascii() {
+            b"" => None,
+            json => serde_json::from_slice(json)
+                .with_context(|| format!("Cannot list {}", self.uri(dir)))?,
+        };
+        Ok(keys
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    /// Streams `data` to `aws s3 cp`, which uploads it in parts; the modified
+    /// time is kept as the object's `mtime` metadata, in seconds since 1970.
+    fn write(&self, path: &Path, data: &mut dyn Read, modified: Option<SystemTime>) -> Result<u64> {
+        let uri = self.uri(path);
+        let mut command = self.aws(&["s3", "cp", "--only-show-errors", "-", &uri]);
+        if let Some(modified) = modified {
+            let secs = modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            command.args(["--metadata", &format!("mtime={}", secs)]);
+        }
+        pipe_into(command, data, &self.describe())
+    }
+
+    fn writes_in_place(&self) -> bool {
+        true
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.run(&[
+            "s3",
+            "mv",
+            "--only-show-errors",
+            &self.uri(from),
+            &self.uri(to),
+        ])?;
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        if !self.exists(path)? {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.run(&["s3", "cp", "--only-show-errors", &self.uri(path), "-"])?
+                .stdout,
+        ))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.run(&["s3", "rm", "--only-show-errors", &self.uri(path)])?;
+        Ok(())
+    }
+
+    /// Downloads the object to hash it, as S3's own checksums of objects
+    /// uploaded in parts are not checksums of their content.
+    fn hash(&self, path: &Path) -> Result<String> {
+        let mut child = self
+            .aws(&["s3", "cp", "--only-show-errors", &self.uri(path), "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Cannot run aws, which an s3:// output needs")?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut hasher = checksum::Hasher::new(HashAlgo::Sha256);
+        let mut buf = vec![0; 256 * 1024];
+        let read = loop {
+            match stdout.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(n) => hasher.update(&buf[..n]),
+                Err(e) => break Err(e),
+            }
+        };
+        drop(stdout);
+        let output = child.wait_with_output()?;
+        check(&self.describe(), &output)?;
+        read?;
+        Ok(hasher.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_local_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("2024-01-15/RAW")).unwrap();
+        fs::write(dir.path().join("2024-01-15/a.jpg"), b"a").unwrap();
+        fs::write(dir.path().join("2024-01-15/RAW/a.cr2"), b"a").unwrap();
+        let dest = LocalDir {
+            root: dir.path().to_path_buf(),
+        };
+        let mut files = dest.list(Path::new("2024-01-15")).unwrap();
+        files.sort();
+        assert_eq!(files, [Path::new("RAW/a.cr2"), Path::new("a.jpg")]);
+        assert!(dest.list(Path::new("2024-01-16")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_s3_url() {
+        let bucket = S3::parse("s3://archive/photos/").unwrap();
+        assert_eq!(
+            bucket.key(Path::new("2024-01-15/IMG_0001.cr2")),
+            "photos/2024-01-15/IMG_0001.cr2"
+        );
+        assert_eq!(bucket.describe(), "s3://archive/photos");
+        assert_eq!(
+            bucket.dir_prefix(Path::new("2024-01-15")),
+            "photos/2024-01-15/"
+        );
+        assert_eq!(bucket.dir_prefix(Path::new("")), "photos/");
+        let root = S3::parse("s3://archive").unwrap();
+        assert_eq!(root.dir_prefix(Path::new("")), "");
+        assert_eq!(
+            root.uri(Path::new(".image-processor-state.json")),
+            "s3://archive/.image-processor-state.json"
+        );
+        assert!(S3::parse("s3:///photos").is_err());
+    }
+
+    #[test]
+    fn test_parse_ssh_url() {
+        let nas = Ssh::parse("sftp://me@nas:2222/volume1/photos/").unwrap();
+        assert_eq!(nas.host, "me@nas");
+        assert_eq!(nas.port, Some(2222));
+        assert_eq!(
+            nas.quoted(Path::new("2024-01-15/IMG_0001.cr2")),
+            "'/volume1/photos/2024-01-15/IMG_0001.cr2'"
+        );
+
+        let home = Ssh::parse("ssh://nas/~/Pictures").unwrap();
+        assert_eq!(
+            home.quoted(Path::new("it's.jpg")),
+            r"'Pictures/it'\''s.jpg'"
+        );
+        assert_eq!(
+            Ssh::parse("sftp://nas/~").unwrap().quoted(Path::new("")),
+            "'.'"
+        );
+
+        assert!(Ssh::parse("sftp:///photos").is_err());
+        assert!(Ssh::parse("sftp://-oProxyCommand=x/photos").is_err());
+        assert!(is_remote(Path::new("sftp://nas/photos")));
+        assert!(is_remote(Path::new("s3://bucket/photos")));
+        assert!(!is_remote(Path::new("/mnt/nas/photos")));
+    }
+}
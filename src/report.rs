@@ -0,0 +1,801 @@
+use chrono::NaiveDateTime;
+use image_processor::duplicates::UnmatchedFile;
+use image_processor::remote;
+use image_processor::transfer::OnConflict;
+use image_processor::{Session, TransferEvent};
+use indicatif::{DecimalBytes, HumanDuration};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Written to each destination of a run in which files failed, listing them.
+pub const ERRORS_FILENAME: &str = "errors.json";
+
+/// Result of a scan or import, printed as JSON with `--json` and posted to
+/// `--webhook-url`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// The first of `inputs`, as reports named their one input before
+    pub input: PathBuf,
+    pub inputs: Vec<PathBuf>,
+    pub dry_run: bool,
+    pub sessions: Vec<SessionReport>,
+    /// Files left out because their date could not be read
+    pub unreadable: Vec<FileError>,
+    /// With `merge-cards`, files that are on only one of the two cards
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<UnmatchedFile>,
+    /// The session gap `--gap auto` picked, in hours
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gap_hours: Option<f64>,
+    pub destinations: Vec<DestinationReport>,
+    /// What an import came to across its destinations, set by
+    /// [`Report::finish`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totals: Option<Totals>,
+    /// Why the run stopped, if it failed
+    pub error: Option<String>,
+    /// How long the run took, set by [`Report::finish`]
+    pub duration_secs: f64,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self {
+            input: PathBuf::new(),
+            inputs: Vec::new(),
+            dry_run: false,
+            sessions: Vec::new(),
+            unreadable: Vec::new(),
+            unmatched: Vec::new(),
+            gap_hours: None,
+            destinations: Vec::new(),
+            totals: None,
+            error: None,
+            duration_secs: 0.0,
+            started: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionReport {
+    pub folder: String,
+    pub bytes: u64,
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub datetime: NaiveDateTime,
+    pub date_source: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileError {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DestinationReport {
+    pub output: PathBuf,
+    pub copied_files: usize,
+    pub copied_bytes: u64,
+    pub files: Vec<FileResult>,
+    /// Files whose transfer failed with an error
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<FileError>,
+    /// Files copied with `--salvage` with parts that could not be read
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub salvaged: Vec<SalvagedFile>,
+    /// How fast files were written, absent when none were
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throughput: Option<Throughput>,
+}
+
+/// A file copied with zeros where it could not be read.
+#[derive(Debug, Serialize)]
+pub struct SalvagedFile {
+    pub path: PathBuf,
+    /// Byte ranges written as zeros
+    pub damaged: Vec<Range<u64>>,
+}
+
+/// How fast a destination was written.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Throughput {
+    /// Seconds from the start of the copy to its end
+    pub secs: f64,
+    pub average_bytes_per_sec: f64,
+    /// Most bytes written in one second, never less than the average
+    pub peak_bytes_per_sec: f64,
+}
+
+/// Files of an import by outcome.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Tally {
+    /// Copied, or that would be on a dry run
+    pub copied_files: usize,
+    pub copied_bytes: u64,
+    /// Already in the library, or left alone for an existing destination
+    pub skipped_files: usize,
+    /// Failed to copy or to verify
+    pub failed_files: usize,
+}
+
+impl Tally {
+    fn add(&mut self, status: FileStatus, bytes: u64) {
+        match status {
+            FileStatus::Copied | FileStatus::WouldCopy => {
+                self.copied_files += 1;
+                self.copied_bytes += bytes;
+            }
+            FileStatus::Duplicate | FileStatus::ConflictSkipped => self.skipped_files += 1,
+            FileStatus::ChecksumMismatch => self.failed_files += 1,
+        }
+    }
+}
+
+/// What an import came to, overall and session by session.
+#[derive(Debug, Serialize)]
+pub struct Totals {
+    #[serde(flatten)]
+    pub files: Tally,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throughput: Option<Throughput>,
+    pub sessions: Vec<SessionTotals>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionTotals {
+    pub folder: String,
+    #[serde(flatten)]
+    pub files: Tally,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileResult {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub status: FileStatus,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Copied,
+    /// Dry run: would have been copied
+    WouldCopy,
+    /// Already in the library (`destination` is the existing file)
+    Duplicate,
+    /// Left alone because the destination already existed
+    ConflictSkipped,
+    /// Copied, but the destination did not match the source
+    ChecksumMismatch,
+}
+
+/// A file the run could not bring over, as listed at the end of the run and
+/// in [`ERRORS_FILENAME`].
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub stage: Stage,
+    pub error: String,
+}
+
+/// Where a file failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Its date could not be read, so it was left out
+    Metadata,
+    /// Copying it failed
+    Copy,
+    /// Its copy did not match it
+    Verify,
+    /// It was copied with `--salvage`, but with parts it could not read
+    Salvage,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Stage::Metadata => "metadata",
+            Stage::Copy => "copy",
+            Stage::Verify => "verify",
+            Stage::Salvage => "salvage",
+        })
+    }
+}
+
+/// Error of a run that went through but left some files out, which exits
+/// with a status of its own.
+#[derive(Debug)]
+pub struct FilesFailed(pub usize);
+
+impl fmt::Display for FilesFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} file(s) failed, see the list above", self.0)
+    }
+}
+
+impl std::error::Error for FilesFailed {}
+
+/// Describe `sessions` and their files.
+pub fn session_reports(sessions: &[Session]) -> Vec<SessionReport> {
+    sessions
+        .iter()
+        .map(|session| {
+            let files: Vec<FileReport> = session
+                .files
+                .iter()
+                .map(|file| FileReport {
+                    path: file.path.clone(),
+                    datetime: file.datetime,
+                    date_source: file.date_source.to_string(),
+                    bytes: file.total_size(),
+                })
+                .collect();
+            SessionReport {
+                folder: session.folder_name.clone(),
+                bytes: files.iter().map(|f| f.bytes).sum(),
+                files,
+            }
+        })
+        .collect()
+}
+
+impl Report {
+    pub fn add_sessions(&mut self, sessions: &[Session]) {
+        self.sessions = session_reports(sessions);
+    }
+
+    /// One line summing up an import across its destinations, or what a
+    /// scan found.
+    pub fn summary(&self) -> String {
+        let mut unreadable = match self.unreadable.len() {
+            0 => String::new(),
+            n => format!(", {} without a readable date", n),
+        };
+        if !self.unmatched.is_empty() {
+            unreadable.push_str(&format!(", {} on only one card", self.unmatched.len()));
+        }
+        if self.destinations.is_empty() {
+            let files: usize = self.sessions.iter().map(|s| s.files.len()).sum();
+            return format!(
+                "Found {} files in {} session(s){}",
+                files,
+                self.sessions.len(),
+                unreadable
+            );
+        }
+        let results = self.destinations.iter().flat_map(|d| &d.files);
+        let count = |status: FileStatus| results.clone().filter(|f| f.status == status).count();
+        let (copied, bytes) = if self.dry_run {
+            let would_copy = results
+                .clone()
+                .filter(|f| f.status == FileStatus::WouldCopy);
+            (
+                would_copy.clone().count(),
+                would_copy.map(|f| f.bytes).sum(),
+            )
+        } else {
+            let copied = self
+                .destinations
+                .iter()
+                .map(|d| (d.copied_files, d.copied_bytes));
+            copied.fold((0, 0), |(files, bytes), (f, b)| (files + f, bytes + b))
+        };
+        let mut summary = format!(
+            "{} {} files ({}) in {} session(s)",
+            if self.dry_run { "Would copy" } else { "Copied" },
+            copied,
+            DecimalBytes(bytes),
+            self.sessions.len()
+        );
+        let skipped = count(FileStatus::Duplicate) + count(FileStatus::ConflictSkipped);
+        if skipped > 0 {
+            summary.push_str(&format!(", {} skipped", skipped));
+        }
+        let mismatched = count(FileStatus::ChecksumMismatch);
+        if mismatched > 0 {
+            summary.push_str(&format!(", {} failed verification", mismatched));
+        }
+        let failed: usize = self.destinations.iter().map(|d| d.failed.len()).sum();
+        if failed > 0 {
+            summary.push_str(&format!(", {} failed", failed));
+        }
+        summary + &unreadable
+    }
+
+    /// Add up the files of every destination, per session too.
+    pub fn totals(&self) -> Totals {
+        let mut sessions: Vec<SessionTotals> = self
+            .sessions
+            .iter()
+            .map(|session| SessionTotals {
+                folder: session.folder.clone(),
+                files: Tally::default(),
+            })
+            .collect();
+        let session_of: HashMap<&Path, usize> = self
+            .sessions
+            .iter()
+            .enumerate()
+            .flat_map(|(i, session)| {
+                session
+                    .files
+                    .iter()
+                    .map(move |file| (file.path.as_path(), i))
+            })
+            .collect();
+        let mut files = Tally::default();
+        for destination in &self.destinations {
+            let results = destination
+                .files
+                .iter()
+                .map(|file| (&file.source, file.status, file.bytes));
+            // A file that failed to copy counts as failed, like one that did not verify
+            let failed = destination
+                .failed
+                .iter()
+                .map(|file| (&file.path, FileStatus::ChecksumMismatch, 0));
+            for (source, status, bytes) in results.chain(failed) {
+                files.add(status, bytes);
+                if let Some(&i) = session_of.get(source.as_path()) {
+                    sessions[i].files.add(status, bytes);
+                }
+            }
+        }
+        let rates: Vec<Throughput> = self
+            .destinations
+            .iter()
+            .filter_map(|d| d.throughput)
+            .collect();
+        let secs: f64 = rates.iter().map(|rate| rate.secs).sum();
+        let throughput = (secs > 0.0).then(|| Throughput {
+            secs,
+            average_bytes_per_sec: rates
+                .iter()
+                .map(|rate| rate.average_bytes_per_sec * rate.secs)
+                .sum::<f64>()
+                / secs,
+            peak_bytes_per_sec: rates
+                .iter()
+                .map(|rate| rate.peak_bytes_per_sec)
+                .fold(0.0, f64::max),
+        });
+        Totals {
+            files,
+            throughput,
+            sessions,
+        }
+    }
+
+    /// Lines detailing an import after its summary: each session's files,
+    /// then how long the run took and how fast it copied. Nothing for a scan
+    /// or a dry run.
+    pub fn details(&self) -> Vec<String> {
+        if self.destinations.is_empty() || self.dry_run {
+            return Vec::new();
+        }
+        let totals = self.totals();
+        let width = totals
+            .sessions
+            .iter()
+            .map(|s| s.folder.len())
+            .max()
+            .unwrap_or(0);
+        let mut lines: Vec<String> = totals
+            .sessions
+            .iter()
+            .map(|session| {
+                let files = &session.files;
+                format!(
+                    "  {:<width$}  {} copied ({}), {} skipped, {} failed",
+                    session.folder,
+                    files.copied_files,
+                    DecimalBytes(files.copied_bytes),
+                    files.skipped_files,
+                    files.failed_files,
+                    width = width
+                )
+            })
+            .collect();
+        let elapsed = self.started.elapsed();
+        let mut took = if elapsed < Duration::from_secs(60) {
+            format!("Took {:.1} seconds", elapsed.as_secs_f64())
+        } else {
+            format!("Took {}", HumanDuration(elapsed))
+        };
+        if let Some(rate) = totals.throughput {
+            took.push_str(&format!(
+                ", copying at {}/s on average, {}/s at peak",
+                DecimalBytes(rate.average_bytes_per_sec as u64),
+                DecimalBytes(rate.peak_bytes_per_sec as u64)
+            ));
+        }
+        lines.push(took);
+        lines
+    }
+
+    /// Record how the run ended: `result` as its error, the time since the
+    /// report was created as its duration and, for an import, its totals.
+    pub fn finish(&mut self, result: &anyhow::Result<()>) {
+        if let Err(e) = result {
+            self.error = Some(format!("{:#}", e));
+        }
+        self.duration_secs = self.started.elapsed().as_secs_f64();
+        if !self.destinations.is_empty() {
+            self.totals = Some(self.totals());
+        }
+    }
+
+    /// Every file that failed, in the order the run came upon them.
+    pub fn failures(&self) -> Vec<Failure> {
+        let unreadable = self.unreadable.iter().map(|file| Failure {
+            path: file.path.clone(),
+            stage: Stage::Metadata,
+            error: file.error.clone(),
+        });
+        let transferred = self.destinations.iter().flat_map(|destination| {
+            let failed = destination.failed.iter().map(|file| Failure {
+                path: file.path.clone(),
+                stage: Stage::Copy,
+                error: file.error.clone(),
+            });
+            let mismatched = destination
+                .files
+                .iter()
+                .filter(|file| file.status == FileStatus::ChecksumMismatch)
+                .map(|file| Failure {
+                    path: file.source.clone(),
+                    stage: Stage::Verify,
+                    error: format!("{} does not match it", file.destination.display()),
+                });
+            let salvaged = destination.salvaged.iter().map(|file| {
+                let ranges: Vec<String> = file
+                    .damaged
+                    .iter()
+                    .map(|range| format!("{}-{}", range.start, range.end))
+                    .collect();
+                Failure {
+                    path: file.path.clone(),
+                    stage: Stage::Salvage,
+                    error: format!(
+                        "bytes {} could not be read and are zeros in the copy",
+                        ranges.join(", ")
+                    ),
+                }
+            });
+            failed.chain(mismatched).chain(salvaged)
+        });
+        unreadable.chain(transferred).collect()
+    }
+
+    /// Write `failures` to [`ERRORS_FILENAME`] in every destination, or
+    /// remove the list an earlier run left there if nothing failed. A dry
+    /// run writes nothing.
+    pub fn write_errors(&self, failures: &[Failure]) -> anyhow::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        // A remote output is left alone, the list is on stderr
+        for destination in self
+            .destinations
+            .iter()
+            .filter(|d| !remote::is_remote(&d.output))
+        {
+            let path = destination.output.join(ERRORS_FILENAME);
+            if failures.is_empty() {
+                match std::fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            } else {
+                let json = serde_json::json!({ "inputs": self.inputs, "failures": failures });
+                std::fs::write(&path, serde_json::to_string_pretty(&json)? + "\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the report as one line of JSON on stdout.
+    pub fn print(&self) -> anyhow::Result<()> {
+        println!("{}", serde_json::to_string(self)?);
+        Ok(())
+    }
+}
+
+/// Collects per-file results from the events of one destination's transfer.
+#[derive(Debug, Default)]
+pub struct TransferLog {
+    files: Mutex<Vec<FileResult>>,
+    failed: Mutex<Vec<FileError>>,
+    salvaged: Mutex<Vec<SalvagedFile>>,
+    rate: Mutex<RateLog>,
+}
+
+/// Bytes written in each second of a transfer.
+#[derive(Debug, Default)]
+struct RateLog {
+    started: Option<Instant>,
+    seconds: Vec<u64>,
+}
+
+impl RateLog {
+    fn add(&mut self, bytes: u64) {
+        let Some(started) = self.started else {
+            return;
+        };
+        let second = started.elapsed().as_secs() as usize;
+        if self.seconds.len() <= second {
+            self.seconds.resize(second + 1, 0);
+        }
+        self.seconds[second] += bytes;
+    }
+
+    /// The rate of the transfer until now. The peak is taken over the
+    /// seconds already over, so a transfer shorter than one second peaks at
+    /// its average.
+    fn throughput(&self) -> Option<Throughput> {
+        let secs = self.started?.elapsed().as_secs_f64();
+        let written: u64 = self.seconds.iter().sum();
+        if written == 0 || secs <= 0.0 {
+            return None;
+        }
+        let average = written as f64 / secs;
+        let over = self.seconds.len().min(secs as usize);
+        let peak = self.seconds[..over].iter().copied().max().unwrap_or(0) as f64;
+        Some(Throughput {
+            secs,
+            average_bytes_per_sec: average,
+            peak_bytes_per_sec: peak.max(average),
+        })
+    }
+}
+
+impl TransferLog {
+    pub fn handle(&self, event: &TransferEvent<'_>) {
+        let (source, destination, status, bytes) = match *event {
+            TransferEvent::Started { .. } => {
+                self.rate.lock().unwrap().started = Some(Instant::now());
+                return;
+            }
+            TransferEvent::Progress { bytes, .. } => {
+                self.rate.lock().unwrap().add(bytes);
+                return;
+            }
+            TransferEvent::Salvaged {
+                source, damaged, ..
+            } => {
+                self.salvaged.lock().unwrap().push(SalvagedFile {
+                    path: source.to_path_buf(),
+                    damaged: damaged.to_vec(),
+                });
+                return;
+            }
+            TransferEvent::Copied {
+                source,
+                dest,
+                bytes,
+                ..
+            } => (source, dest, FileStatus::Copied, bytes),
+            TransferEvent::WouldCopy { source, dest } => {
+                (source, dest, FileStatus::WouldCopy, file_size(source))
+            }
+            TransferEvent::Duplicate { source, existing } => {
+                (source, existing, FileStatus::Duplicate, 0)
+            }
+            TransferEvent::Conflict {
+                source,
+                existing,
+                resolution: OnConflict::Skip | OnConflict::Ask,
+                ..
+            } => (source, existing, FileStatus::ConflictSkipped, 0),
+            TransferEvent::ChecksumMismatch { dest, .. } => {
+                let mut files = self.files.lock().unwrap();
+                if let Some(file) = files.iter_mut().rev().find(|f| f.destination == dest) {
+                    file.status = FileStatus::ChecksumMismatch;
+                }
+                return;
+            }
+            TransferEvent::Failed { source, error, .. } => {
+                // The outermost context names the file, which is listed anyway
+                let causes: Vec<String> = error
+                    .chain()
+                    .skip(1)
+                    .map(|cause| cause.to_string())
+                    .collect();
+                self.failed.lock().unwrap().push(FileError {
+                    path: source.to_path_buf(),
+                    error: causes.join(": "),
+                });
+                return;
+            }
+            _ => return,
+        };
+        self.files.lock().unwrap().push(FileResult {
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            status,
+            bytes,
+        });
+    }
+
+    pub fn into_report(self, output: &Path) -> DestinationReport {
+        let files = self.files.into_inner().unwrap();
+        let copied = files.iter().filter(|f| f.status == FileStatus::Copied);
+        DestinationReport {
+            output: output.to_path_buf(),
+            copied_files: copied.clone().count(),
+            copied_bytes: copied.map(|f| f.bytes).sum(),
+            files,
+            failed: self.failed.into_inner().unwrap(),
+            salvaged: self.salvaged.into_inner().unwrap(),
+            throughput: self.rate.into_inner().unwrap().throughput(),
+        }
+    }
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_log_results() {
+        let log = TransferLog::default();
+        let (a, b) = (
+            Path::new("/card/IMG_0001.CR2"),
+            Path::new("/card/IMG_0002.CR2"),
+        );
+        let (dest_a, dest_b) = (
+            Path::new("/out/IMG_0001.cr2"),
+            Path::new("/out/IMG_0002.cr2"),
+        );
+        log.handle(&TransferEvent::Started {
+            total_bytes: 20,
+            done_bytes: 0,
+            workers: 1,
+        });
+        for (source, dest) in [(a, dest_a), (b, dest_b)] {
+            log.handle(&TransferEvent::Progress {
+                worker: 0,
+                bytes: 10,
+            });
+            log.handle(&TransferEvent::Copied {
+                worker: 0,
+                source,
+                dest,
+                bytes: 10,
+            });
+        }
+        log.handle(&TransferEvent::ChecksumMismatch {
+            source: b,
+            dest: dest_b,
+        });
+        log.handle(&TransferEvent::Finished);
+
+        let report = log.into_report(Path::new("/out"));
+        assert_eq!(report.copied_files, 1);
+        assert_eq!(report.copied_bytes, 10);
+        let statuses: Vec<FileStatus> = report.files.iter().map(|f| f.status).collect();
+        assert_eq!(statuses, [FileStatus::Copied, FileStatus::ChecksumMismatch]);
+        let rate = report.throughput.unwrap();
+        assert!(
+            rate.average_bytes_per_sec > 0.0
+                && rate.peak_bytes_per_sec >= rate.average_bytes_per_sec
+        );
+
+        let report = Report {
+            sessions: vec![SessionReport {
+                folder: "2024-01-15".to_string(),
+                bytes: 10,
+                files: vec![FileReport {
+                    path: a.to_path_buf(),
+                    datetime: NaiveDateTime::default(),
+                    date_source: "exif".to_string(),
+                    bytes: 10,
+                }],
+            }],
+            destinations: vec![report],
+            ..Report::default()
+        };
+        assert_eq!(
+            report.summary(),
+            "Copied 1 files (10 B) in 1 session(s), 1 failed verification"
+        );
+        let totals = report.totals();
+        assert_eq!(
+            (totals.files.copied_files, totals.files.failed_files),
+            (1, 1)
+        );
+        assert_eq!(totals.sessions[0].files.copied_bytes, 10);
+        assert_eq!(totals.sessions[0].files.failed_files, 0);
+        assert!(
+            report.details()[0].starts_with("  2024-01-15  1 copied (10 B), 0 skipped, 0 failed")
+        );
+    }
+
+    #[test]
+    fn test_failures_listed_and_written() {
+        let out = tempfile::tempdir().unwrap();
+        let log = TransferLog::default();
+        let (a, b) = (
+            Path::new("/card/IMG_0001.CR2"),
+            Path::new("/card/IMG_0002.CR2"),
+        );
+        let dest_a = out.path().join("IMG_0001.cr2");
+        log.handle(&TransferEvent::Copied {
+            worker: 0,
+            source: a,
+            dest: &dest_a,
+            bytes: 10,
+        });
+        log.handle(&TransferEvent::ChecksumMismatch {
+            source: a,
+            dest: &dest_a,
+        });
+        let error =
+            anyhow::anyhow!("Input/output error").context("Failed to transfer /card/IMG_0002.CR2");
+        log.handle(&TransferEvent::Failed {
+            worker: 1,
+            source: b,
+            error: &error,
+        });
+        let mut report = Report {
+            unreadable: vec![FileError {
+                path: PathBuf::from("/card/IMG_0003.CR2"),
+                error: "no date found".to_string(),
+            }],
+            ..Report::default()
+        };
+        report.destinations.push(log.into_report(out.path()));
+
+        let failures = report.failures();
+        let listed: Vec<(&Path, Stage, &str)> = failures
+            .iter()
+            .map(|f| (f.path.as_path(), f.stage, f.error.as_str()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (
+                    Path::new("/card/IMG_0003.CR2"),
+                    Stage::Metadata,
+                    "no date found"
+                ),
+                (b, Stage::Copy, "Input/output error"),
+                (
+                    a,
+                    Stage::Verify,
+                    format!("{} does not match it", dest_a.display()).as_str()
+                ),
+            ]
+        );
+
+        let errors = out.path().join(ERRORS_FILENAME);
+        report.write_errors(&failures).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&errors).unwrap()).unwrap();
+        assert_eq!(written["failures"][1]["stage"], "copy");
+        // A later run without failures removes the stale list
+        report.write_errors(&[]).unwrap();
+        assert!(!errors.exists());
+    }
+}
@@ -0,0 +1,262 @@
+//! `--review`: edit the planned sessions at a prompt before anything is copied.
+
+use anyhow::Result;
+use image_processor::session::{self, Session};
+use std::io::{self, BufRead, IsTerminal, Write};
+
+const HELP: &str = "\
+  l                 list the sessions
+  f <n>             list the files of session n
+  m <n> <m>...      merge sessions m... into session n
+  s <n> <file>      split session n before its file number <file>
+  r <n> <name>      rename session n's folder (`/` nests folders)
+  x <n>             exclude session n from the import
+  d                 done: import the sessions as listed
+  q                 quit without importing";
+
+/// What the prompt does after a command.
+#[derive(Debug, PartialEq, Eq)]
+enum Next {
+    Prompt,
+    Import,
+    Cancel,
+}
+
+/// Let the user merge, split, rename and exclude `sessions` until they are
+/// done, and return the sessions to import. Prompts go to stderr so they
+/// never mix with a `--json` report on stdout.
+pub fn review(mut sessions: Vec<Session>) -> Result<Vec<Session>> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("--review needs a terminal to prompt on");
+    }
+    eprintln!("Review the sessions before copying (? for help):");
+    list(&sessions);
+    let mut stdin = io::stdin().lock();
+    loop {
+        eprint!("review> ");
+        io::stderr().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            anyhow::bail!("Import cancelled during review");
+        }
+        match apply(&mut sessions, &line) {
+            Ok(Next::Prompt) => {}
+            Ok(Next::Import) => return Ok(sessions),
+            Ok(Next::Cancel) => anyhow::bail!("Import cancelled during review"),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+fn list(sessions: &[Session]) {
+    for (i, session) in sessions.iter().enumerate() {
+        let first = session.files.iter().map(|f| f.datetime).min();
+        let last = session.files.iter().map(|f| f.datetime).max();
+        let span = match (first, last) {
+            (Some(first), Some(last)) => format!(
+                "{} - {}",
+                first.format("%Y-%m-%d %H:%M"),
+                last.format("%H:%M")
+            ),
+            _ => String::new(),
+        };
+        eprintln!(
+            "  {}) {} ({} files, {})",
+            i + 1,
+            session.folder_name,
+            session.files.len(),
+            span
+        );
+    }
+}
+
+/// Run one prompt command on `sessions`, listing them again after a change.
+fn apply(sessions: &mut Vec<Session>, line: &str) -> Result<Next> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(Next::Prompt);
+    };
+    let args: Vec<&str> = words.collect();
+    let number = |arg: Option<&&str>, len: usize| -> Result<usize> {
+        let arg = arg.ok_or_else(|| anyhow::anyhow!("Missing a number, ? for help"))?;
+        match arg.parse::<usize>() {
+            Ok(n) if (1..=len).contains(&n) => Ok(n - 1),
+            _ => anyhow::bail!("No {} here, pick 1-{}", arg, len),
+        }
+    };
+
+    match command {
+        "l" => list(sessions),
+        "f" => {
+            let session = &sessions[number(args.first(), sessions.len())?];
+            for (i, file) in session.files.iter().enumerate() {
+                eprintln!("  {}) {} {}", i + 1, file.path.display(), file.datetime);
+            }
+            return Ok(Next::Prompt);
+        }
+        "m" => {
+            let into = number(args.first(), sessions.len())?;
+            let mut merged = Vec::new();
+            for arg in &args[1..] {
+                let n = number(Some(arg), sessions.len())?;
+                if n != into && !merged.contains(&n) {
+                    merged.push(n);
+                }
+            }
+            if merged.is_empty() {
+                anyhow::bail!("Name the sessions to merge into {}", into + 1);
+            }
+            // Remove from the end so the other indices stay valid
+            merged.sort_unstable_by(|a, b| b.cmp(a));
+            let mut into = into;
+            let mut files = Vec::new();
+            for n in merged {
+                files.extend(sessions.remove(n).files);
+                if n < into {
+                    into -= 1;
+                }
+            }
+            let session = &mut sessions[into];
+            session.files.extend(files);
+            session.files.sort_by_key(|f| f.datetime);
+            list(sessions);
+        }
+        "s" => {
+            let n = number(args.first(), sessions.len())?;
+            let at = number(args.get(1), sessions[n].files.len())?;
+            if at == 0 {
+                anyhow::bail!("Split before file 2 or later, file 1 starts the session");
+            }
+            let files = sessions[n].files.split_off(at);
+            let folder_name = unused_name(sessions, &sessions[n].folder_name);
+            sessions.insert(n + 1, Session { folder_name, files });
+            list(sessions);
+        }
+        "r" => {
+            let n = number(args.first(), sessions.len())?;
+            let name = folder_name(&args[1..].join(" "))?;
+            if sessions
+                .iter()
+                .enumerate()
+                .any(|(i, s)| i != n && s.folder_name == name)
+            {
+                anyhow::bail!("Another session is already named {}", name);
+            }
+            sessions[n].folder_name = name;
+            list(sessions);
+        }
+        "x" => {
+            let n = number(args.first(), sessions.len())?;
+            let session = sessions.remove(n);
+            eprintln!(
+                "Excluded {} ({} files)",
+                session.folder_name,
+                session.files.len()
+            );
+            list(sessions);
+        }
+        "d" if sessions.is_empty() => anyhow::bail!("Every session is excluded, q to quit"),
+        "d" => return Ok(Next::Import),
+        "q" => return Ok(Next::Cancel),
+        _ => eprintln!("{}", HELP),
+    }
+    Ok(Next::Prompt)
+}
+
+/// A name for a session split off `base`, with the letter suffixes of
+/// sessions sharing a day: the next free one of its group, `2024-01-15_c`
+/// after `2024-01-15_b`, or `base_b` for a session not in one.
+fn unused_name(sessions: &[Session], base: &str) -> String {
+    let taken = |name: &str| sessions.iter().any(|s| s.folder_name == name);
+    let stem = base
+        .rsplit_once('_')
+        .filter(|(stem, suffix)| {
+            !suffix.is_empty()
+                && suffix.bytes().all(|b| b.is_ascii_lowercase())
+                && taken(&format!("{}_a", stem))
+        })
+        .map_or(base, |(stem, _)| stem);
+    (1..)
+        .map(|index| format!("{}_{}", stem, session::letter_suffix(index)))
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free")
+}
+
+/// A folder name typed at the prompt, with each `/`-separated part made
+/// safe for the filesystem.
+fn folder_name(typed: &str) -> Result<String> {
+    let parts: Option<Vec<String>> = typed.split('/').map(session::folder_component).collect();
+    match parts {
+        Some(parts) => Ok(parts.join("/")),
+        None => anyhow::bail!("Not a usable folder name: `{}`", typed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use image_processor::metadata::DateSource;
+    use image_processor::session::DatedFile;
+    use std::path::PathBuf;
+
+    fn session(name: &str, hours: &[u32]) -> Session {
+        let files = hours
+            .iter()
+            .map(|&hour| DatedFile {
+                path: PathBuf::from(format!("IMG_{:02}00.CR2", hour)),
+                datetime: NaiveDate::from_ymd_opt(2024, 1, 15)
+                    .unwrap()
+                    .and_hms_opt(hour, 0, 0)
+                    .unwrap(),
+                date_source: DateSource::Exif,
+                sequence_number: None,
+                sidecars: Vec::new(),
+                identical: Vec::new(),
+                camera: None,
+            })
+            .collect();
+        Session {
+            folder_name: name.to_string(),
+            files,
+        }
+    }
+
+    #[test]
+    fn test_review_commands() {
+        let mut sessions = vec![
+            session("a", &[7]),
+            session("b", &[10, 11, 12]),
+            session("c", &[20]),
+        ];
+        let names = |sessions: &[Session]| {
+            sessions
+                .iter()
+                .map(|s| format!("{}:{}", s.folder_name, s.files.len()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(apply(&mut sessions, "m 2 1").unwrap(), Next::Prompt);
+        assert_eq!(names(&sessions), ["b:4", "c:1"]);
+        assert_eq!(sessions[0].files[0].datetime.format("%H").to_string(), "07");
+
+        apply(&mut sessions, "s 1 3").unwrap();
+        assert_eq!(names(&sessions), ["b:2", "b_b:2", "c:1"]);
+
+        apply(&mut sessions, "r 1 b_a").unwrap();
+        apply(&mut sessions, "s 2 2").unwrap();
+        assert_eq!(names(&sessions), ["b_a:2", "b_b:1", "b_c:1", "c:1"]);
+        apply(&mut sessions, "x 3").unwrap();
+
+        apply(&mut sessions, "r 2 2024/Evening: party").unwrap();
+        apply(&mut sessions, "x 3").unwrap();
+        assert_eq!(names(&sessions), ["b_a:2", "2024/Evening_ party:1"]);
+
+        assert!(apply(&mut sessions, "r 1 2024/../x").is_err());
+        assert!(apply(&mut sessions, "r 1 2024/Evening_ party").is_err());
+        assert!(apply(&mut sessions, "m 5 1").is_err());
+        assert!(apply(&mut sessions, "s 1 1").is_err());
+        assert_eq!(apply(&mut sessions, "d").unwrap(), Next::Import);
+        assert_eq!(apply(&mut sessions, "q").unwrap(), Next::Cancel);
+    }
+}
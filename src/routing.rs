@@ -0,0 +1,233 @@
+use crate::session::Session;
+use std::path::{Path, PathBuf};
+
+/// A `--route` rule sending files with the given extensions to a destination root.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Lowercased extensions without the leading dot
+    pub extensions: Vec<String>,
+    pub root: PathBuf,
+}
+
+/// Parse a route of the form `cr2,cr3=/Volumes/Work`.
+pub fn parse_route(s: &str) -> Result<Route, String> {
+    let (exts, root) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXT[,EXT...]=DIR, got '{}'", s))?;
+    let extensions: Vec<String> = exts
+        .split(',')
+        .map(|e| e.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+    if extensions.is_empty() {
+        return Err(format!("route '{}' lists no extensions", s));
+    }
+    if root.trim().is_empty() {
+        return Err(format!("route '{}' has an empty destination", s));
+    }
+    Ok(Route {
+        extensions,
+        root: PathBuf::from(root.trim()),
+    })
+}
+
+/// Resolves the destination root of each file from the `--route` rules,
+/// falling back to `--output` for files no rule matches.
+#[derive(Debug, Clone)]
+pub struct Router {
+    routes: Vec<Route>,
+    default: Option<PathBuf>,
+}
+
+impl Router {
+    pub fn new(routes: Vec<Route>, default: Option<PathBuf>) -> Self {
+        Self { routes, default }
+    }
+
+    /// Destination root for a file. The first matching route wins.
+    pub fn root_for(&self, path: &Path) -> Option<&Path> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        ext.and_then(|ext| self.routes.iter().find(|r| r.extensions.contains(&ext)))
+            .map(|r| r.root.as_path())
+            .or(self.default.as_deref())
+    }
+}
+
+/// Split sessions by destination root, in order of first use.
+/// Sessions keep their folder names, so a session spread over several roots
+/// gets the same folder name in each of them.
+pub fn split_by_root(sessions: &[Session], router: &Router) -> Vec<(PathBuf, Vec<Session>)> {
+    let mut destinations: Vec<(PathBuf, Vec<Session>)> = Vec::new();
+
+    for session in sessions {
+        for file in &session.files {
+            let Some(root) = router.root_for(&file.path) else {
+                continue;
+            };
+            let idx = match destinations.iter().position(|(r, _)| r == root) {
+                Some(idx) => idx,
+                None => {
+                    destinations.push((root.to_path_buf(), Vec::new()));
+                    destinations.len() - 1
+                }
+            };
+            let root_sessions = &mut destinations[idx].1;
+            match root_sessions.last_mut() {
+                Some(last) if last.folder_name == session.folder_name => {
+                    last.files.push(file.clone());
+                }
+                _ => root_sessions.push(Session {
+                    folder_name: session.folder_name.clone(),
+                    files: vec![file.clone()],
+                }),
+            }
+        }
+    }
+
+    destinations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::HashAlgo;
+    use crate::journal::ImportJournal;
+    use crate::metadata::DateSource;
+    use crate::scanner::LrfMode;
+    use crate::session::DatedFile;
+    use crate::state::TransferState;
+    use crate::transfer::{self, ExtensionCase, LinkMode, TransferOptions};
+    use chrono::NaiveDate;
+    use std::fs;
+
+    #[test]
+    fn test_parse_route() {
+        let route = parse_route("CR2, .cr3=/Volumes/Work").unwrap();
+        assert_eq!(route.extensions, vec!["cr2", "cr3"]);
+        assert_eq!(route.root, PathBuf::from("/Volumes/Work"));
+        assert!(parse_route("cr2").is_err());
+        assert!(parse_route("=/Volumes/Work").is_err());
+        assert!(parse_route("cr2=").is_err());
+    }
+
+    #[test]
+    fn test_root_for_falls_back_to_default() {
+        let routes = vec![parse_route("cr2=/work").unwrap()];
+        let router = Router::new(routes.clone(), Some(PathBuf::from("/default")));
+        assert_eq!(
+            router.root_for(Path::new("IMG_0001.CR2")),
+            Some(Path::new("/work"))
+        );
+        assert_eq!(
+            router.root_for(Path::new("MVI_0002.MP4")),
+            Some(Path::new("/default"))
+        );
+
+        let router = Router::new(routes, None);
+        assert_eq!(router.root_for(Path::new("MVI_0002.MP4")), None);
+    }
+
+    #[test]
+    fn test_mixed_session_routed_to_two_roots() {
+        let card = tempfile::tempdir().unwrap();
+        let work = tempfile::tempdir().unwrap();
+        let archive = tempfile::tempdir().unwrap();
+
+        let datetime = NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let files: Vec<DatedFile> = ["IMG_0001.CR2", "MVI_0002.MP4", "IMG_0003.CR2"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let path = card.path().join(name);
+                fs::write(&path, name.as_bytes()).unwrap();
+                DatedFile {
+                    path,
+                    datetime,
+                    date_source: DateSource::Exif,
+                    sequence_number: Some(i as u64 + 1),
+                    sidecars: Vec::new(),
+                    identical: Vec::new(),
+                    camera: None,
+                }
+            })
+            .collect();
+        let sessions = vec![Session {
+            folder_name: "2024-01-15".to_string(),
+            files,
+        }];
+
+        let router = Router::new(
+            vec![
+                parse_route(&format!("cr2={}", work.path().display())).unwrap(),
+                parse_route(&format!("mp4={}", archive.path().display())).unwrap(),
+            ],
+            None,
+        );
+        let destinations = split_by_root(&sessions, &router);
+        assert_eq!(destinations.len(), 2);
+
+        let options = TransferOptions {
+            dry_run: false,
+            extension_case: ExtensionCase::Lower,
+            split_by_type: false,
+            chapter_folders: false,
+            lrf: LrfMode::Copy,
+            link_mode: LinkMode::Copy,
+            mirror_state_to_input: false,
+            max_path: transfer::DEFAULT_MAX_PATH,
+            verify: true,
+            hash_algo: HashAlgo::Sha256,
+            jobs: 2,
+            move_sources: false,
+            skip_duplicates: false,
+            on_conflict: transfer::OnConflict::Rename,
+            on_error: transfer::OnError::Abort,
+            time_zone: None,
+            fsync: false,
+            preserve: Vec::new(),
+            backup_dir: None,
+            max_rate: None,
+            retry: transfer::Retry::default(),
+            buffers: transfer::Buffers::default(),
+            force: false,
+            force_unlock: false,
+            card_id: None,
+            skip_imported: false,
+            manifest: false,
+            checksum_file: false,
+            thumbnails: false,
+            previews: None,
+            proxy_command: None,
+            rename: None,
+        };
+        for (root, root_sessions) in &destinations {
+            let total = root_sessions.iter().map(|s| s.files.len()).sum();
+            let mut state = TransferState::new(total, 0);
+            let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+            transfer::transfer_sessions(
+                root_sessions,
+                root,
+                &[card.path()],
+                &mut state,
+                &mut journal,
+                &options,
+                &transfer::Hooks::default(),
+            )
+            .unwrap();
+            assert!(state.all_done());
+        }
+
+        let session_dir = |root: &Path| root.join("2024-01-15");
+        assert!(session_dir(work.path()).join("IMG_0001.cr2").is_file());
+        assert!(session_dir(work.path()).join("IMG_0003.cr2").is_file());
+        assert!(!session_dir(work.path()).join("MVI_0002.mp4").exists());
+        assert!(session_dir(archive.path()).join("MVI_0002.mp4").is_file());
+        assert!(!session_dir(archive.path()).join("IMG_0001.cr2").exists());
+    }
+}
@@ -1,4 +1,6 @@
 use anyhow::Result;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
@@ -6,16 +8,293 @@ use walkdir::WalkDir;
 pub struct ScannedFile {
     pub path: PathBuf,
     pub sequence_number: Option<u64>,
+    /// Sidecar files in the same folder sharing the file's stem, the JPEG
+    /// the camera wrote alongside a raw, and the later chapters of a GoPro
+    /// recording with their own sidecars
+    pub sidecars: Vec<PathBuf>,
 }
 
-pub fn scan_files(input_dir: &Path) -> Result<Vec<ScannedFile>> {
+/// Extensions of metadata sidecars that travel with their primary file:
+/// also the flight log subtitles (`.SRT`) and low-resolution previews
+/// (`.LRF`) DJI drones write next to each video.
+pub const SIDECAR_EXTENSIONS: &[&str] = &["xmp", "thm", "srt", "lrf"];
+
+/// What is done with the `.LRF` previews DJI drones write next to each video.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LrfMode {
+    /// Copy them next to their video like other sidecars
+    #[default]
+    Copy,
+    /// Leave them on the card
+    Skip,
+    /// Copy them to the session's `proxies` folder
+    Proxies,
+}
+
+/// Whether `path` is a DJI low-resolution preview.
+pub fn is_lrf(path: &Path) -> bool {
+    has_extension(path, &["lrf"])
+}
+
+/// Extensions of raw files a camera may write a JPEG alongside.
+pub const RAW_EXTENSIONS: &[&str] = &["cr2", "cr3", "nef", "arw", "dng", "raf", "orf", "rw2"];
+
+/// Extensions of the JPEGs paired with a raw of the same name.
+const PAIRED_EXTENSIONS: &[&str] = &["jpg", "jpeg"];
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Whether `path`, one of a file's sidecars, is a metadata sidecar rather
+/// than a paired JPEG.
+pub fn is_sidecar(path: &Path) -> bool {
+    has_extension(path, SIDECAR_EXTENSIONS)
+}
+
+/// Where a GoPro video falls in the recording it split into chapters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Name prefix telling the recording's encoding, lowercased: `gh`
+    /// (H.264), `gx` (HEVC), or `gp` for the older `GOPR`/`GP` scheme
+    pub prefix: String,
+    pub recording: u64,
+    /// 1 for the first chapter
+    pub number: u32,
+}
+
+/// The GoPro chapter `path` is, or whose sidecar it is, from its name:
+/// `GH010123.MP4` and `GH020123.MP4` are chapters 1 and 2 of recording 123
+/// (`GX` for HEVC); models before the HERO6 name them `GOPR0123.MP4` then
+/// `GP010123.MP4`.
+pub fn gopro_chapter(path: &Path) -> Option<Chapter> {
+    let name = path.file_name()?.to_str()?;
+    let stem = name.split('.').next()?.to_ascii_lowercase();
+    if stem.len() != 8 || !stem.is_ascii() || !stem[4..].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let recording = stem[4..].parse().ok()?;
+    let (prefix, number) = match (&stem[..2], &stem[2..4]) {
+        ("go", "pr") => ("gp", 1),
+        (prefix @ ("gh" | "gx" | "gp"), number) => (
+            prefix,
+            number.parse::<u32>().ok()? + u32::from(prefix == "gp"),
+        ),
+        _ => return None,
+    };
+    (number > 0).then(|| Chapter {
+        prefix: prefix.to_string(),
+        recording,
+        number,
+    })
+}
+
+/// Folder and lowercased stem of `path`, which files travelling together share.
+fn stem_key(path: &Path) -> (PathBuf, String) {
+    let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    (parent, stem)
+}
+
+/// Extensions imported when `--extensions` is not given.
+pub const DEFAULT_EXTENSIONS: &str = "cr2,cr3,nef,arw,heic,heif,mp4,mov,mts,wav,mp3";
+
+/// Normalize a `--extensions` entry: lowercase, without a leading dot.
+pub fn parse_extension(s: &str) -> Result<String, String> {
+    let ext = s.trim().trim_start_matches('.').to_ascii_lowercase();
+    if ext.is_empty() {
+        return Err("empty extension".to_string());
+    }
+    Ok(ext)
+}
+
+/// Check that an `--exclude` entry is a valid glob pattern.
+pub fn parse_glob(s: &str) -> Result<String, String> {
+    Glob::new(s).map_err(|e| e.to_string())?;
+    Ok(s.to_string())
+}
+
+/// Card files left out of a scan besides those with other extensions.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    /// Glob patterns matched case-insensitively against the path relative to
+    /// the input directory. `*` also matches `/`, so `*.CTG` excludes those
+    /// files in every folder and `**/MISC/**` everything below any `MISC`
+    /// folder.
+    exclude: GlobSet,
+    /// Files smaller than this many bytes are skipped, such as the empty
+    /// files a failing card leaves behind
+    pub min_size: Option<u64>,
+    /// Files larger than this many bytes are skipped
+    pub max_size: Option<u64>,
+    /// Check each file's structure (see [`crate::validate`]), leaving out
+    /// the damaged ones as if their date could not be read
+    pub check_structure: bool,
+    /// With [`LrfMode::Skip`], DJI previews are not attached to their video
+    pub lrf: LrfMode,
+    /// Read only the camera folders of a card (see [`camera_folders`])
+    /// instead of walking all of it
+    pub dcim: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            exclude: GlobSet::empty(),
+            min_size: None,
+            max_size: None,
+            check_structure: false,
+            lrf: LrfMode::Copy,
+            dcim: false,
+        }
+    }
+}
+
+impl Filter {
+    /// A filter leaving out the paths matching any of `patterns`.
+    pub fn exclude(patterns: &[String]) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(GlobBuilder::new(pattern).case_insensitive(true).build()?);
+        }
+        Ok(Self {
+            exclude: builder.build()?,
+            ..Self::default()
+        })
+    }
+
+    /// Whether `relative`, a path below the input directory, is excluded.
+    pub fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude.is_match(relative)
+    }
+
+    /// Whether a file of `len` bytes is within the size limits.
+    pub fn size_matches(&self, len: u64) -> bool {
+        self.min_size.is_none_or(|min| len >= min) && self.max_size.is_none_or(|max| len <= max)
+    }
+}
+
+/// Camcorder folders holding video clips outside `DCIM`.
+const CLIP_FOLDERS: &[&str] = &["PRIVATE/AVCHD/BDMV/STREAM", "PRIVATE/M4ROOT/CLIP"];
+
+/// The folders a camera writes its files to on the card at `input_dir`: the
+/// numbered folders of its `DCIM` folder (`100CANON`, `101CANON`, ...), named
+/// by three digits from 100 then five letters, digits or underscores, and
+/// the clip folders of AVCHD and XAVC camcorders. Other folders, such as
+/// `MISC`, `CANONMSC` or the databases some cameras keep, are left out.
+/// `input_dir` may also be a `DCIM` folder itself, or hold it one folder
+/// down, as the storages of a camera downloaded over PTP do.
+pub fn camera_folders(input_dir: &Path) -> Result<Vec<PathBuf>> {
+    let is_dcim = |path: &Path| {
+        path.file_name()
+            .is_some_and(|name| name.eq_ignore_ascii_case("DCIM"))
+    };
+    let subfolders = |dir: &Path| -> Result<Vec<PathBuf>> {
+        let mut dirs = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            }
+        }
+        Ok(dirs)
+    };
+    let mut dcims = Vec::new();
+    if is_dcim(input_dir) {
+        dcims.push(input_dir.to_path_buf());
+    } else {
+        for dir in subfolders(input_dir)? {
+            if is_dcim(&dir) {
+                dcims.push(dir);
+            } else if let Ok(storage) = subfolders(&dir) {
+                dcims.extend(storage.into_iter().filter(|dir| is_dcim(dir)));
+            }
+        }
+    }
+    let mut folders = Vec::new();
+    for dcim in &dcims {
+        for entry in std::fs::read_dir(dcim)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            let numbered = name.len() == 8
+                && name.is_ascii()
+                && name[..3].parse::<u32>().is_ok_and(|n| n >= 100)
+                && name[3..]
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_');
+            if numbered && path.is_dir() {
+                folders.push(path);
+            } else {
+                log::debug!("Skipped {}: not a camera folder", path.display());
+            }
+        }
+    }
+    folders.extend(
+        CLIP_FOLDERS
+            .iter()
+            .map(|folder| input_dir.join(folder))
+            .filter(|dir| dir.is_dir()),
+    );
+    if dcims.is_empty() && folders.is_empty() {
+        anyhow::bail!("No DCIM folder in {}", input_dir.display());
+    }
+    folders.sort();
+    Ok(folders)
+}
+
+/// Recursively collect files whose lowercased extension is in `extensions`,
+/// or with [`Filter::dcim`] those of the card's camera folders, sorted by
+/// sequence number. A JPEG sharing a raw's folder and stem
+/// (`IMG_0042.JPG` next to `IMG_0042.CR2`) is paired with it: it becomes one
+/// of the raw's sidecars, so both are dated once, land in the same session
+/// and are kept or skipped together. Excluded paths are left out first, each
+/// on its own, then the size limits apply to the primary files, taking their
+/// paired JPEGs along and weighing a recording's chapters together.
+pub fn scan_files(
+    input_dir: &Path,
+    extensions: &[String],
+    filter: &Filter,
+) -> Result<Vec<ScannedFile>> {
     let mut files = Vec::new();
+    // Sidecars by (folder, lowercased stem)
+    let mut sidecars: HashMap<(PathBuf, String), Vec<PathBuf>> = HashMap::new();
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
 
-    for entry in WalkDir::new(input_dir)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
+    let excluded = |path: &Path| {
+        let relative = path.strip_prefix(input_dir).unwrap_or(path);
+        let excluded = !relative.as_os_str().is_empty() && filter.is_excluded(relative);
+        if excluded {
+            log::debug!("Excluded {}", path.display());
+        }
+        excluded
+    };
+    // Camera folders are flat, the whole input is walked down
+    let walks = if filter.dcim {
+        camera_folders(input_dir)?
+            .into_iter()
+            .map(|dir| WalkDir::new(dir).max_depth(1))
+            .collect()
+    } else {
+        vec![WalkDir::new(input_dir)]
+    };
+    let entries = walks.into_iter().flat_map(|walk| {
+        walk.follow_links(true)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| !(e.file_type().is_dir() && excluded(e.path())))
+            .filter_map(|e| e.ok())
+    });
+    for entry in entries {
         let path = entry.path();
         if !path.is_file() {
             continue;
@@ -26,18 +305,141 @@ pub fn scan_files(input_dir: &Path) -> Result<Vec<ScannedFile>> {
             .and_then(|e| e.to_str())
             .map(|e| e.to_ascii_lowercase());
 
-        match ext.as_deref() {
-            Some("cr2") | Some("mp4") => {}
+        match ext {
+            Some(ext) if extensions.contains(&ext) => {}
+            Some(ext) if SIDECAR_EXTENSIONS.contains(&ext.as_str()) => {
+                if !excluded(path) && (filter.lrf != LrfMode::Skip || ext != "lrf") {
+                    sidecars
+                        .entry(stem_key(path))
+                        .or_default()
+                        .push(path.to_path_buf());
+                }
+                continue;
+            }
             _ => continue,
         }
 
-        let sequence_number = extract_sequence_number(path);
+        // Left out before pairing and grouping, so a paired JPEG or a
+        // chapter is excluded on its own
+        if excluded(path) {
+            continue;
+        }
+        let chapter = gopro_chapter(path);
+        sizes.insert(
+            path.to_path_buf(),
+            entry.metadata().map(|m| m.len()).unwrap_or(0),
+        );
+        // Chapters number like the camera's photos by their recording
+        let sequence_number = match chapter {
+            Some(chapter) => Some(chapter.recording),
+            None => extract_sequence_number(path),
+        };
         files.push(ScannedFile {
             path: path.to_path_buf(),
             sequence_number,
+            sidecars: Vec::new(),
         });
     }
 
+    // Attach `IMG_0001.xmp` and `IMG_0001.CR2.xmp` style sidecars
+    for file in &mut files {
+        let parent = file.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        for name in [file.path.file_stem(), file.path.file_name()]
+            .into_iter()
+            .flatten()
+        {
+            let key = (parent.clone(), name.to_string_lossy().to_lowercase());
+            if let Some(found) = sidecars.remove(&key) {
+                file.sidecars.extend(found);
+            }
+        }
+    }
+
+    // Later chapters of a GoPro recording travel with the first one found,
+    // in order, bringing their sidecars along
+    let mut recordings: HashMap<(PathBuf, String, u64), Vec<(u32, PathBuf)>> = HashMap::new();
+    for file in &files {
+        if let Some(chapter) = gopro_chapter(&file.path) {
+            let folder = file.path.parent().unwrap_or(Path::new("")).to_path_buf();
+            recordings
+                .entry((folder, chapter.prefix, chapter.recording))
+                .or_default()
+                .push((chapter.number, file.path.clone()));
+        }
+    }
+    let mut first_chapters: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    for mut chapters in recordings
+        .into_values()
+        .filter(|chapters| chapters.len() > 1)
+    {
+        chapters.sort();
+        let first = chapters.remove(0).1;
+        // The size limits apply to the whole recording
+        let mut total = sizes.get(&first).copied().unwrap_or(0);
+        for (_, chapter) in &chapters {
+            log::debug!(
+                "{} is a later chapter of {}",
+                chapter.display(),
+                first.display()
+            );
+            total += sizes.get(chapter).copied().unwrap_or(0);
+        }
+        sizes.insert(first.clone(), total);
+        first_chapters.insert(first, chapters.into_iter().map(|(_, path)| path).collect());
+    }
+    let later: HashSet<PathBuf> = first_chapters.values().flatten().cloned().collect();
+    let mut later_sidecars: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    files.retain(|f| {
+        if later.contains(&f.path) {
+            later_sidecars.insert(f.path.clone(), f.sidecars.clone());
+        }
+        !later.contains(&f.path)
+    });
+    for file in &mut files {
+        for chapter in first_chapters.remove(&file.path).unwrap_or_default() {
+            let sidecars = later_sidecars.remove(&chapter).unwrap_or_default();
+            file.sidecars.push(chapter);
+            file.sidecars.extend(sidecars);
+        }
+    }
+
+    // Pair JPEGs with their raw, bringing the JPEG's own sidecars along
+    let raws: HashMap<(PathBuf, String), PathBuf> = files
+        .iter()
+        .filter(|f| has_extension(&f.path, RAW_EXTENSIONS))
+        .map(|f| (stem_key(&f.path), f.path.clone()))
+        .collect();
+    let mut paired: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    files.retain(|f| {
+        let raw = has_extension(&f.path, PAIRED_EXTENSIONS)
+            .then(|| raws.get(&stem_key(&f.path)))
+            .flatten();
+        if let Some(raw) = raw {
+            log::debug!("Paired {} with {}", f.path.display(), raw.display());
+            let companions = paired.entry(raw.clone()).or_default();
+            companions.push(f.path.clone());
+            companions.extend(f.sidecars.iter().cloned());
+        }
+        raw.is_none()
+    });
+    for file in &mut files {
+        if let Some(companions) = paired.remove(&file.path) {
+            file.sidecars.extend(companions);
+        }
+    }
+
+    files.retain(|f| {
+        let len = sizes.get(&f.path).copied().unwrap_or(0);
+        if !filter.size_matches(len) {
+            log::debug!(
+                "Skipped {}: {} bytes is outside the size limits",
+                f.path.display(),
+                len
+            );
+        }
+        filter.size_matches(len)
+    });
+
     // Sort by sequence number, files without a sequence number go last
     files.sort_by(|a, b| {
         let sa = a.sequence_number.unwrap_or(u64::MAX);
@@ -48,18 +450,77 @@ pub fn scan_files(input_dir: &Path) -> Result<Vec<ScannedFile>> {
     Ok(files)
 }
 
+/// File count and total size for one extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionStats {
+    /// Lowercased extension without the leading dot
+    pub extension: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Aggregate file counts and sizes per lowercased extension, largest total first.
+pub fn summarize_extensions<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Vec<ExtensionStats> {
+    let mut stats: Vec<ExtensionStats> = Vec::new();
+
+    for path in paths {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let bytes = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        match stats.iter_mut().find(|s| s.extension == extension) {
+            Some(entry) => {
+                entry.files += 1;
+                entry.bytes += bytes;
+            }
+            None => stats.push(ExtensionStats {
+                extension,
+                files: 1,
+                bytes,
+            }),
+        }
+    }
+
+    stats.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| a.extension.cmp(&b.extension))
+    });
+    stats
+}
+
 /// Extract the trailing digits from the file stem as a sequence number.
+/// Only the final run of digits counts, so a folder-roll prefix separated by
+/// `_` or `-` is ignored.
 /// Examples:
 ///   _MG_1001.CR2  -> 1001
 ///   IMG_0042.CR2   -> 42
 ///   MVI_0042.MP4   -> 42
+///   100_0042.JPG   -> 42
+///   100-0042.JPG   -> 42
+///   DSC01234.ARW   -> 1234
+///
+/// Digits that directly follow letters are taken as a whole: `DSC01234` reads
+/// as 1234 even though some bodies use the first digit as a model marker, and
+/// a roll number written without separator (`1000042`) cannot be told apart
+/// from the frame number.
 fn extract_sequence_number(path: &Path) -> Option<u64> {
     let stem = path.file_stem()?.to_str()?;
-    let digits: String = stem.chars().rev().take_while(|c| c.is_ascii_digit()).collect();
-    if digits.is_empty() {
-        return None;
+    let digits_start = stem
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_ascii_digit())
+        .last()
+        .map(|(i, _)| i)?;
+    let digits = &stem[digits_start..];
+
+    // The run must be preceded by a separator, a letter, or nothing at all
+    match stem[..digits_start].chars().last() {
+        None | Some('_') | Some('-') => {}
+        Some(c) if c.is_alphabetic() => {}
+        Some(_) => return None,
     }
-    let digits: String = digits.chars().rev().collect();
     digits.parse().ok()
 }
 
@@ -74,14 +535,374 @@ mod tests {
             extract_sequence_number(Path::new("_MG_1001.CR2")),
             Some(1001)
         );
+        assert_eq!(extract_sequence_number(Path::new("IMG_0042.CR2")), Some(42));
+        assert_eq!(extract_sequence_number(Path::new("MVI_0042.MP4")), Some(42));
+        assert_eq!(extract_sequence_number(Path::new("nodigits.CR2")), None);
+    }
+
+    #[test]
+    fn test_extract_sequence_number_separators() {
+        assert_eq!(extract_sequence_number(Path::new("100_0042.JPG")), Some(42));
+        assert_eq!(extract_sequence_number(Path::new("100-0042.JPG")), Some(42));
+        // Known ambiguity: the digits after the prefix are read as one number
+        assert_eq!(
+            extract_sequence_number(Path::new("DSC01234.ARW")),
+            Some(1234)
+        );
+        assert_eq!(extract_sequence_number(Path::new("0042.CR2")), Some(42));
+        assert_eq!(extract_sequence_number(Path::new("IMG_0042 (1).CR2")), None);
+        assert_eq!(extract_sequence_number(Path::new("IMG_0042.1.CR2")), None);
+    }
+
+    #[test]
+    fn test_scan_files_filters_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["IMG_0001.CR2", "IMG_0002.jpg", "MVI_0003.MP4", "notes.txt"] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let names = |exts: &[&str]| {
+            let exts: Vec<String> = exts.iter().map(|e| parse_extension(e).unwrap()).collect();
+            scan_files(dir.path(), &exts, &Filter::default())
+                .unwrap()
+                .into_iter()
+                .map(|f| f.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&["cr2", "mp4"]), vec!["IMG_0001.CR2", "MVI_0003.MP4"]);
+        assert_eq!(names(&[".JPG"]), vec!["IMG_0002.jpg"]);
+    }
+
+    #[test]
+    fn test_scan_files_exclude() {
+        let dir = tempfile::tempdir().unwrap();
+        for folder in ["DCIM/100CANON", "DCIM/MISC", "REJECTS"] {
+            std::fs::create_dir_all(dir.path().join(folder)).unwrap();
+        }
+        for name in [
+            "DCIM/100CANON/IMG_0001.CR2",
+            "DCIM/100CANON/IMG_0001.CTG",
+            "DCIM/MISC/IMG_0002.CR2",
+            "REJECTS/IMG_0003.CR2",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let patterns: Vec<String> = ["**/misc/**", "*.ctg", "REJECTS"]
+            .iter()
+            .map(|p| parse_glob(p).unwrap())
+            .collect();
+        let exts = vec!["cr2".to_string(), "ctg".to_string()];
+        let files = scan_files(dir.path(), &exts, &Filter::exclude(&patterns).unwrap()).unwrap();
+        let paths: Vec<PathBuf> = files
+            .iter()
+            .map(|f| f.path.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(paths, vec![PathBuf::from("DCIM/100CANON/IMG_0001.CR2")]);
+        assert!(parse_glob("[unclosed").is_err());
+    }
+
+    #[test]
+    fn test_scan_files_size_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, len) in [
+            ("IMG_0001.CR2", 0),
+            ("IMG_0002.CR2", 10),
+            ("MVI_0003.MP4", 1000),
+        ] {
+            std::fs::write(dir.path().join(name), vec![0u8; len]).unwrap();
+        }
+        std::fs::write(dir.path().join("IMG_0001.xmp"), b"").unwrap();
+        let exts = vec!["cr2".to_string(), "mp4".to_string()];
+        let filter = Filter {
+            min_size: Some(1),
+            max_size: Some(100),
+            ..Filter::default()
+        };
+        let files = scan_files(dir.path(), &exts, &filter).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["IMG_0002.CR2"]);
+    }
+
+    #[test]
+    fn test_scan_files_groups_gopro_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "GH020123.MP4",
+            "GH020123.THM",
+            "GH010123.MP4",
+            "GX010124.MP4",
+            "GOPR0125.MP4",
+            "GP010125.MP4",
+            "GOPR0126.JPG",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let chapter = gopro_chapter(Path::new("GP010125.MP4")).unwrap();
         assert_eq!(
-            extract_sequence_number(Path::new("IMG_0042.CR2")),
-            Some(42)
+            (chapter.prefix.as_str(), chapter.recording, chapter.number),
+            ("gp", 125, 2)
         );
+        assert_eq!(gopro_chapter(Path::new("GOPR0125.MP4")).unwrap().number, 1);
+        assert_eq!(gopro_chapter(Path::new("IMG_0123.MP4")), None);
+
+        let exts = vec!["mp4".to_string(), "jpg".to_string()];
+        let files = scan_files(dir.path(), &exts, &Filter::default()).unwrap();
+        let names = |paths: &[PathBuf]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+        let primaries: Vec<PathBuf> = files.iter().map(|f| f.path.clone()).collect();
         assert_eq!(
-            extract_sequence_number(Path::new("MVI_0042.MP4")),
-            Some(42)
+            names(&primaries),
+            [
+                "GH010123.MP4",
+                "GX010124.MP4",
+                "GOPR0125.MP4",
+                "GOPR0126.JPG"
+            ]
+        );
+        assert_eq!(names(&files[0].sidecars), ["GH020123.MP4", "GH020123.THM"]);
+        assert_eq!(names(&files[2].sidecars), ["GP010125.MP4"]);
+        assert_eq!(files[0].sequence_number, Some(123));
+    }
+
+    #[test]
+    fn test_scan_files_filters_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, len) in [
+            ("GH010123.MP4", 60),
+            ("GH020123.MP4", 60),
+            ("GH010124.MP4", 60),
+            ("GH020124.MP4", 10),
+            ("GH030124.MP4", 10),
+        ] {
+            std::fs::write(dir.path().join(name), vec![0u8; len]).unwrap();
+        }
+        let exts = vec!["mp4".to_string()];
+        let filter = Filter {
+            max_size: Some(100),
+            ..Filter::exclude(&["GH03*".to_string()]).unwrap()
+        };
+        // 0123 is too large as a whole, 0124 is left without its last chapter
+        let files = scan_files(dir.path(), &exts, &filter).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["GH010124.MP4"]);
+        assert_eq!(files[0].sidecars, [dir.path().join("GH020124.MP4")]);
+    }
+
+    #[test]
+    fn test_scan_files_dcim_camera_folders() {
+        let card = tempfile::tempdir().unwrap();
+        for folder in [
+            "DCIM/100CANON/nested",
+            "DCIM/101_FUJI",
+            "DCIM/CANONMSC",
+            "MISC",
+            "PRIVATE/AVCHD/BDMV/STREAM",
+        ] {
+            std::fs::create_dir_all(card.path().join(folder)).unwrap();
+        }
+        for name in [
+            "DCIM/100CANON/IMG_0001.CR2",
+            "DCIM/100CANON/nested/IMG_0002.CR2",
+            "DCIM/101_FUJI/DSCF0003.CR2",
+            "DCIM/CANONMSC/IMG_0004.CR2",
+            "DCIM/IMG_0005.CR2",
+            "MISC/IMG_0006.CR2",
+            "PRIVATE/AVCHD/BDMV/STREAM/00007.MTS",
+        ] {
+            std::fs::write(card.path().join(name), b"x").unwrap();
+        }
+        let exts = vec!["cr2".to_string(), "mts".to_string()];
+        let filter = Filter {
+            dcim: true,
+            ..Filter::default()
+        };
+        let paths = |input: &Path| -> Vec<PathBuf> {
+            let files = scan_files(input, &exts, &filter).unwrap();
+            files
+                .iter()
+                .map(|f| f.path.strip_prefix(card.path()).unwrap().to_path_buf())
+                .collect()
+        };
+        assert_eq!(
+            paths(card.path()),
+            [
+                Path::new("DCIM/100CANON/IMG_0001.CR2"),
+                Path::new("DCIM/101_FUJI/DSCF0003.CR2"),
+                Path::new("PRIVATE/AVCHD/BDMV/STREAM/00007.MTS"),
+            ]
+        );
+        assert_eq!(paths(&card.path().join("DCIM")).len(), 2);
+        assert!(scan_files(&card.path().join("MISC"), &exts, &filter).is_err());
+        assert_eq!(
+            scan_files(card.path(), &exts, &Filter::default())
+                .unwrap()
+                .len(),
+            7
+        );
+    }
+
+    #[test]
+    fn test_scan_files_dji_sidecars() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["DJI_0042.MP4", "DJI_0042.SRT", "DJI_0042.LRF"] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let exts = vec!["mp4".to_string()];
+        let sidecars = |lrf: LrfMode| -> Vec<String> {
+            let filter = Filter {
+                lrf,
+                ..Filter::default()
+            };
+            let files = scan_files(dir.path(), &exts, &filter).unwrap();
+            files[0]
+                .sidecars
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+        assert_eq!(sidecars(LrfMode::Copy), ["DJI_0042.LRF", "DJI_0042.SRT"]);
+        assert_eq!(sidecars(LrfMode::Skip), ["DJI_0042.SRT"]);
+    }
+
+    #[test]
+    fn test_scan_files_attaches_sidecars() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "IMG_0001.CR2",
+            "IMG_0001.XMP",
+            "IMG_0002.CR2",
+            "IMG_0002.CR2.xmp",
+            "MVI_0003.MP4",
+            "MVI_0003.THM",
+            "IMG_0004.xmp",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let exts = vec!["cr2".to_string(), "mp4".to_string()];
+        let files = scan_files(dir.path(), &exts, &Filter::default()).unwrap();
+        let sidecar_names: Vec<Vec<String>> = files
+            .iter()
+            .map(|f| {
+                f.sidecars
+                    .iter()
+                    .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            sidecar_names,
+            vec![
+                vec!["IMG_0001.XMP".to_string()],
+                vec!["IMG_0002.CR2.xmp".to_string()],
+                vec!["MVI_0003.THM".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_files_pairs_raw_and_jpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "IMG_0001.CR2",
+            "IMG_0001.JPG",
+            "IMG_0001.JPG.xmp",
+            "IMG_0002.JPG",
+            "MVI_0003.MP4",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let exts = vec!["cr2".to_string(), "jpg".to_string(), "mp4".to_string()];
+        let files = scan_files(dir.path(), &exts, &Filter::default()).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["IMG_0001.CR2", "IMG_0002.JPG", "MVI_0003.MP4"]);
+        let companions: Vec<_> = files[0]
+            .sidecars
+            .iter()
+            .map(|p| p.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(companions, ["IMG_0001.JPG", "IMG_0001.JPG.xmp"]);
+        assert!(!is_sidecar(&files[0].sidecars[0]) && is_sidecar(&files[0].sidecars[1]));
+    }
+
+    #[test]
+    fn test_scan_files_filters_pairs_by_raw() {
+        // A JPEG too small travels with its raw, an excluded one stays behind
+        let dir = tempfile::tempdir().unwrap();
+        for (name, len) in [
+            ("IMG_0001.CR2", 100),
+            ("IMG_0001.JPG", 1),
+            ("IMG_0002.CR2", 1),
+            ("IMG_0002.JPG", 100),
+        ] {
+            std::fs::write(dir.path().join(name), vec![0u8; len]).unwrap();
+        }
+        let exts = vec!["cr2".to_string(), "jpg".to_string()];
+        let filter = Filter {
+            min_size: Some(10),
+            ..Filter::default()
+        };
+        let files = scan_files(dir.path(), &exts, &filter).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["IMG_0001.CR2"]);
+        assert_eq!(files[0].sidecars, [dir.path().join("IMG_0001.JPG")]);
+
+        let filter = Filter::exclude(&["IMG_0001.jpg".to_string()]).unwrap();
+        let files = scan_files(dir.path(), &exts, &filter).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["IMG_0001.CR2", "IMG_0002.CR2"]);
+        assert!(files[0].sidecars.is_empty());
+    }
+
+    #[test]
+    fn test_summarize_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("IMG_0001.CR2", 300),
+            ("IMG_0002.cr2", 200),
+            ("MVI_0003.MP4", 1000),
+        ];
+        let paths: Vec<PathBuf> = files
+            .iter()
+            .map(|(name, size)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, vec![0u8; *size]).unwrap();
+                path
+            })
+            .collect();
+
+        let stats = summarize_extensions(paths.iter().map(|p| p.as_path()));
+        assert_eq!(
+            stats,
+            vec![
+                ExtensionStats {
+                    extension: "mp4".to_string(),
+                    files: 1,
+                    bytes: 1000
+                },
+                ExtensionStats {
+                    extension: "cr2".to_string(),
+                    files: 2,
+                    bytes: 500
+                },
+            ]
         );
-        assert_eq!(extract_sequence_number(Path::new("nodigits.CR2")), None);
     }
 }
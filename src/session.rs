@@ -1,23 +1,138 @@
+use crate::metadata::{self, Camera, DateSource, Location};
 use chrono::NaiveDateTime;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct DatedFile {
     pub path: PathBuf,
     pub datetime: NaiveDateTime,
+    pub date_source: DateSource,
     pub sequence_number: Option<u64>,
+    /// Sidecar files (XMP, THM) copied alongside this file
+    pub sidecars: Vec<PathBuf>,
+    /// Byte-identical copies of this file elsewhere in the scan, which are
+    /// not copied (see [`crate::duplicates::merge_identical`])
+    pub identical: Vec<PathBuf>,
+    /// The camera that recorded the file, read once during the scan
+    pub camera: Option<Camera>,
 }
 
-#[derive(Debug)]
+impl DatedFile {
+    /// Size of the file plus its sidecars, in bytes.
+    pub fn total_size(&self) -> u64 {
+        std::iter::once(&self.path)
+            .chain(&self.sidecars)
+            .filter_map(|p| std::fs::metadata(p).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Session {
     pub folder_name: String,
     pub files: Vec<DatedFile>,
 }
 
-/// Group sorted files into sessions based on the gap threshold.
-/// Files must be pre-sorted by sequence number.
+/// Session gap used when `--gap auto` finds no clear break, in hours.
+pub const DEFAULT_GAP_HOURS: f64 = 6.0;
+
+/// Bounds of the session gap `--gap auto` picks, in hours.
+const AUTO_GAP_HOURS: (f64, f64) = (1.0, 24.0);
+
+/// How far apart two consecutive files must be to start a new session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gap {
+    Hours(f64),
+    /// Picked for each import from the intervals between its files, see
+    /// [`auto_gap_hours`]
+    Auto,
+}
+
+impl Gap {
+    /// Parse `auto` or a number of hours such as `6` or `1.5`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Gap::Auto);
+        }
+        match s.parse::<f64>() {
+            Ok(hours) if hours.is_finite() && hours >= 0.0 => Ok(Gap::Hours(hours)),
+            _ => Err(format!("expected a number of hours or `auto`, got `{}`", s)),
+        }
+    }
+
+    /// The gap in hours to group `files` with.
+    pub fn hours_for(&self, files: &[DatedFile]) -> f64 {
+        match *self {
+            Gap::Hours(hours) => hours,
+            Gap::Auto => auto_gap_hours(files),
+        }
+    }
+}
+
+/// A session gap for `files`, in the order they will be grouped: the
+/// intervals between consecutive files are sorted and the gap is put in
+/// the widest break between two neighbouring intervals (by ratio) whose
+/// larger side is at least an hour, so the bursts within a shoot fall
+/// below it and the pauses between shoots above. The result is kept
+/// between one hour and a day; without a clear break it is
+/// [`DEFAULT_GAP_HOURS`].
+pub fn auto_gap_hours(files: &[DatedFile]) -> f64 {
+    // A break must at least triple the interval to count as one
+    const MIN_RATIO: f64 = 3.0;
+    let (min, max) = AUTO_GAP_HOURS;
+
+    let mut intervals: Vec<f64> = files
+        .windows(2)
+        .map(|pair| {
+            (pair[1].datetime - pair[0].datetime)
+                .num_seconds()
+                .abs()
+                .max(1) as f64
+                / 3600.0
+        })
+        .collect();
+    intervals.sort_by(f64::total_cmp);
+    let widest = intervals
+        .windows(2)
+        .filter(|pair| pair[1] >= min && pair[1] / pair[0] >= MIN_RATIO)
+        .max_by(|a, b| (a[1] / a[0]).total_cmp(&(b[1] / b[0])));
+    match widest {
+        Some(pair) => (pair[0] * pair[1]).sqrt().clamp(min, max),
+        None => DEFAULT_GAP_HOURS,
+    }
+}
+
+/// Order in which files are grouped into sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Sort {
+    /// By the number in the file name, which survives a camera clock that
+    /// was reset, but not a mix of cameras or name prefixes
+    Seq,
+    /// By capture time
+    Time,
+    /// By capture time, then by number for files taken the same second
+    Hybrid,
+}
+
+/// Sort `files` for [`group_into_sessions`]. The sort is stable, so files
+/// that compare equal keep the order they were scanned in; files without a
+/// number go after the others.
+pub fn sort_files(files: &mut [DatedFile], sort: Sort) {
+    let seq = |f: &DatedFile| f.sequence_number.unwrap_or(u64::MAX);
+    match sort {
+        Sort::Seq => files.sort_by_key(seq),
+        Sort::Time => files.sort_by_key(|f| f.datetime),
+        Sort::Hybrid => files.sort_by_key(|f| (f.datetime, seq(f))),
+    }
+}
+
+/// Group files into sessions in the order they are given (see
+/// [`sort_files`]): a session ends where two consecutive files are more than
+/// `gap_hours` apart, earlier or later.
 pub fn group_into_sessions(files: Vec<DatedFile>, gap_hours: f64) -> Vec<Session> {
     if files.is_empty() {
         return Vec::new();
@@ -43,46 +158,462 @@ pub fn group_into_sessions(files: Vec<DatedFile>, gap_hours: f64) -> Vec<Session
     name_sessions(sessions)
 }
 
-/// Assign folder names to sessions.
+/// Assign folder names to sessions with the default `{date}` template.
 /// Single session on a day: "2024-01-15"
 /// Multiple sessions on same day: "2024-01-15_a", "2024-01-15_b", etc.
 fn name_sessions(sessions: Vec<Vec<DatedFile>>) -> Vec<Session> {
-    // Group sessions by date to detect collisions
-    let mut date_counts: HashMap<String, usize> = HashMap::new();
-    let session_dates: Vec<String> = sessions
-        .iter()
-        .map(|s| {
-            let first = &s[0];
-            first.datetime.format("%Y-%m-%d").to_string()
+    let mut sessions: Vec<Session> = sessions
+        .into_iter()
+        .map(|files| Session {
+            folder_name: String::new(),
+            files,
         })
         .collect();
+    apply_folder_template(&mut sessions, &FolderTemplate::default(), None);
+    sessions
+}
+
+/// Variables a folder template may use.
+const TEMPLATE_VARIABLES: &[&str] = &[
+    "date", "year", "month", "day", "time", "index", "camera", "count", "event",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Variable(String),
+}
+
+/// Pattern for session folder names, such as `{year}/{month}/{date}`. A `/`
+/// nests folders. Variables are taken from the session's first file:
+/// `{date}` (2024-01-15), `{year}`, `{month}`, `{day}`, `{time}` (1430),
+/// `{camera}` (EXIF model), plus `{index}` (1-based session number),
+/// `{count}` (files in the session) and `{event}` (given by the user).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl Default for FolderTemplate {
+    fn default() -> Self {
+        Self {
+            parts: vec![TemplatePart::Variable("date".to_string())],
+        }
+    }
+}
+
+/// Split `template` into literal text and `{variable}`s, which must be
+/// among `variables`.
+fn parse_template(template: &str, variables: &[&str]) -> Result<Vec<TemplatePart>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            parts.push(TemplatePart::Literal(rest[..open].to_string()));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in `{}`", template))?;
+        let name = &rest[open + 1..open + close];
+        if !variables.contains(&name) {
+            return Err(format!(
+                "unknown variable {{{}}}, expected one of {}",
+                name,
+                variables.join(", ")
+            ));
+        }
+        parts.push(TemplatePart::Variable(name.to_string()));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest.to_string()));
+    }
+    Ok(parts)
+}
 
-    for date in &session_dates {
-        *date_counts.entry(date.clone()).or_insert(0) += 1;
+impl FolderTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let parts = parse_template(template, TEMPLATE_VARIABLES)?;
+        if parts.is_empty()
+            || template.starts_with('/')
+            || template
+                .split('/')
+                .any(|c| c.is_empty() || c == "." || c == "..")
+        {
+            return Err(format!("`{}` is not a relative folder path", template));
+        }
+        Ok(Self { parts })
     }
 
-    // Track how many times we've seen each date so far for suffix assignment
-    let mut date_seen: HashMap<String, usize> = HashMap::new();
+    /// Whether the template uses `{variable}`.
+    pub fn uses(&self, variable: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|p| matches!(p, TemplatePart::Variable(v) if v == variable))
+    }
 
-    sessions
-        .into_iter()
-        .zip(session_dates.iter())
-        .map(|(files, date)| {
-            let count = date_counts[date];
-            let folder_name = if count == 1 {
-                date.clone()
-            } else {
-                let idx = date_seen.entry(date.clone()).or_insert(0);
-                let suffix = (b'a' + *idx as u8) as char;
-                *idx += 1;
-                format!("{}_{}", date, suffix)
-            };
+    fn render(&self, index: usize, files: &[DatedFile], event: Option<&str>) -> String {
+        let first = &files[0];
+        self.parts
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Literal(text) => text.clone(),
+                TemplatePart::Variable(name) => {
+                    let value = match name.as_str() {
+                        "date" => first.datetime.format("%Y-%m-%d").to_string(),
+                        "year" => first.datetime.format("%Y").to_string(),
+                        "month" => first.datetime.format("%m").to_string(),
+                        "day" => first.datetime.format("%d").to_string(),
+                        "time" => first.datetime.format("%H%M").to_string(),
+                        "index" => (index + 1).to_string(),
+                        "count" => files.len().to_string(),
+                        "camera" => files
+                            .iter()
+                            .find_map(|f| f.camera.as_ref().map(|c| c.model.clone()))
+                            .unwrap_or_else(|| "unknown".to_string()),
+                        "event" => event.unwrap_or_default().to_string(),
+                        _ => unreachable!("variables are checked by parse"),
+                    };
+                    sanitize_component(&value)
+                }
+            })
+            .collect::<String>()
+            // A value can still leave a part empty or make it `..`, which
+            // would put the session outside its own folder
+            .split('/')
+            .map(|component| {
+                if is_folder_name(component) {
+                    component
+                } else {
+                    "unknown"
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Variables a file name template may use.
+const NAME_TEMPLATE_VARIABLES: &[&str] = &[
+    "date", "year", "month", "day", "time", "seq", "camera", "name", "ext",
+];
+
+/// Pattern for destination file names, such as `{date}_{time}_{seq}.{ext}`.
+/// Variables are taken from the file: `{date}` (20240115), `{year}`,
+/// `{month}`, `{day}`, `{time}` (1430), `{seq}` (the number in its name,
+/// 0042, or its position in the session), `{camera}` (`CanonEOSR5`),
+/// `{name}` (its name without extension) and `{ext}`. The extension is
+/// appended when the template has no `{ext}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let parts = parse_template(template, NAME_TEMPLATE_VARIABLES)?;
+        if parts.is_empty() || template.contains(['/', '\\']) {
+            return Err(format!("`{}` is not a file name", template));
+        }
+        Ok(Self { parts })
+    }
+
+    /// The name of `file`, the `position`th (from 0) in its session, whose
+    /// extension is written as `ext`.
+    pub fn render(&self, file: &DatedFile, position: usize, ext: Option<&str>) -> String {
+        let mut name: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Literal(text) => text.clone(),
+                TemplatePart::Variable(name) => {
+                    let value = match name.as_str() {
+                        "date" => file.datetime.format("%Y%m%d").to_string(),
+                        "year" => file.datetime.format("%Y").to_string(),
+                        "month" => file.datetime.format("%m").to_string(),
+                        "day" => file.datetime.format("%d").to_string(),
+                        "time" => file.datetime.format("%H%M").to_string(),
+                        "seq" => {
+                            format!("{:04}", file.sequence_number.unwrap_or(position as u64 + 1))
+                        }
+                        "camera" => file
+                            .camera
+                            .as_ref()
+                            .map(Camera::label)
+                            .unwrap_or_else(|| "unknown".to_string()),
+                        "name" => file
+                            .path
+                            .file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into_owned(),
+                        "ext" => ext.unwrap_or_default().to_string(),
+                        _ => unreachable!("variables are checked by parse"),
+                    };
+                    sanitize_component(&value)
+                }
+            })
+            .collect();
+        if let Some(ext) = ext.filter(|_| !self.uses("ext")) {
+            name = format!("{}.{}", name, ext);
+        }
+        name
+    }
 
-            Session { folder_name, files }
+    fn uses(&self, variable: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|p| matches!(p, TemplatePart::Variable(v) if v == variable))
+    }
+}
+
+/// Replace characters that cannot appear in a folder name.
+pub fn sanitize_component(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
         })
         .collect()
 }
 
+/// Whether `component` names a folder of its own, rather than being empty,
+/// `.` or `..`.
+fn is_folder_name(component: &str) -> bool {
+    !matches!(component, "" | "." | "..")
+}
+
+/// `value` [made safe](sanitize_component) as one folder name, or `None` if
+/// that leaves it empty, `.` or `..`.
+pub fn folder_component(value: &str) -> Option<String> {
+    let component = sanitize_component(value);
+    is_folder_name(&component).then_some(component)
+}
+
+/// Name sessions from `template`. Sessions whose names come out the same are
+/// told apart with a letter suffix: "2024-01-15_a", "2024-01-15_b", etc.,
+/// continuing with "_aa", "_ab" after "_z".
+pub fn apply_folder_template(
+    sessions: &mut [Session],
+    template: &FolderTemplate,
+    event: Option<&str>,
+) {
+    let names: Vec<String> = sessions
+        .iter()
+        .enumerate()
+        .map(|(index, session)| template.render(index, &session.files, event))
+        .collect();
+
+    // Group sessions by name to detect collisions
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for name in &names {
+        *name_counts.entry(name).or_insert(0) += 1;
+    }
+
+    // Track how many times we've seen each name so far for suffix assignment
+    let mut name_seen: HashMap<&str, usize> = HashMap::new();
+
+    for (session, name) in sessions.iter_mut().zip(&names) {
+        session.folder_name = if name_counts[name.as_str()] == 1 {
+            name.clone()
+        } else {
+            let idx = name_seen.entry(name).or_insert(0);
+            let suffix = letter_suffix(*idx);
+            *idx += 1;
+            format!("{}_{}", name, suffix)
+        };
+    }
+}
+
+/// Folders the session folders are nested in under the output root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Layout {
+    /// Session folders right in the output root
+    Flat,
+    /// In a folder per year: `2024/2024-06-14_a`
+    Year,
+    /// In a folder per year and month: `2024/06/2024-06-14_a`
+    YearMonth,
+}
+
+/// Nest each session's folder as `layout` says, by the date of its first
+/// file like the folder template.
+pub fn apply_layout(sessions: &mut [Session], layout: Layout) {
+    let format = match layout {
+        Layout::Flat => return,
+        Layout::Year => "%Y",
+        Layout::YearMonth => "%Y/%m",
+    };
+    for session in sessions {
+        if let Some(first) = session.files.first() {
+            session.folder_name =
+                format!("{}/{}", first.datetime.format(format), session.folder_name);
+        }
+    }
+}
+
+/// `a` to `z` for 0 to 25, then `aa`, `ab`, ... like spreadsheet columns.
+pub fn letter_suffix(mut index: usize) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    letters.iter().rev().map(|&b| b as char).collect()
+}
+
+/// Folder gathering the sessions below `--min-session-files` with
+/// `--stragglers misc`.
+pub const MISC_FOLDER: &str = "_misc";
+
+/// Where the files of sessions smaller than `--min-session-files` go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Stragglers {
+    /// Into the session closest in time, before or after
+    Nearest,
+    /// Into one `_misc` folder
+    Misc,
+}
+
+/// Merge every session of fewer than `min_files` files into the neighbouring
+/// session closest in time, smallest first, until none is left or only one
+/// session remains. Folder names are left for the template to redo.
+pub fn merge_small_sessions(mut sessions: Vec<Session>, min_files: usize) -> Vec<Session> {
+    let first = |s: &Session| s.files.iter().map(|f| f.datetime).min();
+    let last = |s: &Session| s.files.iter().map(|f| f.datetime).max();
+    let gap = |a: &Session, b: &Session| Some((first(b)? - last(a)?).num_seconds().abs());
+
+    while sessions.len() > 1 {
+        let Some(i) = (0..sessions.len())
+            .filter(|&i| sessions[i].files.len() < min_files)
+            .min_by_key(|&i| sessions[i].files.len())
+        else {
+            break;
+        };
+        let before = i
+            .checked_sub(1)
+            .and_then(|p| gap(&sessions[p], &sessions[i]));
+        let after = sessions.get(i + 1).and_then(|next| gap(&sessions[i], next));
+        let into_previous = match (before, after) {
+            (Some(before), Some(after)) => before <= after,
+            _ => i > 0,
+        };
+        let small = sessions.remove(i);
+        log::debug!(
+            "Merging session {} of {} files into its neighbour",
+            small.folder_name,
+            small.files.len()
+        );
+        if into_previous {
+            sessions[i - 1].files.extend(small.files);
+        } else {
+            let next = &mut sessions[i].files;
+            let later = std::mem::replace(next, small.files);
+            next.extend(later);
+        }
+    }
+    sessions
+}
+
+/// Remove the sessions of fewer than `min_files` files from `sessions` and
+/// return their files as one session named [`MISC_FOLDER`], if there were any.
+pub fn take_small_sessions(sessions: &mut Vec<Session>, min_files: usize) -> Option<Session> {
+    let (small, kept): (Vec<Session>, Vec<Session>) = std::mem::take(sessions)
+        .into_iter()
+        .partition(|s| s.files.len() < min_files);
+    *sessions = kept;
+    (!small.is_empty()).then(|| Session {
+        folder_name: MISC_FOLDER.to_string(),
+        files: small.into_iter().flat_map(|s| s.files).collect(),
+    })
+}
+
+/// Split sessions where consecutive files were taken more than `km` apart,
+/// for travel days with several shoots inside the gap. Files without a GPS
+/// position stay with the file before them. The pieces keep the session's
+/// name until the folder template is applied again.
+pub fn split_by_location(sessions: Vec<Session>, km: f64) -> Vec<Session> {
+    split_by_location_with(sessions, km, metadata::location)
+}
+
+fn split_by_location_with(
+    sessions: Vec<Session>,
+    km: f64,
+    location_of: impl Fn(&Path) -> Option<Location>,
+) -> Vec<Session> {
+    let mut split = Vec::new();
+    for session in sessions {
+        let mut last: Option<Location> = None;
+        let mut current = Vec::new();
+        for file in session.files {
+            if let Some(here) = location_of(&file.path) {
+                if last.is_some_and(|last| last.distance_km(&here) > km) && !current.is_empty() {
+                    split.push(Session {
+                        folder_name: session.folder_name.clone(),
+                        files: std::mem::take(&mut current),
+                    });
+                }
+                last = Some(here);
+            }
+            current.push(file);
+        }
+        if !current.is_empty() {
+            split.push(Session {
+                folder_name: session.folder_name,
+                files: current,
+            });
+        }
+    }
+    split
+}
+
+/// Split each session into a subfolder per camera body, such as
+/// `2024-01-15/CanonEOSR5`. Bodies of the same model are told apart by serial
+/// number. Files without camera metadata, such as videos, stay in the session
+/// folder itself.
+pub fn split_by_camera(sessions: Vec<Session>) -> Vec<Session> {
+    let mut split = Vec::new();
+    for session in sessions {
+        let mut groups: Vec<(Option<Camera>, Vec<DatedFile>)> = Vec::new();
+        for file in session.files {
+            let camera = file.camera.clone();
+            match groups.iter_mut().find(|(c, _)| *c == camera) {
+                Some((_, files)) => files.push(file),
+                None => groups.push((camera, vec![file])),
+            }
+        }
+
+        let labels: Vec<Option<String>> = groups
+            .iter()
+            .map(|(camera, _)| camera.as_ref().map(Camera::label))
+            .collect();
+        for ((camera, files), label) in groups.into_iter().zip(&labels) {
+            let folder_name = match (camera, label) {
+                (Some(camera), Some(label)) => {
+                    let shared = labels.iter().filter(|l| l.as_ref() == Some(label)).count() > 1;
+                    let name = match camera.serial {
+                        Some(serial) if shared => format!("{}_{}", label, serial),
+                        _ => label.clone(),
+                    };
+                    let name = folder_component(&name).unwrap_or_else(|| "unknown".to_string());
+                    format!("{}/{}", session.folder_name, name)
+                }
+                _ => session.folder_name.clone(),
+            };
+            split.push(Session { folder_name, files });
+        }
+    }
+    split
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,7 +626,11 @@ mod tests {
                 .unwrap()
                 .and_hms_opt(hour, 0, 0)
                 .unwrap(),
+            date_source: DateSource::Exif,
             sequence_number: Some(seq),
+            sidecars: Vec::new(),
+            identical: Vec::new(),
+            camera: None,
         }
     }
 
@@ -121,4 +656,252 @@ mod tests {
         assert_eq!(sessions[0].folder_name, "2024-01-15_a");
         assert_eq!(sessions[1].folder_name, "2024-01-15_b");
     }
+
+    #[test]
+    fn test_sort_strategies() {
+        // Two cameras numbering their files independently, shooting the
+        // morning and the afternoon together
+        let scanned = vec![
+            make_file(16, 1),
+            make_file(8, 5),
+            make_file(17, 7),
+            make_file(9, 9),
+            make_file(8, 3),
+        ];
+        let sorted = |sort| {
+            let mut files = scanned.clone();
+            sort_files(&mut files, sort);
+            files
+        };
+        let order = |files: &[DatedFile]| {
+            files
+                .iter()
+                .map(|f| f.sequence_number.unwrap())
+                .collect::<Vec<_>>()
+        };
+        let sizes = |files| {
+            group_into_sessions(files, 6.0)
+                .iter()
+                .map(|s| s.files.len())
+                .collect::<Vec<_>>()
+        };
+
+        // Interleaved by number, the cameras' files break into many sessions
+        assert_eq!(order(&sorted(Sort::Seq)), [1, 3, 5, 7, 9]);
+        assert_eq!(sizes(sorted(Sort::Seq)), [1, 2, 1, 1]);
+
+        assert_eq!(order(&sorted(Sort::Time)), [5, 3, 9, 1, 7]);
+        assert_eq!(order(&sorted(Sort::Hybrid)), [3, 5, 9, 1, 7]);
+        assert_eq!(sizes(sorted(Sort::Hybrid)), [3, 2]);
+    }
+
+    #[test]
+    fn test_auto_gap_splits_at_the_widest_break() {
+        // Shots every 2 minutes, a 20 minute pause, then 3 hours until the next shoot
+        let start = make_file(8, 1).datetime;
+        let minutes = [0, 2, 4, 24, 26, 206, 208, 210];
+        let files: Vec<DatedFile> = minutes
+            .iter()
+            .enumerate()
+            .map(|(i, &m)| DatedFile {
+                datetime: start + chrono::Duration::minutes(m),
+                ..make_file(8, i as u64 + 1)
+            })
+            .collect();
+        assert_eq!(auto_gap_hours(&files), 1.0);
+        let gap = Gap::parse("auto").unwrap().hours_for(&files);
+        let sizes: Vec<usize> = group_into_sessions(files, gap)
+            .iter()
+            .map(|s| s.files.len())
+            .collect();
+        assert_eq!(sizes, [5, 3]);
+
+        // Evenly spaced files have no break to find
+        let files = vec![make_file(8, 1), make_file(9, 2), make_file(10, 3)];
+        assert_eq!(auto_gap_hours(&files), DEFAULT_GAP_HOURS);
+        assert_eq!(Gap::parse("1.5"), Ok(Gap::Hours(1.5)));
+        assert!(Gap::parse("soon").is_err());
+    }
+
+    #[test]
+    fn test_small_sessions_merge_or_go_to_misc() {
+        // A test shot at 7am, a shoot from 10am, one stray file at 11pm
+        let files = vec![
+            make_file(7, 1),
+            make_file(10, 2),
+            make_file(11, 3),
+            make_file(12, 4),
+            make_file(23, 5),
+        ];
+        let sessions = group_into_sessions(files, 2.0);
+        assert_eq!(sessions.len(), 3);
+
+        let sizes =
+            |sessions: &[Session]| sessions.iter().map(|s| s.files.len()).collect::<Vec<_>>();
+        let merged = merge_small_sessions(sessions.clone(), 2);
+        assert_eq!(sizes(&merged), [5]);
+        let seqs: Vec<_> = merged[0]
+            .files
+            .iter()
+            .map(|f| f.sequence_number.unwrap())
+            .collect();
+        assert_eq!(seqs, [1, 2, 3, 4, 5]);
+
+        let mut kept = sessions;
+        let misc = take_small_sessions(&mut kept, 2).unwrap();
+        assert_eq!(sizes(&kept), [3]);
+        assert_eq!(misc.folder_name, MISC_FOLDER);
+        assert_eq!(sizes(&[misc]), [2]);
+    }
+
+    #[test]
+    fn test_many_sessions_with_the_same_name() {
+        // 30 sessions, all named by {year}
+        let mut sessions: Vec<Session> = (0..30)
+            .map(|i| Session {
+                folder_name: String::new(),
+                files: vec![make_file(0, i + 1)],
+            })
+            .collect();
+        apply_folder_template(
+            &mut sessions,
+            &FolderTemplate::parse("{year}").unwrap(),
+            None,
+        );
+        let names: Vec<&str> = sessions.iter().map(|s| s.folder_name.as_str()).collect();
+        assert_eq!(
+            names[25..],
+            ["2024_z", "2024_aa", "2024_ab", "2024_ac", "2024_ad"]
+        );
+        assert_eq!(letter_suffix(26 * 27), "aaa");
+    }
+
+    #[test]
+    fn test_folder_template() {
+        let files = vec![
+            make_file(8, 1),
+            make_file(9, 2),
+            make_file(16, 3),
+            make_file(17, 4),
+        ];
+        let mut sessions = group_into_sessions(files, 6.0);
+
+        let template = FolderTemplate::parse("{year}/{month}/{date}_{event}").unwrap();
+        apply_folder_template(&mut sessions, &template, Some("Wedding: Day 1"));
+        assert_eq!(
+            sessions[0].folder_name,
+            "2024/01/2024-01-15_Wedding_ Day 1_a"
+        );
+        assert_eq!(
+            sessions[1].folder_name,
+            "2024/01/2024-01-15_Wedding_ Day 1_b"
+        );
+
+        // Nor can a value leave the output or be no folder at all
+        let template = FolderTemplate::parse("{event}/{camera}").unwrap();
+        apply_folder_template(&mut sessions, &template, Some(".."));
+        assert_eq!(sessions[0].folder_name, "unknown/unknown_a");
+        assert_eq!(folder_component(" .. "), None);
+        assert_eq!(folder_component("a/.."), Some("a_..".to_string()));
+
+        let template = FolderTemplate::parse("{date}_{time}_{index}_{count}").unwrap();
+        apply_folder_template(&mut sessions, &template, None);
+        assert_eq!(sessions[0].folder_name, "2024-01-15_0800_1_2");
+        assert_eq!(sessions[1].folder_name, "2024-01-15_1600_2_2");
+
+        apply_folder_template(&mut sessions, &FolderTemplate::default(), None);
+        apply_layout(&mut sessions, Layout::YearMonth);
+        assert_eq!(sessions[0].folder_name, "2024/01/2024-01-15_a");
+        assert_eq!(sessions[1].folder_name, "2024/01/2024-01-15_b");
+    }
+
+    #[test]
+    fn test_split_by_camera() {
+        let camera = |model: &str, serial: &str| Camera {
+            make: Some("Canon".to_string()),
+            model: model.to_string(),
+            serial: Some(serial.to_string()),
+        };
+        let files: Vec<DatedFile> = (1..=6)
+            .map(|seq| DatedFile {
+                camera: match seq {
+                    1 | 3 => Some(camera("Canon EOS R5", "0101")),
+                    2 => Some(camera("Canon EOS R5", "0202")),
+                    4 => Some(camera("EOS 5D Mark IV", "0303")),
+                    5 => Some(Camera {
+                        make: None,
+                        ..camera(" .. ", "0404")
+                    }),
+                    _ => None,
+                },
+                ..make_file(10, seq)
+            })
+            .collect();
+        let sessions = split_by_camera(group_into_sessions(files, 6.0));
+        let names: Vec<(&str, usize)> = sessions
+            .iter()
+            .map(|s| (s.folder_name.as_str(), s.files.len()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("2024-01-15/CanonEOSR5_0101", 2),
+                ("2024-01-15/CanonEOSR5_0202", 1),
+                ("2024-01-15/CanonEOS5DMarkIV", 1),
+                // A model that would name the session's parent folder
+                ("2024-01-15/unknown", 1),
+                ("2024-01-15", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_by_location() {
+        let files: Vec<DatedFile> = (1..=5).map(|seq| make_file(10, seq)).collect();
+        let mut sessions = group_into_sessions(files, 6.0);
+        let at = |longitude| {
+            Some(Location {
+                latitude: 45.0,
+                longitude,
+            })
+        };
+
+        // One degree of longitude at 45°N is about 79 km
+        sessions = split_by_location_with(sessions, 20.0, |path| match path.to_str().unwrap() {
+            "IMG_0001.CR2" => at(6.0),
+            "IMG_0002.CR2" => at(6.1),
+            "IMG_0004.CR2" => at(7.0),
+            "IMG_0005.CR2" => at(7.05),
+            _ => None,
+        });
+        let counts: Vec<usize> = sessions.iter().map(|s| s.files.len()).collect();
+        assert_eq!(counts, [3, 2]);
+
+        apply_folder_template(&mut sessions, &FolderTemplate::default(), None);
+        assert_eq!(sessions[0].folder_name, "2024-01-15_a");
+        assert_eq!(sessions[1].folder_name, "2024-01-15_b");
+    }
+
+    #[test]
+    fn test_name_template() {
+        let file = make_file(14, 42);
+        let template = NameTemplate::parse("{date}_{time}_{seq}_{name}.{ext}").unwrap();
+        assert_eq!(
+            template.render(&file, 0, Some("cr2")),
+            "20240115_1400_0042_IMG_0042.cr2"
+        );
+        let template = NameTemplate::parse("{date}-{seq}").unwrap();
+        assert_eq!(template.render(&file, 0, Some("CR2")), "20240115-0042.CR2");
+        assert!(NameTemplate::parse("{date}/{seq}").is_err());
+        assert!(NameTemplate::parse("{index}").is_err());
+    }
+
+    #[test]
+    fn test_folder_template_rejects_bad_patterns() {
+        assert!(FolderTemplate::parse("{date}_{lens}").is_err());
+        assert!(FolderTemplate::parse("{date").is_err());
+        assert!(FolderTemplate::parse("/photos/{date}").is_err());
+        assert!(FolderTemplate::parse("../{date}").is_err());
+        assert!(FolderTemplate::parse("").is_err());
+    }
 }
@@ -1,44 +1,246 @@
-use anyhow::Result;
+use crate::journal::Fingerprint;
+use crate::remote::Destination;
+use crate::store::Store;
+use crate::Session;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
 const STATE_FILENAME: &str = ".image-processor-state.json";
 
+/// Layout of the state files this version writes. Raised when a change needs
+/// more than a new field with a default, with a step in [`migrate`] from the
+/// previous layout.
+pub const STATE_VERSION: u64 = 2;
+
+/// A state written by a newer version of the tool, which this one cannot
+/// resume without losing what the newer one recorded.
+#[derive(Debug)]
+pub struct NewerVersion {
+    pub location: String,
+    pub version: u64,
+    pub supported: u64,
+}
+
+impl std::fmt::Display for NewerVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The import state in {} was written by a newer version of image-processor \
+             (state version {}, this version reads up to {}). Upgrade image-processor to resume it",
+            self.location, self.version, self.supported
+        )
+    }
+}
+
+impl std::error::Error for NewerVersion {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TransferState {
+    /// Layout of the file the state was read from, [`STATE_VERSION`] once
+    /// migrated
+    pub version: u64,
     pub transfer_id: String,
     pub completed_files: HashSet<String>,
     pub total_files: usize,
     pub total_bytes: u64,
+    /// Bytes of the files in `completed_files`
+    #[serde(default)]
+    pub done_bytes: u64,
+    /// Destination files being copied when the state was last saved, with
+    /// the number of bytes already written
+    #[serde(default)]
+    pub in_progress: HashMap<String, u64>,
+    /// Files copied to the backup destination, when the import writes one.
+    /// Tracked apart from `completed_files` so either copy can be resumed
+    /// on its own.
+    #[serde(default)]
+    pub backup_completed: Option<HashSet<String>>,
+    /// Size and modified time of the sources in `completed_files` when they
+    /// were copied, so a card reformatted and shot again since is noticed
+    #[serde(default)]
+    pub sources: HashMap<String, Fingerprint>,
+    /// Session folder each file goes to, so the sessions an interrupted
+    /// import left incomplete can be told
+    #[serde(default)]
+    pub sessions: HashMap<String, String>,
+    /// Changes not yet written to the store
+    #[serde(skip)]
+    changes: Vec<Change>,
+}
+
+/// One update to a [`TransferState`], so the store can write just what
+/// changed instead of the whole state after every file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Change {
+    Completed(String),
+    BackedUp(String),
+    Partial(String, u64),
+    PartialDone(String),
+    Source(String, Fingerprint),
+    /// The file goes to the session folder
+    Session(String, String),
+    /// The file is no longer completed, nor backed up
+    Forgotten(String),
 }
 
 impl TransferState {
     pub fn new(total_files: usize, total_bytes: u64) -> Self {
         Self {
+            version: STATE_VERSION,
             transfer_id: uuid_v4(),
             completed_files: HashSet::new(),
             total_files,
             total_bytes,
+            done_bytes: 0,
+            in_progress: HashMap::new(),
+            backup_completed: None,
+            sources: HashMap::new(),
+            sessions: HashMap::new(),
+            changes: Vec::new(),
         }
     }
 
+    /// Start tracking a backup destination, keeping what an earlier run of
+    /// this import already backed up.
+    pub fn track_backup(&mut self) {
+        self.backup_completed.get_or_insert_with(HashSet::new);
+    }
+
     pub fn is_completed(&self, file_key: &str) -> bool {
         self.completed_files.contains(file_key)
     }
 
-    pub fn mark_completed(&mut self, file_key: String) {
-        self.completed_files.insert(file_key);
+    /// Record that the file `file_key`, `bytes` long with its sidecars, has
+    /// reached the output.
+    pub fn mark_completed(&mut self, file_key: String, bytes: u64) {
+        if self.completed_files.insert(file_key.clone()) {
+            self.done_bytes += bytes;
+            self.changes.push(Change::Completed(file_key));
+        }
+    }
+
+    /// Remember what the source of the completed file `file_key` looked like
+    /// when it was copied.
+    pub fn record_source(&mut self, file_key: String, source: Fingerprint) {
+        self.sources.insert(file_key.clone(), source);
+        self.changes.push(Change::Source(file_key, source));
+    }
+
+    /// Whether `path`, the source of the completed file `file_key`, is no
+    /// longer the file that was copied. Files copied before sources were
+    /// recorded, or removed since by `--move`, are taken as unchanged.
+    pub fn source_changed(&self, file_key: &str, path: &Path) -> bool {
+        self.sources
+            .get(file_key)
+            .is_some_and(|copied| Fingerprint::of(path).is_ok_and(|now| now != *copied))
+    }
+
+    /// Record the session folder each file of `sessions` goes to.
+    pub fn plan_sessions(&mut self, sessions: &[Session], inputs: &[&Path]) {
+        for session in sessions {
+            for file in &session.files {
+                let key = file_key(&file.path, inputs);
+                if self.sessions.get(&key) != Some(&session.folder_name) {
+                    self.sessions
+                        .insert(key.clone(), session.folder_name.clone());
+                    self.changes
+                        .push(Change::Session(key, session.folder_name.clone()));
+                }
+            }
+        }
+    }
+
+    /// The files copied and the files there are in each session folder, by
+    /// folder name.
+    pub fn session_progress(&self) -> BTreeMap<&str, (usize, usize)> {
+        let mut progress = BTreeMap::new();
+        for (key, folder) in &self.sessions {
+            let (done, total) = progress.entry(folder.as_str()).or_insert((0, 0));
+            *done += usize::from(self.is_completed(key));
+            *total += 1;
+        }
+        progress
+    }
+
+    /// Mark the file `file_key`, `bytes` long with its sidecars, as not
+    /// copied anywhere, so it is copied again.
+    pub fn forget(&mut self, file_key: &str, bytes: u64) {
+        if self.completed_files.remove(file_key) {
+            self.done_bytes = self.done_bytes.saturating_sub(bytes);
+        }
+        if let Some(done) = self.backup_completed.as_mut() {
+            done.remove(file_key);
+        }
+        self.sources.remove(file_key);
+        self.changes.push(Change::Forgotten(file_key.to_string()));
+    }
+
+    /// Bytes still to copy to the output, counting what interrupted copies
+    /// already wrote.
+    pub fn remaining_bytes(&self) -> u64 {
+        let partial: u64 = self.in_progress.values().sum();
+        self.total_bytes.saturating_sub(self.done_bytes + partial)
+    }
+
+    /// Whether the file still has to be copied to the backup destination.
+    pub fn needs_backup(&self, file_key: &str) -> bool {
+        self.backup_completed
+            .as_ref()
+            .is_some_and(|done| !done.contains(file_key))
+    }
+
+    pub fn mark_backed_up(&mut self, file_key: String) {
+        if let Some(done) = self.backup_completed.as_mut() {
+            if done.insert(file_key.clone()) {
+                self.changes.push(Change::BackedUp(file_key));
+            }
+        }
+    }
+
+    /// Record that the first `offset` bytes of `dest` have been written.
+    pub fn record_partial(&mut self, dest: &Path, offset: u64) {
+        let dest = dest.to_string_lossy().into_owned();
+        self.in_progress.insert(dest.clone(), offset);
+        self.changes.push(Change::Partial(dest, offset));
+    }
+
+    /// How many bytes of `dest` an interrupted run had written, 0 if none.
+    pub fn partial_offset(&self, dest: &Path) -> u64 {
+        self.in_progress
+            .get(dest.to_string_lossy().as_ref())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Forget the partial copy of `dest` once it is complete.
+    pub fn clear_partial(&mut self, dest: &Path) {
+        if let Some((dest, _)) = self
+            .in_progress
+            .remove_entry(dest.to_string_lossy().as_ref())
+        {
+            self.changes.push(Change::PartialDone(dest));
+        }
+    }
+
+    /// Take the changes made since the last call.
+    pub(crate) fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
     }
 
     pub fn all_done(&self) -> bool {
         self.completed_files.len() >= self.total_files
+            && self
+                .backup_completed
+                .as_ref()
+                .is_none_or(|done| done.len() >= self.total_files)
     }
 }
 
 /// Generate a simple unique ID without pulling in the uuid crate.
-fn uuid_v4() -> String {
+pub fn uuid_v4() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
     let d = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -47,34 +249,113 @@ fn uuid_v4() -> String {
 }
 
 /// Build the canonical file key used to track completion.
-/// Uses the relative path from the input directory.
-pub fn file_key(file_path: &Path, input_dir: &Path) -> String {
-    file_path
-        .strip_prefix(input_dir)
-        .unwrap_or(file_path)
-        .to_string_lossy()
-        .to_string()
+/// Uses the relative path from the input directory holding the file,
+/// prefixed with that input's position (`2:DCIM/...`) when there are several
+/// so files at the same path on two cards stay apart.
+pub fn file_key(file_path: &Path, inputs: &[&Path]) -> String {
+    let found = inputs.iter().enumerate().find_map(|(i, dir)| {
+        file_path
+            .strip_prefix(dir)
+            .ok()
+            .map(|relative| (i, relative))
+    });
+    match found {
+        Some((i, relative)) if inputs.len() > 1 => {
+            format!("{}:{}", i + 1, relative.to_string_lossy())
+        }
+        Some((_, relative)) => relative.to_string_lossy().to_string(),
+        None => file_path.to_string_lossy().to_string(),
+    }
 }
 
 fn state_path(dir: &Path) -> PathBuf {
     dir.join(STATE_FILENAME)
 }
 
-/// Try to load an existing state file from the output directory, falling back to the input directory.
-pub fn load_state(input_dir: &Path, output_dir: &Path) -> Option<TransferState> {
+/// Try to load an existing state from the output directory's store, then
+/// from a state file in the output directory (written by older versions),
+/// falling back to the state file in the input directories. A state written
+/// by a newer version is an error rather than ignored, so resuming with an
+/// older tool does not start over.
+pub fn load_state(inputs: &[&Path], output_dir: &Path) -> Result<Option<TransferState>> {
     // Prefer output dir state (always writable)
-    load_from(output_dir).or_else(|| load_from(input_dir))
+    let stored = match Store::open_existing(output_dir) {
+        Ok(store) => store.and_then(|store| store.load_state().transpose()),
+        Err(e) => Some(Err(e)),
+    };
+    match stored {
+        Some(Ok(state)) => return Ok(Some(state)),
+        Some(Err(e)) if e.is::<NewerVersion>() => return Err(e),
+        Some(Err(e)) => log::warn!(
+            "Could not read the state database in {}: {:#}",
+            output_dir.display(),
+            e
+        ),
+        None => {}
+    }
+    for dir in std::iter::once(&output_dir).chain(inputs) {
+        if let Some(state) = load_from(dir)? {
+            return Ok(Some(state));
+        }
+    }
+    Ok(None)
 }
 
-fn load_from(dir: &Path) -> Option<TransferState> {
+fn load_from(dir: &Path) -> Result<Option<TransferState>> {
     let path = state_path(dir);
-    let data = fs::read_to_string(&path).ok()?;
-    serde_json::from_str(&data).ok()
+    let Ok(data) = fs::read(&path) else {
+        return Ok(None);
+    };
+    match parse_state(&data, &path.display().to_string()) {
+        Ok(state) => Ok(Some(state)),
+        Err(e) if e.is::<NewerVersion>() => Err(e),
+        Err(e) => {
+            log::warn!(
+                "Ignoring the unreadable state file {}: {:#}",
+                path.display(),
+                e
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Read a state file of any layout up to [`STATE_VERSION`], migrating older
+/// ones. Files from before the layout was versioned are version 1.
+fn parse_state(data: &[u8], location: &str) -> Result<TransferState> {
+    let mut value: serde_json::Value = serde_json::from_slice(data)?;
+    let version = value
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(1);
+    if version > STATE_VERSION {
+        return Err(NewerVersion {
+            location: location.to_string(),
+            version,
+            supported: STATE_VERSION,
+        }
+        .into());
+    }
+    migrate(&mut value, version)?;
+    Ok(serde_json::from_value(value)?)
 }
 
-/// Write state atomically to a directory. Returns Ok(()) even if the write
-/// fails on a read-only filesystem (best-effort for input/SD card).
-pub fn save_state(state: &TransferState, dir: &Path, best_effort: bool) -> Result<()> {
+/// Bring a state of layout `version` up to [`STATE_VERSION`], one layout at
+/// a time.
+fn migrate(value: &mut serde_json::Value, version: u64) -> Result<()> {
+    for from in version..STATE_VERSION {
+        match from {
+            // Version 1 lacks only fields that have defaults
+            1 => {}
+            _ => anyhow::bail!("No state file was ever written with version {}", from),
+        }
+    }
+    value["version"] = STATE_VERSION.into();
+    Ok(())
+}
+
+/// Write state atomically to a directory.
+pub fn save_state(state: &TransferState, dir: &Path) -> Result<()> {
     let target = state_path(dir);
     let tmp = dir.join(format!(".image-processor-state.tmp.{}", std::process::id()));
     let data = serde_json::to_string_pretty(state)?;
@@ -82,31 +363,159 @@ pub fn save_state(state: &TransferState, dir: &Path, best_effort: bool) -> Resul
     match fs::write(&tmp, &data) {
         Ok(()) => {
             fs::rename(&tmp, &target)?;
+            log::trace!(
+                "Saved state ({}/{} files) to {}",
+                state.completed_files.len(),
+                state.total_files,
+                target.display()
+            );
             Ok(())
         }
-        Err(e) if best_effort => {
-            // Silently ignore write failures on read-only media
+        Err(e) => {
             let _ = fs::remove_file(&tmp);
-            eprintln!(
-                "Warning: could not write state to {}: {}",
-                dir.display(),
-                e
-            );
-            Ok(())
+            Err(anyhow::Error::new(e).context(format!("Cannot write state to {}", dir.display())))
         }
-        Err(e) => Err(e.into()),
     }
 }
 
-/// Save state to both input (best-effort) and output (required) directories.
-pub fn save_state_both(state: &TransferState, input_dir: &Path, output_dir: &Path) -> Result<()> {
-    save_state(state, output_dir, false)?;
-    save_state(state, input_dir, true)?;
-    Ok(())
+/// Load the state kept at the root of a remote destination.
+pub fn load_remote(dest: &dyn Destination) -> Result<Option<TransferState>> {
+    match dest.read(Path::new(STATE_FILENAME))? {
+        Some(data) => Ok(Some(parse_state(&data, &dest.describe())?)),
+        None => Ok(None),
+    }
+}
+
+/// Write the state to the root of a remote destination, replacing the
+/// previous one only once it is complete.
+pub fn save_remote(state: &TransferState, dest: &dyn Destination) -> Result<()> {
+    let target = Path::new(STATE_FILENAME);
+    let data = serde_json::to_vec_pretty(state)?;
+    if dest.writes_in_place() {
+        dest.write(target, &mut data.as_slice(), None)?;
+        return Ok(());
+    }
+    let tmp = PathBuf::from(format!(".image-processor-state.tmp.{}", std::process::id()));
+    dest.write(&tmp, &mut data.as_slice(), None)?;
+    dest.rename(&tmp, target)
+        .with_context(|| format!("Cannot write state to {}", dest.describe()))
 }
 
-/// Remove state files from both directories after a successful transfer.
-pub fn cleanup_state(input_dir: &Path, output_dir: &Path) {
+/// Remove the state from a remote destination after a successful transfer.
+pub fn cleanup_remote(dest: &dyn Destination) {
+    if let Err(e) = dest.remove(Path::new(STATE_FILENAME)) {
+        log::warn!(
+            "Could not remove the state from {}: {:#}",
+            dest.describe(),
+            e
+        );
+    }
+}
+
+/// Remove the state from the output's store and the state files from the
+/// output and input directories after a successful transfer. The store's
+/// import history is kept.
+pub fn cleanup_state(inputs: &[&Path], output_dir: &Path) {
+    if let Ok(Some(store)) = Store::open_existing(output_dir) {
+        if let Err(e) = store.clear_state() {
+            log::warn!(
+                "Could not clear the state database in {}: {:#}",
+                output_dir.display(),
+                e
+            );
+        }
+    }
     let _ = fs::remove_file(state_path(output_dir));
-    let _ = fs::remove_file(state_path(input_dir));
+    for input_dir in inputs {
+        let _ = fs::remove_file(state_path(input_dir));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_key_tells_inputs_apart() {
+        let photo = Path::new("/slot2/DCIM/IMG_0001.CR2");
+        assert_eq!(file_key(photo, &[Path::new("/slot2")]), "DCIM/IMG_0001.CR2");
+        let inputs = [Path::new("/slot1"), Path::new("/slot2")];
+        assert_eq!(file_key(photo, &inputs), "2:DCIM/IMG_0001.CR2");
+        assert_eq!(
+            file_key(Path::new("/slot1/DCIM/IMG_0001.CR2"), &inputs),
+            "1:DCIM/IMG_0001.CR2"
+        );
+    }
+
+    #[test]
+    fn test_remaining_bytes() {
+        let mut state = TransferState::new(3, 100);
+        state.mark_completed("DCIM/IMG_0001.CR2".to_string(), 30);
+        state.mark_completed("DCIM/IMG_0001.CR2".to_string(), 30);
+        state.record_partial(Path::new("/out/2024-01-15/IMG_0002.cr2"), 20);
+        assert_eq!((state.done_bytes, state.remaining_bytes()), (30, 50));
+    }
+
+    #[test]
+    fn test_changed_source_is_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("IMG_0001.CR2");
+        fs::write(&source, b"first shot").unwrap();
+        let mut state = TransferState::new(1, 10);
+        state.track_backup();
+        state.mark_completed("IMG_0001.CR2".to_string(), 10);
+        state.mark_backed_up("IMG_0001.CR2".to_string());
+        state.record_source(
+            "IMG_0001.CR2".to_string(),
+            Fingerprint::of(&source).unwrap(),
+        );
+        assert!(!state.source_changed("IMG_0001.CR2", &source));
+
+        // The card was formatted and the number reused
+        fs::write(&source, b"another shot").unwrap();
+        assert!(state.source_changed("IMG_0001.CR2", &source));
+        state.forget("IMG_0001.CR2", 10);
+        assert!(!state.is_completed("IMG_0001.CR2") && state.needs_backup("IMG_0001.CR2"));
+        assert_eq!(state.done_bytes, 0);
+    }
+
+    #[test]
+    fn test_state_file_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        // Written before the layout was versioned
+        fs::write(
+            state_path(dir.path()),
+            r#"{"transfer_id":"old","completed_files":["DCIM/IMG_0001.CR2"],"total_files":2,"total_bytes":6}"#,
+        )
+        .unwrap();
+        let state = load_state(&[], dir.path()).unwrap().unwrap();
+        assert_eq!(
+            (state.version, state.transfer_id.as_str()),
+            (STATE_VERSION, "old")
+        );
+        assert!(state.is_completed("DCIM/IMG_0001.CR2"));
+
+        save_state(&state, dir.path()).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_slice(&fs::read(state_path(dir.path())).unwrap()).unwrap();
+        assert_eq!(saved["version"], STATE_VERSION);
+
+        fs::write(
+            state_path(dir.path()),
+            format!(r#"{{"version":{},"transfer_id":"new"}}"#, STATE_VERSION + 1),
+        )
+        .unwrap();
+        let error = load_state(&[], dir.path()).unwrap_err();
+        assert!(error.is::<NewerVersion>(), "{:#}", error);
+
+        // A version that never existed is an unreadable file, not a panic
+        let error = parse_state(br#"{"version":0,"transfer_id":"bad"}"#, "test").unwrap_err();
+        assert!(error.to_string().contains("version 0"), "{:#}", error);
+        fs::write(
+            state_path(dir.path()),
+            r#"{"version":0,"transfer_id":"bad"}"#,
+        )
+        .unwrap();
+        assert!(load_state(&[], dir.path()).unwrap().is_none());
+    }
 }
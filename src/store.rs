@@ -0,0 +1,405 @@
+use crate::journal::Fingerprint;
+use crate::state::{Change, NewerVersion, TransferState};
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+const DB_FILENAME: &str = ".image-processor.db";
+
+/// Layout of the database, kept in its `user_version`. Databases from before
+/// it was set read 0 and are migrated like version 1.
+const SCHEMA_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS transfer (
+        id TEXT PRIMARY KEY,
+        total_files INTEGER NOT NULL,
+        total_bytes INTEGER NOT NULL,
+        tracks_backup INTEGER NOT NULL,
+        done_bytes INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS completed (
+        key TEXT NOT NULL,
+        backup INTEGER NOT NULL,
+        PRIMARY KEY (key, backup)
+    );
+    CREATE TABLE IF NOT EXISTS source (
+        key TEXT PRIMARY KEY,
+        len INTEGER NOT NULL,
+        modified_ns INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS session (
+        key TEXT PRIMARY KEY,
+        folder TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS in_progress (
+        dest TEXT PRIMARY KEY,
+        offset INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        card TEXT NOT NULL,
+        file_id TEXT NOT NULL,
+        imported_at TEXT NOT NULL,
+        PRIMARY KEY (card, file_id)
+    );
+";
+
+/// Empties the tables of the import in progress.
+const CLEAR_STATE: &str =
+    "DELETE FROM transfer; DELETE FROM completed; DELETE FROM in_progress; DELETE FROM source; DELETE FROM session;";
+
+fn insert_source(
+    tx: &rusqlite::Transaction,
+    key: &str,
+    source: &Fingerprint,
+) -> rusqlite::Result<usize> {
+    tx.execute(
+        "INSERT OR REPLACE INTO source (key, len, modified_ns) VALUES (?1, ?2, ?3)",
+        params![key, source.len as i64, source.modified_ns as i64],
+    )
+}
+
+/// SQLite database in an output directory holding the state of the import
+/// in progress and the import history of every card. Each save writes only
+/// what changed, in one transaction, and the write-ahead log keeps the
+/// database consistent if the process or the machine dies mid-write.
+pub struct Store {
+    conn: Connection,
+}
+
+fn db_path(dir: &Path) -> PathBuf {
+    dir.join(DB_FILENAME)
+}
+
+impl Store {
+    /// Open the store of `dir`, creating it if needed.
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = db_path(dir);
+        let conn =
+            Connection::open(&path).with_context(|| format!("Cannot open {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(NewerVersion {
+                location: path.display().to_string(),
+                version: version as u64,
+                supported: SCHEMA_VERSION as u64,
+            }
+            .into());
+        }
+        conn.execute_batch(SCHEMA)?;
+        if version < 2 {
+            // Databases written before bytes were counted
+            let has_done_bytes = conn
+                .prepare("SELECT 1 FROM pragma_table_info('transfer') WHERE name = 'done_bytes'")?
+                .exists([])?;
+            if !has_done_bytes {
+                conn.execute_batch(
+                    "ALTER TABLE transfer ADD COLUMN done_bytes INTEGER NOT NULL DEFAULT 0",
+                )?;
+            }
+        }
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(Self { conn })
+    }
+
+    /// Open the store of `dir` if it has one, without creating it.
+    pub fn open_existing(dir: &Path) -> Result<Option<Self>> {
+        if db_path(dir).exists() {
+            Self::open(dir).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The state of the import in progress, if any.
+    pub fn load_state(&self) -> Result<Option<TransferState>> {
+        let transfer = self
+            .conn
+            .query_row(
+                "SELECT id, total_files, total_bytes, tracks_backup, done_bytes FROM transfer",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, bool>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((transfer_id, total_files, total_bytes, tracks_backup, done_bytes)) = transfer
+        else {
+            return Ok(None);
+        };
+        let mut state = TransferState::new(total_files as usize, total_bytes as u64);
+        state.transfer_id = transfer_id;
+        state.done_bytes = done_bytes as u64;
+
+        let mut backed_up = HashSet::new();
+        let mut stmt = self.conn.prepare("SELECT key, backup FROM completed")?;
+        for row in stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+        })? {
+            match row? {
+                (key, false) => state.completed_files.insert(key),
+                (key, true) => backed_up.insert(key),
+            };
+        }
+        state.backup_completed = tracks_backup.then_some(backed_up);
+        let mut stmt = self.conn.prepare("SELECT dest, offset FROM in_progress")?;
+        for row in stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })? {
+            let (dest, offset) = row?;
+            state.in_progress.insert(dest, offset as u64);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT key, len, modified_ns FROM source")?;
+        for row in stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })? {
+            let (key, len, modified_ns) = row?;
+            let source = Fingerprint {
+                len: len as u64,
+                modified_ns: modified_ns as u64,
+            };
+            state.sources.insert(key, source);
+        }
+        let mut stmt = self.conn.prepare("SELECT key, folder FROM session")?;
+        for row in stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })? {
+            let (key, folder) = row?;
+            state.sessions.insert(key, folder);
+        }
+        state.take_changes();
+        Ok(Some(state))
+    }
+
+    /// Write what changed in `state` since it was last saved. A state the
+    /// store does not hold yet, such as a new import or one resumed from a
+    /// state file, replaces the stored one whole.
+    pub fn save_state(&mut self, state: &mut TransferState) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        let stored_id: Option<String> = tx
+            .query_row("SELECT id FROM transfer", [], |row| row.get(0))
+            .optional()?;
+        let changes = state.take_changes();
+        if stored_id.as_deref() != Some(state.transfer_id.as_str()) {
+            tx.execute_batch(CLEAR_STATE)?;
+            let backed_up = state.backup_completed.iter().flatten();
+            for (key, backup) in state
+                .completed_files
+                .iter()
+                .map(|k| (k, false))
+                .chain(backed_up.map(|k| (k, true)))
+            {
+                tx.execute(
+                    "INSERT INTO completed (key, backup) VALUES (?1, ?2)",
+                    params![key, backup],
+                )?;
+            }
+            for (dest, offset) in &state.in_progress {
+                tx.execute(
+                    "INSERT INTO in_progress (dest, offset) VALUES (?1, ?2)",
+                    params![dest, *offset as i64],
+                )?;
+            }
+            for (key, source) in &state.sources {
+                insert_source(&tx, key, source)?;
+            }
+            for (key, folder) in &state.sessions {
+                tx.execute(
+                    "INSERT INTO session (key, folder) VALUES (?1, ?2)",
+                    params![key, folder],
+                )?;
+            }
+        } else {
+            for change in changes {
+                match change {
+                    Change::Completed(key) => tx.execute(
+                        "INSERT OR IGNORE INTO completed (key, backup) VALUES (?1, 0)",
+                        params![key],
+                    ),
+                    Change::BackedUp(key) => tx.execute(
+                        "INSERT OR IGNORE INTO completed (key, backup) VALUES (?1, 1)",
+                        params![key],
+                    ),
+                    Change::Partial(dest, offset) => tx.execute(
+                        "INSERT OR REPLACE INTO in_progress (dest, offset) VALUES (?1, ?2)",
+                        params![dest, offset as i64],
+                    ),
+                    Change::PartialDone(dest) => {
+                        tx.execute("DELETE FROM in_progress WHERE dest = ?1", params![dest])
+                    }
+                    Change::Source(key, source) => insert_source(&tx, &key, &source),
+                    Change::Session(key, folder) => tx.execute(
+                        "INSERT OR REPLACE INTO session (key, folder) VALUES (?1, ?2)",
+                        params![key, folder],
+                    ),
+                    Change::Forgotten(key) => tx
+                        .execute("DELETE FROM completed WHERE key = ?1", params![key])
+                        .and_then(|_| {
+                            tx.execute("DELETE FROM source WHERE key = ?1", params![key])
+                        }),
+                }?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO transfer (id, total_files, total_bytes, tracks_backup, done_bytes) \
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                state.transfer_id,
+                state.total_files as i64,
+                state.total_bytes as i64,
+                state.backup_completed.is_some(),
+                state.done_bytes as i64
+            ],
+        )?;
+        tx.commit()?;
+        log::trace!(
+            "Saved state ({}/{} files) to {}",
+            state.completed_files.len(),
+            state.total_files,
+            self.conn.path().unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    /// Forget the import in progress, keeping the history.
+    pub fn clear_state(&self) -> Result<()> {
+        self.conn.execute_batch(CLEAR_STATE)?;
+        Ok(())
+    }
+
+    /// Whether the history has the file `file_id` (see
+    /// [`crate::history::file_id`]) as imported from `card`.
+    pub fn is_imported(&self, card: &str, file_id: &str) -> Result<bool> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM history WHERE card = ?1 AND file_id = ?2",
+                params![card, file_id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Add files imported from `card` to the history.
+    pub fn record_imported(
+        &mut self,
+        card: &str,
+        file_ids: impl IntoIterator<Item = String>,
+    ) -> Result<()> {
+        let imported_at = chrono::Local::now().naive_local().to_string();
+        let tx = self.conn.transaction()?;
+        for file_id in file_ids {
+            tx.execute(
+                "INSERT OR IGNORE INTO history (card, file_id, imported_at) VALUES (?1, ?2, ?3)",
+                params![card, file_id, imported_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_saved_incrementally() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::open(dir.path()).unwrap();
+        assert!(store.load_state().unwrap().is_none());
+
+        let mut state = TransferState::new(2, 6);
+        state.track_backup();
+        for key in ["DCIM/IMG_0001.CR2", "DCIM/IMG_0002.CR2"] {
+            state
+                .sessions
+                .insert(key.to_string(), "2024-01-15".to_string());
+        }
+        state.mark_completed("DCIM/IMG_0001.CR2".to_string(), 3);
+        store.save_state(&mut state).unwrap();
+        state.mark_backed_up("DCIM/IMG_0001.CR2".to_string());
+        state.record_partial(Path::new("/out/2024-01-15/IMG_0002.cr2"), 4);
+        let source = Fingerprint {
+            len: 3,
+            modified_ns: 1_700_000_000_000_000_000,
+        };
+        state.record_source("DCIM/IMG_0001.CR2".to_string(), source);
+        store.save_state(&mut state).unwrap();
+
+        let loaded = Store::open_existing(dir.path())
+            .unwrap()
+            .unwrap()
+            .load_state()
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.transfer_id, state.transfer_id);
+        assert_eq!(loaded.completed_files, state.completed_files);
+        assert_eq!(loaded.backup_completed, state.backup_completed);
+        assert_eq!(loaded.in_progress, state.in_progress);
+        assert_eq!(loaded.sources, state.sources);
+        assert_eq!(loaded.sessions, state.sessions);
+        assert_eq!(loaded.session_progress().get("2024-01-15"), Some(&(1, 2)));
+        assert_eq!(
+            (loaded.total_files, loaded.total_bytes, loaded.done_bytes),
+            (2, 6, 3)
+        );
+
+        // A new import replaces the old state, the history stays
+        store
+            .record_imported("uuid:1234-ABCD", ["IMG_0001.CR2|3|0".to_string()])
+            .unwrap();
+        store.save_state(&mut TransferState::new(1, 3)).unwrap();
+        let loaded = store.load_state().unwrap().unwrap();
+        assert!(loaded.completed_files.is_empty() && loaded.backup_completed.is_none());
+        store.clear_state().unwrap();
+        assert!(store.load_state().unwrap().is_none());
+        assert!(store
+            .is_imported("uuid:1234-ABCD", "IMG_0001.CR2|3|0")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_open_upgrades_old_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = Connection::open(db_path(dir.path())).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE transfer (id TEXT PRIMARY KEY, total_files INTEGER NOT NULL, \
+             total_bytes INTEGER NOT NULL, tracks_backup INTEGER NOT NULL);
+             INSERT INTO transfer VALUES ('old', 2, 6, 0);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let state = Store::open(dir.path())
+            .unwrap()
+            .load_state()
+            .unwrap()
+            .unwrap();
+        assert_eq!((state.transfer_id.as_str(), state.done_bytes), ("old", 0));
+
+        // A database from a newer version is refused, not opened and changed
+        let conn = Connection::open(db_path(dir.path())).unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        drop(conn);
+        let error = Store::open(dir.path()).err().unwrap();
+        assert!(error.is::<NewerVersion>(), "{:#}", error);
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket capping the bytes written per second across all workers.
+/// Up to one second's worth of bytes can be written in a burst.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that may be written without waiting; negative while the
+    /// workers are ahead of the rate
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        Self {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `bytes` from the bucket, sleeping until the rate allows them.
+    pub fn take(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+            bucket.refilled = now;
+            bucket.tokens -= bytes as f64;
+            (-bucket.tokens / self.bytes_per_sec).max(0.0)
+        };
+        // Sleep outside the lock; later callers see the debt and wait longer
+        if wait > 0.0 {
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_paces_after_burst() {
+        let throttle = Throttle::new(1000);
+        let start = Instant::now();
+        throttle.take(1000);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        throttle.take(300);
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+}
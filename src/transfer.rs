@@ -1,108 +1,2961 @@
-use crate::session::Session;
+use crate::checksum::{self, HashAlgo, HashCache};
+use crate::control::{Control, Interrupted};
+use crate::duplicates::LibraryIndex;
+use crate::history;
+use crate::journal::{self, Fingerprint, ImportJournal};
+use crate::link;
+use crate::lock::{ImportLock, RemoteLock};
+use crate::manifest::{self, FileHash, ManifestEntry};
+use crate::metadata::Zone;
+use crate::preserve::{self, Attribute};
+use crate::preview;
+use crate::proxy;
+use crate::remote::{self, Destination};
+use crate::scanner;
+use crate::session::{DatedFile, NameTemplate, Session};
 use crate::state::{self, TransferState};
-use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::store::Store;
+use crate::throttle::Throttle;
+use crate::volumes;
+use anyhow::{Context, Result};
+use indicatif::DecimalBytes;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 const BUFFER_SIZE: usize = 256 * 1024; // 256 KB
 
+/// Bytes copied per call when the kernel copies a file, between progress
+/// updates.
+const KERNEL_COPY_CHUNK: usize = 8 * 1024 * 1024;
+
+/// How often the state records how far a large file's copy has got.
+const CHECKPOINT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Least time between two copies of the state mirrored to the inputs, as
+/// writing the whole state to a card after every file adds up on a slow one.
+const MIRROR_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Least time between two saves of the state to a remote output, where
+/// each save uploads the whole state again.
+const REMOTE_STATE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Size of the blocks a read that keeps failing is split into with
+/// [`Retry::salvage`], so one bad sector costs no more than itself.
+const SALVAGE_BLOCK: usize = 4096;
+
+/// Extensions `--split-by-type` files under `JPEG`, besides raws and videos.
+const JPEG_EXTENSIONS: &[&str] = &["jpg", "jpeg", "heic", "heif"];
+
+/// Extensions `--split-by-type` files under `VIDEO`.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "m4v", "avi", "mts"];
+
+/// Extensions `--split-by-type` files under `AUDIO`.
+const AUDIO_EXTENSIONS: &[&str] = &["wav", "mp3"];
+
+/// Longest file or folder name most filesystems accept, in bytes.
+const MAX_COMPONENT_BYTES: usize = 255;
+
+/// Appended to a destination's name while it is written, so an interrupted
+/// copy never looks like a complete file.
+pub const PART_SUFFIX: &str = ".part";
+
+/// Longest full destination path accepted by default, in bytes.
+/// Windows limits paths to MAX_PATH unless extended-length paths are used.
+pub const DEFAULT_MAX_PATH: usize = if cfg!(windows) { 260 } else { 4096 };
+
+/// Case applied to destination file extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ExtensionCase {
+    Lower,
+    Upper,
+    Preserve,
+}
+
+/// How a file is placed at its destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LinkMode {
+    /// Copy the data, cloning it when the filesystem supports it
+    Copy,
+    /// Hard-link to the source when both are on the same filesystem,
+    /// copying otherwise
+    Hardlink,
+    /// Symbolic link to the source's absolute path
+    Symlink,
+}
+
+/// What to do when a planned destination file already exists and was not
+/// written by this import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OnConflict {
+    /// Leave the existing file alone and do not copy the source
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Copy under the first free name with a numeric suffix (`IMG_0001_1.cr2`)
+    Rename,
+    /// Decide per file through [`Hooks::on_conflict`]
+    Ask,
+}
+
+/// What to do when a file cannot be transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OnError {
+    /// Stop the transfer once the other workers are done with their files
+    Abort,
+    /// Go on with the other files and fail the transfer at its end
+    Continue,
+}
+
+/// Options controlling how files are written to the destination.
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    pub dry_run: bool,
+    pub extension_case: ExtensionCase,
+    /// Put each session's files in `RAW`, `JPEG`, `VIDEO` and `AUDIO` subfolders by
+    /// extension (see [`type_folder`])
+    pub split_by_type: bool,
+    /// Put each GoPro recording split into chapters in a folder of its own
+    /// (see [`file_outputs`])
+    pub chapter_folders: bool,
+    /// Where the DJI previews attached to videos are copied
+    pub lrf: scanner::LrfMode,
+    /// Copy, hard-link or symlink files into the output. A backup is
+    /// always copied.
+    pub link_mode: LinkMode,
+    /// Also keep a copy of the state in the input directory. Only one
+    /// destination can own that copy, so this is off when routing to several.
+    pub mirror_state_to_input: bool,
+    /// Longest destination path allowed, in bytes
+    pub max_path: usize,
+    /// Hash the source while copying and compare with the destination read back
+    pub verify: bool,
+    /// Algorithm files are hashed with, to verify them and in manifests
+    pub hash_algo: HashAlgo,
+    /// Number of files transferred concurrently
+    pub jobs: usize,
+    /// Delete each source file once its copy is verified and recorded
+    pub move_sources: bool,
+    /// Skip files already anywhere under the output directory with the same
+    /// name, size and capture date
+    pub skip_duplicates: bool,
+    pub on_conflict: OnConflict,
+    pub on_error: OnError,
+    /// Zone the sessions' capture times were normalized to, used when reading
+    /// the dates of files already in the library
+    pub time_zone: Option<Zone>,
+    /// Flush each destination file and its folder to disk before the file
+    /// is marked as copied
+    pub fsync: bool,
+    /// Attributes copied from each source besides its modification time
+    pub preserve: Vec<Attribute>,
+    /// Second root every file is also written to, from the same read of
+    /// its source
+    pub backup_dir: Option<PathBuf>,
+    /// Most bytes written per second, shared by all workers
+    pub max_rate: Option<u64>,
+    /// How failed reads of a source are retried
+    pub retry: Retry,
+    /// Buffers between reading a source and writing its destinations
+    pub buffers: Buffers,
+    /// Start even when the pending files do not fit in a destination's free
+    /// space, with a warning
+    pub force: bool,
+    /// Break the lock another import holds on the output (see [`crate::lock`])
+    pub force_unlock: bool,
+    /// Card the input is on (see [`volumes::volume_id`]). Its imported files
+    /// are recorded in the output's import history.
+    pub card_id: Option<String>,
+    /// Leave out files the import history says were already imported from
+    /// this card, even by an import that has since completed
+    pub skip_imported: bool,
+    /// Write a `manifest.json` into every session folder describing the
+    /// files copied there
+    pub manifest: bool,
+    /// Write a checksum file, `SHA256SUMS` or the like for
+    /// [`TransferOptions::hash_algo`], into every session folder listing
+    /// the files copied there
+    pub checksum_file: bool,
+    /// Extract the full-size JPEG embedded in each CR2 into the session's
+    /// `previews` folder
+    pub thumbnails: bool,
+    /// Once the files are copied, render a JPEG preview of each photo no
+    /// larger than this many pixels into the session's `_previews` folder
+    pub previews: Option<u32>,
+    /// Command template (see [`proxy::make_proxy`]) run on every video
+    /// copied to the output to make a proxy in the session's `proxies` folder
+    pub proxy_command: Option<String>,
+    /// Name destination files with this template instead of their original
+    /// names. Resume state stays keyed on the source files.
+    pub rename: Option<NameTemplate>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            extension_case: ExtensionCase::Lower,
+            split_by_type: false,
+            chapter_folders: false,
+            lrf: scanner::LrfMode::Copy,
+            link_mode: LinkMode::Copy,
+            mirror_state_to_input: true,
+            max_path: DEFAULT_MAX_PATH,
+            verify: false,
+            hash_algo: HashAlgo::Sha256,
+            jobs: 1,
+            move_sources: false,
+            skip_duplicates: false,
+            on_conflict: OnConflict::Rename,
+            on_error: OnError::Abort,
+            time_zone: None,
+            fsync: false,
+            preserve: Vec::new(),
+            backup_dir: None,
+            max_rate: None,
+            retry: Retry::default(),
+            buffers: Buffers::default(),
+            force: false,
+            force_unlock: false,
+            card_id: None,
+            skip_imported: false,
+            manifest: false,
+            checksum_file: false,
+            thumbnails: false,
+            previews: None,
+            proxy_command: None,
+            rename: None,
+        }
+    }
+}
+
+/// How a read of a source that fails is retried. Flaky card readers often
+/// recover from an I/O error once the file is opened again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retry {
+    /// Attempts after a failed read before the file is given up on
+    pub retries: u32,
+    /// Wait before the first attempt, doubled for each one after
+    pub delay: Duration,
+    /// Instead of giving up on the file, read what failed block by block,
+    /// each tried `retries` times more, fill the blocks that still cannot
+    /// be read with zeros and copy the rest
+    pub salvage: bool,
+}
+
+/// The same as the command line's defaults.
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            delay: Duration::from_secs(1),
+            salvage: false,
+        }
+    }
+}
+
+/// Buffers passed from the thread reading a source to the thread writing
+/// its destinations, so the card and the destination are busy at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buffers {
+    /// Buffers in flight per file; the reader waits once they are all full
+    pub count: usize,
+    /// Bytes read into each buffer
+    pub size: usize,
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            size: BUFFER_SIZE,
+        }
+    }
+}
+
+/// Progress reported while a transfer runs. Workers report concurrently, so
+/// events from different workers interleave.
+#[derive(Debug)]
+pub enum TransferEvent<'a> {
+    /// Files completed by an interrupted run will be skipped
+    Resuming { completed: usize, total: usize },
+    /// Copying begins; `done_bytes` of `total_bytes` were copied by earlier runs
+    Started {
+        total_bytes: u64,
+        done_bytes: u64,
+        workers: usize,
+    },
+    /// A worker picked up a file; `bytes` includes its sidecars
+    FileStarted {
+        worker: usize,
+        name: &'a str,
+        bytes: u64,
+    },
+    /// A worker wrote `bytes` more bytes
+    Progress { worker: usize, bytes: u64 },
+    /// `source` was written to `dest` (`bytes` long), before any verification
+    Copied {
+        worker: usize,
+        source: &'a Path,
+        dest: &'a Path,
+        bytes: u64,
+    },
+    /// Reading `source` failed at byte `offset`; it is opened again and read
+    /// from there for the `attempt`th time
+    RetryingRead {
+        worker: usize,
+        source: &'a Path,
+        offset: u64,
+        attempt: u32,
+        error: &'a anyhow::Error,
+    },
+    /// A worker is reading a destination back to verify it
+    Verifying { worker: usize, name: &'a str },
+    /// Parts of `source` could not be read and were written as zeros, at
+    /// the byte ranges `damaged`
+    Salvaged {
+        worker: usize,
+        source: &'a Path,
+        damaged: &'a [Range<u64>],
+    },
+    /// A destination did not match its source after copying
+    ChecksumMismatch { source: &'a Path, dest: &'a Path },
+    /// `source` is not copied because an earlier run of this import did
+    AlreadyCopied { source: &'a Path },
+    /// An earlier run of this import copied `source`, but it has changed
+    /// since, so it is copied again
+    SourceChanged { source: &'a Path },
+    /// `source` is not copied because the library already has it at `existing`
+    Duplicate {
+        source: &'a Path,
+        existing: &'a Path,
+    },
+    /// A destination already existed; `dest` is where `source` goes instead,
+    /// or the existing file when it is skipped or overwritten
+    Conflict {
+        source: &'a Path,
+        existing: &'a Path,
+        dest: &'a Path,
+        resolution: OnConflict,
+    },
+    /// A worker is making a proxy of a copied video
+    MakingProxy { worker: usize, name: &'a str },
+    /// No proxy could be made of `source`; the video itself was copied
+    ProxyFailed {
+        source: &'a Path,
+        error: &'a anyhow::Error,
+    },
+    /// Previews of `total` copied photos are being rendered
+    RenderingPreviews { total: usize },
+    /// No preview could be written for `source`; the file itself was copied
+    PreviewFailed {
+        source: &'a Path,
+        error: &'a anyhow::Error,
+    },
+    /// `source` is written as `name` in `folder_name` instead of under its own
+    /// name, for `reason`
+    Renamed {
+        source: &'a Path,
+        folder_name: &'a str,
+        name: &'a str,
+        reason: RenameReason,
+    },
+    /// `root` has only `available` of the `needed` bytes free and the
+    /// transfer starts anyway, as forced
+    LowSpace {
+        root: &'a Path,
+        needed: u64,
+        available: u64,
+    },
+    /// The state could not be mirrored to the input `dir`, a read-only card
+    /// for instance; it is not tried again during this transfer
+    StateNotMirrored {
+        dir: &'a Path,
+        error: &'a anyhow::Error,
+    },
+    /// Dry run: `source` would be copied to `dest`
+    WouldCopy { source: &'a Path, dest: &'a Path },
+    /// `source` and its sidecars are transferred, verified and recorded
+    FileFinished { worker: usize, source: &'a Path },
+    /// Transferring `source` failed with `error`; the transfer stops unless
+    /// it goes on under [`OnError::Continue`]
+    Failed {
+        worker: usize,
+        source: &'a Path,
+        error: &'a anyhow::Error,
+    },
+    /// `source` was skipped through the transfer's [`Control`] before it was
+    /// done; what was written of it is resumed by the next run
+    Skipped { worker: usize, source: &'a Path },
+    /// Every file of the session going to `folder_name` was transferred
+    SessionFinished { folder_name: &'a str },
+    /// The session going to `folder_name` is done with, but `failed` of its
+    /// files did not pass verification, failed or were skipped
+    SessionFailed { folder_name: &'a str, failed: usize },
+    /// A worker has no more files to transfer
+    WorkerFinished { worker: usize },
+    /// Every file was transferred
+    Finished,
+    /// The state files were removed because nothing is left to resume
+    StateCleanedUp,
+}
+
+/// Why a file is not written under its own name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameReason {
+    /// The name is too long for the destination filesystem and was truncated
+    TooLong,
+    /// Another file of the session has the same name but for case
+    CaseConflict,
+}
+
+/// Error of a transfer that went through but for files that failed
+/// verification, or could not be transferred under [`OnError::Continue`].
+/// They are not marked as copied, so the next run copies them again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilesFailed {
+    pub mismatched: usize,
+    pub errors: usize,
+}
+
+impl std::fmt::Display for FilesFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut failed = Vec::new();
+        if self.mismatched > 0 {
+            failed.push(format!(
+                "{} file(s) failed checksum verification",
+                self.mismatched
+            ));
+        }
+        if self.errors > 0 {
+            failed.push(format!("{} file(s) could not be transferred", self.errors));
+        }
+        write!(
+            f,
+            "{} and were not marked as copied; re-run to copy them again",
+            failed.join(" and ")
+        )
+    }
+}
+
+impl std::error::Error for FilesFailed {}
+
+/// Callback receiving [`TransferEvent`]s, shared by all workers.
+pub type EventHandler<'a> = dyn Fn(TransferEvent<'_>) + Sync + 'a;
+
+/// Callback deciding a conflict under [`OnConflict::Ask`], given the source
+/// and the existing destination. Returning `Ask` again skips the file.
+pub type ConflictHandler<'a> = dyn Fn(&Path, &Path) -> OnConflict + Sync + 'a;
+
+/// Callbacks a transfer reports progress to and asks for decisions.
+pub struct Hooks<'a> {
+    pub on_event: Box<EventHandler<'a>>,
+    /// Called while planning, before any file is copied
+    pub on_conflict: Box<ConflictHandler<'a>>,
+    /// Pauses, skips and aborts asked of the workers from elsewhere
+    pub control: Option<&'a Control>,
+}
+
+impl Default for Hooks<'_> {
+    fn default() -> Self {
+        Self {
+            on_event: Box::new(|_| {}),
+            on_conflict: Box::new(|_, _| OnConflict::Skip),
+            control: None,
+        }
+    }
+}
+
+/// Imports sessions into one destination directory, resuming an interrupted
+/// run from its state file and journaling what it creates so it can be undone.
+pub struct Transfer<'a> {
+    inputs: Vec<PathBuf>,
+    output_dir: PathBuf,
+    options: TransferOptions,
+    hooks: Hooks<'a>,
+}
+
+impl<'a> Transfer<'a> {
+    pub fn new(input_dir: impl Into<PathBuf>, output_dir: impl Into<PathBuf>) -> Self {
+        Self::with_inputs([input_dir], output_dir)
+    }
+
+    /// Import from several input directories, such as the two slots of a
+    /// camera, into one destination.
+    pub fn with_inputs(
+        inputs: impl IntoIterator<Item = impl Into<PathBuf>>,
+        output_dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            inputs: inputs.into_iter().map(Into::into).collect(),
+            output_dir: output_dir.into(),
+            options: TransferOptions::default(),
+            hooks: Hooks::default(),
+        }
+    }
+
+    pub fn options(mut self, options: TransferOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Call `on_event` with progress as the transfer runs.
+    pub fn on_progress(mut self, on_event: impl Fn(TransferEvent<'_>) + Sync + 'a) -> Self {
+        self.hooks.on_event = Box::new(on_event);
+        self
+    }
+
+    /// Decide conflicts with `on_conflict` when the policy is [`OnConflict::Ask`].
+    pub fn on_conflict(
+        mut self,
+        on_conflict: impl Fn(&Path, &Path) -> OnConflict + Sync + 'a,
+    ) -> Self {
+        self.hooks.on_conflict = Box::new(on_conflict);
+        self
+    }
+
+    /// Let `control` pause the workers, skip the files they copy and abort
+    /// the transfer while it runs.
+    pub fn control(mut self, control: &'a Control) -> Self {
+        self.hooks.control = Some(control);
+        self
+    }
+
+    pub fn run(&self, sessions: &[Session]) -> Result<()> {
+        if let Some(dest) = remote::open(&self.output_dir)? {
+            return self.run_remote(&*dest, sessions);
+        }
+        let options = &self.options;
+        let inputs: Vec<&Path> = self.inputs.iter().map(|dir| dir.as_path()).collect();
+        let output_dir = self.output_dir.as_path();
+        let input_state_dirs: &[&Path] = if options.mirror_state_to_input {
+            &inputs
+        } else {
+            &[]
+        };
+        // Held until the transfer ends, so a second import into the same
+        // output stops before racing this one on its state
+        let mut locks = Vec::new();
+        if !options.dry_run {
+            fs::create_dir_all(output_dir)?;
+            locks.extend(ImportLock::acquire(output_dir, options.force_unlock)?);
+            if let Some(backup_dir) = &options.backup_dir {
+                fs::create_dir_all(backup_dir)?;
+                locks.extend(ImportLock::acquire(backup_dir, options.force_unlock)?);
+            }
+        }
+
+        // Load or create transfer state
+        let total_files = sessions.iter().map(|s| s.files.len()).sum::<usize>();
+        let total_bytes: u64 = sessions
+            .iter()
+            .flat_map(|s| &s.files)
+            .map(|f| f.total_size())
+            .sum();
+        // A dry run reads the state too, to show what a resume would skip
+        let mut transfer_state = match state::load_state(input_state_dirs, output_dir)? {
+            Some(existing) => {
+                let completed = existing.completed_files.len();
+                if completed > 0 {
+                    (self.hooks.on_event)(TransferEvent::Resuming {
+                        completed,
+                        total: total_files,
+                    });
+                }
+                existing
+            }
+            _ => TransferState::new(total_files, total_bytes),
+        };
+        if options.backup_dir.is_some() {
+            transfer_state.track_backup();
+        }
+        transfer_state.plan_sessions(sessions, &inputs);
+
+        // Files an earlier import took from this card count as done
+        let history_store = match &options.card_id {
+            Some(_) if options.skip_imported => Store::open_existing(output_dir)?,
+            _ => None,
+        };
+        if let (Some(card), Some(store)) = (&options.card_id, &history_store) {
+            for file in sessions.iter().flat_map(|s| &s.files) {
+                let key = state::file_key(&file.path, &inputs);
+                let Some(id) = history::file_id(&file.path, &key) else {
+                    continue;
+                };
+                if store.is_imported(card, &id)? {
+                    transfer_state.mark_completed(key.clone(), file.total_size());
+                    transfer_state.mark_backed_up(key);
+                }
+            }
+        }
+
+        // Record what this import creates so it can be undone
+        let mut import_journal =
+            journal::load_or_new(output_dir, &transfer_state.transfer_id, &inputs);
+        import_journal.moved_sources |= options.move_sources;
+
+        let result = transfer_sessions(
+            sessions,
+            output_dir,
+            &inputs,
+            &mut transfer_state,
+            &mut import_journal,
+            options,
+            &self.hooks,
+        );
+
+        // Remember what reached every destination, even if the transfer failed
+        if let (Some(card), false) = (&options.card_id, options.dry_run) {
+            let imported = imported_file_ids(sessions, &inputs, &transfer_state);
+            Store::open(output_dir)?.record_imported(card, imported)?;
+        }
+        result?;
+
+        // Cleanup state files on successful completion
+        if !options.dry_run && transfer_state.all_done() {
+            state::cleanup_state(input_state_dirs, output_dir);
+            (self.hooks.on_event)(TransferEvent::StateCleanedUp);
+        }
+        Ok(())
+    }
+}
+
+impl Transfer<'_> {
+    /// Import into a destination outside the local filesystem, which keeps
+    /// the state itself.
+    fn run_remote(&self, dest: &dyn Destination, sessions: &[Session]) -> Result<()> {
+        let options = &self.options;
+        let unsupported = [
+            (options.link_mode != LinkMode::Copy, "--link-mode"),
+            (options.move_sources, "--move"),
+            (options.backup_dir.is_some(), "--backup-dir"),
+            (options.skip_duplicates, "--skip-duplicates"),
+            (options.skip_imported, "--skip-imported"),
+            (!options.preserve.is_empty(), "--preserve"),
+            (options.thumbnails, "--thumbnails"),
+            (options.previews.is_some(), "--previews"),
+            (options.proxy_command.is_some(), "--proxy"),
+            (options.retry.salvage, "--salvage"),
+            (options.jobs > 1, "--jobs"),
+            // Remote outputs are read back as SHA-256
+            (options.hash_algo != HashAlgo::Sha256, "--hash-algo"),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|(used, _)| *used) {
+            anyhow::bail!("{} is not supported with a remote output", option);
+        }
+        let inputs: Vec<&Path> = self.inputs.iter().map(|dir| dir.as_path()).collect();
+        let _lock = if options.dry_run {
+            None
+        } else {
+            dest.create_dir_all(Path::new(""))?;
+            Some(RemoteLock::acquire(dest, options.force_unlock)?)
+        };
+
+        let total_files = sessions.iter().map(|s| s.files.len()).sum::<usize>();
+        let total_bytes = sessions
+            .iter()
+            .flat_map(|s| &s.files)
+            .map(|f| f.total_size())
+            .sum();
+        let mut state = match state::load_remote(dest)? {
+            Some(existing) => {
+                let completed = existing.completed_files.len();
+                if completed > 0 {
+                    (self.hooks.on_event)(TransferEvent::Resuming {
+                        completed,
+                        total: total_files,
+                    });
+                }
+                existing
+            }
+            None => TransferState::new(total_files, total_bytes),
+        };
+        state.plan_sessions(sessions, &inputs);
+        upload_sessions(
+            sessions,
+            &self.output_dir,
+            dest,
+            &inputs,
+            &mut state,
+            options,
+            &self.hooks,
+        )?;
+        if !options.dry_run && state.all_done() {
+            state::cleanup_remote(dest);
+            (self.hooks.on_event)(TransferEvent::StateCleanedUp);
+        }
+        Ok(())
+    }
+}
+
+/// History ids of the files of `sessions` that `state` has fully transferred.
+fn imported_file_ids<'a>(
+    sessions: &'a [Session],
+    inputs: &'a [&'a Path],
+    state: &'a TransferState,
+) -> impl Iterator<Item = String> + 'a {
+    sessions
+        .iter()
+        .flat_map(|s| &s.files)
+        .filter_map(move |file| {
+            let key = state::file_key(&file.path, inputs);
+            let done = state.is_completed(&key) && !state.needs_backup(&key);
+            done.then(|| history::file_id(&file.path, &key)).flatten()
+        })
+}
+
+/// One file (with its sidecars) waiting to be transferred.
+struct Job<'a> {
+    key: String,
+    file: &'a DatedFile,
+    folder_name: &'a str,
+    /// Session folder in the output, unless an earlier run already copied
+    /// the file there
+    session_dir: Option<PathBuf>,
+    /// Session folder in the backup, if the file still has to be backed up
+    backup_session_dir: Option<PathBuf>,
+    dest_name: String,
+}
+
+impl Job<'_> {
+    /// The session folders the file is written to, output first.
+    fn session_dirs(&self) -> impl Iterator<Item = &PathBuf> {
+        self.session_dir.iter().chain(&self.backup_session_dir)
+    }
+}
+
+/// Completion records shared by all workers, updated under one lock so the
+/// state and journal on disk always describe a consistent set of files.
+struct Records<'a> {
+    state: &'a mut TransferState,
+    journal: &'a mut ImportJournal,
+    /// Where the state is saved in the output, none in a dry run
+    store: Option<Store>,
+    /// Inputs the state could not be mirrored to, not tried again
+    unmirrored: HashSet<PathBuf>,
+    /// When the state was last mirrored to the inputs
+    mirrored_at: Option<Instant>,
+}
+
+impl Records<'_> {
+    /// Save the state's changes to the output's store, and the whole state
+    /// to the inputs as well when it is mirrored there, at most every
+    /// [`MIRROR_INTERVAL`]. The input copy is best-effort, as cards may be
+    /// read-only: the output's is enough to resume.
+    fn save_state(&mut self, input_state_dirs: &[&Path], on_event: &EventHandler) -> Result<()> {
+        if let Some(store) = &mut self.store {
+            store.save_state(self.state)?;
+        }
+        if self
+            .mirrored_at
+            .is_none_or(|at| at.elapsed() >= MIRROR_INTERVAL)
+        {
+            self.mirror_state(input_state_dirs, on_event);
+        }
+        Ok(())
+    }
+
+    /// Write the whole state to the inputs now.
+    fn mirror_state(&mut self, input_state_dirs: &[&Path], on_event: &EventHandler) {
+        self.mirrored_at = Some(Instant::now());
+        for input_dir in input_state_dirs {
+            if self.unmirrored.contains(*input_dir) {
+                continue;
+            }
+            if let Err(error) = state::save_state(self.state, input_dir) {
+                log::warn!("{:#}", error);
+                on_event(TransferEvent::StateNotMirrored {
+                    dir: input_dir,
+                    error: &error,
+                });
+                self.unmirrored.insert(input_dir.to_path_buf());
+            }
+        }
+    }
+}
+
+/// Everything a transfer worker needs besides the job itself.
+struct Worker<'a> {
+    output_dir: &'a Path,
+    inputs: &'a [&'a Path],
+    options: &'a TransferOptions,
+    /// Every input shares a filesystem with the output, so files may be
+    /// cloned or hard-linked
+    same_filesystem: bool,
+    records: &'a Mutex<Records<'a>>,
+    hash_cache: Option<&'a Mutex<HashCache>>,
+    throttle: Option<&'a Throttle>,
+    /// Manifest and checksum file entries of the verified copies, by session
+    /// folder
+    manifests: &'a Mutex<BTreeMap<PathBuf, Vec<ManifestEntry>>>,
+    /// Verified copies in the output to render previews of
+    photos: &'a Mutex<Vec<PathBuf>>,
+    on_event: &'a EventHandler<'a>,
+    control: Option<&'a Control>,
+}
+
+/// Reports bytes written by one worker and hands it the requests of the
+/// transfer's [`Control`].
+struct Progress<'a> {
+    worker: usize,
+    on_event: &'a EventHandler<'a>,
+    control: Option<&'a Control>,
+}
+
+impl Progress<'_> {
+    fn inc(&self, bytes: u64) {
+        (self.on_event)(TransferEvent::Progress {
+            worker: self.worker,
+            bytes,
+        });
+    }
+
+    /// Wait while the transfer is paused; fails with [`Interrupted`] when the
+    /// file is skipped or the transfer aborted.
+    fn check(&self) -> Result<()> {
+        match self.control {
+            Some(control) => Ok(control.check(self.worker)?),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Copy every pending file of `sessions` into `output_dir`, recording
+/// progress in `state` and created paths in `journal` as it goes.
 pub fn transfer_sessions(
     sessions: &[Session],
     output_dir: &Path,
-    input_dir: &Path,
+    inputs: &[&Path],
     state: &mut TransferState,
-    dry_run: bool,
+    journal: &mut ImportJournal,
+    options: &TransferOptions,
+    hooks: &Hooks,
 ) -> Result<()> {
-    let total_bytes: u64 = sessions
+    let dry_run = options.dry_run;
+    let same_filesystem = !dry_run
+        && inputs
+            .iter()
+            .all(|dir| link::same_filesystem(dir, output_dir));
+    let on_event = &*hooks.on_event;
+
+    // Plan every destination up front so bad paths fail before anything is copied
+    let mut plans: Vec<Vec<String>> = sessions
         .iter()
-        .flat_map(|s| &s.files)
-        .filter(|f| !state.is_completed(&state::file_key(&f.path, input_dir)))
-        .filter_map(|f| fs::metadata(&f.path).ok())
-        .map(|m| m.len())
-        .sum();
-
-    let pb = ProgressBar::new(total_bytes);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{msg}\n{wide_bar} {percent}% {bytes}/{total_bytes} [{eta}]")?
-            .progress_chars("=> "),
-    );
+        .map(|s| {
+            plan_names(
+                s,
+                options.extension_case,
+                options.rename.as_ref(),
+                |source, name, reason| {
+                    on_event(TransferEvent::Renamed {
+                        source,
+                        folder_name: &s.folder_name,
+                        name,
+                        reason,
+                    })
+                },
+            )
+        })
+        .collect();
+
+    // Index the library before this import adds to it
+    let library = options
+        .skip_duplicates
+        .then(|| LibraryIndex::build(output_dir, options.time_zone));
+
+    // Collect the files still to transfer
+    let mut jobs = Vec::new();
+    let mut total_bytes = 0;
+    let mut done_bytes = 0;
+    for (session, dest_names) in sessions.iter().zip(&mut plans) {
+        let session_dir = output_dir.join(&session.folder_name);
+        let backup_session_dir = options
+            .backup_dir
+            .as_ref()
+            .map(|dir| dir.join(&session.folder_name));
+        let mut taken: HashSet<String> = dest_names.iter().map(|n| n.to_lowercase()).collect();
+
+        for (file, dest_name) in session.files.iter().zip(dest_names.iter_mut()) {
+            let key = state::file_key(&file.path, inputs);
+            let size = file.total_size();
+            total_bytes += size;
+            if state.is_completed(&key) && state.source_changed(&key, &file.path) {
+                log::warn!(
+                    "{} changed since it was copied, copying it again",
+                    file.path.display()
+                );
+                on_event(TransferEvent::SourceChanged { source: &file.path });
+                state.forget(&key, size);
+            }
+            let copy = !state.is_completed(&key);
+            let backup = state.needs_backup(&key);
+            if !copy && !backup {
+                on_event(TransferEvent::AlreadyCopied { source: &file.path });
+                done_bytes += size;
+                continue;
+            }
+            // A file already copied to the output would find itself there
+            let duplicate = if copy {
+                library.as_ref().and_then(|index| index.find(file))
+            } else {
+                None
+            };
+            if let Some(existing) = duplicate {
+                log::info!(
+                    "Skipping {}: already in the library as {}",
+                    file.path.display(),
+                    existing.display()
+                );
+                on_event(TransferEvent::Duplicate {
+                    source: &file.path,
+                    existing,
+                });
+                if !dry_run {
+                    state.mark_completed(key.clone(), size);
+                    state.mark_backed_up(key);
+                }
+                done_bytes += size;
+                continue;
+            }
+
+            let job_dirs: Vec<&PathBuf> = copy
+                .then_some(&session_dir)
+                .into_iter()
+                .chain(backup_session_dir.as_ref().filter(|_| backup))
+                .collect();
+            // Files written by an interrupted run of this import are not conflicts
+            let existing = file_outputs(file, dest_name, options)
+                .into_iter()
+                .flat_map(|(_, name)| job_dirs.iter().map(move |dir| dir.join(&name)))
+                .find(|dest| dest.exists() && !journal.created_files.contains(dest));
+            if let Some(existing) = existing {
+                let resolution = match options.on_conflict {
+                    OnConflict::Ask if dry_run => OnConflict::Ask,
+                    OnConflict::Ask => match (hooks.on_conflict)(&file.path, &existing) {
+                        OnConflict::Ask => OnConflict::Skip,
+                        decided => decided,
+                    },
+                    policy => policy,
+                };
+                // Importing a card again once its state is gone finds its own copies
+                if resolution == OnConflict::Rename
+                    && already_there(file, dest_name, &job_dirs, options)?
+                {
+                    log::info!(
+                        "{} is already in the output as {}",
+                        file.path.display(),
+                        existing.display()
+                    );
+                    on_event(TransferEvent::AlreadyCopied { source: &file.path });
+                    if !dry_run {
+                        state.mark_completed(key.clone(), size);
+                        state.mark_backed_up(key);
+                    }
+                    done_bytes += size;
+                    continue;
+                }
+                if resolution == OnConflict::Rename {
+                    *dest_name = free_name(&job_dirs, file, dest_name, &taken, options);
+                    taken.insert(dest_name.to_lowercase());
+                }
+                log::info!(
+                    "{} already exists for {}: {:?}",
+                    existing.display(),
+                    file.path.display(),
+                    resolution
+                );
+                on_event(TransferEvent::Conflict {
+                    source: &file.path,
+                    existing: &existing,
+                    dest: &job_dirs[0].join(primary_name(file, dest_name, options)),
+                    resolution,
+                });
+                if matches!(resolution, OnConflict::Skip | OnConflict::Ask) {
+                    if !dry_run {
+                        state.mark_completed(key.clone(), size);
+                        state.mark_backed_up(key);
+                    }
+                    done_bytes += size;
+                    continue;
+                }
+            }
+
+            jobs.push(Job {
+                key,
+                file,
+                folder_name: &session.folder_name,
+                session_dir: copy.then(|| session_dir.clone()),
+                backup_session_dir: backup_session_dir.clone().filter(|_| backup),
+                dest_name: dest_name.clone(),
+            });
+        }
+    }
+    let roots: Vec<&Path> = std::iter::once(output_dir)
+        .chain(options.backup_dir.as_deref())
+        .collect();
+    for root in &roots {
+        check_path_lengths(root, sessions, &plans, options)?;
+    }
+    if !dry_run {
+        // Links take no space, unless a hard link falls back to copying, and
+        // neither do clones, which only the output gets
+        let output_links = match options.link_mode {
+            LinkMode::Copy => false,
+            LinkMode::Hardlink => same_filesystem,
+            LinkMode::Symlink => true,
+        };
+        let cloned = |job: &Job| job.backup_session_dir.is_none();
+        let output_clones = options.link_mode == LinkMode::Copy
+            && same_filesystem
+            && jobs
+                .iter()
+                .find(|job| cloned(job))
+                .is_some_and(|job| clones_into(&job.file.path, output_dir));
+        let output_bytes = jobs
+            .iter()
+            .filter(|job| !output_links && (!output_clones || !cloned(job)))
+            .filter_map(|job| {
+                Some(pending_bytes(
+                    job,
+                    job.session_dir.as_ref()?,
+                    state,
+                    journal,
+                    options,
+                ))
+            })
+            .sum();
+        check_free_space(output_dir, output_bytes, options.force, on_event)?;
+        if let Some(backup_dir) = &options.backup_dir {
+            let backup_bytes = jobs
+                .iter()
+                .filter_map(|job| {
+                    Some(pending_bytes(
+                        job,
+                        job.backup_session_dir.as_ref()?,
+                        state,
+                        journal,
+                        options,
+                    ))
+                })
+                .sum();
+            check_free_space(backup_dir, backup_bytes, options.force, on_event)?;
+        }
+    }
+
+    // Create session folders, recording each level a nested template adds
+    // and the type and recording folders the session's files go to
+    if !dry_run {
+        let planned = roots
+            .iter()
+            .flat_map(|root| sessions.iter().zip(&plans).map(move |s| (root, s)));
+        for (root, (session, dest_names)) in planned {
+            let session_dir = root.join(&session.folder_name);
+            let file_dirs = session
+                .files
+                .iter()
+                .zip(dest_names)
+                .flat_map(|(file, name)| file_outputs(file, name, options))
+                .flat_map(|(_, name)| {
+                    Path::new(&name)
+                        .ancestors()
+                        .skip(1)
+                        .map(Path::to_path_buf)
+                        .collect::<Vec<_>>()
+                })
+                .filter(|folder| !folder.as_os_str().is_empty())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|folder| session_dir.join(folder));
+            let mut dirs = Vec::new();
+            let mut dir = root.to_path_buf();
+            for component in Path::new(&session.folder_name).components() {
+                dir.push(component);
+                dirs.push(dir.clone());
+            }
+            for dir in dirs.into_iter().chain(file_dirs) {
+                if !dir.exists() {
+                    fs::create_dir(&dir)?;
+                    journal.record_dir(dir.clone());
+                    if options.fsync {
+                        sync_dir(dir.parent().unwrap_or(root))?;
+                    }
+                }
+            }
+        }
+    }
+
+    if !dry_run {
+        // Written in full once, so each file after only appends to its log
+        journal::save_journal(journal, output_dir)?;
+    }
+
+    // Dry-run output stays in order with a single worker
+    let workers = if dry_run {
+        1
+    } else {
+        options.jobs.clamp(1, jobs.len().max(1))
+    };
+    on_event(TransferEvent::Started {
+        total_bytes,
+        done_bytes,
+        workers,
+    });
+
+    let hash_cache = (options.verify && !dry_run).then(|| Mutex::new(HashCache::load(output_dir)));
+    let throttle = options.max_rate.map(Throttle::new);
+    let store = if dry_run {
+        None
+    } else {
+        Some(Store::open(output_dir)?)
+    };
+    let records = Mutex::new(Records {
+        state,
+        journal,
+        store,
+        unmirrored: HashSet::new(),
+        mirrored_at: None,
+    });
+    let manifests = Mutex::new(BTreeMap::new());
+    let photos = Mutex::new(Vec::new());
+    let worker = Worker {
+        output_dir,
+        inputs,
+        options,
+        same_filesystem,
+        records: &records,
+        hash_cache: hash_cache.as_ref(),
+        throttle: throttle.as_ref(),
+        manifests: &manifests,
+        photos: &photos,
+        on_event,
+        control: hooks.control,
+    };
+    let next = AtomicUsize::new(0);
+    let abort = AtomicBool::new(false);
+    let failed = AtomicUsize::new(0);
+    let errors = AtomicUsize::new(0);
+    // Files left to transfer and files that failed verification, per session folder
+    let mut pending: HashMap<&str, (usize, usize)> = HashMap::new();
+    for job in &jobs {
+        pending.entry(job.folder_name).or_default().0 += 1;
+    }
+    let pending = Mutex::new(pending);
+
+    let results: Vec<Result<()>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|id| {
+                let (worker, jobs, next, abort, failed, errors, pending) =
+                    (&worker, &jobs, &next, &abort, &failed, &errors, &pending);
+                scope.spawn(move || -> Result<()> {
+                    let result = (|| {
+                        while !abort.load(Ordering::Relaxed) {
+                            if worker.control.is_some_and(|control| !control.wait()) {
+                                break;
+                            }
+                            let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                                break;
+                            };
+                            let verified = match worker.run(id, job) {
+                                Ok(verified) => {
+                                    if !verified {
+                                        failed.fetch_add(1, Ordering::Relaxed);
+                                    }
+                                    verified
+                                }
+                                Err(e) => match e.downcast_ref::<Interrupted>() {
+                                    Some(Interrupted::Aborted) => break,
+                                    Some(Interrupted::Skipped) => {
+                                        log::info!("Skipped {}", job.file.path.display());
+                                        on_event(TransferEvent::Skipped {
+                                            worker: id,
+                                            source: &job.file.path,
+                                        });
+                                        false
+                                    }
+                                    None => {
+                                        let e = e.context(format!(
+                                            "Failed to transfer {}",
+                                            job.file.path.display()
+                                        ));
+                                        on_event(TransferEvent::Failed {
+                                            worker: id,
+                                            source: &job.file.path,
+                                            error: &e,
+                                        });
+                                        if worker.options.on_error == OnError::Abort {
+                                            // Let the other workers finish their current file and stop
+                                            abort.store(true, Ordering::Relaxed);
+                                            return Err(e);
+                                        }
+                                        log::error!("{:#}", e);
+                                        errors.fetch_add(1, Ordering::Relaxed);
+                                        false
+                                    }
+                                },
+                            };
+                            let (left, session_failed) = {
+                                let mut pending = pending.lock().unwrap();
+                                let (left, session_failed) = pending
+                                    .get_mut(job.folder_name)
+                                    .expect("every job is counted");
+                                *left -= 1;
+                                *session_failed += usize::from(!verified);
+                                (*left, *session_failed)
+                            };
+                            if left == 0 {
+                                worker.write_session_manifests(job.folder_name)?;
+                            }
+                            match (left, session_failed) {
+                                (0, 0) => on_event(TransferEvent::SessionFinished {
+                                    folder_name: job.folder_name,
+                                }),
+                                (0, failed) => on_event(TransferEvent::SessionFailed {
+                                    folder_name: job.folder_name,
+                                    failed,
+                                }),
+                                _ => {}
+                            }
+                        }
+                        Ok(())
+                    })();
+                    on_event(TransferEvent::WorkerFinished { worker: id });
+                    result
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("transfer worker panicked"))
+            .collect()
+    });
+
+    // Describe what was copied of the sessions left unfinished too
+    let manifests = std::mem::take(&mut *manifests.lock().unwrap());
+    write_manifests(manifests, &records, options)?;
+    let aborted = hooks.control.is_some_and(Control::is_aborted);
+    if let (Some(max_size), false) = (options.previews, aborted) {
+        let photos = std::mem::take(&mut *photos.lock().unwrap());
+        render_previews(&photos, max_size, &records, on_event)?;
+    }
+    if !dry_run {
+        let mut records = records.lock().unwrap();
+        // Files done since the state was last mirrored
+        records.mirror_state(worker.input_state_dirs(), on_event);
+        // The log the workers appended to is folded into the journal
+        journal::save_journal(records.journal, output_dir)?;
+    }
+
+    for result in results {
+        result?;
+    }
+    if aborted {
+        anyhow::bail!("Import aborted; run it again to resume");
+    }
+    on_event(TransferEvent::Finished);
+
+    let (mismatched, errors) = (failed.into_inner(), errors.into_inner());
+    if mismatched > 0 || errors > 0 {
+        return Err(FilesFailed { mismatched, errors }.into());
+    }
+    Ok(())
+}
+
+/// Copy every pending file of `sessions` to the remote `dest`, named
+/// `output` in events, one file at a time. Each file is written under its
+/// part name and renamed once complete and verified. The state is saved to
+/// `dest` at most every [`REMOTE_STATE_INTERVAL`] and once the uploads stop,
+/// and the files already there are listed once per session rather than
+/// looked up one by one. Partly written files are written again from their
+/// start.
+fn upload_sessions(
+    sessions: &[Session],
+    output: &Path,
+    dest: &dyn Destination,
+    inputs: &[&Path],
+    state: &mut TransferState,
+    options: &TransferOptions,
+    hooks: &Hooks,
+) -> Result<()> {
+    let on_event = &*hooks.on_event;
+    let mut jobs = Vec::new();
+    let (mut total_bytes, mut done_bytes) = (0, 0);
+    for session in sessions {
+        let session_dir = Path::new(&session.folder_name);
+        let present: HashSet<PathBuf> = dest.list(session_dir)?.into_iter().collect();
+        let mut dest_names =
+            plan_destination_names_with(session, options.extension_case, options.rename.as_ref());
+        let mut taken: HashSet<String> = dest_names.iter().map(|n| n.to_lowercase()).collect();
+        for (file, dest_name) in session.files.iter().zip(&mut dest_names) {
+            let key = state::file_key(&file.path, inputs);
+            let size = file.total_size();
+            total_bytes += size;
+            if state.is_completed(&key) && state.source_changed(&key, &file.path) {
+                log::warn!(
+                    "{} changed since it was copied, copying it again",
+                    file.path.display()
+                );
+                on_event(TransferEvent::SourceChanged { source: &file.path });
+                state.forget(&key, size);
+            }
+            if state.is_completed(&key) {
+                on_event(TransferEvent::AlreadyCopied { source: &file.path });
+                done_bytes += size;
+                continue;
+            }
+            let outputs_exist = |name: &str| {
+                file_outputs(file, name, options)
+                    .into_iter()
+                    .find(|(_, name)| present.contains(Path::new(name)))
+                    .map(|(_, name)| output.join(session_dir).join(name))
+            };
+            if let Some(existing) = outputs_exist(dest_name) {
+                let resolution = match options.on_conflict {
+                    OnConflict::Ask if options.dry_run => OnConflict::Ask,
+                    OnConflict::Ask => match (hooks.on_conflict)(&file.path, &existing) {
+                        OnConflict::Ask => OnConflict::Skip,
+                        decided => decided,
+                    },
+                    policy => policy,
+                };
+                if resolution == OnConflict::Rename {
+                    let path = Path::new(dest_name.as_str());
+                    let stem = path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    let ext = path.extension().map(|e| e.to_string_lossy().to_string());
+                    for n in 1.. {
+                        let candidate = join_file_name(&stem, &format!("_{}", n), ext.as_deref());
+                        if !taken.contains(&candidate.to_lowercase())
+                            && outputs_exist(&candidate).is_none()
+                        {
+                            *dest_name = candidate;
+                            break;
+                        }
+                    }
+                    taken.insert(dest_name.to_lowercase());
+                }
+                on_event(TransferEvent::Conflict {
+                    source: &file.path,
+                    existing: &existing,
+                    dest: &output
+                        .join(session_dir)
+                        .join(primary_name(file, dest_name, options)),
+                    resolution,
+                });
+                if matches!(resolution, OnConflict::Skip | OnConflict::Ask) {
+                    if !options.dry_run {
+                        state.mark_completed(key, size);
+                    }
+                    done_bytes += size;
+                    continue;
+                }
+            }
+            jobs.push((key, file, session.folder_name.as_str(), dest_name.clone()));
+        }
+    }
+
+    on_event(TransferEvent::Started {
+        total_bytes,
+        done_bytes,
+        workers: 1,
+    });
+    let throttle = options.max_rate.map(Throttle::new);
+    let progress = Progress {
+        worker: 0,
+        on_event,
+        control: hooks.control,
+    };
+    let mut pending: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (_, _, folder_name, _) in &jobs {
+        pending.entry(folder_name).or_default().0 += 1;
+    }
+    let mut created = HashSet::new();
+    let mut manifests: BTreeMap<&str, Vec<ManifestEntry>> = BTreeMap::new();
+    let (mut mismatched, mut errors) = (0, 0);
+    let mut failure = None;
+    let mut saved_at = Instant::now();
+    for (key, file, folder_name, dest_name) in &jobs {
+        let folder_name: &str = folder_name;
+        if hooks.control.is_some_and(|control| !control.wait()) {
+            break;
+        }
+        on_event(TransferEvent::FileStarted {
+            worker: 0,
+            name: &format!("{}/{}", folder_name, dest_name),
+            bytes: file.total_size(),
+        });
+        if let Some(control) = hooks.control {
+            control.clear_skip(0);
+        }
+        let mut entries = Vec::new();
+        let uploaded = (|| -> Result<bool> {
+            for (src, name) in file_outputs(file, dest_name, options) {
+                let dest_path = Path::new(folder_name).join(&name);
+                if options.dry_run {
+                    on_event(TransferEvent::WouldCopy {
+                        source: src,
+                        dest: &output.join(&dest_path),
+                    });
+                    progress.inc(fs::metadata(src).map(|m| m.len()).unwrap_or(0));
+                    continue;
+                }
+                if let Some(dir) = dest_path.parent().filter(|dir| !created.contains(*dir)) {
+                    dest.create_dir_all(dir)?;
+                    created.insert(dir.to_path_buf());
+                }
+                match upload_file(
+                    src,
+                    &dest_path,
+                    dest,
+                    output,
+                    options,
+                    &progress,
+                    throttle.as_ref(),
+                )? {
+                    Some(hash) if options.manifest || options.checksum_file => {
+                        entries.push(ManifestEntry::new(
+                            file,
+                            src,
+                            &name,
+                            FileHash::new(options.hash_algo, hash),
+                        ))
+                    }
+                    Some(_) => {}
+                    None => {
+                        mismatched += 1;
+                        return Ok(false);
+                    }
+                }
+            }
+            Ok(true)
+        })();
+        let uploaded = match uploaded {
+            Ok(uploaded) => uploaded,
+            Err(e) => match e.downcast_ref::<Interrupted>() {
+                Some(Interrupted::Aborted) => break,
+                Some(Interrupted::Skipped) => {
+                    on_event(TransferEvent::Skipped {
+                        worker: 0,
+                        source: &file.path,
+                    });
+                    false
+                }
+                None => {
+                    let e = e.context(format!("Failed to transfer {}", file.path.display()));
+                    on_event(TransferEvent::Failed {
+                        worker: 0,
+                        source: &file.path,
+                        error: &e,
+                    });
+                    if options.on_error == OnError::Abort {
+                        failure = Some(e);
+                        break;
+                    }
+                    log::error!("{:#}", e);
+                    errors += 1;
+                    false
+                }
+            },
+        };
+        if uploaded && !options.dry_run {
+            state.mark_completed(key.clone(), file.total_size());
+            if let Ok(source) = Fingerprint::of(&file.path) {
+                state.record_source(key.clone(), source);
+            }
+            if saved_at.elapsed() >= REMOTE_STATE_INTERVAL {
+                state::save_remote(state, dest)?;
+                saved_at = Instant::now();
+            }
+            if !entries.is_empty() {
+                manifests.entry(folder_name).or_default().extend(entries);
+            }
+            on_event(TransferEvent::FileFinished {
+                worker: 0,
+                source: &file.path,
+            });
+        }
+        let (left, failed) = pending.get_mut(folder_name).expect("every job is counted");
+        *left -= 1;
+        *failed += usize::from(!uploaded);
+        match (*left, *failed) {
+            (0, 0) => on_event(TransferEvent::SessionFinished { folder_name }),
+            (0, failed) => on_event(TransferEvent::SessionFailed {
+                folder_name,
+                failed,
+            }),
+            _ => {}
+        }
+    }
+    on_event(TransferEvent::WorkerFinished { worker: 0 });
+    if !options.dry_run {
+        state::save_remote(state, dest)?;
+    }
+    // Describe what was uploaded even if the transfer stopped
+    for (folder_name, entries) in manifests {
+        if options.checksum_file {
+            manifest::update_checksums(dest, Path::new(folder_name), options.hash_algo, &entries)?;
+        }
+        if options.manifest {
+            manifest::update_manifest(dest, Path::new(folder_name), entries)?;
+        }
+    }
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    if hooks.control.is_some_and(Control::is_aborted) {
+        anyhow::bail!("Import aborted; run it again to resume");
+    }
+    on_event(TransferEvent::Finished);
+    if mismatched > 0 || errors > 0 {
+        return Err(FilesFailed { mismatched, errors }.into());
+    }
+    Ok(())
+}
+
+/// Upload `src` to `dest_path` in `dest` with the source's modified time,
+/// under its part name renamed into place unless the destination
+/// [writes in place](Destination::writes_in_place). With `--verify`, the
+/// upload is checksummed on the destination first and left out if it does
+/// not match the source. Returns the source's checksum, empty unless it is
+/// verified or goes in a manifest, or none if the upload did not match.
+fn upload_file(
+    src: &Path,
+    dest_path: &Path,
+    dest: &dyn Destination,
+    output: &Path,
+    options: &TransferOptions,
+    progress: &Progress,
+    throttle: Option<&Throttle>,
+) -> Result<Option<String>> {
+    let part = if dest.writes_in_place() {
+        dest_path.to_path_buf()
+    } else {
+        part_path(dest_path)
+    };
+    let source =
+        fs::File::open(src).with_context(|| format!("Failed to open {}", src.display()))?;
+    let modified = source.metadata()?.modified()?;
+    let mut reader = UploadReader {
+        source,
+        hasher: (options.verify || options.manifest || options.checksum_file)
+            .then(|| checksum::Hasher::new(options.hash_algo)),
+        progress,
+        throttle,
+        interrupted: None,
+    };
+    let written = match dest.write(&part, &mut reader, Some(modified)) {
+        Ok(written) => written,
+        Err(e) => return Err(reader.interrupted.map_or(e, Into::into)),
+    };
+    let full = output.join(dest_path);
+    (progress.on_event)(TransferEvent::Copied {
+        worker: progress.worker,
+        source: src,
+        dest: &full,
+        bytes: written,
+    });
+    let hash = reader
+        .hasher
+        .map(checksum::Hasher::finish)
+        .unwrap_or_default();
+    if options.verify {
+        (progress.on_event)(TransferEvent::Verifying {
+            worker: progress.worker,
+            name: &dest_path.to_string_lossy(),
+        });
+        if dest.hash(&part)? != hash {
+            log::error!("Checksum mismatch for {}", full.display());
+            (progress.on_event)(TransferEvent::ChecksumMismatch {
+                source: src,
+                dest: &full,
+            });
+            dest.remove(&part)?;
+            return Ok(None);
+        }
+    }
+    if part != dest_path {
+        dest.rename(&part, dest_path)?;
+    }
+    log::info!("Uploaded {} to {}", src.display(), full.display());
+    Ok(Some(hash))
+}
+
+/// Reads a source for [`upload_file`], hashing, pacing and reporting what
+/// it reads. A skip or abort asked meanwhile is kept in `interrupted`, as
+/// the read fails with a plain I/O error.
+struct UploadReader<'a> {
+    source: fs::File,
+    hasher: Option<checksum::Hasher>,
+    progress: &'a Progress<'a>,
+    throttle: Option<&'a Throttle>,
+    interrupted: Option<Interrupted>,
+}
+
+impl Read for UploadReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Err(e) = self.progress.check() {
+            let interrupted = e.downcast::<Interrupted>().map_err(io::Error::other)?;
+            self.interrupted = Some(interrupted);
+            return Err(io::Error::other(interrupted));
+        }
+        let n = self.source.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        if let Some(throttle) = self.throttle {
+            throttle.take(n as u64);
+        }
+        self.progress.inc(n as u64);
+        Ok(n)
+    }
+}
+
+impl Worker<'_> {
+    /// Write the manifests of the session `folder_name` once its last file
+    /// is done, so they describe it even if a later session fails.
+    fn write_session_manifests(&self, folder_name: &str) -> Result<()> {
+        let dirs = [Some(self.output_dir), self.options.backup_dir.as_deref()];
+        let done: BTreeMap<PathBuf, Vec<ManifestEntry>> = {
+            let mut manifests = self.manifests.lock().unwrap();
+            dirs.iter()
+                .flatten()
+                .filter_map(|root| manifests.remove_entry(&root.join(folder_name)))
+                .collect()
+        };
+        write_manifests(done, self.records, self.options)?;
+        journal::append_journal(self.records.lock().unwrap().journal, self.output_dir)
+    }
+
+    /// Source and destination name of each of a job's files.
+    fn outputs<'j>(&self, job: &Job<'j>) -> Vec<(&'j Path, String)> {
+        file_outputs(job.file, &job.dest_name, self.options)
+    }
+
+    /// Where the state is mirrored besides the output: every input, unless
+    /// mirroring is off.
+    fn input_state_dirs(&self) -> &[&Path] {
+        if self.options.mirror_state_to_input {
+            self.inputs
+        } else {
+            &[]
+        }
+    }
+
+    /// Transfer one file and its sidecars. Returns false if verification failed,
+    /// in which case the file is not marked as completed where it failed.
+    fn run(&self, id: usize, job: &Job) -> Result<bool> {
+        let dry_run = self.options.dry_run;
+        let label = format!("{}/{}", job.folder_name, job.dest_name);
+        (self.on_event)(TransferEvent::FileStarted {
+            worker: id,
+            name: &label,
+            bytes: job.file.total_size(),
+        });
+        if let Some(control) = self.control {
+            control.clear_skip(id);
+        }
+        let progress = Progress {
+            worker: id,
+            on_event: self.on_event,
+            control: self.control,
+        };
+
+        // The primary file and its sidecars are tracked under one state key,
+        // separately for the output and the backup
+        let session_dirs: Vec<&PathBuf> = job.session_dirs().collect();
+        let mut verified = vec![true; session_dirs.len()];
+        let mut manifest_entries = vec![Vec::new(); session_dirs.len()];
+        // Whether zeros stand in for parts of the file or a sidecar
+        let mut salvaged = false;
+        for (src, name) in self.outputs(job) {
+            let dests: Vec<PathBuf> = session_dirs.iter().map(|dir| dir.join(&name)).collect();
+
+            if dry_run {
+                for dest in &dests {
+                    (self.on_event)(TransferEvent::WouldCopy { source: src, dest });
+                }
+                if let Ok(meta) = fs::metadata(src) {
+                    progress.inc(meta.len());
+                }
+                continue;
+            }
+
+            // Files that were already there before the import are not ours to undo
+            let mut resume_from = u64::MAX;
+            {
+                let mut records = self.records.lock().unwrap();
+                for dest in &dests {
+                    let created = !dest.exists() || records.journal.created_files.contains(dest);
+                    // Recorded before writing so an interrupted copy is resumed, not
+                    // mistaken for a conflict, and is removed by undo
+                    if created && !records.journal.created_files.contains(dest) {
+                        records.journal.record_file(dest.clone());
+                        journal::append_journal(records.journal, self.output_dir)?;
+                    }
+                    let offset = if created {
+                        records.state.partial_offset(dest)
+                    } else {
+                        0
+                    };
+                    resume_from = resume_from.min(offset);
+                }
+            }
+            let algo = self.options.hash_algo;
+            let hashed =
+                self.hash_cache.is_some() || self.options.manifest || self.options.checksum_file;
+            let mut hasher = hashed.then(|| checksum::Hasher::new(algo));
+            // Linking and cloning only apply to the output, a backup needs a real
+            // copy, and cloning would replace what an interrupted copy wrote
+            let output_only = job.backup_session_dir.is_none();
+            let linked = output_only && self.link(src, &dests[0], &progress)?;
+            let mut damaged = false;
+            let source_hash = if linked {
+                hasher.map(|_| checksum::hash_file(src, algo)).transpose()?
+            } else if self.same_filesystem
+                && output_only
+                && resume_from == 0
+                && clone_file(src, &dests[0], &progress)
+            {
+                log::info!("Cloned {} -> {}", src.display(), dests[0].display());
+                hasher.map(|_| checksum::hash_file(src, algo)).transpose()?
+            } else {
+                let mut checkpoint = |offset| {
+                    let mut records = self.records.lock().unwrap();
+                    for dest in &dests {
+                        records.state.record_partial(dest, offset);
+                    }
+                    records.save_state(self.input_state_dirs(), self.on_event)
+                };
+                damaged = copy_with_progress(
+                    src,
+                    &dests,
+                    resume_from,
+                    &progress,
+                    hasher.as_mut(),
+                    self.throttle,
+                    self.options.retry,
+                    self.options.buffers,
+                    &mut checkpoint,
+                )?;
+                salvaged |= damaged;
+                for dest in &dests {
+                    log::info!("Copied {} -> {}", src.display(), dest.display());
+                }
+                hasher.map(|h| h.finish())
+            };
+
+            // The hash of a salvaged file is that of the zeros, not of the file
+            let listed = (self.options.manifest || self.options.checksum_file) && !damaged;
+            if let (true, Some(source_hash)) = (listed, &source_hash) {
+                let entry = ManifestEntry::new(
+                    job.file,
+                    src,
+                    &name,
+                    FileHash::new(algo, source_hash.clone()),
+                );
+                for entries in &mut manifest_entries {
+                    entries.push(entry.clone());
+                }
+            }
+
+            for (dest, verified) in dests.iter().zip(&mut verified) {
+                // A link already has the source's attributes
+                if !linked && !self.options.preserve.is_empty() {
+                    preserve::preserve(src, dest, &self.options.preserve).with_context(|| {
+                        format!(
+                            "Cannot preserve attributes of {} on {}",
+                            src.display(),
+                            dest.display()
+                        )
+                    })?;
+                }
+                if self.options.fsync {
+                    sync_file(dest)?;
+                }
+                (self.on_event)(TransferEvent::Copied {
+                    worker: id,
+                    source: src,
+                    dest,
+                    bytes: fs::metadata(dest).map(|m| m.len()).unwrap_or(0),
+                });
+
+                // Re-read the destination and compare with the hash of what was read
+                if let (Some(cache), Some(source_hash)) = (self.hash_cache, &source_hash) {
+                    (self.on_event)(TransferEvent::Verifying {
+                        worker: id,
+                        name: &format!("{}/{}", job.folder_name, name),
+                    });
+                    // Always read back: the copy has the source's mtime, so a
+                    // cached hash of a file it overwrote could still match
+                    let dest_hash = checksum::hash_file(dest, algo)?;
+                    cache.lock().unwrap().store(dest, algo, dest_hash.clone())?;
+                    if dest_hash != *source_hash {
+                        log::error!(
+                            "Checksum mismatch: {} ({}) -> {} ({})",
+                            src.display(),
+                            source_hash,
+                            dest.display(),
+                            dest_hash
+                        );
+                        (self.on_event)(TransferEvent::ChecksumMismatch { source: src, dest });
+                        *verified = false;
+                    } else {
+                        log::debug!("Verified {} ({})", dest.display(), dest_hash);
+                    }
+                    cache.lock().unwrap().save(self.output_dir)?;
+                }
+            }
+        }
+
+        if dry_run {
+            return Ok(true);
+        }
+        for ((session_dir, &verified), entries) in
+            session_dirs.iter().zip(&verified).zip(manifest_entries)
+        {
+            // A mismatched copy is left out of the state so the next run copies it again
+            if !verified {
+                continue;
+            }
+            if self.options.fsync {
+                sync_dir(session_dir)?;
+            }
+            let mut records = self.records.lock().unwrap();
+            for (_, name) in self.outputs(job) {
+                let dest = session_dir.join(name);
+                records.state.clear_partial(&dest);
+                if records.journal.created_files.contains(&dest) {
+                    records.journal.record_written(&dest)?;
+                }
+            }
+            journal::append_journal(records.journal, self.output_dir)?;
+            if salvaged {
+                // Left to be copied again by a later run, which may read
+                // what this one could not
+                log::warn!(
+                    "{} is not marked as imported, parts of it could not be read",
+                    job.file.path.display()
+                );
+            } else if job.session_dir.as_ref() == Some(*session_dir) {
+                records
+                    .state
+                    .mark_completed(job.key.clone(), job.file.total_size());
+                if let Ok(source) = Fingerprint::of(&job.file.path) {
+                    records.state.record_source(job.key.clone(), source);
+                }
+            } else {
+                records.state.mark_backed_up(job.key.clone());
+            }
+            records.save_state(self.input_state_dirs(), self.on_event)?;
+            if !entries.is_empty() {
+                self.manifests
+                    .lock()
+                    .unwrap()
+                    .entry((*session_dir).clone())
+                    .or_default()
+                    .extend(entries);
+            }
+        }
+        if verified.contains(&false) {
+            return Ok(false);
+        }
+        if self.options.thumbnails {
+            self.write_thumbnail(job)?;
+        }
+        if self.options.proxy_command.is_some() {
+            self.write_proxy(id, job)?;
+        }
+        if let (Some(_), Some(session_dir)) = (self.options.previews, &job.session_dir) {
+            self.photos
+                .lock()
+                .unwrap()
+                .push(session_dir.join(primary_name(job.file, &job.dest_name, self.options)));
+        }
+        if self.options.move_sources && !salvaged {
+            // Only reached once every output is verified and the state is on disk,
+            // and never for a file only partly read.
+            // Each deletion is journaled first so undo can put the file back.
+            let session_dir = job
+                .session_dirs()
+                .next()
+                .context("A moved file has no destination")?;
+            for (src, name) in self.outputs(job) {
+                {
+                    let mut records = self.records.lock().unwrap();
+                    records
+                        .journal
+                        .record_moved(src.to_path_buf(), session_dir.join(&name));
+                    journal::append_journal(records.journal, self.output_dir)?;
+                }
+                fs::remove_file(src)
+                    .with_context(|| format!("Failed to remove source {}", src.display()))?;
+                log::info!("Removed source {}", src.display());
+            }
+        }
+        (self.on_event)(TransferEvent::FileFinished {
+            worker: id,
+            source: &job.file.path,
+        });
+        Ok(true)
+    }
+
+    /// Place `src` at `dest` as a link if the link mode asks for one. Returns
+    /// false when the file has to be copied instead.
+    fn link(&self, src: &Path, dest: &Path, progress: &Progress) -> Result<bool> {
+        let linked = match self.options.link_mode {
+            LinkMode::Copy => false,
+            // A hard link shares the source inode, so the mtime is already identical
+            LinkMode::Hardlink => self.same_filesystem && link::hard_link(src, dest).is_ok(),
+            LinkMode::Symlink => {
+                link::symlink(src, dest).with_context(|| {
+                    format!("Cannot link {} to {}", dest.display(), src.display())
+                })?;
+                true
+            }
+        };
+        if linked {
+            log::info!("Linked {} -> {}", src.display(), dest.display());
+            if let Ok(meta) = fs::metadata(src) {
+                progress.inc(meta.len());
+            }
+        }
+        Ok(linked)
+    }
+
+    /// Make a proxy of the video copied to the output in the session's
+    /// proxies folder, journaling what is created. A failed proxy is
+    /// reported, not fatal.
+    fn write_proxy(&self, id: usize, job: &Job) -> Result<()> {
+        let (Some(template), Some(session_dir)) = (&self.options.proxy_command, &job.session_dir)
+        else {
+            return Ok(());
+        };
+        let video = session_dir.join(primary_name(job.file, &job.dest_name, self.options));
+        if !proxy::is_video(&video) {
+            return Ok(());
+        }
+        (self.on_event)(TransferEvent::MakingProxy {
+            worker: id,
+            name: &format!("{}/{}", job.folder_name, job.dest_name),
+        });
+        let dir = session_dir.join(proxy::PROXIES_DIR);
+        let dir_created = !dir.exists();
+        match proxy::make_proxy(template, &video, &dir) {
+            Ok(Some(written)) => {
+                log::info!("Made proxy {}", written.display());
+                let mut records = self.records.lock().unwrap();
+                if dir_created {
+                    records.journal.record_dir(dir);
+                }
+                records.journal.record_file(written);
+                journal::append_journal(records.journal, self.output_dir)?;
+            }
+            Ok(None) => {}
+            Err(error) => {
+                log::warn!(
+                    "Could not make a proxy of {}: {:#}",
+                    job.file.path.display(),
+                    error
+                );
+                (self.on_event)(TransferEvent::ProxyFailed {
+                    source: &job.file.path,
+                    error: &error,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Extract the JPEG embedded in the raw copied to the output into the
+    /// session's previews folder, journaling what is created. A raw the JPEG
+    /// cannot be read from is reported, not fatal.
+    fn write_thumbnail(&self, job: &Job) -> Result<()> {
+        let Some(session_dir) = &job.session_dir else {
+            return Ok(());
+        };
+        let dir = session_dir.join(preview::PREVIEWS_DIR);
+        let dir_created = !dir.exists();
+        let raw = session_dir.join(primary_name(job.file, &job.dest_name, self.options));
+        match preview::extract_thumbnail(&raw, &dir) {
+            Ok(Some(written)) => {
+                log::info!("Extracted preview {}", written.display());
+                let mut records = self.records.lock().unwrap();
+                if dir_created {
+                    records.journal.record_dir(dir);
+                }
+                records.journal.record_file(written);
+                journal::append_journal(records.journal, self.output_dir)?;
+            }
+            Ok(None) => {}
+            Err(error) => {
+                log::warn!(
+                    "Could not extract a preview from {}: {:#}",
+                    job.file.path.display(),
+                    error
+                );
+                (self.on_event)(TransferEvent::PreviewFailed {
+                    source: &job.file.path,
+                    error: &error,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add the entries gathered for each session folder to its manifest and
+/// checksum file, as `options` asks, journaling the files created.
+fn write_manifests(
+    manifests: BTreeMap<PathBuf, Vec<ManifestEntry>>,
+    records: &Mutex<Records>,
+    options: &TransferOptions,
+) -> Result<()> {
+    if manifests.is_empty() {
+        return Ok(());
+    }
+    let mut records = records.lock().unwrap();
+    for (session_dir, entries) in manifests {
+        let dest = remote::LocalDir { root: session_dir };
+        let session = Path::new("");
+        if options.checksum_file {
+            let created =
+                !dest.exists(Path::new(manifest::checksums_filename(options.hash_algo)))?;
+            let path = manifest::update_checksums(&dest, session, options.hash_algo, &entries)?;
+            if created {
+                records.journal.record_file(dest.root.join(path));
+            }
+        }
+        if !options.manifest {
+            continue;
+        }
+        let created = !dest.exists(Path::new(manifest::MANIFEST_FILENAME))?;
+        let path = manifest::update_manifest(&dest, session, entries)?;
+        if created {
+            records.journal.record_file(dest.root.join(path));
+        }
+    }
+    Ok(())
+}
+
+/// Render the previews of `photos` on every core, journaling the folders
+/// and files created. Photos no preview can be rendered for are reported.
+/// A raw shot with a JPEG gets the JPEG's preview (see
+/// [`preview::preview_sources`]).
+fn render_previews(
+    photos: &[PathBuf],
+    max_size: u32,
+    records: &Mutex<Records>,
+    on_event: &EventHandler,
+) -> Result<()> {
+    if photos.is_empty() {
+        return Ok(());
+    }
+    let photos = preview::preview_sources(photos);
+    on_event(TransferEvent::RenderingPreviews {
+        total: photos.len(),
+    });
+    let next = AtomicUsize::new(0);
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(photos.len());
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    while let Some(photo) = photos.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let Some(session_dir) = photo.parent() else {
+                            continue;
+                        };
+                        let dir = session_dir.join(preview::RENDERED_PREVIEWS_DIR);
+                        let dir_created = !dir.exists();
+                        match preview::render_preview(photo, &dir, max_size) {
+                            Ok(Some(written)) => {
+                                log::debug!("Rendered preview {}", written.display());
+                                let mut records = records.lock().unwrap();
+                                if dir_created {
+                                    records.journal.record_dir(dir);
+                                }
+                                records.journal.record_file(written);
+                            }
+                            Ok(None) => {}
+                            Err(error) => {
+                                log::warn!(
+                                    "Could not render a preview of {}: {:#}",
+                                    photo.display(),
+                                    error
+                                );
+                                on_event(TransferEvent::PreviewFailed {
+                                    source: photo,
+                                    error: &error,
+                                });
+                            }
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|h| h.join().expect("preview worker panicked"))
+    })
+}
+
+/// Outcome of re-checking previously imported files against their sources.
+#[derive(Debug, Default)]
+pub struct RevalidateReport {
+    pub verified: usize,
+    pub mismatched: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+}
+
+/// Hash every planned destination file and compare it with its source,
+/// without copying anything. Destination hashes come from the output
+/// directory's hash cache when the file is unchanged since it was last hashed.
+/// `on_checked` is called with the name of each destination file, as
+/// `<session folder>/<file>`, once it is checked.
+#[allow(clippy::too_many_arguments)]
+pub fn revalidate_sessions(
+    sessions: &[Session],
+    output_dir: &Path,
+    extension_case: ExtensionCase,
+    split_by_type: bool,
+    chapter_folders: bool,
+    rename: Option<&NameTemplate>,
+    lrf: scanner::LrfMode,
+    hash_algo: HashAlgo,
+    mut on_checked: impl FnMut(&str),
+) -> Result<RevalidateReport> {
+    let mut cache = HashCache::load(output_dir);
+    let mut report = RevalidateReport::default();
+    let naming = TransferOptions {
+        extension_case,
+        split_by_type,
+        chapter_folders,
+        lrf,
+        ..Default::default()
+    };
+
+    for session in sessions {
+        let session_dir = output_dir.join(&session.folder_name);
+        let dest_names = plan_destination_names_with(session, extension_case, rename);
+        for (file, dest_name) in session.files.iter().zip(&dest_names) {
+            for (src, name) in file_outputs(file, dest_name, &naming) {
+                let dest = session_dir.join(&name);
+                if !dest.is_file() {
+                    report.missing.push(dest);
+                } else if cache.hash(&dest, hash_algo)? == checksum::hash_file(src, hash_algo)? {
+                    report.verified += 1;
+                } else {
+                    report.mismatched.push(dest);
+                }
+                on_checked(&format!("{}/{}", session.folder_name, name));
+            }
+        }
+    }
+
+    cache.save(output_dir)?;
+    Ok(report)
+}
+
+/// Compute the destination file name of every file in a session, in order.
+/// Extensions are normalized to `extension_case`. Names that would collide on a
+/// case-insensitive filesystem (e.g. `IMG_0001.CR2` and `img_0001.cr2` from two
+/// card folders) are reported and disambiguated with a numeric suffix:
+/// `IMG_0001.cr2`, `IMG_0001_1.cr2`, ...
+/// Stems too long for a single path component are truncated.
+pub fn plan_destination_names(session: &Session, extension_case: ExtensionCase) -> Vec<String> {
+    plan_destination_names_with(session, extension_case, None)
+}
+
+/// Like [`plan_destination_names`], with files named by `rename` instead of
+/// keeping their original names.
+pub fn plan_destination_names_with(
+    session: &Session,
+    extension_case: ExtensionCase,
+    rename: Option<&NameTemplate>,
+) -> Vec<String> {
+    plan_names(session, extension_case, rename, |_, _, _| {})
+}
 
-    for session in sessions {
-        let session_dir = output_dir.join(&session.folder_name);
+/// [`plan_destination_names_with`], calling `on_renamed` for every file
+/// that cannot keep its planned name.
+fn plan_names(
+    session: &Session,
+    extension_case: ExtensionCase,
+    rename: Option<&NameTemplate>,
+    mut on_renamed: impl FnMut(&Path, &str, RenameReason),
+) -> Vec<String> {
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut names = Vec::with_capacity(session.files.len());
 
-        if !dry_run {
-            fs::create_dir_all(&session_dir)?;
+    for (position, file) in session.files.iter().enumerate() {
+        let (mut stem, mut ext) = split_file_name(&file.path, extension_case);
+        if let Some(template) = rename {
+            let renamed = PathBuf::from(template.render(file, position, ext.as_deref()));
+            (stem, ext) = split_file_name(&renamed, ExtensionCase::Preserve);
+        }
+        let mut name = join_file_name(&stem, "", ext.as_deref());
+        if !name.starts_with(stem.as_str()) {
+            log::warn!(
+                "{} is too long for the destination filesystem, copying as {}",
+                file.path.display(),
+                name
+            );
+            on_renamed(&file.path, &name, RenameReason::TooLong);
+        }
+        if taken.contains(&name.to_lowercase()) {
+            let mut n = 1;
+            let renamed = loop {
+                let candidate = join_file_name(&stem, &format!("_{}", n), ext.as_deref());
+                if !taken.contains(&candidate.to_lowercase()) {
+                    break candidate;
+                }
+                n += 1;
+            };
+            log::warn!(
+                "{} conflicts with another file in {} (case-insensitive), copying as {}",
+                file.path.display(),
+                session.folder_name,
+                renamed
+            );
+            on_renamed(&file.path, &renamed, RenameReason::CaseConflict);
+            name = renamed;
         }
+        taken.insert(name.to_lowercase());
+        names.push(name);
+    }
 
-        for file in &session.files {
-            let key = state::file_key(&file.path, input_dir);
+    names
+}
 
-            if state.is_completed(&key) {
-                // Already copied in a previous run, skip but count the bytes
-                if let Ok(meta) = fs::metadata(&file.path) {
-                    pb.inc(meta.len());
-                }
-                continue;
+/// Whether every output of `file` (see [`file_outputs`]) is in each of
+/// `session_dirs` with the contents of its source.
+fn already_there(
+    file: &DatedFile,
+    dest_name: &str,
+    session_dirs: &[&PathBuf],
+    options: &TransferOptions,
+) -> Result<bool> {
+    for (src, name) in file_outputs(file, dest_name, options) {
+        let Ok(meta) = fs::metadata(src) else {
+            return Ok(false);
+        };
+        let (len, mut hash) = (meta.len(), None);
+        for dir in session_dirs {
+            let dest = dir.join(&name);
+            if fs::metadata(&dest).map(|meta| meta.len()).ok() != Some(len) {
+                return Ok(false);
+            }
+            let src_hash = match &hash {
+                Some(hash) => hash,
+                None => hash.insert(checksum::hash_file(src, options.hash_algo)?),
+            };
+            if checksum::hash_file(&dest, options.hash_algo)? != *src_hash {
+                return Ok(false);
             }
+        }
+    }
+    Ok(true)
+}
+
+/// First `<stem>_<n>.<ext>` variant of `dest_name` that is not planned for
+/// another file and whose outputs (the file and its sidecars) do not exist in
+/// any of `session_dirs`.
+fn free_name(
+    session_dirs: &[&PathBuf],
+    file: &DatedFile,
+    dest_name: &str,
+    taken: &HashSet<String>,
+    options: &TransferOptions,
+) -> String {
+    let path = Path::new(dest_name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().map(|e| e.to_string_lossy());
+    (1..)
+        .map(|n| join_file_name(&stem, &format!("_{}", n), ext.as_deref()))
+        .find(|candidate| {
+            !taken.contains(&candidate.to_lowercase())
+                && file_outputs(file, candidate, options)
+                    .iter()
+                    .all(|(_, name)| session_dirs.iter().all(|dir| !dir.join(name).exists()))
+        })
+        .expect("some numeric suffix is free")
+}
+
+/// Source and destination file name of a file and each of its sidecars.
+/// Sidecars follow the primary's destination name, so `IMG_0001.xmp` stays
+/// next to `IMG_0001.cr2` even when the primary was renamed, and the
+/// `IMG_0001.CR2.xmp` style keeps the primary's full name.
+/// The later chapters of a GoPro recording keep their own names, or take a
+/// renamed first chapter's with their number (`<name>_02.mp4`), and their
+/// sidecars follow them. With [`TransferOptions::chapter_folders`], a
+/// recording of several chapters is put in a folder named after its first,
+/// and with [`TransferOptions::split_by_type`], names are prefixed with the type folder of each output
+/// (see [`output_folder`]), e.g. `RAW/IMG_0001.cr2`. With
+/// [`LrfMode::Proxies`](scanner::LrfMode::Proxies), DJI previews go to the
+/// session's proxies folder instead.
+pub fn file_outputs<'a>(
+    file: &'a DatedFile,
+    dest_name: &str,
+    options: &TransferOptions,
+) -> Vec<(&'a Path, String)> {
+    let mut outputs = vec![(file.path.as_path(), dest_name.to_string())];
+    let dest_stem = Path::new(dest_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    // The files sidecars are named after: the primary, then later chapters
+    let recording = scanner::gopro_chapter(&file.path);
+    let chapters: Vec<&PathBuf> = file
+        .sidecars
+        .iter()
+        .filter(|path| !scanner::is_sidecar(path))
+        .filter(|path| {
+            let chapter = scanner::gopro_chapter(path);
+            chapter
+                .as_ref()
+                .zip(recording.as_ref())
+                .is_some_and(|(c, r)| {
+                    (&c.prefix, c.recording) == (&r.prefix, r.recording) && c.number != r.number
+                })
+        })
+        .collect();
+    let renamed = !file
+        .path
+        .file_stem()
+        .is_some_and(|stem| stem.to_string_lossy().eq_ignore_ascii_case(&dest_stem));
+    let mut anchors = vec![(file.path.as_path(), dest_name.to_string())];
+    for chapter in &chapters {
+        let (stem, ext) = split_file_name(chapter, options.extension_case);
+        let stem = match scanner::gopro_chapter(chapter) {
+            Some(c) if renamed => format!("{}_{:02}", dest_stem, c.number),
+            _ => stem,
+        };
+        anchors.push((chapter.as_path(), join_file_name(&stem, "", ext.as_deref())));
+    }
 
-            let file_name = file
-                .path
-                .file_name()
+    for sidecar in &file.sidecars {
+        if let Some((_, name)) = anchors.iter().find(|(src, _)| src == sidecar) {
+            outputs.push((sidecar.as_path(), name.clone()));
+            continue;
+        }
+        let (stem, ext) = split_file_name(sidecar, options.extension_case);
+        // `IMG_0001.CR2.xmp` keeps its file's whole name, `IMG_0001.xmp` its stem
+        let named_after = |src: &Path| {
+            let name = src.file_name().unwrap_or_default().to_string_lossy();
+            let src_stem = src.file_stem().unwrap_or_default().to_string_lossy();
+            if stem.eq_ignore_ascii_case(&name) {
+                Some(true)
+            } else {
+                stem.eq_ignore_ascii_case(&src_stem).then_some(false)
+            }
+        };
+        let (whole, dest) = anchors[1..]
+            .iter()
+            .find_map(|(src, dest)| named_after(src).map(|whole| (whole, dest)))
+            .unwrap_or((named_after(&file.path) == Some(true), &anchors[0].1));
+        let base = if whole {
+            dest.clone()
+        } else {
+            Path::new(dest)
+                .file_stem()
                 .unwrap_or_default()
-                .to_string_lossy();
-            let dest = session_dir.join(&*file_name);
+                .to_string_lossy()
+                .into_owned()
+        };
+        let name = match ext {
+            Some(ext) => format!("{}.{}", base, ext),
+            None => base,
+        };
+        outputs.push((sidecar.as_path(), name));
+    }
+    if options.chapter_folders && !chapters.is_empty() {
+        for (_, name) in &mut outputs {
+            *name = format!("{}/{}", dest_stem, name);
+        }
+    }
+    if options.split_by_type {
+        for (src, name) in &mut outputs {
+            if let Some(folder) = output_folder(file, src) {
+                *name = format!("{}/{}", folder, name);
+            }
+        }
+    }
+    if options.lrf == scanner::LrfMode::Proxies {
+        for (src, name) in &mut outputs {
+            if scanner::is_lrf(src) {
+                let file_name = Path::new(name.as_str())
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                *name = format!("{}/{}", proxy::PROXIES_DIR, file_name);
+            }
+        }
+    }
+    outputs
+}
 
-            pb.set_message(format!(
-                "{}/{}",
-                session.folder_name,
-                file_name
-            ));
+/// Destination name of `file` itself within its session folder.
+fn primary_name(file: &DatedFile, dest_name: &str, options: &TransferOptions) -> String {
+    file_outputs(file, dest_name, options).swap_remove(0).1
+}
 
-            if dry_run {
-                println!(
-                    "[dry-run] {} -> {}",
-                    file.path.display(),
+/// Subfolder of a session that `--split-by-type` puts files with the
+/// extension of `path` in: `RAW`, `JPEG` (also HEIC stills), `VIDEO` or
+/// `AUDIO`.
+/// Other files stay in the session folder itself.
+pub fn type_folder(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    if scanner::RAW_EXTENSIONS.contains(&ext.as_str()) {
+        Some("RAW")
+    } else if JPEG_EXTENSIONS.contains(&ext.as_str()) {
+        Some("JPEG")
+    } else if VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+        Some("VIDEO")
+    } else if AUDIO_EXTENSIONS.contains(&ext.as_str()) {
+        Some("AUDIO")
+    } else {
+        None
+    }
+}
+
+/// Type folder of `src`, `file` or one of its sidecars. A JPEG paired with
+/// a raw goes to `JPEG` while metadata sidecars stay next to their file.
+fn output_folder(file: &DatedFile, src: &Path) -> Option<&'static str> {
+    type_folder(src).or_else(|| type_folder(&file.path))
+}
+
+fn split_file_name(path: &Path, extension_case: ExtensionCase) -> (String, Option<String>) {
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let ext = path.extension().map(|e| {
+        let e = e.to_string_lossy();
+        match extension_case {
+            ExtensionCase::Lower => e.to_lowercase(),
+            ExtensionCase::Upper => e.to_uppercase(),
+            ExtensionCase::Preserve => e.to_string(),
+        }
+    });
+    (stem, ext)
+}
+
+/// Join `stem`, `suffix` and `ext` into a file name, truncating the stem so
+/// the whole name fits in one path component.
+fn join_file_name(stem: &str, suffix: &str, ext: Option<&str>) -> String {
+    // The name must still fit with the part suffix while it is written
+    let reserved = suffix.len() + ext.map_or(0, |e| e.len() + 1) + PART_SUFFIX.len();
+    let mut end = stem.len().min(MAX_COMPONENT_BYTES.saturating_sub(reserved));
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    let stem = &stem[..end];
+    match ext {
+        Some(ext) => format!("{}{}.{}", stem, suffix, ext),
+        None => format!("{}{}", stem, suffix),
+    }
+}
+
+/// Fail with the offending path if any planned destination has a folder name
+/// longer than a path component allows or a full path over `max_path` bytes.
+fn check_path_lengths(
+    output_dir: &Path,
+    sessions: &[Session],
+    plans: &[Vec<String>],
+    options: &TransferOptions,
+) -> Result<()> {
+    let max_path = options.max_path;
+    // The planned names already have their extension case
+    let naming = TransferOptions {
+        extension_case: ExtensionCase::Preserve,
+        ..options.clone()
+    };
+    for (session, names) in sessions.iter().zip(plans) {
+        for component in Path::new(&session.folder_name).components() {
+            let len = component.as_os_str().len();
+            if len > MAX_COMPONENT_BYTES {
+                anyhow::bail!(
+                    "Session folder name '{}' has a {}-byte component, over the {}-byte filesystem limit",
+                    session.folder_name,
+                    len,
+                    MAX_COMPONENT_BYTES
+                );
+            }
+        }
+
+        let session_dir = output_dir.join(&session.folder_name);
+        let outputs = session
+            .files
+            .iter()
+            .zip(names)
+            .flat_map(|(file, name)| file_outputs(file, name, &naming));
+        for (_, name) in outputs {
+            let dest = session_dir.join(name);
+            let len = part_path(&dest).as_os_str().len();
+            if len > max_path {
+                anyhow::bail!(
+                    "Destination path is {} bytes while it is written, over the {}-byte limit: {}\n\
+                     Use a shorter output directory, or raise the limit with --max-path \
+                     (e.g. when using Windows extended-length paths)",
+                    len,
+                    max_path,
                     dest.display()
                 );
-                if let Ok(meta) = fs::metadata(&file.path) {
-                    pb.inc(meta.len());
-                }
-            } else {
-                copy_with_progress(&file.path, &dest, &pb)?;
-                state.mark_completed(key);
-                state::save_state_both(state, input_dir, output_dir)?;
             }
         }
     }
+    Ok(())
+}
+
+/// Bytes `job` still has to write into `session_dir`: its outputs, less what
+/// an interrupted run of this import already wrote of them.
+fn pending_bytes(
+    job: &Job,
+    session_dir: &Path,
+    state: &TransferState,
+    journal: &ImportJournal,
+    options: &TransferOptions,
+) -> u64 {
+    file_outputs(job.file, &job.dest_name, options)
+        .into_iter()
+        .map(|(src, name)| {
+            let dest = session_dir.join(name);
+            let written = if journal.created_files.contains(&dest) {
+                let complete = fs::metadata(&dest).map(|meta| meta.len()).unwrap_or(0);
+                complete.max(state.partial_offset(&dest))
+            } else {
+                0
+            };
+            fs::metadata(src)
+                .map(|meta| meta.len())
+                .unwrap_or(0)
+                .saturating_sub(written)
+        })
+        .sum()
+}
+
+/// Whether files copied from `src`'s filesystem into `output_dir` are
+/// cloned (see [`clone_file`]), found by cloning `src` once.
+fn clones_into(src: &Path, output_dir: &Path) -> bool {
+    let probe = output_dir.join(format!(".image-processor-clone.{}", std::process::id()));
+    let cloned = link::reflink(src, &probe).is_ok();
+    let _ = fs::remove_file(&probe);
+    cloned
+}
 
-    pb.finish_with_message("Transfer complete");
+/// Fail before copying anything if `needed` bytes do not fit in the free
+/// space of `root`'s filesystem, or only report it when `force` is set.
+/// Passes when the free space cannot be read.
+fn check_free_space(root: &Path, needed: u64, force: bool, on_event: &EventHandler) -> Result<()> {
+    let Some(available) = volumes::available_space(root) else {
+        return Ok(());
+    };
+    if needed <= available {
+        return Ok(());
+    }
+    let message = format!(
+        "{} needed in {} but only {} is available",
+        DecimalBytes(needed),
+        root.display(),
+        DecimalBytes(available)
+    );
+    if !force {
+        anyhow::bail!(
+            "Not enough space: {}. Free up space, or pass --force to start anyway",
+            message
+        );
+    }
+    log::warn!("Not enough space: {}", message);
+    on_event(TransferEvent::LowSpace {
+        root,
+        needed,
+        available,
+    });
     Ok(())
 }
 
-fn copy_with_progress(src: &Path, dest: &Path, pb: &ProgressBar) -> Result<()> {
-    let mut source = fs::File::open(src)?;
-    let mut destination = fs::File::create(dest)?;
-    let mut buffer = vec![0u8; BUFFER_SIZE];
+/// Try to create `dest` as a copy-on-write clone of `src` (FICLONE on
+/// Linux, clonefile on macOS), which shares the source's blocks until either
+/// file changes. Returns false if the filesystem cannot clone, leaving the
+/// copy to the buffered path.
+fn clone_file(src: &Path, dest: &Path, progress: &Progress) -> bool {
+    if let Err(e) = link::reflink(src, dest) {
+        log::trace!("Cannot clone {}: {}", src.display(), e);
+        return false;
+    }
+    if let Ok(meta) = fs::metadata(src) {
+        // A clone is a new inode, restore the source mtime like a copy would
+        if let Ok(mtime) = meta.modified() {
+            let _ = filetime_set(dest, mtime);
+        }
+        progress.inc(meta.len());
+    }
+    true
+}
 
-    loop {
-        let bytes_read = source.read(&mut buffer)?;
-        if bytes_read == 0 {
+/// Where `dest` is written before it is complete: the same name with
+/// [`PART_SUFFIX`] appended.
+pub fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(PART_SUFFIX);
+    dest.with_file_name(name)
+}
+
+/// Copy `src` to every path in `dests`, reading it once and feeding every
+/// block to `hasher` so verification does not need a second pass over the
+/// source. Each destination is written as its [`part_path`] and renamed
+/// once complete. Resumes at `resume_from` when every part file already
+/// holds that much of the source; stale part files are written over
+/// otherwise. Writes are paced by `throttle`, failed reads retried as
+/// `retry` says. A single destination that is not hashed is copied by the
+/// kernel where it can; otherwise, or from where the kernel gave up, the
+/// source is read on a thread of its own, ahead of the writes by up to
+/// `buffers.count` buffers. Returns whether parts of the source could not be
+/// read and were [salvaged](salvage_block) as zeros.
+#[allow(clippy::too_many_arguments)]
+fn copy_with_progress(
+    src: &Path,
+    dests: &[PathBuf],
+    resume_from: u64,
+    progress: &Progress,
+    mut hasher: Option<&mut checksum::Hasher>,
+    throttle: Option<&Throttle>,
+    retry: Retry,
+    buffers: Buffers,
+    checkpoint: &mut dyn FnMut(u64) -> Result<()>,
+) -> Result<bool> {
+    let mut source = fs::File::open(src)?;
+    let parts: Vec<PathBuf> = dests.iter().map(|dest| part_path(dest)).collect();
+    let algo = hasher
+        .as_ref()
+        .map_or(HashAlgo::default(), |hasher| hasher.algo());
+    let mut prefix = None;
+    for part in &parts {
+        source.seek(SeekFrom::Start(0))?;
+        prefix = matching_prefix(&mut source, part, resume_from, algo)?;
+        if prefix.is_none() {
             break;
         }
-        destination.write_all(&buffer[..bytes_read])?;
-        pb.inc(bytes_read as u64);
+    }
+    let mut offset = 0;
+    let mut destinations = Vec::with_capacity(dests.len());
+    match prefix {
+        Some(prefix_hash) => {
+            // Keep what the interrupted run wrote and append the rest
+            for part in &parts {
+                let mut existing = fs::OpenOptions::new().write(true).open(part)?;
+                existing.set_len(resume_from)?;
+                existing.seek(SeekFrom::Start(resume_from))?;
+                log::info!("Resuming {} at byte {}", part.display(), resume_from);
+                destinations.push(existing);
+            }
+            if let Some(hasher) = hasher.as_mut() {
+                **hasher = prefix_hash;
+            }
+            offset = resume_from;
+            progress.inc(resume_from);
+        }
+        None => {
+            source.seek(SeekFrom::Start(0))?;
+            for part in &parts {
+                if part.exists() {
+                    log::info!("Removing stale {}", part.display());
+                    fs::remove_file(part)?;
+                }
+                destinations.push(fs::File::create(part)?);
+            }
+        }
+    }
+    if let (None, [destination]) = (&hasher, destinations.as_slice()) {
+        offset = copy_in_kernel(&source, destination, offset, progress, throttle, checkpoint)?;
+    }
+
+    // Full buffers go to the writer, which hands them back once written
+    let (full_tx, full_rx) = mpsc::sync_channel::<(Vec<u8>, usize)>(buffers.count);
+    let (empty_tx, empty_rx) = mpsc::channel::<Vec<u8>>();
+    for _ in 0..buffers.count.max(1) {
+        empty_tx
+            .send(vec![0u8; buffers.size.max(1)])
+            .expect("the receiver is alive");
+    }
+    let start = offset;
+    let damaged = thread::scope(|scope| {
+        let reader = scope.spawn(move || -> Result<Vec<Range<u64>>> {
+            let mut offset = start;
+            let mut damaged = Vec::new();
+            // Stops early when the writer failed and dropped its channels
+            while let Ok(mut buffer) = empty_rx.recv() {
+                progress.check()?;
+                let bytes_read = read_block(
+                    &mut source,
+                    src,
+                    offset,
+                    &mut buffer,
+                    retry,
+                    progress,
+                    &mut damaged,
+                )?;
+                if bytes_read == 0 || full_tx.send((buffer, bytes_read)).is_err() {
+                    break;
+                }
+                offset += bytes_read as u64;
+            }
+            Ok(damaged)
+        });
+        let written = (move || -> Result<()> {
+            let mut last_checkpoint = offset;
+            for (buffer, bytes_read) in full_rx {
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(&buffer[..bytes_read]);
+                }
+                if let Some(throttle) = throttle {
+                    throttle.take((bytes_read * destinations.len()) as u64);
+                }
+                for destination in &mut destinations {
+                    destination.write_all(&buffer[..bytes_read])?;
+                }
+                progress.inc(bytes_read as u64);
+                offset += bytes_read as u64;
+                if offset - last_checkpoint >= CHECKPOINT_BYTES {
+                    checkpoint(offset)?;
+                    last_checkpoint = offset;
+                }
+                // The reader may be done already
+                let _ = empty_tx.send(buffer);
+            }
+            Ok(())
+        })();
+        // A read error ends the writes early, so it is the one to report
+        let damaged = reader.join().expect("reader thread panicked")?;
+        written.map(|()| damaged)
+    })?;
+    if !damaged.is_empty() {
+        let lost: u64 = damaged.iter().map(|range| range.end - range.start).sum();
+        log::warn!(
+            "{}: {} bytes could not be read and were written as zeros",
+            src.display(),
+            lost
+        );
+        (progress.on_event)(TransferEvent::Salvaged {
+            worker: progress.worker,
+            source: src,
+            damaged: &damaged,
+        });
+    }
+    for (part, dest) in parts.iter().zip(dests) {
+        fs::rename(part, dest)
+            .with_context(|| format!("Cannot rename {} to {}", part.display(), dest.display()))?;
     }
 
     // Preserve modified time
     if let Ok(meta) = fs::metadata(src) {
         if let Ok(mtime) = meta.modified() {
-            let _ = filetime_set(dest, mtime);
+            for dest in dests {
+                let _ = filetime_set(dest, mtime);
+            }
+        }
+    }
+
+    Ok(!damaged.is_empty())
+}
+
+/// Copy what is left of `source` to `dest` in the kernel, without passing
+/// the data through this process, in chunks so progress and checkpoints go
+/// on. Returns the offset reached: the end of the source, or where the
+/// kernel could not go on, an unsupported filesystem or a read error, for
+/// the caller to copy the rest itself.
+fn copy_in_kernel(
+    source: &fs::File,
+    dest: &fs::File,
+    mut offset: u64,
+    progress: &Progress,
+    throttle: Option<&Throttle>,
+    checkpoint: &mut dyn FnMut(u64) -> Result<()>,
+) -> Result<u64> {
+    let mut last_checkpoint = offset;
+    loop {
+        progress.check()?;
+        let copied = match link::copy_range(source, dest, KERNEL_COPY_CHUNK) {
+            Ok(0) => break,
+            Ok(copied) => copied,
+            Err(e) => {
+                log::debug!("Copying in the kernel stopped at byte {}: {}", offset, e);
+                break;
+            }
+        };
+        if let Some(throttle) = throttle {
+            throttle.take(copied as u64);
+        }
+        progress.inc(copied as u64);
+        offset += copied as u64;
+        if offset - last_checkpoint >= CHECKPOINT_BYTES {
+            checkpoint(offset)?;
+            last_checkpoint = offset;
+        }
+    }
+    Ok(offset)
+}
+
+/// Read the next block of `src`, at `offset`, into `buffer`. After a read
+/// error the file is opened again and read from `offset`, up to
+/// `retry.retries` times with a doubling wait in between. When they all
+/// fail and `retry.salvage` is set, the block is [salvaged](salvage_block),
+/// adding what could not be read to `damaged`.
+fn read_block(
+    source: &mut fs::File,
+    src: &Path,
+    offset: u64,
+    buffer: &mut [u8],
+    retry: Retry,
+    progress: &Progress,
+    damaged: &mut Vec<Range<u64>>,
+) -> Result<usize> {
+    let mut attempt = 0;
+    loop {
+        let error = match source.read(buffer) {
+            Ok(bytes_read) => return Ok(bytes_read),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => anyhow::Error::new(e).context(format!(
+                "Cannot read {} at byte {}",
+                src.display(),
+                offset
+            )),
+        };
+        if attempt >= retry.retries {
+            if retry.salvage {
+                let salvaged = source.metadata().and_then(|meta| {
+                    salvage_block(source, meta.len(), offset, buffer, retry, damaged)
+                });
+                return salvaged.map_err(|_| error);
+            }
+            return Err(error);
+        }
+        attempt += 1;
+        log::warn!("{:#}, retrying ({} of {})", error, attempt, retry.retries);
+        (progress.on_event)(TransferEvent::RetryingRead {
+            worker: progress.worker,
+            source: src,
+            offset,
+            attempt,
+            error: &error,
+        });
+        thread::sleep(retry.delay.saturating_mul(1 << (attempt - 1).min(16)));
+        // A file that cannot be opened again is read through the old handle,
+        // which fails the next attempt if the card is gone
+        let reopened = fs::File::open(src)
+            .and_then(|mut file| file.seek(SeekFrom::Start(offset)).map(|_| file));
+        match reopened {
+            Ok(file) => *source = file,
+            Err(e) => log::warn!("Cannot open {} again: {}", src.display(), e),
+        }
+    }
+}
+
+/// Fill `buffer` with what can still be read of `source`, `len` bytes long,
+/// at `offset`, in [`SALVAGE_BLOCK`]s each tried `retry.retries + 1` times,
+/// and zeros for the blocks that cannot be, whose ranges are added to
+/// `damaged`. Leaves `source` after the buffer and returns its length, short
+/// at the end of the file.
+fn salvage_block(
+    source: &mut (impl Read + Seek),
+    len: u64,
+    offset: u64,
+    buffer: &mut [u8],
+    retry: Retry,
+    damaged: &mut Vec<Range<u64>>,
+) -> io::Result<usize> {
+    let filled = buffer.len().min(len.saturating_sub(offset) as usize);
+    for (i, block) in buffer[..filled].chunks_mut(SALVAGE_BLOCK).enumerate() {
+        let start = offset + (i * SALVAGE_BLOCK) as u64;
+        let read = (0..=retry.retries).any(|_| {
+            source.seek(SeekFrom::Start(start)).is_ok() && source.read_exact(block).is_ok()
+        });
+        if read {
+            continue;
+        }
+        block.fill(0);
+        let end = start + block.len() as u64;
+        match damaged.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => damaged.push(start..end),
+        }
+    }
+    source.seek(SeekFrom::Start(offset + filled as u64))?;
+    Ok(filled)
+}
+
+/// If the first `len` bytes of `dest` are those of `source`, the `algo` hash
+/// of that prefix. Leaves `source` positioned after the prefix when it matches.
+fn matching_prefix(
+    source: &mut fs::File,
+    dest: &Path,
+    len: u64,
+    algo: HashAlgo,
+) -> Result<Option<checksum::Hasher>> {
+    if len == 0 {
+        return Ok(None);
+    }
+    let Ok(mut existing) = fs::File::open(dest) else {
+        return Ok(None);
+    };
+    if existing.metadata()?.len() < len || source.metadata()?.len() < len {
+        return Ok(None);
+    }
+    let mut hasher = checksum::Hasher::new(algo);
+    let mut ours = vec![0u8; BUFFER_SIZE];
+    let mut theirs = vec![0u8; BUFFER_SIZE];
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(BUFFER_SIZE as u64) as usize;
+        source.read_exact(&mut ours[..n])?;
+        existing.read_exact(&mut theirs[..n])?;
+        if ours[..n] != theirs[..n] {
+            return Ok(None);
         }
+        hasher.update(&ours[..n]);
+        remaining -= n as u64;
     }
+    Ok(Some(hasher))
+}
+
+/// Flush a file's data and metadata to disk.
+fn sync_file(path: &Path) -> Result<()> {
+    // Windows only flushes handles opened for writing
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(cfg!(windows))
+        .open(path)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Flush a folder's entries to disk, so files just created in it survive a
+/// power loss.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<()> {
+    fs::File::open(path)?.sync_all()?;
+    Ok(())
+}
 
+/// Windows cannot open folders for flushing; NTFS journals their entries.
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<()> {
     Ok(())
 }
 
@@ -112,3 +2965,1268 @@ fn filetime_set(path: &Path, mtime: std::time::SystemTime) -> Result<()> {
     file.set_modified(mtime)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::DateSource;
+    use crate::scanner::LrfMode;
+    use chrono::NaiveDate;
+    use std::path::PathBuf;
+
+    fn make_session(paths: &[&str]) -> Session {
+        let datetime = NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        Session {
+            folder_name: "2024-01-15".to_string(),
+            files: paths
+                .iter()
+                .map(|p| DatedFile {
+                    path: PathBuf::from(p),
+                    datetime,
+                    date_source: DateSource::Exif,
+                    sequence_number: None,
+                    sidecars: Vec::new(),
+                    identical: Vec::new(),
+                    camera: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_extension_case_normalized() {
+        let session = make_session(&[
+            "card/IMG_0001.CR2",
+            "card/IMG_0002.Cr2",
+            "card/MVI_0003.mp4",
+        ]);
+        assert_eq!(
+            plan_destination_names(&session, ExtensionCase::Lower),
+            vec!["IMG_0001.cr2", "IMG_0002.cr2", "MVI_0003.mp4"]
+        );
+        assert_eq!(
+            plan_destination_names(&session, ExtensionCase::Upper),
+            vec!["IMG_0001.CR2", "IMG_0002.CR2", "MVI_0003.MP4"]
+        );
+        assert_eq!(
+            plan_destination_names(&session, ExtensionCase::Preserve),
+            vec!["IMG_0001.CR2", "IMG_0002.Cr2", "MVI_0003.mp4"]
+        );
+    }
+
+    #[test]
+    fn test_case_only_collisions_renamed() {
+        let session = make_session(&[
+            "card/100CANON/IMG_0001.CR2",
+            "card/101CANON/IMG_0001.cr2",
+            "card/102CANON/img_0001.Cr2",
+        ]);
+        assert_eq!(
+            plan_destination_names(&session, ExtensionCase::Lower),
+            vec!["IMG_0001.cr2", "IMG_0001_1.cr2", "img_0001_2.cr2"]
+        );
+        // Preserving case still treats names differing only in case as conflicts
+        assert_eq!(
+            plan_destination_names(&session, ExtensionCase::Preserve),
+            vec!["IMG_0001.CR2", "IMG_0001_1.cr2", "img_0001_2.Cr2"]
+        );
+    }
+
+    #[test]
+    fn test_sidecars_follow_primary_name() {
+        let mut session =
+            make_session(&["card/100CANON/IMG_0001.CR2", "card/101CANON/IMG_0001.CR2"]);
+        session.files[1].sidecars = vec![
+            PathBuf::from("card/101CANON/IMG_0001.XMP"),
+            PathBuf::from("card/101CANON/IMG_0001.CR2.xmp"),
+        ];
+        let names = plan_destination_names(&session, ExtensionCase::Lower);
+        let outputs: Vec<String> =
+            file_outputs(&session.files[1], &names[1], &TransferOptions::default())
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect();
+        assert_eq!(
+            outputs,
+            vec!["IMG_0001_1.cr2", "IMG_0001_1.xmp", "IMG_0001_1.cr2.xmp"]
+        );
+    }
+
+    #[test]
+    fn test_gopro_chapters_named_and_foldered() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        for name in [
+            "GH010123.MP4",
+            "GH010123.THM",
+            "GH020123.MP4",
+            "GH020123.THM",
+            "GH030123.MP4",
+        ] {
+            fs::write(card.path().join(name), name).unwrap();
+        }
+        let mut session = make_session(&[card.path().join("GH010123.MP4").to_str().unwrap()]);
+        session.files[0].sidecars = [
+            "GH010123.THM",
+            "GH020123.MP4",
+            "GH020123.THM",
+            "GH030123.MP4",
+        ]
+        .iter()
+        .map(|name| card.path().join(name))
+        .collect();
+        let names = |dest_name: &str, chapter_folders: bool| -> Vec<String> {
+            let options = TransferOptions {
+                chapter_folders,
+                ..Default::default()
+            };
+            file_outputs(&session.files[0], dest_name, &options)
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect()
+        };
+        assert_eq!(
+            names("GH010123.mp4", false),
+            [
+                "GH010123.mp4",
+                "GH010123.thm",
+                "GH020123.mp4",
+                "GH020123.thm",
+                "GH030123.mp4"
+            ]
+        );
+        assert_eq!(
+            names("GH010123_1.mp4", false),
+            [
+                "GH010123_1.mp4",
+                "GH010123_1.thm",
+                "GH010123_1_02.mp4",
+                "GH010123_1_02.thm",
+                "GH010123_1_03.mp4"
+            ]
+        );
+
+        let mut state = TransferState::new(1, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        let options = TransferOptions {
+            mirror_state_to_input: false,
+            split_by_type: true,
+            chapter_folders: true,
+            ..Default::default()
+        };
+        transfer_sessions(
+            std::slice::from_ref(&session),
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+        let recording = out.path().join("2024-01-15/VIDEO/GH010123");
+        assert_eq!(
+            fs::read(recording.join("GH020123.thm")).unwrap(),
+            b"GH020123.THM"
+        );
+        assert!(recording.join("GH030123.mp4").is_file());
+        assert!(journal.created_dirs.contains(&recording));
+    }
+
+    #[test]
+    fn test_lrf_routed_to_proxies() {
+        let mut session = make_session(&["card/DJI_0042.MP4"]);
+        session.files[0].sidecars = vec![
+            PathBuf::from("card/DJI_0042.LRF"),
+            PathBuf::from("card/DJI_0042.SRT"),
+        ];
+        let names = |lrf: LrfMode| -> Vec<String> {
+            let options = TransferOptions {
+                split_by_type: true,
+                lrf,
+                ..Default::default()
+            };
+            file_outputs(&session.files[0], "DJI_0042.mp4", &options)
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect()
+        };
+        assert_eq!(
+            names(LrfMode::Copy),
+            [
+                "VIDEO/DJI_0042.mp4",
+                "VIDEO/DJI_0042.lrf",
+                "VIDEO/DJI_0042.srt"
+            ]
+        );
+        assert_eq!(
+            names(LrfMode::Proxies),
+            [
+                "VIDEO/DJI_0042.mp4",
+                "proxies/DJI_0042.lrf",
+                "VIDEO/DJI_0042.srt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_names_truncated_to_component_limit() {
+        let long = format!("card/{}.CR2", "a".repeat(300));
+        let session = make_session(&[&long, &long]);
+        let names = plan_destination_names(&session, ExtensionCase::Lower);
+        assert_eq!(names[0], format!("{}.cr2", "a".repeat(246)));
+        assert_eq!(names[1], format!("{}_1.cr2", "a".repeat(244)));
+        assert!(names
+            .iter()
+            .all(|n| n.len() + PART_SUFFIX.len() <= MAX_COMPONENT_BYTES));
+    }
+
+    #[test]
+    fn test_path_over_max_path_rejected() {
+        let session = make_session(&["card/IMG_0001.CR2"]);
+        let plans = vec![plan_destination_names(&session, ExtensionCase::Lower)];
+        let output = Path::new("/photos");
+        let max_path = |max_path| TransferOptions {
+            max_path,
+            ..Default::default()
+        };
+        // "/photos/2024-01-15/IMG_0001.cr2.part" is 36 bytes
+        assert!(check_path_lengths(
+            output,
+            std::slice::from_ref(&session),
+            &plans,
+            &max_path(36)
+        )
+        .is_ok());
+        let err = check_path_lengths(
+            output,
+            std::slice::from_ref(&session),
+            &plans,
+            &max_path(35),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("/photos/2024-01-15/IMG_0001.cr2"));
+
+        let mut long_folder = session;
+        long_folder.folder_name = "x".repeat(256);
+        assert!(check_path_lengths(output, &[long_folder], &plans, &max_path(4096)).is_err());
+    }
+
+    #[test]
+    fn test_copy_resumes_after_matching_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("MVI_0001.MP4");
+        let data: Vec<u8> = (0..BUFFER_SIZE * 3).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data).unwrap();
+        let on_event = |_: TransferEvent| {};
+        let progress = Progress {
+            worker: 0,
+            on_event: &on_event,
+            control: None,
+        };
+
+        // An interrupted run wrote one buffer, plus some bytes after the checkpoint
+        let dest = dir.path().join("resumed.mp4");
+        fs::write(part_path(&dest), &data[..BUFFER_SIZE + 100]).unwrap();
+        let mut source = fs::File::open(&src).unwrap();
+        assert!(matching_prefix(
+            &mut source,
+            &part_path(&dest),
+            BUFFER_SIZE as u64,
+            HashAlgo::Sha256
+        )
+        .unwrap()
+        .is_some());
+
+        let mut hasher = checksum::Hasher::new(HashAlgo::Blake3);
+        copy_with_progress(
+            &src,
+            std::slice::from_ref(&dest),
+            BUFFER_SIZE as u64,
+            &progress,
+            Some(&mut hasher),
+            None,
+            Retry::default(),
+            Buffers::default(),
+            &mut |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), data);
+        assert!(!part_path(&dest).exists());
+        assert_eq!(
+            hasher.finish(),
+            checksum::hash_file(&src, HashAlgo::Blake3).unwrap()
+        );
+
+        // Without hashing, the rest is copied in the kernel where it can be
+        let dest = dir.path().join("unhashed.mp4");
+        fs::write(part_path(&dest), &data[..BUFFER_SIZE]).unwrap();
+        copy_with_progress(
+            &src,
+            std::slice::from_ref(&dest),
+            BUFFER_SIZE as u64,
+            &progress,
+            None,
+            None,
+            Retry::default(),
+            Buffers::default(),
+            &mut |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), data);
+
+        // A prefix that does not match the source is copied again from the start
+        let dest = dir.path().join("changed.mp4");
+        fs::write(part_path(&dest), vec![0u8; BUFFER_SIZE]).unwrap();
+        let mut source = fs::File::open(&src).unwrap();
+        assert!(matching_prefix(
+            &mut source,
+            &part_path(&dest),
+            BUFFER_SIZE as u64,
+            HashAlgo::Sha256
+        )
+        .unwrap()
+        .is_none());
+        copy_with_progress(
+            &src,
+            std::slice::from_ref(&dest),
+            BUFFER_SIZE as u64,
+            &progress,
+            None,
+            None,
+            Retry::default(),
+            Buffers::default(),
+            &mut |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), data);
+    }
+
+    #[test]
+    fn test_copy_through_small_buffers() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("MVI_0001.MP4");
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data).unwrap();
+        let copied = AtomicUsize::new(0);
+        let on_event = |event: TransferEvent| {
+            if let TransferEvent::Progress { bytes, .. } = event {
+                copied.fetch_add(bytes as usize, Ordering::Relaxed);
+            }
+        };
+        let progress = Progress {
+            worker: 0,
+            on_event: &on_event,
+            control: None,
+        };
+        let dests = [dir.path().join("a.mp4"), dir.path().join("b.mp4")];
+        let buffers = Buffers {
+            count: 2,
+            size: 999,
+        };
+        let mut hasher = checksum::Hasher::new(HashAlgo::Sha256);
+        let salvaged = copy_with_progress(
+            &src,
+            &dests,
+            0,
+            &progress,
+            Some(&mut hasher),
+            None,
+            Retry::default(),
+            buffers,
+            &mut |_| Ok(()),
+        )
+        .unwrap();
+        assert!(!salvaged);
+
+        for dest in &dests {
+            assert_eq!(fs::read(dest).unwrap(), data);
+        }
+        assert_eq!(copied.into_inner(), data.len());
+        assert_eq!(
+            hasher.finish(),
+            checksum::hash_file(&src, HashAlgo::Sha256).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unreadable_blocks_salvaged_as_zeros() {
+        /// Fails every read touching the bytes `bad`, like a dying card
+        struct Flaky {
+            data: io::Cursor<Vec<u8>>,
+            bad: Range<u64>,
+        }
+
+        impl Read for Flaky {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let start = self.data.position();
+                if start < self.bad.end && start + buf.len() as u64 > self.bad.start {
+                    return Err(io::Error::other("Input/output error"));
+                }
+                self.data.read(buf)
+            }
+        }
+
+        impl Seek for Flaky {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.data.seek(pos)
+            }
+        }
+
+        let data: Vec<u8> = (0..5 * SALVAGE_BLOCK + 100)
+            .map(|i| (i % 251 + 1) as u8)
+            .collect();
+        let bad = (SALVAGE_BLOCK as u64 + 10)..(3 * SALVAGE_BLOCK as u64 - 10);
+        let mut source = Flaky {
+            data: io::Cursor::new(data.clone()),
+            bad: bad.clone(),
+        };
+        let retry = Retry {
+            retries: 2,
+            delay: Duration::ZERO,
+            salvage: true,
+        };
+        let mut buffer = vec![0xFF; 8 * SALVAGE_BLOCK];
+        let mut damaged = Vec::new();
+        let filled = salvage_block(
+            &mut source,
+            data.len() as u64,
+            0,
+            &mut buffer,
+            retry,
+            &mut damaged,
+        )
+        .unwrap();
+
+        assert_eq!(filled, data.len());
+        assert_eq!(source.data.position(), data.len() as u64);
+        // The two blocks the bad bytes touch are one damaged range
+        let lost = SALVAGE_BLOCK..3 * SALVAGE_BLOCK;
+        assert_eq!(
+            damaged,
+            vec![Range {
+                start: lost.start as u64,
+                end: lost.end as u64
+            }]
+        );
+        assert!(buffer[lost.clone()].iter().all(|&b| b == 0));
+        assert_eq!(buffer[..lost.start], data[..lost.start]);
+        assert_eq!(buffer[lost.end..filled], data[lost.end..]);
+    }
+
+    #[test]
+    fn test_failed_read_retried_on_reopened_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw data").unwrap();
+        let retries = AtomicUsize::new(0);
+        let on_event = |event: TransferEvent| {
+            if let TransferEvent::RetryingRead { offset: 4, .. } = event {
+                retries.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        let progress = Progress {
+            worker: 0,
+            on_event: &on_event,
+            control: None,
+        };
+        let retry = Retry {
+            retries: 1,
+            delay: Duration::ZERO,
+            salvage: false,
+        };
+        // Reads through a write-only handle fail, like a card reader's EIO
+        let unreadable = || fs::OpenOptions::new().write(true).open(&src).unwrap();
+        let mut buffer = [0u8; 16];
+        let mut damaged = Vec::new();
+
+        let n = read_block(
+            &mut unreadable(),
+            &src,
+            4,
+            &mut buffer,
+            retry,
+            &progress,
+            &mut damaged,
+        )
+        .unwrap();
+        assert_eq!(&buffer[..n], b"data");
+        assert_eq!(retries.load(Ordering::Relaxed), 1);
+
+        let no_retry = Retry {
+            retries: 0,
+            ..retry
+        };
+        assert!(read_block(
+            &mut unreadable(),
+            &src,
+            4,
+            &mut buffer,
+            no_retry,
+            &progress,
+            &mut damaged
+        )
+        .is_err());
+        assert!(damaged.is_empty());
+    }
+
+    #[test]
+    fn test_free_space_check() {
+        let out = tempfile::tempdir().unwrap();
+        assert!(check_free_space(out.path(), 1, false, &|_| {}).is_ok());
+        // A destination still to be created is checked on its parent's filesystem
+        let missing = out.path().join("Photos/2024");
+        let err = check_free_space(&missing, u64::MAX, false, &|_| {}).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        let reported = AtomicBool::new(false);
+        let on_event = |event: TransferEvent| {
+            if let TransferEvent::LowSpace { needed, .. } = event {
+                reported.store(needed == u64::MAX, Ordering::Relaxed);
+            }
+        };
+        assert!(check_free_space(&missing, u64::MAX, true, &on_event).is_ok());
+        assert!(reported.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_pending_bytes_less_what_was_written() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0001.CR2");
+        fs::write(&src, [0u8; 10]).unwrap();
+        let session = make_session(&[src.to_str().unwrap()]);
+        let session_dir = out.path().join(&session.folder_name);
+        let job = Job {
+            key: String::new(),
+            file: &session.files[0],
+            folder_name: &session.folder_name,
+            session_dir: Some(session_dir.clone()),
+            backup_session_dir: None,
+            dest_name: "IMG_0001.cr2".to_string(),
+        };
+        let options = TransferOptions::default();
+        let mut state = TransferState::new(1, 10);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        assert_eq!(
+            pending_bytes(&job, &session_dir, &state, &journal, &options),
+            10
+        );
+
+        // An interrupted copy resumes where it stopped
+        let dest = session_dir.join("IMG_0001.cr2");
+        journal.record_file(dest.clone());
+        state.record_partial(&dest, 4);
+        assert_eq!(
+            pending_bytes(&job, &session_dir, &state, &journal, &options),
+            6
+        );
+    }
+
+    #[test]
+    fn test_move_deletes_sources_after_verified_copy() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0001.CR2");
+        let sidecar = card.path().join("IMG_0001.xmp");
+        fs::write(&src, b"raw").unwrap();
+        fs::write(&sidecar, b"xmp").unwrap();
+
+        let mut session = make_session(&[src.to_str().unwrap()]);
+        session.files[0].sidecars.push(sidecar.clone());
+        let options = TransferOptions {
+            dry_run: false,
+            extension_case: ExtensionCase::Lower,
+            split_by_type: false,
+            chapter_folders: false,
+            lrf: LrfMode::Copy,
+            link_mode: LinkMode::Copy,
+            mirror_state_to_input: false,
+            max_path: DEFAULT_MAX_PATH,
+            verify: true,
+            hash_algo: HashAlgo::Xxh3,
+            jobs: 1,
+            move_sources: true,
+            skip_duplicates: false,
+            on_conflict: OnConflict::Rename,
+            on_error: OnError::Abort,
+            time_zone: None,
+            fsync: true,
+            preserve: Vec::new(),
+            backup_dir: None,
+            max_rate: None,
+            retry: Retry::default(),
+            buffers: Buffers::default(),
+            force: false,
+            force_unlock: false,
+            card_id: None,
+            skip_imported: false,
+            manifest: false,
+            checksum_file: true,
+            thumbnails: false,
+            previews: None,
+            proxy_command: None,
+            rename: None,
+        };
+        let mut state = TransferState::new(1, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        transfer_sessions(
+            &[session],
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+
+        let session_dir = out.path().join("2024-01-15");
+        assert_eq!(fs::read(session_dir.join("IMG_0001.cr2")).unwrap(), b"raw");
+        assert!(session_dir.join("IMG_0001.xmp").is_file());
+        assert!(!src.exists());
+        assert!(!sidecar.exists());
+        let sums = fs::read_to_string(session_dir.join("XXH3SUMS")).unwrap();
+        let line = |name: &str| {
+            format!(
+                "XXH3_{}  {}\n",
+                checksum::hash_file(&session_dir.join(name), HashAlgo::Xxh3).unwrap(),
+                name
+            )
+        };
+        assert_eq!(sums, line("IMG_0001.cr2") + &line("IMG_0001.xmp"));
+        assert!(journal
+            .created_files
+            .contains(&session_dir.join("XXH3SUMS")));
+        let moved: Vec<&Path> = journal
+            .moved_files
+            .iter()
+            .flatten()
+            .map(|m| m.source.as_path())
+            .collect();
+        assert_eq!(moved, [src.as_path(), sidecar.as_path()]);
+        assert_eq!(
+            journal::load_journal(out.path()).unwrap().moved_files,
+            journal.moved_files
+        );
+        assert!(state.all_done());
+    }
+
+    #[test]
+    fn test_state_mirrored_to_card_once_done() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = (1..=3)
+            .map(|n| card.path().join(format!("IMG_000{}.CR2", n)))
+            .collect();
+        for path in &paths {
+            fs::write(path, b"raw").unwrap();
+        }
+        let session = make_session(
+            &paths
+                .iter()
+                .map(|p| p.to_str().unwrap())
+                .collect::<Vec<_>>(),
+        );
+        let mut state = TransferState::new(3, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        transfer_sessions(
+            &[session],
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &TransferOptions::default(),
+            &Hooks::default(),
+        )
+        .unwrap();
+
+        // Mirrored after the first file, then not again until the end
+        let mirrored = state::load_state(&[card.path()], card.path())
+            .unwrap()
+            .unwrap();
+        for path in &paths {
+            assert!(mirrored.is_completed(&state::file_key(path, &[card.path()])));
+        }
+    }
+
+    #[test]
+    fn test_verify_reads_destination_back_despite_cached_hash() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw").unwrap();
+
+        // A file an earlier import wrote there, with the same size and mtime
+        // the copy will have, left a hash in the cache
+        let dest = out.path().join("2024-01-15/IMG_0001.cr2");
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&dest, b"old").unwrap();
+        filetime_set(&dest, fs::metadata(&src).unwrap().modified().unwrap()).unwrap();
+        let mut cache = HashCache::default();
+        cache
+            .store(&dest, HashAlgo::Sha256, "stale".to_string())
+            .unwrap();
+        cache.save(out.path()).unwrap();
+        fs::remove_file(&dest).unwrap();
+
+        let options = TransferOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let mut state = TransferState::new(1, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        let session = make_session(&[src.to_str().unwrap()]);
+        transfer_sessions(
+            &[session],
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+        assert!(state.all_done());
+        let cache = HashCache::load(out.path());
+        assert_eq!(
+            cache.cached(&dest, HashAlgo::Sha256).unwrap(),
+            Some(checksum::hash_file(&src, HashAlgo::Sha256).unwrap())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link_modes() {
+        use std::os::unix::fs::MetadataExt;
+
+        let card = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw").unwrap();
+
+        for mode in [LinkMode::Hardlink, LinkMode::Symlink] {
+            let out = tempfile::tempdir_in(card.path()).unwrap();
+            let options = TransferOptions {
+                mirror_state_to_input: false,
+                link_mode: mode,
+                verify: true,
+                ..Default::default()
+            };
+            let mut state = TransferState::new(1, 0);
+            let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+            let session = make_session(&[src.to_str().unwrap()]);
+            transfer_sessions(
+                &[session],
+                out.path(),
+                &[card.path()],
+                &mut state,
+                &mut journal,
+                &options,
+                &Hooks::default(),
+            )
+            .unwrap();
+
+            let dest = out.path().join("2024-01-15/IMG_0001.cr2");
+            assert_eq!(fs::read(&dest).unwrap(), b"raw");
+            match mode {
+                LinkMode::Hardlink => {
+                    assert_eq!(
+                        fs::metadata(&dest).unwrap().ino(),
+                        fs::metadata(&src).unwrap().ino()
+                    )
+                }
+                _ => assert_eq!(
+                    fs::read_link(&dest).unwrap(),
+                    fs::canonicalize(&src).unwrap()
+                ),
+            }
+            assert!(state.all_done());
+        }
+    }
+
+    #[test]
+    fn test_backup_written_and_tracked_separately() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let backup = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0001.CR2");
+        fs::write(&src, b"raw").unwrap();
+
+        let options = TransferOptions {
+            mirror_state_to_input: false,
+            verify: true,
+            backup_dir: Some(backup.path().to_path_buf()),
+            ..Default::default()
+        };
+        let mut state = TransferState::new(1, 0);
+        state.track_backup();
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        let session = make_session(&[src.to_str().unwrap()]);
+        transfer_sessions(
+            std::slice::from_ref(&session),
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+        let (primary, copy) = (
+            out.path().join("2024-01-15/IMG_0001.cr2"),
+            backup.path().join("2024-01-15/IMG_0001.cr2"),
+        );
+        assert_eq!(fs::read(&primary).unwrap(), b"raw");
+        assert_eq!(fs::read(&copy).unwrap(), b"raw");
+        assert!(state.all_done());
+        assert!(journal.created_files.contains(&copy));
+
+        // A backup interrupted after the output was done only writes the backup
+        fs::remove_file(&copy).unwrap();
+        fs::write(&primary, b"kept").unwrap();
+        state.backup_completed = Some(HashSet::new());
+        assert!(!state.all_done());
+        transfer_sessions(
+            &[session],
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&primary).unwrap(), b"kept");
+        assert_eq!(fs::read(&copy).unwrap(), b"raw");
+        assert!(state.all_done());
+    }
+
+    #[test]
+    fn test_revalidate_reports_mismatched_and_missing() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["IMG_0001.CR2", "IMG_0002.CR2", "IMG_0003.CR2"]
+            .iter()
+            .map(|name| card.path().join(name))
+            .collect();
+        for path in &paths {
+            fs::write(path, b"raw").unwrap();
+        }
+        let session = make_session(
+            &paths
+                .iter()
+                .map(|p| p.to_str().unwrap())
+                .collect::<Vec<_>>(),
+        );
+        let mut state = TransferState::new(3, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        let options = TransferOptions {
+            mirror_state_to_input: false,
+            ..Default::default()
+        };
+        transfer_sessions(
+            std::slice::from_ref(&session),
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+        fs::write(out.path().join("2024-01-15/IMG_0002.cr2"), b"rot").unwrap();
+        fs::remove_file(out.path().join("2024-01-15/IMG_0003.cr2")).unwrap();
+
+        let report = revalidate_sessions(
+            &[session],
+            out.path(),
+            ExtensionCase::Lower,
+            false,
+            false,
+            None,
+            LrfMode::Copy,
+            HashAlgo::Sha256,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(report.verified, 1);
+        assert_eq!(
+            report.mismatched,
+            [out.path().join("2024-01-15/IMG_0002.cr2")]
+        );
+        assert_eq!(report.missing, [out.path().join("2024-01-15/IMG_0003.cr2")]);
+    }
+
+    #[test]
+    fn test_manifest_written_as_each_session_finishes() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["IMG_0001.CR2", "IMG_0002.CR2"]
+            .iter()
+            .map(|name| card.path().join(name))
+            .collect();
+        fs::write(&paths[0], b"raw").unwrap();
+        let first = make_session(&[paths[0].to_str().unwrap()]);
+        let mut second = make_session(&[paths[1].to_str().unwrap()]);
+        second.folder_name = "2024-01-16".to_string();
+        let mut state = TransferState::new(2, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        let options = TransferOptions {
+            mirror_state_to_input: false,
+            manifest: true,
+            ..Default::default()
+        };
+        let finished = Mutex::new(Vec::new());
+        let hooks = Hooks {
+            on_event: Box::new(|event| {
+                if let TransferEvent::SessionFinished { folder_name } = event {
+                    let manifest = out
+                        .path()
+                        .join(folder_name)
+                        .join(manifest::MANIFEST_FILENAME);
+                    finished
+                        .lock()
+                        .unwrap()
+                        .push((folder_name.to_string(), manifest.exists()));
+                }
+            }),
+            ..Default::default()
+        };
+        // The second session's file is gone from the card, failing the transfer
+        let result = transfer_sessions(
+            &[first, second],
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &hooks,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(
+            *finished.lock().unwrap(),
+            [("2024-01-15".to_string(), true)]
+        );
+        let manifest = manifest::load_manifest(&out.path().join("2024-01-15"));
+        assert_eq!(manifest.files[0].file, "IMG_0001.cr2");
+        assert!(journal.created_files.contains(
+            &out.path()
+                .join("2024-01-15")
+                .join(manifest::MANIFEST_FILENAME)
+        ));
+    }
+
+    #[test]
+    fn test_split_by_type() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        for name in [
+            "IMG_0001.CR2",
+            "IMG_0001.JPG",
+            "IMG_0001.XMP",
+            "MVI_0002.MP4",
+            "NOTES.TXT",
+        ] {
+            fs::write(card.path().join(name), name).unwrap();
+        }
+        let mut session = make_session(&[
+            card.path().join("IMG_0001.CR2").to_str().unwrap(),
+            card.path().join("MVI_0002.MP4").to_str().unwrap(),
+            card.path().join("NOTES.TXT").to_str().unwrap(),
+        ]);
+        session.files[0].sidecars = vec![
+            card.path().join("IMG_0001.JPG"),
+            card.path().join("IMG_0001.XMP"),
+        ];
+        let mut state = TransferState::new(3, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        let options = TransferOptions {
+            mirror_state_to_input: false,
+            split_by_type: true,
+            ..Default::default()
+        };
+        transfer_sessions(
+            std::slice::from_ref(&session),
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+
+        let session_dir = out.path().join("2024-01-15");
+        for name in [
+            "RAW/IMG_0001.cr2",
+            "JPEG/IMG_0001.jpg",
+            "RAW/IMG_0001.xmp",
+            "VIDEO/MVI_0002.mp4",
+            "NOTES.txt",
+        ] {
+            assert!(session_dir.join(name).is_file(), "{} is missing", name);
+        }
+        assert!(journal.created_dirs.contains(&session_dir.join("VIDEO")));
+        let report = revalidate_sessions(
+            &[session],
+            out.path(),
+            ExtensionCase::Lower,
+            true,
+            false,
+            None,
+            LrfMode::Copy,
+            HashAlgo::Sha256,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(report.verified, 5);
+    }
+
+    #[test]
+    fn test_upload_to_destination() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["IMG_0001.CR2", "IMG_0002.CR2"]
+            .iter()
+            .map(|name| card.path().join(name))
+            .collect();
+        for path in &paths {
+            fs::write(path, path.file_name().unwrap().as_encoded_bytes()).unwrap();
+        }
+        fs::create_dir(out.path().join("2024-01-15")).unwrap();
+        fs::write(out.path().join("2024-01-15/IMG_0002.cr2"), b"other").unwrap();
+        let session = make_session(
+            &paths
+                .iter()
+                .map(|p| p.to_str().unwrap())
+                .collect::<Vec<_>>(),
+        );
+        let dest = remote::LocalDir {
+            root: out.path().to_path_buf(),
+        };
+        let options = TransferOptions {
+            verify: true,
+            manifest: true,
+            ..Default::default()
+        };
+        let events = Mutex::new(Vec::new());
+        let hooks = Hooks {
+            on_event: Box::new(|event| {
+                if let TransferEvent::FileFinished { source, .. } = event {
+                    events.lock().unwrap().push(source.to_path_buf());
+                }
+            }),
+            ..Default::default()
+        };
+        let mut state = TransferState::new(2, 0);
+        upload_sessions(
+            std::slice::from_ref(&session),
+            out.path(),
+            &dest,
+            &[card.path()],
+            &mut state,
+            &options,
+            &hooks,
+        )
+        .unwrap();
+
+        let session_dir = out.path().join("2024-01-15");
+        assert_eq!(
+            fs::read(session_dir.join("IMG_0001.cr2")).unwrap(),
+            b"IMG_0001.CR2"
+        );
+        assert_eq!(
+            fs::read(session_dir.join("IMG_0002_1.cr2")).unwrap(),
+            b"IMG_0002.CR2"
+        );
+        assert_eq!(
+            fs::metadata(session_dir.join("IMG_0001.cr2"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            fs::metadata(&paths[0]).unwrap().modified().unwrap()
+        );
+        assert!(!session_dir.join("IMG_0001.cr2.part").exists());
+        assert_eq!(*events.lock().unwrap(), paths);
+        let manifest = manifest::load_manifest(&session_dir);
+        assert_eq!(manifest.files[1].file, "IMG_0002_1.cr2");
+        assert_eq!(
+            manifest.files[1].hash,
+            FileHash::Sha256(checksum::hash_file(&paths[1], HashAlgo::Sha256).unwrap())
+        );
+
+        // The state kept in the destination resumes the next run
+        let mut state = state::load_remote(&dest).unwrap().unwrap();
+        assert!(state.all_done());
+        events.lock().unwrap().clear();
+        upload_sessions(
+            &[session],
+            out.path(),
+            &dest,
+            &[card.path()],
+            &mut state,
+            &options,
+            &hooks,
+        )
+        .unwrap();
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_skips_files_imported_before() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let first = card.path().join("IMG_0001.CR2");
+        fs::write(&first, b"raw").unwrap();
+        let options = TransferOptions {
+            mirror_state_to_input: false,
+            card_id: Some("uuid:1234-ABCD".to_string()),
+            skip_imported: true,
+            ..Default::default()
+        };
+        let run = |paths: &[&str]| {
+            let copied = Mutex::new(Vec::new());
+            Transfer::new(card.path(), out.path())
+                .options(options.clone())
+                .on_progress(|event| {
+                    if let TransferEvent::Copied { source, .. } = event {
+                        copied.lock().unwrap().push(source.to_path_buf());
+                    }
+                })
+                .run(&[make_session(paths)])
+                .unwrap();
+            copied.into_inner().unwrap()
+        };
+        assert_eq!(
+            run(&[first.to_str().unwrap()]),
+            std::slice::from_ref(&first)
+        );
+
+        // The completed import left no state, and the library copy is gone
+        fs::remove_dir_all(out.path().join("2024-01-15")).unwrap();
+        let second = card.path().join("IMG_0002.CR2");
+        fs::write(&second, b"raw").unwrap();
+        assert_eq!(
+            run(&[first.to_str().unwrap(), second.to_str().unwrap()]),
+            [second]
+        );
+    }
+
+    #[test]
+    fn test_error_policy() {
+        let card = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0002.CR2");
+        fs::write(&src, b"raw").unwrap();
+        // The first file vanished from the card after the scan
+        let missing = card.path().join("IMG_0001.CR2");
+        let session = make_session(&[missing.to_str().unwrap(), src.to_str().unwrap()]);
+
+        for (on_error, copied) in [(OnError::Abort, false), (OnError::Continue, true)] {
+            let out = tempfile::tempdir().unwrap();
+            let failed = AtomicUsize::new(0);
+            let result = Transfer::new(card.path(), out.path())
+                .options(TransferOptions {
+                    mirror_state_to_input: false,
+                    on_error,
+                    ..Default::default()
+                })
+                .on_progress(|event| {
+                    if let TransferEvent::Failed { source, .. } = event {
+                        assert_eq!(source, missing);
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+                .run(std::slice::from_ref(&session));
+
+            let error = result.unwrap_err();
+            assert_eq!(failed.into_inner(), 1);
+            assert_eq!(out.path().join("2024-01-15/IMG_0002.cr2").exists(), copied);
+            assert_eq!(
+                error.downcast_ref::<FilesFailed>(),
+                copied.then_some(&FilesFailed {
+                    mismatched: 0,
+                    errors: 1
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_existing_destination_renamed_or_skipped() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0001.CR2");
+        fs::write(&src, b"new").unwrap();
+        let session_dir = out.path().join("2024-01-15");
+        fs::create_dir(&session_dir).unwrap();
+        fs::write(session_dir.join("IMG_0001.cr2"), b"old").unwrap();
+
+        for (policy, expected_new) in [
+            (OnConflict::Skip, None),
+            (OnConflict::Rename, Some("IMG_0001_1.cr2")),
+        ] {
+            let session = make_session(&[src.to_str().unwrap()]);
+            let options = TransferOptions {
+                mirror_state_to_input: false,
+                on_conflict: policy,
+                ..Default::default()
+            };
+            let mut state = TransferState::new(1, 0);
+            let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+            transfer_sessions(
+                &[session],
+                out.path(),
+                &[card.path()],
+                &mut state,
+                &mut journal,
+                &options,
+                &Hooks::default(),
+            )
+            .unwrap();
+
+            assert!(state.all_done());
+            assert_eq!(fs::read(session_dir.join("IMG_0001.cr2")).unwrap(), b"old");
+            match expected_new {
+                Some(name) => assert_eq!(fs::read(session_dir.join(name)).unwrap(), b"new"),
+                None => assert!(!session_dir.join("IMG_0001_1.cr2").exists()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_identical_destination_not_renamed() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let src = card.path().join("IMG_0001.CR2");
+        fs::write(&src, b"same").unwrap();
+        let session_dir = out.path().join("2024-01-15");
+        fs::create_dir(&session_dir).unwrap();
+        fs::write(session_dir.join("IMG_0001.cr2"), b"same").unwrap();
+
+        let session = make_session(&[src.to_str().unwrap()]);
+        let options = TransferOptions {
+            mirror_state_to_input: false,
+            ..Default::default()
+        };
+        let mut state = TransferState::new(1, 0);
+        let mut journal = ImportJournal::new(&state.transfer_id, &[card.path()]);
+        transfer_sessions(
+            &[session],
+            out.path(),
+            &[card.path()],
+            &mut state,
+            &mut journal,
+            &options,
+            &Hooks::default(),
+        )
+        .unwrap();
+
+        assert!(state.all_done());
+        assert!(!session_dir.join("IMG_0001_1.cr2").exists());
+        assert!(journal.created_files.is_empty());
+    }
+}
@@ -0,0 +1,653 @@
+//! `tui`: an import in a full-screen terminal UI.
+//!
+//! The import runs on a thread of its own and reports to the UI through
+//! [`UiEvent`]s, the way [`crate::ndjson`] reports to stdout. The UI thread
+//! draws them and turns keys into requests on the transfer's [`Control`].
+
+use anyhow::Result;
+use image_processor::control::Control;
+use image_processor::{ScanEvent, Session, TransferEvent};
+use indicatif::DecimalBytes;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Gauge, List, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the UI waits for a key before drawing again.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Throughput samples kept for the graph, one per second.
+const THROUGHPUT_SAMPLES: usize = 120;
+
+/// Problems kept for the error list, the oldest dropped first.
+const MAX_PROBLEMS: usize = 500;
+
+const HELP: &str = " p pause/resume   s skip the selected file   ↑↓ select a worker   q abort/quit";
+
+struct Ui {
+    events: Sender<UiEvent>,
+    control: Control,
+}
+
+/// Set while the TUI runs, see [`is_active`].
+static UI: OnceLock<Ui> = OnceLock::new();
+
+/// Whether the import reports to the TUI instead of the console.
+pub fn is_active() -> bool {
+    UI.get().is_some()
+}
+
+/// The control the UI's keys act on, while it runs.
+pub fn control() -> Option<&'static Control> {
+    UI.get().map(|ui| &ui.control)
+}
+
+fn send(event: UiEvent) {
+    if let Some(ui) = UI.get() {
+        // Nobody listens once the UI has stopped, and the import is aborted then
+        let _ = ui.events.send(event);
+    }
+}
+
+/// What the import tells the UI, owned so it can cross threads.
+#[derive(Debug)]
+enum UiEvent {
+    ScanStarted(PathBuf),
+    Found(usize),
+    Dated,
+    Sessions(Vec<(String, usize)>),
+    Started {
+        total_bytes: u64,
+        done_bytes: u64,
+        workers: usize,
+    },
+    FileStarted {
+        worker: usize,
+        name: String,
+        bytes: u64,
+    },
+    Progress {
+        worker: usize,
+        bytes: u64,
+    },
+    /// A worker moved on to verifying or making a proxy of its file
+    Stage {
+        worker: usize,
+        stage: &'static str,
+    },
+    WorkerFinished(usize),
+    SessionDone {
+        folder: String,
+        failed: usize,
+    },
+    /// An error or warning for the list
+    Problem(String),
+    /// The import is over, with its error if it failed
+    Finished(Option<String>),
+}
+
+pub fn scan_started(input: &Path) {
+    send(UiEvent::ScanStarted(input.to_path_buf()));
+}
+
+pub fn scan_event(event: &ScanEvent<'_>) {
+    send(match event {
+        ScanEvent::Found { total } => UiEvent::Found(*total),
+        ScanEvent::Dated { .. } => UiEvent::Dated,
+        ScanEvent::Skipped { path, error } => UiEvent::Problem(format!(
+            "Could not read the date of {}: {:#}",
+            path.display(),
+            error
+        )),
+    });
+}
+
+/// The sessions the scanned files were grouped into.
+pub fn sessions(sessions: &[Session]) {
+    send(UiEvent::Sessions(
+        sessions
+            .iter()
+            .map(|s| (s.folder_name.clone(), s.files.len()))
+            .collect(),
+    ));
+}
+
+pub fn warning(message: String) {
+    send(UiEvent::Problem(format!("Warning: {}", message)));
+}
+
+pub fn transfer_event(event: &TransferEvent<'_>) {
+    send(match *event {
+        TransferEvent::Started {
+            total_bytes,
+            done_bytes,
+            workers,
+        } => UiEvent::Started {
+            total_bytes,
+            done_bytes,
+            workers,
+        },
+        TransferEvent::FileStarted {
+            worker,
+            name,
+            bytes,
+        } => UiEvent::FileStarted {
+            worker,
+            name: name.to_string(),
+            bytes,
+        },
+        TransferEvent::Progress { worker, bytes } => UiEvent::Progress { worker, bytes },
+        TransferEvent::Verifying { worker, .. } => UiEvent::Stage {
+            worker,
+            stage: "verifying",
+        },
+        TransferEvent::MakingProxy { worker, .. } => UiEvent::Stage {
+            worker,
+            stage: "making proxy",
+        },
+        TransferEvent::WorkerFinished { worker } => UiEvent::WorkerFinished(worker),
+        TransferEvent::SessionFinished { folder_name } => UiEvent::SessionDone {
+            folder: folder_name.to_string(),
+            failed: 0,
+        },
+        TransferEvent::SessionFailed {
+            folder_name,
+            failed,
+        } => UiEvent::SessionDone {
+            folder: folder_name.to_string(),
+            failed,
+        },
+        TransferEvent::ChecksumMismatch { source, dest } => UiEvent::Problem(format!(
+            "Checksum mismatch after copying {} to {}",
+            source.display(),
+            dest.display()
+        )),
+        TransferEvent::RetryingRead { attempt, error, .. } => {
+            UiEvent::Problem(format!("Warning: {:#}, retrying ({})", error, attempt))
+        }
+        TransferEvent::Salvaged {
+            source, damaged, ..
+        } => UiEvent::Problem(format!(
+            "Warning: {} byte range(s) of {} could not be read and were written as zeros",
+            damaged.len(),
+            source.display()
+        )),
+        TransferEvent::SourceChanged { source } => UiEvent::Problem(format!(
+            "Warning: {} changed since it was copied, copying it again",
+            source.display()
+        )),
+        TransferEvent::Failed { error, .. } => UiEvent::Problem(format!("{:#}", error)),
+        TransferEvent::Skipped { source, .. } => UiEvent::Problem(format!(
+            "Skipped {}, it is copied by the next run",
+            source.display()
+        )),
+        TransferEvent::ProxyFailed { source, error } => UiEvent::Problem(format!(
+            "Could not make a proxy of {}: {:#}",
+            source.display(),
+            error
+        )),
+        TransferEvent::PreviewFailed { source, error } => UiEvent::Problem(format!(
+            "Could not write a preview of {}: {:#}",
+            source.display(),
+            error
+        )),
+        TransferEvent::StateNotMirrored { dir, error } => UiEvent::Problem(format!(
+            "Could not write state to {}: {:#}",
+            dir.display(),
+            error
+        )),
+        TransferEvent::LowSpace {
+            root,
+            needed,
+            available,
+        } => UiEvent::Problem(format!(
+            "Not enough space: {} needed in {} but only {} is available, starting anyway",
+            DecimalBytes(needed),
+            root.display(),
+            DecimalBytes(available)
+        )),
+        _ => return,
+    });
+}
+
+/// Run `import` on a thread of its own while the UI shows its progress,
+/// until the user quits once it is over. Quitting earlier aborts it.
+pub fn run(import: impl FnOnce() -> Result<()> + Send) -> Result<()> {
+    if !io::stdout().is_terminal() {
+        anyhow::bail!("tui needs a terminal, use import instead");
+    }
+    let (sender, events) = mpsc::channel();
+    let ui = Ui {
+        events: sender,
+        control: Control::new(),
+    };
+    if UI.set(ui).is_err() {
+        anyhow::bail!("The TUI is already running");
+    }
+    let control = control().expect("the UI was just set");
+    // The screen is the UI's while it runs: nothing else is printed on stdout
+    crate::output::set_machine_readable();
+    let mut terminal = ratatui::try_init()?;
+    let result = thread::scope(|scope| {
+        let import = scope.spawn(|| {
+            let result = import();
+            send(UiEvent::Finished(
+                result.as_ref().err().map(|e| format!("{:#}", e)),
+            ));
+            result
+        });
+        let shown = show(&mut terminal, &events, control);
+        if shown.is_err() {
+            // Without a screen the import cannot be followed or stopped
+            control.abort();
+        }
+        let result = import.join().expect("import thread panicked");
+        shown.and(result)
+    });
+    ratatui::restore();
+    result
+}
+
+/// Draw `events` and act on keys until the user quits.
+fn show(
+    terminal: &mut DefaultTerminal,
+    events: &Receiver<UiEvent>,
+    control: &Control,
+) -> Result<()> {
+    let mut app = App::new(Instant::now());
+    loop {
+        while let Ok(event) = events.try_recv() {
+            app.apply(event);
+        }
+        app.tick(Instant::now());
+        if app.aborting && app.finished.is_some() {
+            return Ok(());
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                if app.finished.is_some() {
+                    return Ok(());
+                }
+                control.abort();
+                app.aborting = true;
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                control.abort();
+                app.aborting = true;
+            }
+            KeyCode::Char('p') if app.finished.is_none() => {
+                control.toggle_pause();
+                app.paused = control.is_paused();
+            }
+            KeyCode::Char('s') => {
+                if let Some(worker) = app.workers.get(app.selected).filter(|w| !w.finished) {
+                    control.skip(app.selected);
+                    app.problems
+                        .push_back(format!("Skipping {}...", worker.name));
+                }
+            }
+            KeyCode::Up => app.selected = app.selected.saturating_sub(1),
+            KeyCode::Down => {
+                app.selected = (app.selected + 1).min(app.workers.len().saturating_sub(1))
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SessionRow {
+    folder: String,
+    files: usize,
+    /// Files that failed verification or were skipped, once the session is done
+    failed: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct WorkerRow {
+    name: String,
+    bytes: u64,
+    written: u64,
+    stage: Option<&'static str>,
+    finished: bool,
+}
+
+/// Everything the UI shows, built up from [`UiEvent`]s.
+#[derive(Debug)]
+struct App {
+    scanning: Option<PathBuf>,
+    found: usize,
+    dated: usize,
+    sessions: Vec<SessionRow>,
+    total_bytes: u64,
+    done_bytes: u64,
+    workers: Vec<WorkerRow>,
+    selected: usize,
+    /// Bytes written each second, the latest last
+    throughput: VecDeque<u64>,
+    /// Bytes written since the last sample
+    sample_bytes: u64,
+    sampled_at: Instant,
+    problems: VecDeque<String>,
+    paused: bool,
+    aborting: bool,
+    finished: Option<Option<String>>,
+}
+
+impl App {
+    fn new(now: Instant) -> Self {
+        Self {
+            scanning: None,
+            found: 0,
+            dated: 0,
+            sessions: Vec::new(),
+            total_bytes: 0,
+            done_bytes: 0,
+            workers: Vec::new(),
+            selected: 0,
+            throughput: VecDeque::new(),
+            sample_bytes: 0,
+            sampled_at: now,
+            problems: VecDeque::new(),
+            paused: false,
+            aborting: false,
+            finished: None,
+        }
+    }
+
+    fn apply(&mut self, event: UiEvent) {
+        match event {
+            UiEvent::ScanStarted(input) => self.scanning = Some(input),
+            UiEvent::Found(total) => self.found += total,
+            UiEvent::Dated => self.dated += 1,
+            UiEvent::Sessions(sessions) => {
+                self.scanning = None;
+                self.sessions = sessions
+                    .into_iter()
+                    .map(|(folder, files)| SessionRow {
+                        folder,
+                        files,
+                        failed: None,
+                    })
+                    .collect();
+            }
+            UiEvent::Started {
+                total_bytes,
+                done_bytes,
+                workers,
+            } => {
+                self.total_bytes = total_bytes;
+                self.done_bytes = done_bytes;
+                self.workers = (0..workers).map(|_| WorkerRow::default()).collect();
+            }
+            UiEvent::FileStarted {
+                worker,
+                name,
+                bytes,
+            } => {
+                if let Some(row) = self.workers.get_mut(worker) {
+                    *row = WorkerRow {
+                        name,
+                        bytes,
+                        ..WorkerRow::default()
+                    };
+                }
+            }
+            UiEvent::Progress { worker, bytes } => {
+                self.done_bytes += bytes;
+                self.sample_bytes += bytes;
+                if let Some(row) = self.workers.get_mut(worker) {
+                    row.written += bytes;
+                }
+            }
+            UiEvent::Stage { worker, stage } => {
+                if let Some(row) = self.workers.get_mut(worker) {
+                    row.stage = Some(stage);
+                }
+            }
+            UiEvent::WorkerFinished(worker) => {
+                if let Some(row) = self.workers.get_mut(worker) {
+                    row.finished = true;
+                }
+            }
+            UiEvent::SessionDone { folder, failed } => {
+                if let Some(row) = self.sessions.iter_mut().find(|s| s.folder == folder) {
+                    row.failed = Some(failed);
+                }
+            }
+            UiEvent::Problem(message) => {
+                self.problems.push_back(message);
+                if self.problems.len() > MAX_PROBLEMS {
+                    self.problems.pop_front();
+                }
+            }
+            UiEvent::Finished(error) => {
+                self.scanning = None;
+                self.paused = false;
+                self.finished = Some(error);
+            }
+        }
+    }
+
+    /// Take a throughput sample once a second has gone by.
+    fn tick(&mut self, now: Instant) {
+        if now.duration_since(self.sampled_at) < Duration::from_secs(1) || self.finished.is_some() {
+            return;
+        }
+        self.throughput.push_back(self.sample_bytes);
+        if self.throughput.len() > THROUGHPUT_SAMPLES {
+            self.throughput.pop_front();
+        }
+        self.sample_bytes = 0;
+        self.sampled_at = now;
+    }
+
+    fn status(&self) -> String {
+        match &self.finished {
+            Some(None) => "Done, q to quit".to_string(),
+            Some(Some(error)) => format!("Stopped: {}", error),
+            None if self.aborting => "Aborting...".to_string(),
+            None if self.paused => "Paused, p to resume".to_string(),
+            None if !self.workers.is_empty() => "Copying".to_string(),
+            None if !self.sessions.is_empty() => "Planning".to_string(),
+            None => match &self.scanning {
+                Some(input) => format!("Scanning {}", input.display()),
+                None => "Starting".to_string(),
+            },
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status, overall, middle, graph, problems, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(5),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [sessions, workers] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(middle);
+
+        frame.render_widget(
+            Paragraph::new(self.status()).style(Style::new().add_modifier(Modifier::BOLD)),
+            status,
+        );
+        self.draw_overall(frame, overall);
+
+        let rows = self.sessions.iter().map(|s| {
+            let mark = match s.failed {
+                None => " ",
+                Some(0) => "✓",
+                Some(_) => "✗",
+            };
+            let failed = s
+                .failed
+                .filter(|&n| n > 0)
+                .map(|n| format!(", {} failed", n))
+                .unwrap_or_default();
+            format!("{} {} ({} files{})", mark, s.folder, s.files, failed)
+        });
+        frame.render_widget(
+            List::new(rows).block(Block::bordered().title(" Sessions ")),
+            sessions,
+        );
+        self.draw_workers(frame, workers);
+
+        let latest = self.throughput.back().copied().unwrap_or(0);
+        let samples: Vec<u64> = self.throughput.iter().copied().collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(format!(" Throughput {}/s ", DecimalBytes(latest))))
+                .data(&samples)
+                .style(Style::new().fg(Color::Green)),
+            graph,
+        );
+
+        let shown = problems.height.saturating_sub(2) as usize;
+        let latest_problems = self
+            .problems
+            .iter()
+            .skip(self.problems.len().saturating_sub(shown));
+        frame.render_widget(
+            List::new(latest_problems.map(String::as_str))
+                .style(Style::new().fg(Color::Yellow))
+                .block(
+                    Block::bordered()
+                        .title(format!(" Errors and warnings ({}) ", self.problems.len())),
+                ),
+            problems,
+        );
+        frame.render_widget(
+            Paragraph::new(HELP).style(Style::new().fg(Color::DarkGray)),
+            help,
+        );
+    }
+
+    /// The bytes copied of the whole import, or the scan's progress before.
+    fn draw_overall(&self, frame: &mut Frame, area: Rect) {
+        let (ratio, label) = if self.total_bytes > 0 {
+            (
+                self.done_bytes as f64 / self.total_bytes as f64,
+                format!(
+                    "{} of {}",
+                    DecimalBytes(self.done_bytes),
+                    DecimalBytes(self.total_bytes)
+                ),
+            )
+        } else {
+            (
+                self.dated as f64 / self.found.max(1) as f64,
+                format!("{} of {} files read", self.dated, self.found),
+            )
+        };
+        frame.render_widget(
+            Gauge::default()
+                .ratio(ratio.clamp(0.0, 1.0))
+                .label(label)
+                .gauge_style(Style::new().fg(Color::Blue)),
+            area,
+        );
+    }
+
+    /// One gauge per worker for the file it copies.
+    fn draw_workers(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Workers ");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        for (i, worker) in self.workers.iter().enumerate().take(inner.height as usize) {
+            let row = Rect {
+                y: inner.y + i as u16,
+                height: 1,
+                ..inner
+            };
+            let selected = if i == self.selected { "> " } else { "  " };
+            let label = match (worker.finished, worker.stage) {
+                (true, _) => format!("{}idle", selected),
+                (false, Some(stage)) => format!("{}{} ({})", selected, worker.name, stage),
+                (false, None) => format!("{}{}", selected, worker.name),
+            };
+            let ratio = if worker.finished {
+                0.0
+            } else {
+                worker.written as f64 / worker.bytes.max(1) as f64
+            };
+            frame.render_widget(
+                Gauge::default()
+                    .ratio(ratio.clamp(0.0, 1.0))
+                    .label(label)
+                    .gauge_style(Style::new().fg(Color::Cyan)),
+                row,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app_follows_the_import() {
+        let start = Instant::now();
+        let mut app = App::new(start);
+        app.apply(UiEvent::ScanStarted(PathBuf::from("/card")));
+        app.apply(UiEvent::Found(2));
+        app.apply(UiEvent::Dated);
+        assert_eq!(app.status(), "Scanning /card");
+
+        app.apply(UiEvent::Sessions(vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 1),
+        ]));
+        app.apply(UiEvent::Started {
+            total_bytes: 100,
+            done_bytes: 20,
+            workers: 2,
+        });
+        app.apply(UiEvent::FileStarted {
+            worker: 1,
+            name: "b/IMG_0002.cr2".to_string(),
+            bytes: 80,
+        });
+        app.apply(UiEvent::Progress {
+            worker: 1,
+            bytes: 30,
+        });
+        assert_eq!(app.status(), "Copying");
+        assert_eq!((app.done_bytes, app.workers[1].written), (50, 30));
+
+        app.tick(start + Duration::from_millis(1500));
+        assert_eq!(app.throughput, [30]);
+        assert_eq!(app.sample_bytes, 0);
+
+        app.apply(UiEvent::SessionDone {
+            folder: "b".to_string(),
+            failed: 1,
+        });
+        assert_eq!(app.sessions[1].failed, Some(1));
+        app.apply(UiEvent::Finished(None));
+        assert_eq!(app.status(), "Done, q to quit");
+    }
+}
@@ -0,0 +1,275 @@
+//! Structure checks telling a file a failing card damaged from one that just
+//! records no date: a raw without a TIFF header, a video without its `moov`
+//! box. With [`crate::scanner::Filter::check_structure`], damaged files are
+//! left out of the sessions instead of being dated by their modified time,
+//! and [`quarantine`] copies them aside with a report.
+
+use crate::bmff;
+use crate::journal::ImportJournal;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Folder of an output damaged files are copied to.
+pub const QUARANTINE_DIR: &str = "_quarantine";
+
+/// The list of the files in [`QUARANTINE_DIR`] and why they are there.
+pub const REPORT_FILENAME: &str = "report.json";
+
+/// Check that `path` is laid out as its extension says. Formats without a
+/// check pass.
+pub fn check(path: &Path) -> Result<()> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    match ext.as_str() {
+        "cr2" | "nef" | "arw" | "dng" | "tif" | "tiff" => check_tiff(&mut file, len, ext == "cr2"),
+        "jpg" | "jpeg" => check_jpeg(&mut file),
+        "mp4" | "mov" | "m4v" | "insv" | "cr3" => check_bmff(&mut file, len, b"moov"),
+        "heic" | "heif" => check_bmff(&mut file, len, b"meta"),
+        "mts" | "m2ts" => check_transport_stream(&mut file, len),
+        _ => Ok(()),
+    }
+}
+
+/// A TIFF header, `II*\0` or `MM\0*`, pointing at a first IFD that fits in
+/// the file; a CR2 also has `CR` after the header.
+fn check_tiff(file: &mut File, len: u64, cr2: bool) -> Result<()> {
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header)
+        .context("Truncated before its TIFF header")?;
+    let little = match &header[..4] {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => anyhow::bail!("No TIFF header"),
+    };
+    if cr2 && &header[8..10] != b"CR" {
+        anyhow::bail!("No CR2 signature after the TIFF header");
+    }
+    let word = |bytes: [u8; 4]| {
+        if little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    let ifd = u64::from(word([header[4], header[5], header[6], header[7]]));
+    if ifd < 8 || ifd + 2 > len {
+        anyhow::bail!("The first IFD is outside the file, at offset {}", ifd);
+    }
+    let mut count = [0u8; 2];
+    file.seek(SeekFrom::Start(ifd))?;
+    file.read_exact(&mut count)?;
+    let count = if little {
+        u16::from_le_bytes(count)
+    } else {
+        u16::from_be_bytes(count)
+    };
+    if count == 0 || ifd + 2 + 12 * u64::from(count) > len {
+        anyhow::bail!(
+            "The first IFD, {} entries at offset {}, is truncated",
+            count,
+            ifd
+        );
+    }
+    Ok(())
+}
+
+/// A JPEG start of image marker.
+fn check_jpeg(file: &mut File) -> Result<()> {
+    let mut marker = [0u8; 3];
+    file.read_exact(&mut marker)
+        .context("Truncated before its JPEG marker")?;
+    if marker != [0xFF, 0xD8, 0xFF] {
+        anyhow::bail!("No JPEG start of image marker");
+    }
+    Ok(())
+}
+
+/// Top-level boxes that fill the file exactly, among them `required`.
+fn check_bmff(file: &mut File, len: u64, required: &[u8; 4]) -> Result<()> {
+    let boxes = bmff::children(file, 0, len)?;
+    if boxes.last().is_none_or(|last| last.end != len) {
+        anyhow::bail!("Truncated in the middle of a box");
+    }
+    if !boxes.iter().any(|b| &b.kind == required) {
+        anyhow::bail!("No '{}' box", String::from_utf8_lossy(required));
+    }
+    Ok(())
+}
+
+/// Whole 192-byte packets of an AVCHD stream, the first with its sync byte
+/// after the 4-byte timestamp.
+fn check_transport_stream(file: &mut File, len: u64) -> Result<()> {
+    let mut packet = [0u8; 5];
+    file.read_exact(&mut packet)
+        .context("Truncated before its first packet")?;
+    if packet[4] != 0x47 {
+        anyhow::bail!("No transport stream sync byte");
+    }
+    if !len.is_multiple_of(192) {
+        anyhow::bail!("Truncated in the middle of a packet");
+    }
+    Ok(())
+}
+
+/// A file copied to [`QUARANTINE_DIR`], as listed in its report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quarantined {
+    pub source: PathBuf,
+    /// Where the copy is, relative to the quarantine folder
+    pub copy: PathBuf,
+    /// Why the file was not imported
+    pub error: String,
+    /// Why the copy failed or stopped short, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_error: Option<String>,
+}
+
+/// Copy each of `files`, with why it could not be imported, to
+/// [`QUARANTINE_DIR`] in `output_dir` under its path on its input, and add
+/// them to the folder's report. A copy that fails keeps what it read, and is
+/// reported with its error rather than stopping the others. The copies and
+/// the folders made for them go in `journal`, for `undo` to remove.
+pub fn quarantine(
+    files: &[(PathBuf, String)],
+    inputs: &[&Path],
+    output_dir: &Path,
+    journal: &mut ImportJournal,
+) -> Result<Vec<Quarantined>> {
+    let dir = output_dir.join(QUARANTINE_DIR);
+    let mut copied = Vec::new();
+    for (source, error) in files {
+        let relative = inputs
+            .iter()
+            .find_map(|input| source.strip_prefix(input).ok())
+            .or_else(|| source.file_name().map(Path::new))
+            .context("A file to quarantine has no name")?;
+        let dest = dir.join(relative);
+        let missing: Vec<&Path> = dest
+            .parent()
+            .unwrap_or(&dir)
+            .ancestors()
+            .take_while(|d| !d.exists())
+            .collect();
+        for folder in missing.into_iter().rev() {
+            fs::create_dir(folder)?;
+            journal.record_dir(folder.to_path_buf());
+        }
+        journal.record_file(dest.clone());
+        let copy_error = fs::copy(source, &dest).err().map(|e| e.to_string());
+        if let Some(e) = &copy_error {
+            log::warn!(
+                "Could not copy {} to {}: {}",
+                source.display(),
+                dest.display(),
+                e
+            );
+        }
+        copied.push(Quarantined {
+            source: source.clone(),
+            copy: relative.to_path_buf(),
+            error: error.clone(),
+            copy_error,
+        });
+    }
+
+    // Earlier runs' entries stay, unless the same copy was made again
+    let report = dir.join(REPORT_FILENAME);
+    let mut listed: Vec<Quarantined> = fs::read(&report)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default();
+    listed.retain(|entry| !copied.iter().any(|c| c.copy == entry.copy));
+    listed.extend(copied.iter().cloned());
+    if !report.exists() {
+        journal.record_file(report.clone());
+    }
+    fs::write(&report, serde_json::to_string_pretty(&listed)? + "\n")
+        .with_context(|| format!("Cannot write {}", report.display()))?;
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_damaged_files_found_and_quarantined() {
+        let card = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = card.path().join(name);
+            fs::write(&path, data).unwrap();
+            path
+        };
+
+        let mut cr2 = b"II*\0\x10\0\0\0CR\x02\0\0\0\0\0\x01\0".to_vec();
+        cr2.extend([0u8; 12]);
+        assert!(check(&write("IMG_0001.CR2", &cr2)).is_ok());
+        let zeroed = write("IMG_0002.CR2", &[0u8; 64]);
+        assert!(check(&zeroed)
+            .unwrap_err()
+            .to_string()
+            .contains("No TIFF header"));
+        assert!(check(&write("IMG_0003.CR2", &cr2[..18])).is_err());
+
+        let mut mp4 = Vec::new();
+        for (kind, payload) in [(b"ftyp", &b"isom"[..]), (b"moov", &[0u8; 8][..])] {
+            mp4.extend((8 + payload.len() as u32).to_be_bytes());
+            mp4.extend(kind);
+            mp4.extend(payload);
+        }
+        assert!(check(&write("MVI_0004.MP4", &mp4)).is_ok());
+        let no_moov = write("MVI_0005.MP4", &mp4[..12]);
+        assert!(check(&no_moov).unwrap_err().to_string().contains("'moov'"));
+        assert!(check(&write("MVI_0006.MP4", &mp4[..20])).is_err());
+        let mut mts = [0u8; 384];
+        mts[4] = 0x47;
+        mts[196] = 0x47;
+        assert!(check(&write("00001.MTS", &mts)).is_ok());
+        assert!(check(&write("00002.MTS", &mts[..300])).is_err());
+        assert!(check(&write("notes.txt", b"anything")).is_ok());
+
+        let files = [
+            (zeroed, "No TIFF header".to_string()),
+            (no_moov, "No 'moov' box".to_string()),
+        ];
+        let mut journal = ImportJournal::new("test", &[card.path()]);
+        let copied = quarantine(&files, &[card.path()], out.path(), &mut journal).unwrap();
+        assert_eq!(copied[0].copy, Path::new("IMG_0002.CR2"));
+        assert!(copied.iter().all(|c| c.copy_error.is_none()));
+        assert_eq!(
+            fs::read(out.path().join(QUARANTINE_DIR).join("MVI_0005.MP4")).unwrap(),
+            &mp4[..12]
+        );
+        let dir = out.path().join(QUARANTINE_DIR);
+        assert_eq!(journal.created_dirs, std::slice::from_ref(&dir));
+        assert_eq!(
+            journal.created_files,
+            [
+                dir.join("IMG_0002.CR2"),
+                dir.join("MVI_0005.MP4"),
+                dir.join(REPORT_FILENAME)
+            ]
+        );
+
+        // A second run replaces its own entries and keeps the others
+        quarantine(
+            &files[..1],
+            &[card.path()],
+            out.path(),
+            &mut ImportJournal::default(),
+        )
+        .unwrap();
+        let report = fs::read(out.path().join(QUARANTINE_DIR).join(REPORT_FILENAME)).unwrap();
+        let listed: Vec<Quarantined> = serde_json::from_slice(&report).unwrap();
+        assert_eq!(listed.len(), 2);
+    }
+}
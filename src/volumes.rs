@@ -0,0 +1,402 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Filesystems memory cards are formatted with. Other mounts (system disks,
+/// network shares) are not probed, so a slow share cannot stall detection.
+#[cfg(target_os = "linux")]
+const CARD_FILESYSTEMS: &[&str] = &[
+    "vfat", "exfat", "msdos", "ntfs", "ntfs3", "fuseblk", "hfsplus",
+];
+
+/// Mounted volumes that look like camera cards, i.e. have a DCIM folder at
+/// their root, or the PRIVATE/AVCHD folder of a camcorder.
+pub fn detect_cards() -> Vec<PathBuf> {
+    mount_points()
+        .into_iter()
+        .filter(|m| has_dcim(m) || has_avchd(m))
+        .collect()
+}
+
+fn has_dcim(volume: &Path) -> bool {
+    // FAT volumes report the folder name as written by the camera, usually
+    // "DCIM", but some cards use lowercase
+    volume.join("DCIM").is_dir() || volume.join("dcim").is_dir()
+}
+
+fn has_avchd(volume: &Path) -> bool {
+    volume.join("PRIVATE/AVCHD").is_dir() || volume.join("private/avchd").is_dir()
+}
+
+#[cfg(target_os = "linux")]
+fn mount_points() -> Vec<PathBuf> {
+    std::fs::read_to_string("/proc/mounts")
+        .map(|mounts| parse_mounts(&mounts))
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "macos")]
+fn mount_points() -> Vec<PathBuf> {
+    std::fs::read_dir("/Volumes")
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(windows)]
+fn mount_points() -> Vec<PathBuf> {
+    (b'A'..=b'Z')
+        .map(|letter| PathBuf::from(format!("{}:\\", letter as char)))
+        .filter(|root| root.exists())
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn mount_points() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+/// Bytes available to this user on the filesystem holding `path`, or on
+/// that of its nearest existing parent when `path` is yet to be created.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let path_c = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path_c.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // The block count is 32 bits on macOS
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+pub fn available_space(path: &Path) -> Option<u64> {
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let wide = wide_path(existing.as_os_str());
+    let mut available = 0u64;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    (ok != 0).then_some(available)
+}
+
+/// `path` as the NUL-terminated UTF-16 string Windows calls take.
+#[cfg(windows)]
+fn wide_path(path: &std::ffi::OsStr) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.encode_wide().chain(Some(0)).collect()
+}
+
+/// The device path Windows opens the volume of drive `prefix` with, e.g.
+/// `\\.\E:` for `E:` or the `\\?\E:` of a canonical path; none for a
+/// path not on a drive letter.
+#[cfg(any(windows, test))]
+fn volume_device(prefix: &str) -> Option<String> {
+    let drive = prefix.strip_prefix(r"\\?\").unwrap_or(prefix);
+    match drive.as_bytes() {
+        [letter, b':'] if letter.is_ascii_alphabetic() => {
+            Some(format!(r"\\.\{}", drive.to_ascii_uppercase()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Mount points of card-like filesystems listed in /proc/mounts.
+#[cfg(target_os = "linux")]
+fn parse_mounts(mounts: &str) -> Vec<PathBuf> {
+    mount_entries(mounts)
+        .into_iter()
+        .filter(|entry| CARD_FILESYSTEMS.contains(&entry.fs_type))
+        .map(|entry| entry.mount_point)
+        .collect()
+}
+
+/// One line of /proc/mounts.
+#[cfg(target_os = "linux")]
+struct MountEntry<'a> {
+    device: &'a str,
+    mount_point: PathBuf,
+    fs_type: &'a str,
+}
+
+#[cfg(target_os = "linux")]
+fn mount_entries(mounts: &str) -> Vec<MountEntry<'_>> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (device, mount_point, fs_type) = (fields.next()?, fields.next()?, fields.next()?);
+            Some(MountEntry {
+                device,
+                mount_point: PathBuf::from(unescape_mount_point(mount_point)),
+                fs_type,
+            })
+        })
+        .collect()
+}
+
+/// A name for the volume holding `path` that stays the same across mounts
+/// and mount points: its filesystem UUID or label where the system exposes
+/// one, otherwise the path itself (on macOS, `/Volumes/<label>`).
+pub fn volume_id(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    platform_volume_id(&path).unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+/// The device mounted at the deepest mount point above `path`.
+#[cfg(target_os = "linux")]
+fn mounted_device(path: &Path) -> Option<String> {
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    let device = mount_entries(&mounts)
+        .into_iter()
+        .filter(|entry| path.starts_with(&entry.mount_point))
+        .max_by_key(|entry| entry.mount_point.components().count())?
+        .device
+        .to_string();
+    Some(device)
+}
+
+/// The UUID, or failing that the label, udev links to the device mounted
+/// at the deepest mount point above `path`.
+#[cfg(target_os = "linux")]
+fn platform_volume_id(path: &Path) -> Option<String> {
+    let device = Path::new(&mounted_device(path)?).canonicalize().ok()?;
+    ["uuid", "label"].iter().find_map(|kind| {
+        std::fs::read_dir(format!("/dev/disk/by-{}", kind))
+            .ok()?
+            .filter_map(|e| e.ok())
+            .find(|e| e.path().canonicalize().ok().as_ref() == Some(&device))
+            .map(|e| format!("{}:{}", kind, e.file_name().to_string_lossy()))
+    })
+}
+
+/// The serial number Windows assigns to the volume when it is formatted.
+#[cfg(windows)]
+fn platform_volume_id(path: &Path) -> Option<String> {
+    use std::path::Component;
+    use windows_sys::Win32::Storage::FileSystem::GetVolumeInformationW;
+
+    let Some(Component::Prefix(prefix)) = path.components().next() else {
+        return None;
+    };
+    let root = Path::new(prefix.as_os_str()).join("\\");
+    let wide = wide_path(root.as_os_str());
+    let mut serial = 0u32;
+    let ok = unsafe {
+        GetVolumeInformationW(
+            wide.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            &mut serial,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (ok != 0).then(|| format!("serial:{:08X}", serial))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn platform_volume_id(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Unmount the volume holding `path` so the card can be pulled out safely.
+/// Every write to it is flushed first.
+pub fn eject(path: &Path) -> Result<()> {
+    let path = path.canonicalize()?;
+    platform_eject(&path)
+}
+
+/// Unmount with udisks, which needs no root for removable media, then
+/// power the card reader's slot off; not every reader can be.
+#[cfg(target_os = "linux")]
+fn platform_eject(path: &Path) -> Result<()> {
+    let device = mounted_device(path)
+        .ok_or_else(|| anyhow::anyhow!("{} is not on a mounted volume", path.display()))?;
+    if !device.starts_with("/dev/") {
+        anyhow::bail!(
+            "{} is on {}, not a device that can be ejected",
+            path.display(),
+            device
+        );
+    }
+    run_eject_command("udisksctl", &["unmount", "--block-device", &device])?;
+    if let Err(e) = run_eject_command("udisksctl", &["power-off", "--block-device", &device]) {
+        log::info!("Unmounted {} but could not power it off: {:#}", device, e);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn platform_eject(path: &Path) -> Result<()> {
+    // Volumes are mounted at /Volumes/<name>; eject that, not a folder in it
+    let volume = path
+        .ancestors()
+        .find(|p| p.parent() == Some(Path::new("/Volumes")))
+        .unwrap_or(path);
+    run_eject_command("diskutil", &["eject", &volume.to_string_lossy()])
+}
+
+/// Lock and dismount the volume, then eject its media, as Explorer's
+/// "Eject" does.
+#[cfg(windows)]
+fn platform_eject(path: &Path) -> Result<()> {
+    use std::path::Component;
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, GENERIC_READ, GENERIC_WRITE, INVALID_HANDLE_VALUE,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        CreateFileW, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    };
+    use windows_sys::Win32::System::Ioctl::{
+        FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME, IOCTL_STORAGE_EJECT_MEDIA,
+    };
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+
+    let device = match path.components().next() {
+        Some(Component::Prefix(prefix)) => volume_device(&prefix.as_os_str().to_string_lossy()),
+        _ => None,
+    };
+    let device =
+        device.ok_or_else(|| anyhow::anyhow!("{} is not on a drive letter", path.display()))?;
+    let wide = wide_path(std::ffi::OsStr::new(&device));
+    let handle = unsafe {
+        CreateFileW(
+            wide.as_ptr(),
+            GENERIC_READ | GENERIC_WRITE,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            0,
+            std::ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let control = |code: u32| {
+        let mut returned = 0u32;
+        let ok = unsafe {
+            DeviceIoControl(
+                handle,
+                code,
+                std::ptr::null(),
+                0,
+                std::ptr::null_mut(),
+                0,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    let result = control(FSCTL_LOCK_VOLUME)
+        .and_then(|_| control(FSCTL_DISMOUNT_VOLUME))
+        .and_then(|_| control(IOCTL_STORAGE_EJECT_MEDIA));
+    unsafe { CloseHandle(handle) };
+    Ok(result?)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn platform_eject(_path: &Path) -> Result<()> {
+    anyhow::bail!("Ejecting is not supported on this system")
+}
+
+/// Run an eject tool, failing with what it printed if it fails.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run_eject_command(program: &str, args: &[&str]) -> Result<()> {
+    use anyhow::Context;
+
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Cannot run {}", program))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// /proc/mounts escapes spaces, tabs, newlines and backslashes as octal (`\040`).
+#[cfg(target_os = "linux")]
+fn unescape_mount_point(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|d| d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match octal {
+            Some(digits) if bytes[i] == b'\\' => {
+                out.push(
+                    digits
+                        .iter()
+                        .fold(0u8, |n, d| n.wrapping_mul(8) + (d - b'0')),
+                );
+                i += 4;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_device() {
+        assert_eq!(volume_device(r"\\?\E:").as_deref(), Some(r"\\.\E:"));
+        assert_eq!(volume_device("e:").as_deref(), Some(r"\\.\E:"));
+        assert_eq!(volume_device(r"\\?\UNC\server\share"), None);
+    }
+
+    #[test]
+    fn test_parse_mounts_keeps_card_filesystems() {
+        let mounts = "\
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+/dev/mmcblk0p1 /media/user/EOS_DIGITAL vfat rw,nosuid 0 0
+/dev/sdb1 /run/media/user/Untitled\\040Card exfat rw 0 0
+server:/photos /mnt/nas nfs4 rw 0 0
+";
+        assert_eq!(
+            parse_mounts(mounts),
+            vec![
+                PathBuf::from("/media/user/EOS_DIGITAL"),
+                PathBuf::from("/run/media/user/Untitled Card"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use crate::output::say;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Run `import` every time `find_card` reports a card, then wait for that
+/// card to be removed before watching for the next one. Failed imports are
+/// reported and the card is waited out like any other, so one bad card does
+/// not stop the loop.
+pub fn watch(
+    interval: Duration,
+    mut find_card: impl FnMut() -> Option<PathBuf>,
+    mut import: impl FnMut(&Path) -> Result<()>,
+) -> Result<()> {
+    loop {
+        let card = wait_for(interval, &mut find_card);
+        if let Err(e) = import(&card) {
+            eprintln!("Error: import from {} failed: {:#}", card.display(), e);
+        }
+
+        say!("Remove the card to import the next one.");
+        while card_present(&card) {
+            thread::sleep(interval);
+        }
+    }
+}
+
+fn wait_for(interval: Duration, find_card: &mut impl FnMut() -> Option<PathBuf>) -> PathBuf {
+    loop {
+        if let Some(card) = find_card() {
+            return card;
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// A mount point usually stays behind as an empty directory once the card is
+/// unmounted, so only a non-empty directory counts as an inserted card.
+pub fn card_present(input: &Path) -> bool {
+    fs::read_dir(input).is_ok_and(|mut entries| entries.next().is_some())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_mount_point_is_not_a_card() {
+        let dir = tempfile::tempdir().unwrap();
+        let mount = dir.path().join("SDCARD");
+        assert!(!card_present(&mount));
+        fs::create_dir(&mount).unwrap();
+        assert!(!card_present(&mount));
+        fs::create_dir(mount.join("DCIM")).unwrap();
+        assert!(card_present(&mount));
+    }
+}
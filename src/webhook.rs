@@ -0,0 +1,128 @@
+use crate::report::{FileStatus, Report};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long the webhook has to answer.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Body posted to `--webhook-url`: the totals of a [`Report`] without its
+/// per-file results.
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    /// The first of `inputs`, as payloads named their one input before
+    input: &'a Path,
+    inputs: &'a [PathBuf],
+    dry_run: bool,
+    /// Whether the run succeeded
+    ok: bool,
+    error: Option<&'a str>,
+    duration_secs: f64,
+    /// The line printed at the end of the run
+    summary: String,
+    sessions: Vec<SessionTotals<'a>>,
+    destinations: Vec<DestinationTotals<'a>>,
+    /// Files left out because their date could not be read
+    unreadable_files: usize,
+    skipped_files: usize,
+    failed_verification: usize,
+    /// Files whose transfer failed with an error
+    failed_files: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionTotals<'a> {
+    folder: &'a str,
+    files: usize,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct DestinationTotals<'a> {
+    output: &'a Path,
+    copied_files: usize,
+    copied_bytes: u64,
+}
+
+fn payload(report: &Report) -> Payload<'_> {
+    let results = report.destinations.iter().flat_map(|d| &d.files);
+    let count = |statuses: &[FileStatus]| {
+        results
+            .clone()
+            .filter(|f| statuses.contains(&f.status))
+            .count()
+    };
+    Payload {
+        input: &report.input,
+        inputs: &report.inputs,
+        dry_run: report.dry_run,
+        ok: report.error.is_none(),
+        error: report.error.as_deref(),
+        duration_secs: report.duration_secs,
+        summary: report.summary(),
+        sessions: report
+            .sessions
+            .iter()
+            .map(|s| SessionTotals {
+                folder: &s.folder,
+                files: s.files.len(),
+                bytes: s.bytes,
+            })
+            .collect(),
+        destinations: report
+            .destinations
+            .iter()
+            .map(|d| DestinationTotals {
+                output: &d.output,
+                copied_files: d.copied_files,
+                copied_bytes: d.copied_bytes,
+            })
+            .collect(),
+        unreadable_files: report.unreadable.len(),
+        skipped_files: count(&[FileStatus::Duplicate, FileStatus::ConflictSkipped]),
+        failed_verification: count(&[FileStatus::ChecksumMismatch]),
+        failed_files: report.destinations.iter().map(|d| d.failed.len()).sum(),
+    }
+}
+
+/// POST the totals of a finished run to `url` as JSON.
+pub fn post(url: &str, report: &Report) -> anyhow::Result<()> {
+    let body = serde_json::to_string(&payload(report))?;
+    ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build()
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body)
+        .map_err(Box::new)?;
+    log::info!("Posted the summary to {}", url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::SessionReport;
+
+    #[test]
+    fn test_payload_has_totals_only() {
+        let mut report = Report::default();
+        report.input = PathBuf::from("/card");
+        report.inputs = vec![PathBuf::from("/card"), PathBuf::from("/card2")];
+        report.sessions.push(SessionReport {
+            folder: "2024-01-15".to_string(),
+            bytes: 30,
+            files: Vec::new(),
+        });
+        report.finish(&Err(anyhow::anyhow!("card removed")));
+
+        let json = serde_json::to_value(payload(&report)).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["input"], "/card");
+        assert_eq!(json["inputs"][1], "/card2");
+        assert_eq!(json["error"], "card removed");
+        assert_eq!(json["sessions"][0]["folder"], "2024-01-15");
+        assert_eq!(json["sessions"][0]["bytes"], 30);
+        assert!(json["duration_secs"].is_number());
+    }
+}